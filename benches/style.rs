@@ -0,0 +1,120 @@
+//! Compares `style::style_tree` against testing every rule on every element.
+//!
+//! Run with `cargo bench --bench style`.
+
+extern crate shigi;
+
+use shigi::css::media::{Device, MediaType};
+use shigi::css::{self, Stylesheet};
+use shigi::html::{self, ElementData, Node, NodeType};
+use shigi::layout::Rect;
+use shigi::style;
+use std::time::{Duration, Instant};
+
+const RULES: usize = 5000;
+const SECTIONS: usize = 50;
+const ITEMS: usize = 40;
+
+fn stylesheet_source() -> String {
+    let mut source = String::new();
+    for i in 0..RULES {
+        let rule = match i % 5 {
+            0 => format!(".c{} {{ padding: 1px; }}\n", i),
+            1 => format!("#id{} {{ margin: 1px; }}\n", i),
+            2 => format!("section.s{} li {{ color: #ff0000; }}\n", i),
+            3 => format!("ul > li.c{} span {{ font-size: 12px; }}\n", i),
+            _ => format!("div.c{} p.c{} {{ height: 1px; }}\n", i, i + 1),
+        };
+        source.push_str(&rule);
+    }
+    source
+}
+
+fn document_source() -> String {
+    let mut source = String::from("<body>");
+    for s in 0..SECTIONS {
+        source.push_str(&format!(r#"<section class="s{}"><ul>"#, s * 5 + 2));
+        for i in 0..ITEMS {
+            source.push_str(&format!(
+                r#"<li class="c{}" id="id{}"><span>item</span></li>"#,
+                (s * ITEMS + i) * 5 % RULES,
+                (i * 5 + 1) % RULES,
+            ));
+        }
+        source.push_str("</ul></section>");
+    }
+    source.push_str("</body>");
+    source
+}
+
+/// The matching cost of the original algorithm: every selector of every rule against
+/// every element.
+fn naive_match_count<'a>(
+    node: &'a Node,
+    ancestors: &mut Vec<&'a ElementData>,
+    stylesheet: &Stylesheet,
+) -> usize {
+    let elem = match node.typ {
+        NodeType::Element(ref elem) => elem,
+        NodeType::Text(_) => return 0,
+    };
+
+    let mut count = stylesheet
+        .rules
+        .iter()
+        .filter(|rule| {
+            rule.selectors
+                .iter()
+                .any(|selector| style::matches(elem, ancestors, selector))
+        })
+        .count();
+
+    ancestors.push(elem);
+    for child in &node.children {
+        count += naive_match_count(child, ancestors, stylesheet);
+    }
+    ancestors.pop();
+    count
+}
+
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    let iterations = 5;
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    start.elapsed() / iterations
+}
+
+fn main() {
+    let stylesheet = css::parse(stylesheet_source());
+    let document = html::parse(document_source());
+    let device = Device::new(MediaType::Print, Rect::new(0.0, 0.0, 800.0, 600.0));
+
+    let naive = time(|| {
+        naive_match_count(&document.root_node, &mut Vec::new(), &stylesheet);
+    });
+    let hashed = time(|| {
+        style::style_tree(&document.root_node, &stylesheet, &device);
+    });
+
+    println!(
+        "{} rules, {} elements",
+        RULES,
+        1 + SECTIONS * (2 + ITEMS * 2)
+    );
+    println!("naive matching:     {:?}", naive);
+    println!("style_tree:         {:?}", hashed);
+    println!(
+        "speedup:            {:.1}x",
+        naive.as_secs_f64() / hashed.as_secs_f64()
+    );
+
+    #[cfg(feature = "parallel")]
+    {
+        let parallel = time(|| {
+            style::par_style_tree(&document.root_node, &stylesheet, &device);
+        });
+        println!("par_style_tree:     {:?}", parallel);
+    }
+}
@@ -0,0 +1,14 @@
+// Besides cairo and fontconfig, shigi links FreeType, to load web fonts, and HarfBuzz, to
+// shape text. Their link flags come from pkg-config where it knows them.
+fn main() {
+    for &(package, library) in &[("freetype2", "freetype"), ("harfbuzz", "harfbuzz")] {
+        if pkg_config::probe_library(package).is_err() {
+            println!(
+                "cargo:warning=pkg-config can't find {}; install its development package \
+                 (see README.md), or make lib{} available to the linker",
+                package, library
+            );
+            println!("cargo:rustc-link-lib={}", library);
+        }
+    }
+}
@@ -14,4 +14,11 @@ pub fn build_app() -> App<'static, 'static> {
                 .help("File name to write output")
                 .default_value("output.pdf"),
         )
+        .arg(
+            Arg::with_name("media")
+                .long("media")
+                .help("Media type to evaluate @media rules against")
+                .possible_values(&["print", "screen"])
+                .default_value("print"),
+        )
 }
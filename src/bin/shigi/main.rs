@@ -2,37 +2,74 @@ extern crate shigi;
 
 mod clap_app;
 
+use shigi::css::media::{Device, MediaType};
 use shigi::layout::{Dimensions, Rect};
-use shigi::{css, display, html, layout, style};
+use shigi::page::{Page, PageStyles};
+use shigi::{css, display, html, layout, loader, style, text};
 use std::fs;
+use std::process;
 
 fn main() {
     let matches = clap_app::build_app().get_matches();
     let html_path = matches.value_of("html-file").unwrap();
     let output = matches.value_of("output").unwrap();
+    let media_type = MediaType::from_name(matches.value_of("media").unwrap());
 
     let bound = Dimensions::new(Rect::new(0.0, 0.0, 800.0, 600.0));
-    render_to_pdf(html_path, output, bound);
+    if let Err(e) = render_to_pdf(html_path, output, bound, media_type) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
 }
 
-fn render_to_pdf<S: Into<String>>(html_path: S, output_path: S, bound: Dimensions) {
+fn render_to_pdf<S: Into<String>>(
+    html_path: S,
+    output_path: S,
+    bound: Dimensions,
+    media_type: MediaType,
+) -> Result<(), String> {
     let html_path = html_path.into();
     let output_path = output_path.into();
 
-    let html_source = fs::read_to_string(html_path).unwrap();
+    let failed = |e: String| format!("Failed to load {}: {}", html_path, e);
+    let html_source = fs::read_to_string(&html_path).map_err(|e| failed(e.to_string()))?;
     let document = html::parse(html_source);
+    let document_url = loader::file_url(&html_path).map_err(failed)?;
 
+    let mut warnings = Vec::new();
     let mut stylesheet = css::Stylesheet::default_style();
-    for css_path in document.collect_css_pathes() {
-        let css_source = fs::read_to_string(css_path).unwrap();
-        stylesheet.merge(css::parse(css_source));
-    }
-    for css_source in document.collect_inline_styles() {
-        stylesheet.merge(css::parse(css_source));
-    }
+    stylesheet.merge(loader::load_stylesheets(
+        &document,
+        &document_url,
+        &mut warnings,
+    ));
 
-    let style_tree = style::style_tree(&document.root_node, &stylesheet);
-    let layout_root = layout::layout_tree(&style_tree, bound);
+    // Pages are as large as `bound` unless `@page` rules say otherwise, and the first page
+    // area is the viewport.
+    let page_styles = PageStyles::new(&stylesheet, &Device::new(media_type, bound.content));
+    let page_area = page_styles.page(0).page_area();
+    let bound = Dimensions::new(Rect::new(0.0, 0.0, page_area.width, page_area.height));
+
+    let device = Device::new(media_type, bound.content);
+    text::add_font_faces(loader::load_font_faces(&stylesheet, &device, &mut warnings));
+    #[cfg(not(feature = "parallel"))]
+    let style_tree = style::style_tree(&document.root_node, &stylesheet, &device);
+    #[cfg(feature = "parallel")]
+    let style_tree = style::par_style_tree(&document.root_node, &stylesheet, &device);
+    let images = loader::load_images(&document, &document_url, &mut warnings);
+    for warning in &warnings {
+        eprintln!("{}", warning);
+    }
+    let mut layout_root = layout::layout_tree_with_images(&style_tree, &images, bound);
+    let page_count = layout::pagination::paginate_with(&mut layout_root, |index| {
+        page_styles.page(index).page_area().height
+    });
+    let pages: Vec<Page> = (0..page_count)
+        .map(|index| page_styles.layout_page(index, page_count))
+        .collect();
     let display_list = display::build_display_list(&layout_root);
-    display::pdf::render(&display_list, bound.content, output_path);
+    let page_lists = display::split_into_pages(&display_list, &pages);
+    let bounds: Vec<Rect> = pages.iter().map(|page| page.bounds()).collect();
+    display::pdf::render(&page_lists, &bounds, output_path);
+    Ok(())
 }
@@ -1,24 +1,55 @@
+pub mod calc;
+pub mod media;
 pub mod stylesheet;
 pub use stylesheet::*;
 
+use calc::CalcNode;
+use media::{MediaFeature, MediaList, MediaQuery, MediaType, Orientation, Range};
+
+/// The properties whose values are grid track sizes, the only ones flexible lengths are
+/// valid in.
+const TRACK_SIZE_PROPERTIES: [&str; 4] = [
+    "grid-template-columns",
+    "grid-template-rows",
+    "grid-auto-columns",
+    "grid-auto-rows",
+];
+
 pub fn parse(source: String) -> Stylesheet {
-    let rules = Parser {
+    let mut parser = Parser {
         pos: 0,
         input: source,
-    }
-    .parse_rules();
+        imports: Vec::new(),
+        pages: Vec::new(),
+        font_faces: Vec::new(),
+    };
+    let rules = parser.parse_rules();
 
-    return Stylesheet { rules };
+    Stylesheet {
+        rules,
+        imports: parser.imports,
+        pages: parser.pages,
+        font_faces: parser.font_faces,
+    }
 }
 
 struct Parser {
     pos: usize,
     input: String,
+    imports: Vec<Import>,
+    pages: Vec<PageRule>,
+    font_faces: Vec<FontFaceRule>,
 }
 
 impl Parser {
     fn parse_rules(&mut self) -> Vec<Rule> {
         let mut rules = Vec::new();
+        self.parse_rule_list(&[], &mut rules);
+        rules
+    }
+
+    /// Parses rules until the end of input or a closing `}`, flattening `@media` blocks.
+    fn parse_rule_list(&mut self, media: &[MediaList], rules: &mut Vec<Rule>) {
         loop {
             self.consume_whitespace();
 
@@ -27,26 +58,361 @@ impl Parser {
                 continue;
             }
 
-            if self.eof() {
+            if self.eof() || self.next_char() == '}' {
                 break;
             }
-            rules.push(self.parse_rule());
+
+            if self.next_char() == '@' {
+                self.parse_at_rule(media, rules);
+                continue;
+            }
+            rules.push(self.parse_rule(media));
         }
-        return rules;
     }
 
-    fn parse_rule(&mut self) -> Rule {
+    fn parse_rule(&mut self, media: &[MediaList]) -> Rule {
         Rule {
             selectors: self.parse_selectors(),
             declarations: self.parse_declarations(),
+            media: media.to_vec(),
+        }
+    }
+
+    fn parse_at_rule(&mut self, media: &[MediaList], rules: &mut Vec<Rule>) {
+        assert_eq!(self.consume_char(), '@');
+        let name = self.parse_identifier();
+        self.consume_whitespace();
+
+        match &*name.to_ascii_lowercase() {
+            "media" => {
+                let mut media = media.to_vec();
+                media.push(self.parse_media_list());
+                if self.eof() || self.next_char() != '{' {
+                    // A statement like `@media print;` has no rules to apply.
+                    self.skip_at_rule();
+                    return;
+                }
+                self.consume_char();
+                self.parse_rule_list(&media, rules);
+                if !self.eof() {
+                    self.consume_char();
+                }
+            }
+            "import" => {
+                let url = match self.next_char() {
+                    _ if self.eof() => return,
+                    '"' | '\'' => self.parse_string(),
+                    _ => match self.parse_value() {
+                        Some(Value::Url(url)) => url,
+                        // Only a string or `url()` names the stylesheet to import.
+                        _ => return self.skip_at_rule(),
+                    },
+                };
+                let media = self.parse_media_list();
+                if !self.eof() {
+                    if self.next_char() != ';' {
+                        return self.skip_at_rule();
+                    }
+                    self.consume_char();
+                }
+                self.imports.push(Import { url, media });
+            }
+            "page" => {
+                let page = self.parse_page_rule(media);
+                self.pages.push(page);
+            }
+            "font-face" => {
+                let font_face = self.parse_font_face_rule(media);
+                self.font_faces.push(font_face);
+            }
+            _ => self.skip_at_rule(),
+        }
+    }
+
+    /// Parses the selector and block of an `@page` rule, with the margin rules nested in it.
+    fn parse_page_rule(&mut self, media: &[MediaList]) -> PageRule {
+        let mut selector = PageSelector::default();
+        if valid_identifier_char(self.next_char()) {
+            selector.name = Some(self.parse_identifier());
+        }
+        while self.next_char() == ':' {
+            self.consume_char();
+            let class = self.parse_identifier().to_ascii_lowercase();
+            selector.pseudo_classes.push(class);
+        }
+        self.consume_whitespace();
+
+        assert_eq!(self.consume_char(), '{');
+        let mut declarations = Vec::new();
+        let mut margin_rules = Vec::new();
+        loop {
+            self.consume_whitespace();
+            match self.next_char() {
+                '}' => break,
+                '@' => {
+                    self.consume_char();
+                    let name = self.parse_identifier().to_ascii_lowercase();
+                    self.consume_whitespace();
+                    margin_rules.push((name, self.parse_declarations()));
+                }
+                _ => declarations.extend(self.parse_declaration()),
+            }
+        }
+        assert_eq!(self.consume_char(), '}');
+
+        PageRule {
+            selector,
+            declarations,
+            margin_rules,
+            media: media.to_vec(),
+        }
+    }
+
+    /// Parses the block of an `@font-face` rule. `unicode-range` and `font-style` are kept as
+    /// one keyword per comma-separated part, since ranges like `U+4??` and angles aren't
+    /// ordinary values.
+    fn parse_font_face_rule(&mut self, media: &[MediaList]) -> FontFaceRule {
+        assert_eq!(self.consume_char(), '{');
+        let mut declarations = Vec::new();
+        loop {
+            self.consume_whitespace();
+            if self.next_char() == '}' {
+                break;
+            }
+            let start = self.pos;
+            let name = self.parse_identifier().to_ascii_lowercase();
+            if name != "unicode-range" && name != "font-style" {
+                self.pos = start;
+                declarations.extend(self.parse_declaration());
+                continue;
+            }
+            self.consume_whitespace();
+            assert_eq!(self.consume_char(), ':');
+            let source = self.consume_while(|c| c != ';' && c != '}');
+            if self.next_char() == ';' {
+                self.consume_char();
+            }
+            let values = source
+                .split(',')
+                .map(str::trim)
+                .filter(|part| !part.is_empty())
+                .map(|part| Value::Keyword(part.to_string()))
+                .collect();
+            declarations.push(Declaration { name, values });
+        }
+        assert_eq!(self.consume_char(), '}');
+
+        FontFaceRule {
+            declarations,
+            media: media.to_vec(),
+        }
+    }
+
+    /// Skips an unsupported at-rule, either up to its `;` or past its block.
+    fn skip_at_rule(&mut self) {
+        let mut depth = 0;
+        while !self.eof() {
+            match self.consume_char() {
+                ';' if depth == 0 => return,
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn parse_media_list(&mut self) -> MediaList {
+        let mut queries = Vec::new();
+        loop {
+            self.consume_whitespace();
+            if self.eof() || self.next_char() == '{' || self.next_char() == ';' {
+                break;
+            }
+            queries.push(self.parse_media_query());
+            self.consume_whitespace();
+            if !self.eof() && self.next_char() == ',' {
+                self.consume_char();
+            }
+        }
+        MediaList(queries)
+    }
+
+    fn parse_media_query(&mut self) -> MediaQuery {
+        let mut query = MediaQuery {
+            negated: false,
+            media_type: MediaType::All,
+            features: Vec::new(),
+        };
+
+        if self.next_char() != '(' {
+            let mut ident = self.parse_identifier().to_ascii_lowercase();
+            if ident == "not" || ident == "only" {
+                query.negated = ident == "not";
+                self.consume_whitespace();
+                ident = self.parse_identifier();
+            }
+            query.media_type = MediaType::from_name(&ident);
+        }
+
+        loop {
+            self.consume_whitespace();
+            if self.eof() {
+                break;
+            }
+            let start = self.pos;
+            let features = match self.next_char() {
+                '(' => self.parse_media_feature(),
+                c if valid_identifier_char(c) => {
+                    if !self.parse_identifier().eq_ignore_ascii_case("and") {
+                        query.features.push(MediaFeature::Unknown);
+                    }
+                    continue;
+                }
+                ',' | '{' | ';' => break,
+                _ => None,
+            };
+            match features {
+                Some(features) => query.features.extend(features),
+                None => {
+                    // A malformed query matches nothing; the list goes on after its comma.
+                    self.pos = start;
+                    self.skip_media_query();
+                    return MediaQuery::not_all();
+                }
+            }
+        }
+        query
+    }
+
+    /// Skips the rest of a malformed media query, up to the `,`, `{` or `;` after it.
+    fn skip_media_query(&mut self) {
+        let mut depth = 0;
+        while !self.eof() {
+            match self.next_char() {
+                ',' | '{' | ';' if depth == 0 => return,
+                '(' => depth += 1,
+                ')' if depth > 0 => depth -= 1,
+                _ => {}
+            }
+            self.consume_char();
+        }
+    }
+
+    /// Parses `(name: value)`, `(name < value)` or `(value < name < value)`, or returns
+    /// `None` if it's malformed.
+    fn parse_media_feature(&mut self) -> Option<Vec<MediaFeature>> {
+        self.consume_char();
+        self.consume_whitespace();
+
+        let mut features = Vec::new();
+        if self.eof() {
+            return None;
+        } else if self.next_char().is_ascii_alphabetic() {
+            let name = self.parse_identifier().to_ascii_lowercase();
+            self.consume_whitespace();
+            match self.next_char() {
+                ':' => {
+                    self.consume_char();
+                    self.consume_whitespace();
+                    let value = self.parse_media_value()?;
+                    let feature = if let Some(name) = name.strip_prefix("min-") {
+                        media_feature(name, Range::Ge, value)
+                    } else if let Some(name) = name.strip_prefix("max-") {
+                        media_feature(name, Range::Le, value)
+                    } else {
+                        media_feature(&name, Range::Eq, value)
+                    };
+                    features.push(feature);
+                }
+                ')' => features.push(media_feature(&name, Range::Gt, MediaValue::Number(0.0))),
+                _ => {
+                    let range = self.parse_media_range()?;
+                    let value = self.parse_media_value()?;
+                    features.push(media_feature(&name, range, value));
+                }
+            }
+        } else {
+            let value = self.parse_media_value()?;
+            let range = self.parse_media_range()?;
+            let name = self.parse_identifier().to_ascii_lowercase();
+            features.push(media_feature(&name, range.flip(), value));
+            self.consume_whitespace();
+            if !self.eof() && self.next_char() != ')' {
+                let range = self.parse_media_range()?;
+                let value = self.parse_media_value()?;
+                features.push(media_feature(&name, range, value));
+            }
+        }
+
+        self.consume_whitespace();
+        if self.eof() || self.consume_char() != ')' {
+            return None;
+        }
+        Some(features)
+    }
+
+    fn parse_media_range(&mut self) -> Option<Range> {
+        let op = self.consume_while(|c| c == '<' || c == '>' || c == '=');
+        self.consume_whitespace();
+        match &*op {
+            "=" => Some(Range::Eq),
+            "<" => Some(Range::Lt),
+            "<=" => Some(Range::Le),
+            ">" => Some(Range::Gt),
+            ">=" => Some(Range::Ge),
+            _ => None,
+        }
+    }
+
+    /// Parses a media feature value into px, dppx or a plain ratio.
+    fn parse_media_value(&mut self) -> Option<MediaValue> {
+        if self.eof() {
+            return None;
         }
+        match self.next_char() {
+            c if c.is_ascii_alphabetic() => {
+                let keyword = self.parse_identifier().to_ascii_lowercase();
+                return Some(MediaValue::Keyword(keyword));
+            }
+            '0'..='9' => {}
+            '.' | '+' | '-' if self.starts_with_number() => {}
+            _ => return None,
+        }
+
+        let f = self.parse_float();
+        let unit = self.parse_identifier().to_ascii_lowercase();
+        let value = match &*unit {
+            "" => {
+                self.consume_whitespace();
+                if !self.eof() && self.next_char() == '/' {
+                    self.consume_char();
+                    self.consume_whitespace();
+                    if self.eof() || !self.next_char().is_ascii_digit() {
+                        return None;
+                    }
+                    f / self.parse_float()
+                } else {
+                    f
+                }
+            }
+            "dppx" | "x" => f,
+            "dpi" => f / 96.0,
+            "dpcm" => f * 2.54 / 96.0,
+            _ => Value::Length(f, parse_unit(&unit)?).to_px(),
+        };
+        self.consume_whitespace();
+        Some(MediaValue::Number(value))
     }
 
     fn parse_selectors(&mut self) -> Vec<Selector> {
         let mut selectors = Vec::new();
         loop {
-            selectors.push(Selector::Simple(self.parse_simple_selector()));
-            self.consume_whitespace();
+            selectors.push(self.parse_selector());
             match self.next_char() {
                 ',' => {
                     self.consume_char();
@@ -56,8 +422,32 @@ impl Parser {
                 c => panic!("Unexpected character {} in selector list", c),
             }
         }
-        selectors.sort_by(|a, b| b.specificity().cmp(&a.specificity()));
-        return selectors;
+        selectors.sort_by_key(|selector| std::cmp::Reverse(selector.specificity()));
+        selectors
+    }
+
+    fn parse_selector(&mut self) -> Selector {
+        let mut selector = Selector::Simple(self.parse_simple_selector());
+        loop {
+            self.consume_whitespace();
+            match self.next_char() {
+                ',' | '{' => return selector,
+                '>' => {
+                    self.consume_char();
+                    self.consume_whitespace();
+                    let simple = self.parse_simple_selector();
+                    selector = Selector::Child(Box::new(selector), simple);
+                }
+                c => {
+                    let start = self.pos;
+                    let simple = self.parse_simple_selector();
+                    if self.pos == start {
+                        panic!("Unexpected character {} in selector", c);
+                    }
+                    selector = Selector::Descendant(Box::new(selector), simple);
+                }
+            }
+        }
     }
 
     fn parse_simple_selector(&mut self) -> SimpleSelector {
@@ -85,7 +475,7 @@ impl Parser {
                 _ => break,
             }
         }
-        return selector;
+        selector
     }
 
     fn parse_identifier(&mut self) -> String {
@@ -100,13 +490,17 @@ impl Parser {
             if self.next_char() == '}' {
                 break;
             }
-            declarations.push(self.parse_declaration());
+            if let Some(declaration) = self.parse_declaration() {
+                declarations.push(declaration);
+            }
         }
         assert_eq!(self.consume_char(), '}');
-        return declarations;
+        declarations
     }
 
-    fn parse_declaration(&mut self) -> Declaration {
+    /// Returns `None` for declarations that are invalid, such as an ill-typed `calc()` or a
+    /// flexible length outside of a track size.
+    fn parse_declaration(&mut self) -> Option<Declaration> {
         let name = self.parse_identifier();
 
         self.consume_whitespace();
@@ -118,11 +512,27 @@ impl Parser {
         self.consume_whitespace();
         assert_eq!(self.consume_char(), ';');
 
-        return Declaration { name, values };
+        let values = values?;
+        let valid = values.iter().all(|value| match *value {
+            Value::Calc(ref node) => node.typ().is_some(),
+            _ => true,
+        });
+        if !valid {
+            return None;
+        }
+        let track_size = TRACK_SIZE_PROPERTIES.contains(&name.to_ascii_lowercase().as_str());
+        if !track_size && values.iter().any(contains_flex) {
+            return None;
+        }
+
+        Some(Declaration { name, values })
     }
 
-    fn parse_values(&mut self) -> Vec<Value> {
+    /// Parses the values up to the `;` ending a declaration, or `None` if any of them is
+    /// invalid.
+    fn parse_values(&mut self) -> Option<Vec<Value>> {
         let mut values = Vec::new();
+        let mut valid = true;
 
         loop {
             self.consume_whitespace();
@@ -131,60 +541,297 @@ impl Parser {
                 break;
             }
 
-            values.push(self.parse_value());
+            match self.parse_value() {
+                Some(value) => values.push(value),
+                None => valid = false,
+            }
         }
 
-        values
+        if valid {
+            Some(values)
+        } else {
+            None
+        }
     }
 
-    fn parse_value(&mut self) -> Value {
-        match self.next_char() {
-            '0'..='9' | '-' | '.' => self.parse_length_value(),
+    /// Parses one value, or returns `None` past an invalid one such as `calc(1px, 2px)`.
+    fn parse_value(&mut self) -> Option<Value> {
+        let value = match self.next_char() {
+            '0'..='9' | '.' | '+' => return self.parse_numeric_value(),
+            '-' if self.starts_with_number() => return self.parse_numeric_value(),
             '#' => self.parse_color_value(),
-            _ => Value::Keyword(self.parse_identifier()),
+            '"' | '\'' => Value::Str(self.parse_string()),
+            '/' | ',' => Value::Keyword(self.consume_char().to_string()),
+            _ => {
+                let name = self.parse_identifier();
+                if !self.eof() && self.next_char() == '(' {
+                    return match name.to_ascii_lowercase().as_str() {
+                        "url" => Some(Value::Url(self.parse_url())),
+                        "calc" | "min" | "max" | "clamp" => self
+                            .parse_math_function(&name)
+                            .map(|node| Value::Calc(Box::new(node))),
+                        _ => self.parse_function(&name),
+                    };
+                }
+                Value::Keyword(name)
+            }
+        };
+        Some(value)
+    }
+
+    /// Parses the comma-separated arguments of a function like `repeat()` or `minmax()`; `name`
+    /// is already consumed up to `(`. An argument of several values is a `Value::List`.
+    fn parse_function(&mut self, name: &str) -> Option<Value> {
+        assert_eq!(self.consume_char(), '(');
+        let mut args = Vec::new();
+        let mut valid = true;
+        loop {
+            let mut values = Vec::new();
+            loop {
+                self.consume_whitespace();
+                match self.next_char() {
+                    ',' | ')' => break,
+                    _ => match self.parse_value() {
+                        Some(value) => values.push(value),
+                        None => valid = false,
+                    },
+                }
+            }
+            args.push(if values.len() == 1 {
+                values.remove(0)
+            } else {
+                Value::List(values)
+            });
+            if self.consume_char() == ')' {
+                break;
+            }
         }
+        if !valid {
+            return None;
+        }
+        Some(Value::Function(name.to_ascii_lowercase(), args))
+    }
+
+    /// Parses the argument of `url(...)`, quoted or not; `url` is already consumed up to `(`.
+    fn parse_url(&mut self) -> String {
+        assert_eq!(self.consume_char(), '(');
+        self.consume_whitespace();
+        let url = match self.next_char() {
+            '"' | '\'' => self.parse_string(),
+            _ => self.consume_while(|c| c != ')' && !c.is_whitespace()),
+        };
+        self.consume_whitespace();
+        assert_eq!(self.consume_char(), ')');
+        url
     }
 
-    fn parse_length_value(&mut self) -> Value {
+    fn parse_string(&mut self) -> String {
+        let open_quote = self.consume_char();
+        let value = self.consume_while(|c| c != open_quote);
+        assert_eq!(self.consume_char(), open_quote);
+        value
+    }
+
+    /// Parses a number, percentage or dimension, or returns `None` past a dimension in a unit
+    /// that isn't supported, such as the angle of `oblique 10deg`.
+    fn parse_numeric_value(&mut self) -> Option<Value> {
         let f = self.parse_float();
-        match self.next_char() {
-            ';' => Value::Number(f),
-            _ => Value::Length(f, self.parse_unit()),
+        if self.eof() {
+            return Some(Value::Number(f));
         }
+        let value = match self.next_char() {
+            '%' => {
+                self.consume_char();
+                Value::Percentage(f)
+            }
+            c if c.is_ascii_alphabetic() => {
+                let unit = self.parse_identifier();
+                match unit.to_ascii_lowercase().as_str() {
+                    "fr" => Value::Flex(f),
+                    _ => Value::Length(f, parse_unit(&unit)?),
+                }
+            }
+            _ => Value::Number(f),
+        };
+        Some(value)
     }
 
     fn parse_float(&mut self) -> f32 {
-        let float_literal = self.consume_while(|c| match c {
-            '0'..='9' | '.' | '-' => true,
+        let sign = self.consume_while(|c| c == '-' || c == '+');
+        let float_literal = self.consume_while(|c| matches!(c, '0'..='9' | '.'));
+
+        let f: f32 = float_literal.parse().unwrap();
+        if sign == "-" {
+            -f
+        } else {
+            f
+        }
+    }
+
+    fn starts_with_number(&self) -> bool {
+        let mut chars = self.input[self.pos..].chars().skip(1);
+        match chars.next() {
+            Some('0'..='9') => true,
+            Some('.') => matches!(chars.next(), Some('0'..='9')),
             _ => false,
-        });
+        }
+    }
+
+    /// Parses the arguments of `calc()`, `min()`, `max()` or `clamp()`; `name` is already
+    /// consumed up to `(`. Returns `None` past the closing `)` if they are invalid, such as
+    /// `calc(1px, 2px)` or `clamp(1px, 2px)`.
+    fn parse_math_function(&mut self, name: &str) -> Option<CalcNode> {
+        assert_eq!(self.consume_char(), '(');
+        let start = self.pos;
+        let mut args = match self.parse_math_arguments() {
+            Some(args) => args,
+            None => {
+                self.pos = start;
+                self.skip_arguments();
+                return None;
+            }
+        };
+
+        match (name.to_ascii_lowercase().as_str(), args.len()) {
+            ("calc", 1) => Some(args.remove(0)),
+            ("min", _) => Some(CalcNode::Min(args)),
+            ("max", _) => Some(CalcNode::Max(args)),
+            ("clamp", 3) => {
+                let max = args.pop()?;
+                let val = args.pop()?;
+                let min = args.pop()?;
+                Some(CalcNode::Clamp(Box::new(min), Box::new(val), Box::new(max)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Parses comma-separated math expressions up to and including the closing `)`.
+    fn parse_math_arguments(&mut self) -> Option<Vec<CalcNode>> {
+        let mut args = Vec::new();
+        loop {
+            self.consume_whitespace();
+            args.push(self.parse_calc_sum()?);
+            self.consume_whitespace();
+            if self.eof() {
+                return None;
+            }
+            match self.consume_char() {
+                ',' => {}
+                ')' => return Some(args),
+                _ => return None,
+            }
+        }
+    }
 
-        return float_literal.parse().unwrap();
+    /// Skips the arguments of a function up to and including its closing `)`, however they
+    /// nest.
+    fn skip_arguments(&mut self) {
+        let mut depth = 0;
+        while !self.eof() {
+            match self.consume_char() {
+                '(' => depth += 1,
+                ')' if depth == 0 => return,
+                ')' => depth -= 1,
+                _ => {}
+            }
+        }
     }
 
-    fn parse_unit(&mut self) -> Unit {
-        let unit = self.parse_identifier();
-        match unit.as_str() {
-            "px" => Unit::Px,
-            "em" => Unit::Em,
-            _ => panic!(""),
+    fn parse_calc_sum(&mut self) -> Option<CalcNode> {
+        let mut node = self.parse_calc_product()?;
+        loop {
+            // `+` and `-` must be surrounded by whitespace, otherwise they belong to a number.
+            let start = self.pos;
+            self.consume_whitespace();
+            if self.eof() || self.pos == start {
+                self.pos = start;
+                return Some(node);
+            }
+            let op = self.next_char();
+            if op != '+' && op != '-' {
+                self.pos = start;
+                return Some(node);
+            }
+            self.consume_char();
+            self.consume_whitespace();
+
+            let rhs = Box::new(self.parse_calc_product()?);
+            node = match op {
+                '+' => CalcNode::Sum(Box::new(node), rhs),
+                _ => CalcNode::Difference(Box::new(node), rhs),
+            };
+        }
+    }
+
+    fn parse_calc_product(&mut self) -> Option<CalcNode> {
+        let mut node = self.parse_calc_value()?;
+        loop {
+            let start = self.pos;
+            self.consume_whitespace();
+            if self.eof() {
+                self.pos = start;
+                return Some(node);
+            }
+            let op = self.next_char();
+            if op != '*' && op != '/' {
+                self.pos = start;
+                return Some(node);
+            }
+            self.consume_char();
+            self.consume_whitespace();
+
+            let rhs = Box::new(self.parse_calc_value()?);
+            node = match op {
+                '*' => CalcNode::Product(Box::new(node), rhs),
+                _ => CalcNode::Quotient(Box::new(node), rhs),
+            };
+        }
+    }
+
+    fn parse_calc_value(&mut self) -> Option<CalcNode> {
+        if self.eof() {
+            return None;
+        }
+        match self.next_char() {
+            '(' => {
+                self.consume_char();
+                self.consume_whitespace();
+                let node = self.parse_calc_sum()?;
+                self.consume_whitespace();
+                if self.eof() || self.consume_char() != ')' {
+                    return None;
+                }
+                Some(node)
+            }
+            '0'..='9' => self.parse_numeric_value().map(CalcNode::Leaf),
+            '.' | '+' | '-' if self.starts_with_number() => {
+                self.parse_numeric_value().map(CalcNode::Leaf)
+            }
+            _ => {
+                let name = self.parse_identifier();
+                if name.is_empty() || self.eof() || self.next_char() != '(' {
+                    return None;
+                }
+                self.parse_math_function(&name)
+            }
         }
     }
 
     fn parse_color_value(&mut self) -> Value {
         assert_eq!(self.consume_char(), '#');
-        return Value::ColorValue(Color {
+        Value::ColorValue(Color {
             r: self.parse_hex(),
             g: self.parse_hex(),
             b: self.parse_hex(),
             a: 255,
-        });
+        })
     }
 
     fn parse_hex(&mut self) -> u8 {
         let hex_literal = &self.input[self.pos..self.pos + 2];
         self.pos += 2;
-        return u8::from_str_radix(hex_literal, 16).unwrap();
+        u8::from_str_radix(hex_literal, 16).unwrap()
     }
 
     fn consume_while<F>(&mut self, test: F) -> String
@@ -195,7 +842,7 @@ impl Parser {
         while !self.eof() && test(self.next_char()) {
             result.push(self.consume_char());
         }
-        return result;
+        result
     }
 
     fn consume_whitespace(&mut self) {
@@ -230,21 +877,79 @@ impl Parser {
         let (_, cur_char) = iter.next().unwrap();
         let (next_pos, _) = iter.next().unwrap_or((1, ' '));
         self.pos += next_pos;
-        return cur_char;
+        cur_char
     }
 }
 
-fn valid_identifier_char(c: char) -> bool {
-    match c {
-        'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => true,
+enum MediaValue {
+    Number(f32),
+    Keyword(String),
+}
+
+fn media_feature(name: &str, range: Range, value: MediaValue) -> MediaFeature {
+    match (name, value) {
+        ("width", MediaValue::Number(f)) => MediaFeature::Width(range, f),
+        ("height", MediaValue::Number(f)) => MediaFeature::Height(range, f),
+        ("aspect-ratio", MediaValue::Number(f)) => MediaFeature::AspectRatio(range, f),
+        ("resolution", MediaValue::Number(f)) => MediaFeature::Resolution(range, f),
+        ("orientation", MediaValue::Keyword(ref k)) if k == "portrait" => {
+            MediaFeature::Orientation(Orientation::Portrait)
+        }
+        ("orientation", MediaValue::Keyword(ref k)) if k == "landscape" => {
+            MediaFeature::Orientation(Orientation::Landscape)
+        }
+        _ => MediaFeature::Unknown,
+    }
+}
+
+/// The length unit named `unit`, or `None` for other units.
+fn parse_unit(unit: &str) -> Option<Unit> {
+    let unit = match unit.to_ascii_lowercase().as_str() {
+        "px" => Unit::Px,
+        "em" => Unit::Em,
+        "rem" => Unit::Rem,
+        "ex" => Unit::Ex,
+        "ch" => Unit::Ch,
+        "vw" => Unit::Vw,
+        "vh" => Unit::Vh,
+        "vmin" => Unit::Vmin,
+        "vmax" => Unit::Vmax,
+        "cm" => Unit::Cm,
+        "mm" => Unit::Mm,
+        "q" => Unit::Q,
+        "in" => Unit::In,
+        "pt" => Unit::Pt,
+        "pc" => Unit::Pc,
+        _ => return None,
+    };
+    Some(unit)
+}
+
+/// Whether `value` is or holds a flexible length.
+fn contains_flex(value: &Value) -> bool {
+    match *value {
+        Value::Flex(_) => true,
+        Value::Function(_, ref values) | Value::List(ref values) => {
+            values.iter().any(contains_flex)
+        }
         _ => false,
     }
 }
 
+fn valid_identifier_char(c: char) -> bool {
+    matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_')
+}
+
 #[cfg(test)]
 mod tests {
     use super::parse;
-    use crate::css::{Color, Declaration, Rule, Selector, SimpleSelector, Stylesheet, Unit, Value};
+    use crate::css::calc::CalcNode;
+    use crate::css::media::{Device, MediaType};
+    use crate::css::{
+        Color, ComputeContext, Declaration, Rule, Selector, SimpleSelector, Stylesheet, Unit, Value,
+    };
+    use crate::layout::Rect;
+    use url::Url;
 
     #[test]
     fn test_parse() {
@@ -289,6 +994,7 @@ mod tests {
                             })],
                         },
                     ],
+                    media: vec![],
                 },
                 Rule {
                     selectors: vec![Selector::Simple(SimpleSelector {
@@ -306,6 +1012,7 @@ mod tests {
                             values: vec![Value::Length(10.0, Unit::Px)],
                         },
                     ],
+                    media: vec![],
                 },
                 Rule {
                     selectors: vec![Selector::Simple(SimpleSelector {
@@ -317,12 +1024,196 @@ mod tests {
                         name: "display".to_string(),
                         values: vec![Value::Keyword("none".to_string())],
                     }],
+                    media: vec![],
                 },
             ],
+            ..Default::default()
         };
 
         let actual = parse(css_source);
 
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn test_flexible_lengths() {
+        let stylesheet = parse(
+            "div {
+                grid-template-columns: 100px 1fr repeat(2, minmax(10px, 2fr));
+                width: 1fr;
+                margin: 0 2fr;
+                grid-auto-rows: calc(1fr + 10px);
+                grid-auto-columns: 1FR;
+            }"
+            .to_string(),
+        );
+        let declarations = &stylesheet.rules[0].declarations;
+        let names: Vec<&str> = declarations.iter().map(|decl| decl.name.as_str()).collect();
+        assert_eq!(names, ["grid-template-columns", "grid-auto-columns"]);
+        assert_eq!(declarations[0].values[1], Value::Flex(1.0));
+        assert_eq!(declarations[1].values, vec![Value::Flex(1.0)]);
+    }
+
+    #[test]
+    fn test_unknown_units() {
+        let stylesheet = parse(
+            "em { font-style: oblique 10deg; color: #ff0000; }
+             p { width: calc(10px + 1turn); margin-left: 2furlongs; height: 5mm; }
+             @media (min-width: 10deg) { p { width: 10px; } }"
+                .to_string(),
+        );
+        let names = |i: usize| -> Vec<&str> {
+            let declarations = &stylesheet.rules[i].declarations;
+            declarations.iter().map(|decl| decl.name.as_str()).collect()
+        };
+        assert_eq!(names(0), ["color"]);
+        assert_eq!(names(1), ["height"]);
+        let device = Device::new(MediaType::Print, Rect::new(0.0, 0.0, 800.0, 600.0));
+        assert!(!stylesheet.rules[2].applies_to(&device));
+    }
+
+    #[test]
+    fn test_parse_math_functions() {
+        let css_source = r#"
+        p {
+            width: calc(100% - 2em);
+            font-size: clamp(12px, 2vw, 20px);
+            height: calc(1px * 2px);
+            top: calc(1px, 2px);
+            left: clamp(1px, 2px) 3px;
+            right: calc(1px ; 2px);
+            bottom: calc(min(1px, ) + (2px));
+            margin-left: max(10px, 5%);
+        }"#
+        .to_string();
+
+        let stylesheet = parse(css_source);
+        let declarations = &stylesheet.rules[0].declarations;
+        assert_eq!(declarations.len(), 3);
+
+        assert_eq!(
+            declarations[0].values,
+            vec![Value::Calc(Box::new(CalcNode::Difference(
+                Box::new(CalcNode::Leaf(Value::Percentage(100.0))),
+                Box::new(CalcNode::Leaf(Value::Length(2.0, Unit::Em))),
+            )))]
+        );
+
+        let cx = ComputeContext {
+            font_size: 10.0,
+            viewport_width: 500.0,
+            ..Default::default()
+        };
+        assert_eq!(declarations[0].values[0].compute(&cx).resolve(200.0), 180.0);
+        assert_eq!(
+            declarations[1].values[0].compute(&cx),
+            Value::Length(12.0, Unit::Px)
+        );
+        assert_eq!(declarations[2].name, "margin-left");
+        assert_eq!(declarations[2].values[0].compute(&cx).resolve(400.0), 20.0);
+    }
+
+    #[test]
+    fn test_parse_media_rules() {
+        let css_source = r#"
+        @charset "utf-8";
+        p { color: #000000; }
+        @media print {
+            p { color: #ff0000; }
+        }
+        @media screen and (min-width: 600px), (orientation: portrait) {
+            @media (400px <= width < 1000px) {
+                div { color: #00ff00; }
+            }
+        }
+        @media not screen and (resolution >= 2dppx) {
+            span { color: #0000ff; }
+        }"#
+        .to_string();
+
+        let stylesheet = parse(css_source);
+        assert_eq!(stylesheet.rules.len(), 4);
+
+        let viewport = Rect::new(0.0, 0.0, 800.0, 600.0);
+        let print = Device::new(MediaType::Print, viewport);
+        let screen = Device::new(MediaType::Screen, viewport);
+        let applies = |device: &Device| -> Vec<bool> {
+            stylesheet
+                .rules
+                .iter()
+                .map(|rule| rule.applies_to(device))
+                .collect()
+        };
+
+        assert_eq!(applies(&print), vec![true, true, false, true]);
+        assert_eq!(applies(&screen), vec![true, false, true, true]);
+        assert_eq!(
+            applies(&Device {
+                resolution: 2.0,
+                ..screen
+            }),
+            vec![true, false, true, false]
+        );
+        assert_eq!(
+            applies(&Device {
+                width: 1200.0,
+                ..screen
+            }),
+            vec![true, false, false, true]
+        );
+    }
+
+    #[test]
+    fn test_malformed_media_queries() {
+        let stylesheet = parse(
+            "@media (min-width 10px) { p { color: #ff0000; } }
+            @media not print and (width: ) { p { color: #00ff00; } }
+            @media (16/ < aspect-ratio), print { em { color: #0000ff; } }
+            @media print;
+            div { color: #000000; }"
+                .to_string(),
+        );
+        assert_eq!(stylesheet.rules.len(), 4);
+
+        let viewport = Rect::new(0.0, 0.0, 800.0, 600.0);
+        let applies = |media_type| -> Vec<bool> {
+            let device = Device::new(media_type, viewport);
+            stylesheet
+                .rules
+                .iter()
+                .map(|rule| rule.applies_to(&device))
+                .collect()
+        };
+        // Each malformed query is `not all`, even when negated, and the list goes on.
+        assert_eq!(applies(MediaType::Print), vec![false, false, true, true]);
+        assert_eq!(applies(MediaType::Screen), vec![false, false, false, true]);
+    }
+
+    #[test]
+    fn test_parse_imports() {
+        let css_source = r#"
+        @import url("base.css");
+        @import '../print.css' print;
+        @import 12px;
+        @import "screen.css" screen { p { color: red; } }
+        div { background: url(img/bg.png); }"#
+            .to_string();
+
+        let mut stylesheet = parse(css_source);
+        stylesheet.resolve_urls(&Url::parse("file:///site/css/main.css").unwrap());
+
+        let urls: Vec<&str> = stylesheet.imports.iter().map(|i| &*i.url).collect();
+        assert_eq!(
+            urls,
+            vec!["file:///site/css/base.css", "file:///site/print.css"]
+        );
+        assert!(stylesheet.imports[0].media.0.is_empty());
+        assert_eq!(stylesheet.imports[1].media.0.len(), 1);
+        // Invalid `@import`s are skipped.
+        assert_eq!(stylesheet.rules.len(), 1);
+        assert_eq!(
+            stylesheet.rules[0].declarations[0].values,
+            vec![Value::Url("file:///site/css/img/bg.png".to_string())]
+        );
+    }
 }
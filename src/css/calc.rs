@@ -0,0 +1,109 @@
+use crate::css::{ComputeContext, Unit, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CalcNode {
+    Leaf(Value),
+    Sum(Box<CalcNode>, Box<CalcNode>),
+    Difference(Box<CalcNode>, Box<CalcNode>),
+    Product(Box<CalcNode>, Box<CalcNode>),
+    Quotient(Box<CalcNode>, Box<CalcNode>),
+    Min(Vec<CalcNode>),
+    Max(Vec<CalcNode>),
+    Clamp(Box<CalcNode>, Box<CalcNode>, Box<CalcNode>),
+}
+
+/// The type of a math expression, following CSS Values 4 §10.9.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalcType {
+    Number,
+    Length,
+    Percentage,
+    LengthPercentage,
+}
+
+impl CalcType {
+    fn add(self, other: CalcType) -> Option<CalcType> {
+        use CalcType::*;
+        match (self, other) {
+            (a, b) if a == b => Some(a),
+            (Number, _) | (_, Number) => None,
+            _ => Some(LengthPercentage),
+        }
+    }
+}
+
+impl CalcNode {
+    /// Returns `None` when the expression is invalid, e.g. `1px * 2px` or `1px + 2`.
+    pub fn typ(&self) -> Option<CalcType> {
+        match *self {
+            CalcNode::Leaf(ref value) => match *value {
+                Value::Number(_) => Some(CalcType::Number),
+                Value::Length(..) => Some(CalcType::Length),
+                Value::Percentage(_) => Some(CalcType::Percentage),
+                _ => None,
+            },
+            CalcNode::Sum(ref a, ref b) | CalcNode::Difference(ref a, ref b) => {
+                a.typ()?.add(b.typ()?)
+            }
+            CalcNode::Product(ref a, ref b) => match (a.typ()?, b.typ()?) {
+                (CalcType::Number, t) | (t, CalcType::Number) => Some(t),
+                _ => None,
+            },
+            CalcNode::Quotient(ref a, ref b) => match b.typ()? {
+                CalcType::Number => a.typ(),
+                _ => None,
+            },
+            CalcNode::Min(ref args) | CalcNode::Max(ref args) => {
+                let mut typ = args.first()?.typ()?;
+                for arg in &args[1..] {
+                    typ = typ.add(arg.typ()?)?;
+                }
+                Some(typ)
+            }
+            CalcNode::Clamp(ref min, ref val, ref max) => {
+                min.typ()?.add(val.typ()?)?.add(max.typ()?)
+            }
+        }
+    }
+
+    /// Converts every relative length leaf into px.
+    pub fn compute(&self, cx: &ComputeContext) -> CalcNode {
+        let compute = |node: &CalcNode| Box::new(node.compute(cx));
+        match *self {
+            CalcNode::Leaf(ref value) => CalcNode::Leaf(value.compute(cx)),
+            CalcNode::Sum(ref a, ref b) => CalcNode::Sum(compute(a), compute(b)),
+            CalcNode::Difference(ref a, ref b) => CalcNode::Difference(compute(a), compute(b)),
+            CalcNode::Product(ref a, ref b) => CalcNode::Product(compute(a), compute(b)),
+            CalcNode::Quotient(ref a, ref b) => CalcNode::Quotient(compute(a), compute(b)),
+            CalcNode::Min(ref args) => CalcNode::Min(args.iter().map(|a| a.compute(cx)).collect()),
+            CalcNode::Max(ref args) => CalcNode::Max(args.iter().map(|a| a.compute(cx)).collect()),
+            CalcNode::Clamp(ref min, ref val, ref max) => {
+                CalcNode::Clamp(compute(min), compute(val), compute(max))
+            }
+        }
+    }
+
+    /// Folds the expression into a plain value when it doesn't depend on a percentage.
+    pub fn simplify(self) -> Value {
+        match self.typ() {
+            Some(CalcType::Number) => Value::Number(self.evaluate(0.0)),
+            Some(CalcType::Length) => Value::Length(self.evaluate(0.0), Unit::Px),
+            _ => Value::Calc(Box::new(self)),
+        }
+    }
+
+    /// Evaluates a computed expression, using `percent_base` as 100%.
+    pub fn evaluate(&self, percent_base: f32) -> f32 {
+        let eval = |node: &CalcNode| node.evaluate(percent_base);
+        match *self {
+            CalcNode::Leaf(ref value) => value.resolve(percent_base),
+            CalcNode::Sum(ref a, ref b) => eval(a) + eval(b),
+            CalcNode::Difference(ref a, ref b) => eval(a) - eval(b),
+            CalcNode::Product(ref a, ref b) => eval(a) * eval(b),
+            CalcNode::Quotient(ref a, ref b) => eval(a) / eval(b),
+            CalcNode::Min(ref args) => args.iter().map(eval).fold(f32::INFINITY, f32::min),
+            CalcNode::Max(ref args) => args.iter().map(eval).fold(f32::NEG_INFINITY, f32::max),
+            CalcNode::Clamp(ref min, ref val, ref max) => eval(min).max(eval(val).min(eval(max))),
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use crate::layout::Rect;
+
+/// The output medium that media queries are evaluated against.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Device {
+    pub media_type: MediaType,
+    pub width: f32,
+    pub height: f32,
+    /// Dots per CSS pixel.
+    pub resolution: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MediaType {
+    All,
+    Screen,
+    Print,
+    Unknown,
+}
+
+/// A comma separated list of queries; it matches when any of them does.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaList(pub Vec<MediaQuery>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaQuery {
+    pub negated: bool,
+    pub media_type: MediaType,
+    pub features: Vec<MediaFeature>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaFeature {
+    Width(Range, f32),
+    Height(Range, f32),
+    AspectRatio(Range, f32),
+    Orientation(Orientation),
+    Resolution(Range, f32),
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Range {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+impl Device {
+    pub fn new(media_type: MediaType, viewport: Rect) -> Self {
+        Self {
+            media_type,
+            width: viewport.width,
+            height: viewport.height,
+            resolution: 1.0,
+        }
+    }
+
+    fn orientation(&self) -> Orientation {
+        if self.height >= self.width {
+            Orientation::Portrait
+        } else {
+            Orientation::Landscape
+        }
+    }
+}
+
+impl MediaType {
+    pub fn from_name(name: &str) -> Self {
+        match &*name.to_ascii_lowercase() {
+            "all" => MediaType::All,
+            "screen" => MediaType::Screen,
+            "print" => MediaType::Print,
+            _ => MediaType::Unknown,
+        }
+    }
+
+    fn matches(self, device: &Device) -> bool {
+        match self {
+            MediaType::All => true,
+            MediaType::Unknown => false,
+            typ => typ == device.media_type,
+        }
+    }
+}
+
+impl MediaList {
+    pub fn matches(&self, device: &Device) -> bool {
+        self.0.is_empty() || self.0.iter().any(|query| query.matches(device))
+    }
+}
+
+impl MediaQuery {
+    /// The query `not all`, which a malformed query stands for.
+    pub fn not_all() -> Self {
+        Self {
+            negated: true,
+            media_type: MediaType::All,
+            features: Vec::new(),
+        }
+    }
+
+    pub fn matches(&self, device: &Device) -> bool {
+        let matches = self.media_type.matches(device)
+            && self.features.iter().all(|feature| feature.matches(device));
+        matches != self.negated
+    }
+}
+
+impl MediaFeature {
+    fn matches(&self, device: &Device) -> bool {
+        match *self {
+            MediaFeature::Width(range, value) => range.test(device.width, value),
+            MediaFeature::Height(range, value) => range.test(device.height, value),
+            MediaFeature::AspectRatio(range, value) => {
+                range.test(device.width / device.height, value)
+            }
+            MediaFeature::Orientation(orientation) => device.orientation() == orientation,
+            MediaFeature::Resolution(range, value) => range.test(device.resolution, value),
+            MediaFeature::Unknown => false,
+        }
+    }
+}
+
+impl Range {
+    fn test(self, actual: f32, value: f32) -> bool {
+        match self {
+            Range::Eq => actual == value,
+            Range::Lt => actual < value,
+            Range::Le => actual <= value,
+            Range::Gt => actual > value,
+            Range::Ge => actual >= value,
+        }
+    }
+
+    /// The same comparison with its operands swapped, for `600px < width`.
+    pub fn flip(self) -> Self {
+        match self {
+            Range::Eq => Range::Eq,
+            Range::Lt => Range::Gt,
+            Range::Le => Range::Ge,
+            Range::Gt => Range::Lt,
+            Range::Ge => Range::Le,
+        }
+    }
+}
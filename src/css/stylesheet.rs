@@ -1,18 +1,65 @@
 use crate::css;
+use crate::css::calc::CalcNode;
+use crate::css::media::{Device, MediaList};
+use url::Url;
 
 #[derive(Debug, PartialEq, Default)]
 pub struct Stylesheet {
     pub rules: Vec<Rule>,
+    pub imports: Vec<Import>,
+    pub pages: Vec<PageRule>,
+    pub font_faces: Vec<FontFaceRule>,
+}
+
+/// An `@import` rule, which has to be fetched and merged in by the loader.
+#[derive(Debug, PartialEq)]
+pub struct Import {
+    pub url: String,
+    pub media: MediaList,
 }
 #[derive(Debug, PartialEq)]
 pub struct Rule {
     pub selectors: Vec<Selector>,
     pub declarations: Vec<Declaration>,
+    /// Conditions of the enclosing `@media` blocks, all of which must match.
+    pub media: Vec<MediaList>,
+}
+
+/// An `@page` rule, styling the pages its selector matches and the boxes in their margins.
+#[derive(Debug, PartialEq)]
+pub struct PageRule {
+    pub selector: PageSelector,
+    pub declarations: Vec<Declaration>,
+    /// The rules nested in it for margin boxes, like `@top-center`, by margin box name.
+    pub margin_rules: Vec<(String, Vec<Declaration>)>,
+    /// Conditions of the enclosing `@media` blocks, all of which must match.
+    pub media: Vec<MediaList>,
+}
+
+/// An `@font-face` rule, making the first of the font files its `src` lists that loads
+/// available under the family name it gives.
+#[derive(Debug, PartialEq)]
+pub struct FontFaceRule {
+    pub declarations: Vec<Declaration>,
+    /// Conditions of the enclosing `@media` blocks, all of which must match.
+    pub media: Vec<MediaList>,
+}
+
+/// The selector of an `@page` rule, like `:first` or `chapter:left`. An empty one matches
+/// every page.
+#[derive(Debug, Default, PartialEq)]
+pub struct PageSelector {
+    pub name: Option<String>,
+    pub pseudo_classes: Vec<String>,
 }
 
 #[derive(Debug, PartialEq)]
 pub enum Selector {
     Simple(SimpleSelector),
+    /// `ancestor descendant`
+    Descendant(Box<Selector>, SimpleSelector),
+    /// `parent > child`
+    Child(Box<Selector>, SimpleSelector),
 }
 
 #[derive(Debug, PartialEq)]
@@ -33,13 +80,37 @@ pub enum Value {
     Keyword(String),
     Length(f32, Unit),
     Number(f32),
+    Percentage(f32),
+    /// A flexible length like `1fr`, a share of the free space in a grid container. Only
+    /// grid track sizes take one.
+    Flex(f32),
     ColorValue(Color),
+    Calc(Box<CalcNode>),
+    Url(String),
+    Str(String),
+    /// A function other than `calc()` and its kin, like `repeat()` or `minmax()`.
+    Function(String, Vec<Value>),
+    /// The space-separated values of a property that takes several, like a track list.
+    List(Vec<Value>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Unit {
     Px,
     Em,
+    Rem,
+    Ex,
+    Ch,
+    Vw,
+    Vh,
+    Vmin,
+    Vmax,
+    Cm,
+    Mm,
+    Q,
+    In,
+    Pt,
+    Pc,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -50,6 +121,15 @@ pub struct Color {
     pub a: u8,
 }
 
+/// Everything needed to turn a relative length into an absolute one.
+#[derive(Debug, Clone, Copy)]
+pub struct ComputeContext {
+    pub font_size: f32,
+    pub root_font_size: f32,
+    pub viewport_width: f32,
+    pub viewport_height: f32,
+}
+
 pub type Specificity = (usize, usize, usize);
 
 pub const DEFAULT_STYLE: &str = include_str!("default.css");
@@ -61,28 +141,196 @@ impl Stylesheet {
 
     pub fn merge(&mut self, other: Stylesheet) {
         self.rules.extend(other.rules);
+        self.imports.extend(other.imports);
+        self.pages.extend(other.pages);
+        self.font_faces.extend(other.font_faces);
+    }
+
+    /// Restricts every rule to `media`, as for a stylesheet imported with a media list.
+    pub fn add_media(&mut self, media: &MediaList) {
+        if media.0.is_empty() {
+            return;
+        }
+        for rule in &mut self.rules {
+            rule.media.insert(0, media.clone());
+        }
+        for page in &mut self.pages {
+            page.media.insert(0, media.clone());
+        }
+        for font_face in &mut self.font_faces {
+            font_face.media.insert(0, media.clone());
+        }
+    }
+
+    /// Makes `@import` and `url()` references absolute, relative to the stylesheet's own URL.
+    pub fn resolve_urls(&mut self, base: &Url) {
+        let resolve = |url: &mut String| {
+            if let Ok(resolved) = base.join(url) {
+                *url = resolved.to_string();
+            }
+        };
+
+        for import in &mut self.imports {
+            resolve(&mut import.url);
+        }
+        let declarations = self.rules.iter_mut().map(|rule| &mut rule.declarations);
+        let font_faces = self
+            .font_faces
+            .iter_mut()
+            .map(|rule| &mut rule.declarations);
+        for declarations in declarations.chain(font_faces) {
+            for decl in declarations {
+                for value in &mut decl.values {
+                    if let Value::Url(ref mut url) = *value {
+                        resolve(url);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Rule {
+    pub fn applies_to(&self, device: &Device) -> bool {
+        self.media.iter().all(|list| list.matches(device))
+    }
+}
+
+impl FontFaceRule {
+    pub fn applies_to(&self, device: &Device) -> bool {
+        self.media.iter().all(|list| list.matches(device))
+    }
+}
+
+impl PageRule {
+    pub fn applies_to(&self, device: &Device) -> bool {
+        self.media.iter().all(|list| list.matches(device))
+    }
+}
+
+impl PageSelector {
+    /// The page name counts most, then `:first` and `:blank`, then `:left` and `:right`.
+    pub fn specificity(&self) -> Specificity {
+        let count = |names: &[&str]| {
+            self.pseudo_classes
+                .iter()
+                .filter(|class| names.contains(&class.as_str()))
+                .count()
+        };
+        (
+            self.name.iter().count(),
+            count(&["first", "blank"]),
+            count(&["left", "right"]),
+        )
+    }
+
+    /// Whether the selector matches the page at `index`, the first page being a right one.
+    /// Pages have no names, as the `page` property isn't supported, so a named selector
+    /// matches none.
+    pub fn matches(&self, index: usize) -> bool {
+        self.name.is_none()
+            && self
+                .pseudo_classes
+                .iter()
+                .all(|class| match (class.as_str(), index % 2) {
+                    ("first", _) => index == 0,
+                    ("left", 1) | ("right", 0) => true,
+                    _ => false,
+                })
     }
 }
 
 impl Selector {
     pub fn specificity(&self) -> Specificity {
-        let Selector::Simple(ref simple) = *self;
-        let a = simple.id.iter().count();
-        let b = simple.class.len();
-        let c = simple.tag_name.iter().count();
-        (a, b, c)
+        let (a, b, c) = match *self {
+            Selector::Simple(_) => (0, 0, 0),
+            Selector::Descendant(ref parent, _) | Selector::Child(ref parent, _) => {
+                parent.specificity()
+            }
+        };
+        let simple = self.rightmost();
+        (
+            a + simple.id.iter().count(),
+            b + simple.class.len(),
+            c + simple.tag_name.iter().count(),
+        )
+    }
+
+    /// The compound selector that has to match the element itself.
+    pub fn rightmost(&self) -> &SimpleSelector {
+        match *self {
+            Selector::Simple(ref simple)
+            | Selector::Descendant(_, ref simple)
+            | Selector::Child(_, ref simple) => simple,
+        }
     }
 }
 
 impl Value {
     pub fn to_px(&self) -> f32 {
         match *self {
-            Value::Length(f, Unit::Px) => f,
-            Value::Length(f, Unit::Em) => f * 16.0,
+            Value::Length(f, ref unit) => unit.to_px(f, &ComputeContext::default()),
             Value::Number(f) => f, // TODO
             _ => 0.0,
         }
     }
+
+    /// Resolves a computed value to pixels, using `percent_base` as 100%.
+    pub fn resolve(&self, percent_base: f32) -> f32 {
+        match *self {
+            Value::Percentage(p) => percent_base * p / 100.0,
+            Value::Calc(ref node) => node.evaluate(percent_base),
+            _ => self.to_px(),
+        }
+    }
+
+    /// Converts relative lengths into px, leaving percentages for layout.
+    pub fn compute(&self, cx: &ComputeContext) -> Value {
+        match *self {
+            Value::Length(f, ref unit) => Value::Length(unit.to_px(f, cx), Unit::Px),
+            Value::Calc(ref node) => node.compute(cx).simplify(),
+            Value::Function(ref name, ref args) => Value::Function(
+                name.clone(),
+                args.iter().map(|arg| arg.compute(cx)).collect(),
+            ),
+            Value::List(ref values) => {
+                Value::List(values.iter().map(|value| value.compute(cx)).collect())
+            }
+            _ => self.clone(),
+        }
+    }
+}
+
+impl Unit {
+    pub fn to_px(&self, f: f32, cx: &ComputeContext) -> f32 {
+        match *self {
+            Unit::Px => f,
+            Unit::Em => f * cx.font_size,
+            Unit::Rem => f * cx.root_font_size,
+            Unit::Ex | Unit::Ch => f * cx.font_size / 2.0,
+            Unit::Vw => f * cx.viewport_width / 100.0,
+            Unit::Vh => f * cx.viewport_height / 100.0,
+            Unit::Vmin => f * cx.viewport_width.min(cx.viewport_height) / 100.0,
+            Unit::Vmax => f * cx.viewport_width.max(cx.viewport_height) / 100.0,
+            Unit::Cm => f * 96.0 / 2.54,
+            Unit::Mm => f * 96.0 / 25.4,
+            Unit::Q => f * 96.0 / 101.6,
+            Unit::In => f * 96.0,
+            Unit::Pt => f * 96.0 / 72.0,
+            Unit::Pc => f * 16.0,
+        }
+    }
+}
+
+impl Default for ComputeContext {
+    fn default() -> Self {
+        Self {
+            font_size: 16.0,
+            root_font_size: 16.0,
+            viewport_width: 800.0,
+            viewport_height: 600.0,
+        }
+    }
 }
 
 impl Color {
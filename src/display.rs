@@ -1,35 +1,454 @@
 pub mod pdf;
 
-use crate::css::{Color, Unit::*, Value};
-use crate::html::NodeType;
-use crate::layout::{BoxType, LayoutBox, Position, Rect};
+use crate::css::{Color, Value};
+use crate::layout::{self, BoxType, LayoutBox, Rect};
+use crate::page::Page;
+use crate::raster::Image;
+use crate::style::{Display, Overflow, Position};
+use crate::text::{face_metrics, font_metrics, GlyphRun};
+use std::sync::Arc;
 
 pub type DisplayList = Vec<DisplayCommand>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum DisplayCommand {
     SolidColor(Color, Rect),
-    Text(String, Position, f32),
+    /// Shaped text drawn in a color, with its baseline starting at the position.
+    Glyphs(GlyphRun, layout::Position, Color),
+    /// An image scaled to fill the rectangle.
+    Image(Arc<Image>, Rect),
+    /// Clips the commands up to the matching `PopClip` to the rectangle, within any
+    /// enclosing clip.
+    PushClip(Rect),
+    PopClip,
 }
 
 pub fn build_display_list(layout_root: &LayoutBox) -> DisplayList {
     let mut list = Vec::new();
-    render_layout_box(&mut list, layout_root);
-    return list;
+    render_stacking_context(&mut list, layout_root);
+    list
 }
 
-fn render_layout_box(list: &mut DisplayList, layout_box: &LayoutBox) {
+/// Splits a display list into the lists of laid out `pages`, and adds their margin boxes. Each page
+/// shows the part of the document below those the pages before it show, as tall as its page
+/// area, moved into the page area and clipped to it. A command painting across a page edge
+/// is on both pages; clips are on every page they clip something on.
+pub fn split_into_pages(list: &DisplayList, pages: &[Page]) -> Vec<DisplayList> {
+    let mut top = 0.0;
+    let mut slices = Vec::new();
+    for page in pages {
+        let area = page.page_area();
+        slices.push((top, area));
+        top += area.height;
+    }
+
+    let mut page_lists: Vec<DisplayList> = slices
+        .iter()
+        .map(|&(_, area)| vec![DisplayCommand::PushClip(area)])
+        .collect();
+    for command in list {
+        let (top, bottom) = match *command {
+            DisplayCommand::SolidColor(_, rect) | DisplayCommand::Image(_, rect) => {
+                (rect.y, rect.y + rect.height)
+            }
+            DisplayCommand::Glyphs(ref run, position, _) => {
+                let metrics = face_metrics(run.face, run.size);
+                (position.y - metrics.ascent, position.y + metrics.descent)
+            }
+            DisplayCommand::PushClip(_) => (f32::NEG_INFINITY, f32::INFINITY),
+            DisplayCommand::PopClip => {
+                for page_list in &mut page_lists {
+                    pop_clip(page_list, Some(Rect::default()));
+                }
+                continue;
+            }
+        };
+        for (page_list, &(slice_top, area)) in page_lists.iter_mut().zip(&slices) {
+            if top < slice_top + area.height && bottom > slice_top {
+                page_list.push(command.translated(area.x, area.y - slice_top));
+            }
+        }
+    }
+
+    for (index, page_list) in page_lists.iter_mut().enumerate() {
+        pop_clip(page_list, Some(Rect::default()));
+        render_margin_boxes(page_list, &pages[index]);
+    }
+    page_lists
+}
+
+/// Paints the text of the margin boxes of a laid out page.
+fn render_margin_boxes(list: &mut DisplayList, page: &Page) {
+    for margin_box in &page.margin_boxes {
+        push_glyphs(
+            list,
+            margin_box.runs.clone(),
+            margin_box.origin,
+            margin_box.color(),
+        );
+    }
+}
+
+/// Paints glyph runs one after the other from `position` on the baseline.
+fn push_glyphs(
+    list: &mut DisplayList,
+    runs: Vec<GlyphRun>,
+    position: layout::Position,
+    color: Color,
+) {
+    let mut x = position.x;
+    for run in runs {
+        let width = run.width();
+        list.push(DisplayCommand::Glyphs(
+            run,
+            layout::Position::new(x, position.y),
+            color,
+        ));
+        x += width;
+    }
+}
+
+impl DisplayCommand {
+    /// The command moved by `dx` and `dy`.
+    fn translated(&self, dx: f32, dy: f32) -> DisplayCommand {
+        let mut command = self.clone();
+        match command {
+            DisplayCommand::SolidColor(_, ref mut rect)
+            | DisplayCommand::Image(_, ref mut rect)
+            | DisplayCommand::PushClip(ref mut rect) => {
+                rect.x += dx;
+                rect.y += dy;
+            }
+            DisplayCommand::Glyphs(_, ref mut position, _) => {
+                position.x += dx;
+                position.y += dy;
+            }
+            DisplayCommand::PopClip => {}
+        }
+        command
+    }
+}
+
+/// A descendant painted after the in-flow content of its stacking context, or before it for
+/// a negative z-index: either a stacking context of its own or a positioned box painted as
+/// one.
+struct Layer<'b, 'a> {
+    layout_box: &'b LayoutBox<'a>,
+    z_index: i32,
+    /// Whether the box creates a stacking context, painting its own positioned descendants.
+    stacking_context: bool,
+    /// The clips of the boxes between the layer and its stacking context, whose own clip
+    /// is already in effect.
+    clips: Vec<Rect>,
+}
+
+/// Paints a stacking context in the order of CSS 2.1 Appendix E: its background and
+/// borders, the child contexts with negative z-indexes, the backgrounds of its in-flow
+/// blocks, its floats, its inline content, and then positioned boxes and the child contexts
+/// with z-indexes of 0 and above.
+fn render_stacking_context(list: &mut DisplayList, root: &LayoutBox) {
+    let mut layers = Vec::new();
+    collect_layers(root, &[], 0, &mut layers);
+    // The sort is stable, keeping layers of the same level in tree order.
+    layers.sort_by_key(|layer| layer.z_index);
+
+    render_box(list, root);
+    let clip = clip_rect(root);
+    push_clip(list, clip);
+    for layer in layers.iter().filter(|layer| layer.z_index < 0) {
+        render_layer(list, layer);
+    }
+    render_in_flow(list, root);
+    for layer in layers.iter().filter(|layer| layer.z_index >= 0) {
+        render_layer(list, layer);
+    }
+    pop_clip(list, clip);
+}
+
+/// Paints a box as if it created a stacking context, leaving its positioned descendants to
+/// the enclosing one. Floats, atomic inlines, flex and grid items, and positioned boxes
+/// without a z-index are painted this way.
+fn render_atomically(list: &mut DisplayList, layout_box: &LayoutBox) {
+    render_box(list, layout_box);
+    let clip = clip_rect(layout_box);
+    push_clip(list, clip);
+    render_in_flow(list, layout_box);
+    pop_clip(list, clip);
+}
+
+fn render_layer(list: &mut DisplayList, layer: &Layer) {
+    for &clip in &layer.clips {
+        push_clip(list, Some(clip));
+    }
+    if layer.stacking_context {
+        render_stacking_context(list, layer.layout_box);
+    } else {
+        render_atomically(list, layer.layout_box);
+    }
+    for &clip in layer.clips.iter().rev() {
+        pop_clip(list, Some(clip));
+    }
+}
+
+/// Collects the layers among the descendants of `parent` that belong to its stacking
+/// context, with the `clips` in effect inside it. Absolutely positioned boxes escape the
+/// clips past the first `contained` ones, which are those of their containing block and
+/// its ancestors.
+fn collect_layers<'b, 'a>(
+    parent: &'b LayoutBox<'a>,
+    clips: &[Rect],
+    contained: usize,
+    layers: &mut Vec<Layer<'b, 'a>>,
+) {
+    for child in &parent.children {
+        let position = positioned(child);
+        let kept = match position {
+            Some(Position::Absolute) => contained,
+            Some(Position::Fixed) => 0,
+            _ => clips.len(),
+        };
+        if let Some(z_index) = stacking_context_z_index(parent, child) {
+            layers.push(Layer {
+                layout_box: child,
+                z_index,
+                stacking_context: true,
+                clips: clips[..kept].to_vec(),
+            });
+            continue;
+        }
+        if position.is_some() {
+            layers.push(Layer {
+                layout_box: child,
+                z_index: 0,
+                stacking_context: false,
+                clips: clips[..kept].to_vec(),
+            });
+        }
+
+        let mut inner = clips[..kept].to_vec();
+        inner.extend(clip_rect(child));
+        let contained = if position.is_some() {
+            inner.len()
+        } else {
+            contained
+        };
+        collect_layers(child, &inner, contained, layers);
+    }
+}
+
+/// The position scheme of a positioned box.
+fn positioned(layout_box: &LayoutBox) -> Option<Position> {
+    match layout_box.box_type {
+        BoxType::BlockNode(style) | BoxType::InlineNode(style) => match style.position() {
+            Position::Static => None,
+            position => Some(position),
+        },
+        _ => None,
+    }
+}
+
+/// The stack level of a box that creates a stacking context: a positioned box with a
+/// z-index, a fixed or sticky box, or a flex or grid item with a z-index.
+fn stacking_context_z_index(parent: &LayoutBox, child: &LayoutBox) -> Option<i32> {
+    let style = match child.box_type {
+        BoxType::BlockNode(style) | BoxType::InlineNode(style) => style,
+        _ => return None,
+    };
+    match style.position() {
+        Position::Fixed | Position::Sticky => Some(style.z_index().unwrap_or(0)),
+        Position::Static if !is_flex_or_grid_item(parent, child) => None,
+        _ => style.z_index(),
+    }
+}
+
+fn is_flex_or_grid_item(parent: &LayoutBox, child: &LayoutBox) -> bool {
+    let container = match parent.box_type {
+        BoxType::BlockNode(style) => matches!(
+            style.display(),
+            Display::Flex | Display::InlineFlex | Display::Grid | Display::InlineGrid
+        ),
+        _ => false,
+    };
+    container && !child.is_out_of_flow()
+}
+
+/// Whether a box is painted as a layer of its stacking context rather than with the
+/// in-flow content.
+fn is_layer(parent: &LayoutBox, child: &LayoutBox) -> bool {
+    positioned(child).is_some() || stacking_context_z_index(parent, child).is_some()
+}
+
+/// Paints the in-flow descendants of a box: first the backgrounds of the block-level ones,
+/// then the floats, then the inline content.
+fn render_in_flow(list: &mut DisplayList, layout_box: &LayoutBox) {
+    if let BoxType::InlineNode(_) = layout_box.box_type {
+        render_inline(list, layout_box);
+        return;
+    }
+    render_blocks(list, layout_box);
+    render_floats(list, layout_box);
+    render_inline_content(list, layout_box);
+}
+
+fn render_blocks(list: &mut DisplayList, parent: &LayoutBox) {
+    for child in &parent.children {
+        if is_layer(parent, child) || child.is_float() || is_flex_or_grid_item(parent, child) {
+            continue;
+        }
+        match child.box_type {
+            BoxType::BlockNode(_)
+            | BoxType::AnonymousBlock(_)
+            | BoxType::Table(_)
+            | BoxType::AnonymousTable(_) => {
+                render_box(list, child);
+                let clip = clip_rect(child);
+                push_clip(list, clip);
+                render_blocks(list, child);
+                pop_clip(list, clip);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn render_floats(list: &mut DisplayList, parent: &LayoutBox) {
+    for child in &parent.children {
+        if is_layer(parent, child) || is_flex_or_grid_item(parent, child) {
+            continue;
+        }
+        if child.is_float() {
+            render_atomically(list, child);
+            continue;
+        }
+        match child.box_type {
+            BoxType::BlockNode(_)
+            | BoxType::AnonymousBlock(_)
+            | BoxType::Table(_)
+            | BoxType::AnonymousTable(_) => {
+                let clip = clip_rect(child);
+                push_clip(list, clip);
+                render_floats(list, child);
+                pop_clip(list, clip);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn render_inline_content(list: &mut DisplayList, parent: &LayoutBox) {
+    for child in &parent.children {
+        if is_layer(parent, child) || child.is_float() {
+            continue;
+        }
+        if is_flex_or_grid_item(parent, child) {
+            render_atomically(list, child);
+            continue;
+        }
+        match child.box_type {
+            BoxType::LineBox { .. } => render_inline(list, child),
+            BoxType::Marker(..) => render_text(list, child),
+            _ => {
+                let clip = clip_rect(child);
+                push_clip(list, clip);
+                render_inline_content(list, child);
+                pop_clip(list, clip);
+            }
+        }
+    }
+}
+
+/// Paints the fragments of a line box or an inline box in tree order, atomic inlines
+/// included.
+fn render_inline(list: &mut DisplayList, parent: &LayoutBox) {
+    for child in &parent.children {
+        if is_layer(parent, child) {
+            continue;
+        }
+        match child.box_type {
+            BoxType::InlineNode(_) => {
+                render_box(list, child);
+                render_inline(list, child);
+            }
+            BoxType::TextRun(..) | BoxType::Marker(..) => render_text(list, child),
+            _ => render_atomically(list, child),
+        }
+    }
+}
+
+fn push_clip(list: &mut DisplayList, clip: Option<Rect>) {
+    if let Some(rect) = clip {
+        list.push(DisplayCommand::PushClip(rect));
+    }
+}
+
+fn pop_clip(list: &mut DisplayList, clip: Option<Rect>) {
+    if clip.is_none() {
+        return;
+    }
+    // A clip around nothing is dropped.
+    if let Some(DisplayCommand::PushClip(_)) = list.last() {
+        list.pop();
+    } else {
+        list.push(DisplayCommand::PopClip);
+    }
+}
+
+fn render_box(list: &mut DisplayList, layout_box: &LayoutBox) {
     // debug_draw(list, layout_box);
 
     render_background(list, layout_box);
     render_borders(list, layout_box);
-    render_text(list, layout_box);
+    render_replaced(list, layout_box);
+}
 
-    for child in &layout_box.children {
-        render_layout_box(list, child);
+/// Paints the content of a replaced element, clipped to its content box: its image, or its
+/// alternative text.
+fn render_replaced(list: &mut DisplayList, layout_box: &LayoutBox) {
+    if !layout_box.is_replaced() {
+        return;
+    }
+    let content = layout_box.dimensions.content;
+    let object = &layout_box.children[0];
+    match object.box_type {
+        BoxType::Image(_, Some(ref image), _) => {
+            push_clip(list, Some(content));
+            list.push(DisplayCommand::Image(
+                image.clone(),
+                object.dimensions.content,
+            ));
+            pop_clip(list, Some(content));
+        }
+        BoxType::Image(style, None, ref runs) => {
+            if runs.is_empty() {
+                return;
+            }
+            let baseline = content.y + font_metrics(&style.font()).ascent;
+            push_clip(list, Some(content));
+            let position = layout::Position::new(content.x, baseline);
+            push_glyphs(list, runs.clone(), position, style.color());
+            pop_clip(list, Some(content));
+        }
+        _ => {}
     }
 }
 
+/// The padding box of a box whose overflow isn't visible, which its descendants are
+/// clipped to.
+fn clip_rect(layout_box: &LayoutBox) -> Option<Rect> {
+    match layout_box.box_type {
+        // A table is clipped by its table box, so that its captions aren't.
+        BoxType::BlockNode(_) if layout_box.is_table_wrapper() => None,
+        BoxType::BlockNode(style) | BoxType::Table(style)
+            if style.overflow() != Overflow::Visible =>
+        {
+            Some(layout_box.dimensions.padding_box())
+        }
+        _ => None,
+    }
+}
+
+/// Tints the margin and content boxes of `layout_box`, when debugging layout.
+#[allow(dead_code)]
 fn debug_draw(list: &mut DisplayList, layout_box: &LayoutBox) {
     list.push(DisplayCommand::SolidColor(
         Color::new(255, 0, 0, 30),
@@ -50,21 +469,30 @@ fn debug_draw(list: &mut DisplayList, layout_box: &LayoutBox) {
 }
 
 fn render_background(list: &mut DisplayList, layout_box: &LayoutBox) {
-    get_color(layout_box, "background").map(|color| {
+    if let Some(color) = get_color(layout_box, "background") {
         list.push(DisplayCommand::SolidColor(
             color,
             layout_box.dimensions.border_box(),
         ))
-    });
+    }
 }
 
 fn get_color(layout_box: &LayoutBox, name: &str) -> Option<Color> {
     match layout_box.box_type {
-        BoxType::BlockNode(style) | BoxType::InlineNode(style) => match style.value(name) {
-            Some(Value::ColorValue(color)) => Some(color),
-            _ => None,
-        },
-        BoxType::AnonymousBlock => None,
+        // A table paints its background and borders on its table box, not on its wrapper.
+        BoxType::BlockNode(_) if layout_box.is_table_wrapper() => None,
+        BoxType::BlockNode(style) | BoxType::InlineNode(style) | BoxType::Table(style) => {
+            match style.value(name) {
+                Some(Value::ColorValue(color)) => Some(color),
+                _ => None,
+            }
+        }
+        BoxType::AnonymousBlock(_)
+        | BoxType::AnonymousTable(_)
+        | BoxType::LineBox { .. }
+        | BoxType::TextRun(..)
+        | BoxType::Marker(..)
+        | BoxType::Image(..) => None,
     }
 }
 
@@ -120,20 +548,50 @@ fn render_borders(list: &mut DisplayList, layout_box: &LayoutBox) {
 
 fn render_text(list: &mut DisplayList, layout_box: &LayoutBox) {
     match layout_box.box_type {
-        BoxType::InlineNode(ref style) => match style.node.typ {
-            NodeType::Text(ref text) => {
-                let pos = layout_box.dimensions.content;
-                let size = style
-                    .value_or("font-size", &Value::Length(16.0, Px))
-                    .to_px();
-                list.push(DisplayCommand::Text(
-                    text.clone(),
-                    Position::new(pos.x, pos.y),
-                    size,
-                ));
-            }
-            _ => {}
-        },
+        BoxType::TextRun(style, _, ref runs) | BoxType::Marker(style, _, ref runs) => {
+            let pos = layout_box.dimensions.content;
+            let baseline = pos.y + font_metrics(&style.font()).ascent;
+            let position = layout::Position::new(pos.x, baseline);
+            push_glyphs(list, runs.clone(), position, style.color());
+        }
         _ => {}
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::testing::layout_html;
+
+    #[test]
+    fn test_paint_order() {
+        let root = layout_html(
+            "<div class=\"root\">\
+             <div class=\"over\"></div>\
+             <div class=\"under\"></div>\
+             <div class=\"float\"></div>\
+             <div class=\"block\">text</div>\
+             <div class=\"relative\"></div>\
+             <div class=\"top\"></div>\
+             </div>",
+            ".root { background: #0a0000; }
+             .over { position: absolute; z-index: 2; width: 10px; height: 10px; background: #050000; }
+             .under { position: absolute; z-index: -1; width: 10px; height: 10px; background: #010000; }
+             .float { float: left; width: 10px; height: 10px; background: #030000; }
+             .block { background: #020000; }
+             .relative { position: relative; height: 10px; background: #040000; }
+             .top { position: absolute; z-index: 1; width: 10px; height: 10px; background: #060000; }",
+            Rect::new(0.0, 0.0, 800.0, 600.0),
+        );
+
+        let painted: Vec<String> = build_display_list(&root)
+            .iter()
+            .map(|command| match command {
+                DisplayCommand::SolidColor(color, _) => color.r.to_string(),
+                DisplayCommand::Glyphs(run, ..) => run.text.clone(),
+                _ => String::new(),
+            })
+            .collect();
+        assert_eq!(painted, ["10", "1", "2", "3", "text", "4", "6", "5"]);
+    }
+}
@@ -1,17 +1,25 @@
 use crate::display::{DisplayCommand, DisplayList};
-use crate::layout::Rect;
-use cairo::{Context, PdfSurface};
+use crate::layout::{Position, Rect};
+use crate::text::{handles, GlyphRun};
+use cairo::{Context, Format, Glyph, ImageSurface, PdfSurface, TextCluster, TextClusterFlags};
 
 pub struct PdfRenderer {
+    surface: PdfSurface,
     ctx: Context,
     pub width: f32,
     pub height: f32,
 }
 
-pub fn render(display_list: &DisplayList, bounds: Rect, file_name: String) {
-    let mut renderer = PdfRenderer::new(bounds.width, bounds.height, file_name);
-    for item in display_list {
-        renderer.render_item(&item);
+/// Renders the display list of each page on a page of its own, as large as its `bounds`.
+pub fn render(pages: &[DisplayList], bounds: &[Rect], file_name: String) {
+    let first = bounds.first().copied().unwrap_or_default();
+    let mut renderer = PdfRenderer::new(first.width, first.height, file_name);
+    for (display_list, bounds) in pages.iter().zip(bounds) {
+        renderer.set_size(bounds.width, bounds.height);
+        for item in display_list {
+            renderer.render_item(item);
+        }
+        renderer.ctx.show_page();
     }
 }
 
@@ -20,9 +28,19 @@ impl PdfRenderer {
         let surface = PdfSurface::new(width as f64, height as f64, file_name).unwrap();
         let ctx = Context::new(&surface);
         PdfRenderer {
-            ctx: ctx,
-            width: width,
-            height: height,
+            surface,
+            ctx,
+            width,
+            height,
+        }
+    }
+
+    /// Sets the size of the pages from the next one on.
+    fn set_size(&mut self, width: f32, height: f32) {
+        if (width, height) != (self.width, self.height) {
+            self.surface.set_size(width as f64, height as f64).unwrap();
+            self.width = width;
+            self.height = height;
         }
     }
 
@@ -43,14 +61,110 @@ impl PdfRenderer {
                 );
                 self.ctx.fill();
             }
-            DisplayCommand::Text(ref text, ref pos, ref size) => {
-                self.ctx.set_font_size(*size as f64);
-                let extents = self.ctx.text_extents(text);
-                let y = pos.y as f64 - extents.y_bearing;
+            DisplayCommand::Glyphs(ref run, ref pos, ref color) => {
+                self.ctx.set_source_rgba(
+                    color.r as f64 / 255.0,
+                    color.g as f64 / 255.0,
+                    color.b as f64 / 255.0,
+                    color.a as f64 / 255.0,
+                );
+                self.render_glyphs(run, pos);
+            }
+            DisplayCommand::Image(ref image, ref rect) => {
+                if image.width == 0 || image.height == 0 {
+                    return;
+                }
+                let stride = Format::ARgb32.stride_for_width(image.width).unwrap();
+                let row = image.width as usize * 4;
+                let mut data = vec![0; stride as usize * image.height as usize];
+                for (y, pixels) in image.data.chunks(row).enumerate() {
+                    let start = y * stride as usize;
+                    data[start..start + row].copy_from_slice(pixels);
+                }
+                let surface = ImageSurface::create_for_data(
+                    data,
+                    Format::ARgb32,
+                    image.width as i32,
+                    image.height as i32,
+                    stride,
+                )
+                .unwrap();
 
-                self.ctx.move_to(pos.x as f64, y);
-                self.ctx.show_text(text);
+                self.ctx.save();
+                self.ctx.translate(rect.x as f64, rect.y as f64);
+                self.ctx.scale(
+                    rect.width as f64 / image.width as f64,
+                    rect.height as f64 / image.height as f64,
+                );
+                self.ctx.set_source_surface(&surface, 0.0, 0.0);
+                self.ctx.paint();
+                self.ctx.restore();
             }
+            DisplayCommand::PushClip(ref rect) => {
+                self.ctx.save();
+                self.ctx.rectangle(
+                    rect.x as f64,
+                    rect.y as f64,
+                    rect.width as f64,
+                    rect.height as f64,
+                );
+                self.ctx.clip();
+            }
+            DisplayCommand::PopClip => self.ctx.restore(),
         }
     }
+
+    /// Draws the glyphs of `run` from `pos` on, mapped to the clusters of its text so it can
+    /// be copied and searched.
+    fn render_glyphs(&self, run: &GlyphRun, pos: &Position) {
+        let cairo_face = match handles(run.face).cairo_face {
+            Some(ref cairo_face) => cairo_face.clone(),
+            None => return,
+        };
+        self.ctx.set_font_face(&cairo_face);
+        self.ctx.set_font_size(run.size as f64);
+
+        let mut x = pos.x;
+        let mut glyphs = Vec::with_capacity(run.glyphs.len());
+        // The clusters in visual order, by the byte offset they start at.
+        let mut clusters: Vec<(usize, TextCluster)> = Vec::new();
+        for glyph in &run.glyphs {
+            glyphs.push(Glyph {
+                index: glyph.id.into(),
+                x: (x + glyph.x_offset) as f64,
+                y: (pos.y - glyph.y_offset) as f64,
+            });
+            x += glyph.x_advance;
+            match clusters.last_mut() {
+                Some((start, cluster)) if *start == glyph.cluster => cluster.num_glyphs += 1,
+                _ => clusters.push((
+                    glyph.cluster,
+                    TextCluster {
+                        num_bytes: 0,
+                        num_glyphs: 1,
+                    },
+                )),
+            }
+        }
+
+        // Each cluster spans the text up to the one after it in logical order.
+        let mut starts: Vec<usize> = clusters.iter().map(|&(start, _)| start).collect();
+        starts.sort_unstable();
+        let clusters: Vec<TextCluster> = clusters
+            .into_iter()
+            .map(|(start, mut cluster)| {
+                let next = starts.partition_point(|&s| s <= start);
+                let end = starts.get(next).copied().unwrap_or(run.text.len());
+                cluster.num_bytes = (end - start) as i32;
+                cluster
+            })
+            .collect();
+        let flags = if run.rtl {
+            TextClusterFlags::Backward
+        } else {
+            TextClusterFlags::None
+        };
+        self.ctx
+            .show_text_glyphs(&run.text, &glyphs, &clusters, flags);
+    }
 }
@@ -46,7 +46,7 @@ impl Parser {
 
             nodes.push(self.parse_node());
         }
-        return nodes;
+        nodes
     }
 
     fn parse_node(&mut self) -> dom::Node {
@@ -76,14 +76,11 @@ impl Parser {
         assert_eq!(self.parse_tag_name(), tag_name);
         assert_eq!(self.consume_char(), '>');
 
-        return dom::elem(tag_name, attrs, children);
+        dom::elem(tag_name, attrs, children)
     }
 
     fn parse_tag_name(&mut self) -> String {
-        self.consume_while(|c| match c {
-            'a'..='z' | 'A'..='Z' | '0'..='9' => true,
-            _ => false,
-        })
+        self.consume_while(|c| matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9'))
     }
 
     fn parse_attributes(&mut self) -> dom::AttrMap {
@@ -96,14 +93,14 @@ impl Parser {
             let (name, value) = self.parse_attr();
             attributes.insert(name, value);
         }
-        return attributes;
+        attributes
     }
 
     fn parse_attr(&mut self) -> (String, String) {
         let name = self.parse_tag_name();
         assert_eq!(self.consume_char(), '=');
         let value = self.parse_attr_value();
-        return (name, value);
+        (name, value)
     }
 
     fn parse_attr_value(&mut self) -> String {
@@ -112,7 +109,7 @@ impl Parser {
 
         let value = self.consume_while(|c| c != open_quote);
         assert_eq!(self.consume_char(), open_quote);
-        return value;
+        value
     }
 
     fn parse_text(&mut self) -> dom::Node {
@@ -127,7 +124,7 @@ impl Parser {
         while !self.eof() && test(self.next_char()) {
             result.push(self.consume_char());
         }
-        return result;
+        result
     }
 
     fn consume_whitespace(&mut self) {
@@ -168,7 +165,7 @@ impl Parser {
         let (_, cur_char) = iter.next().unwrap();
         let (next_pos, _) = iter.next().unwrap_or((1, ' '));
         self.pos += next_pos;
-        return cur_char;
+        cur_char
     }
 }
 
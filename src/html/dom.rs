@@ -1,4 +1,5 @@
 use std::collections::{HashMap, HashSet};
+use url::Url;
 
 pub type AttrMap = HashMap<String, String>;
 
@@ -59,7 +60,23 @@ impl Document {
         }
     }
 
-    pub fn collect_css_pathes(&self) -> Vec<String> {
+    /// The URL relative references resolve against, honouring `<base href>`.
+    pub fn base_url(&self, document_url: &Url) -> Url {
+        let mut bases = Vec::new();
+        self.collect_tags(&self.root_node, "base", &mut bases);
+
+        bases
+            .iter()
+            .filter_map(|node| match node.typ {
+                NodeType::Element(ref dat) => dat.attrs.get("href"),
+                _ => None,
+            })
+            .find_map(|href| document_url.join(href).ok())
+            .unwrap_or_else(|| document_url.clone())
+    }
+
+    pub fn collect_css_urls(&self, document_url: &Url) -> Vec<Url> {
+        let base_url = self.base_url(document_url);
         let mut links = Vec::new();
         self.collect_tags(&self.root_node, "link", &mut links);
 
@@ -69,12 +86,23 @@ impl Document {
                 NodeType::Element(ref dat) => Some(dat),
                 _ => None,
             })
-            .filter(|node| {
-                node.attrs
-                    .get("rel")
-                    .map_or(false, |rel| rel == "stylesheet")
+            .filter(|node| node.attrs.get("rel").is_some_and(|rel| rel == "stylesheet"))
+            .filter_map(|node| node.attrs.get("href"))
+            .filter_map(|href| base_url.join(href).ok())
+            .collect()
+    }
+
+    /// The `src` attributes of the images, in document order.
+    pub fn collect_image_sources(&self) -> Vec<String> {
+        let mut images = Vec::new();
+        self.collect_tags(&self.root_node, "img", &mut images);
+
+        images
+            .iter()
+            .filter_map(|node| match node.typ {
+                NodeType::Element(ref dat) => dat.attrs.get("src").cloned(),
+                _ => None,
             })
-            .filter_map(|node| node.attrs.get("href").cloned())
             .collect()
     }
 
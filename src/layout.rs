@@ -1,9 +1,24 @@
 pub mod block;
+pub mod flex;
+pub mod float;
+pub mod grid;
 pub mod inline;
+pub mod intrinsic;
+pub mod list;
+pub mod pagination;
+pub mod positioned;
+pub mod replaced;
+pub mod table;
 
 use crate::css::Unit::Px;
-use crate::css::Value::{Keyword, Length};
-use crate::style::{Display, StyledNode};
+use crate::css::Value::{self, Keyword, Length};
+use crate::html::NodeType;
+use crate::raster::{Image, ImageMap};
+use crate::style::{self, Display, ListStylePosition, StyledNode};
+use crate::text::GlyphRun;
+use float::FloatContext;
+use list::Counters;
+use std::sync::Arc;
 
 #[derive(Debug, Default, Copy, Clone)]
 pub struct Dimensions {
@@ -42,18 +57,38 @@ pub struct Region {
     pub height: f32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LayoutBox<'a> {
     pub dimensions: Dimensions,
     pub box_type: BoxType<'a>,
     pub children: Vec<LayoutBox<'a>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum BoxType<'a> {
     BlockNode(&'a StyledNode<'a>),
     InlineNode(&'a StyledNode<'a>),
-    AnonymousBlock,
+    /// A block wrapping inline content, inheriting from the style of its block container.
+    AnonymousBlock(&'a StyledNode<'a>),
+    /// One line of an inline formatting context, holding inline fragments. Its baseline is
+    /// given from its top.
+    LineBox {
+        baseline: f32,
+    },
+    /// The box of a table holding its rows and columns, inside the block-level wrapper box
+    /// that holds its captions.
+    Table(&'a StyledNode<'a>),
+    /// A table generated around table parts outside of any table, inheriting from the style
+    /// of their parent.
+    AnonymousTable(&'a StyledNode<'a>),
+    /// The part of a text node that fits on one line, and the glyph runs it was shaped into.
+    TextRun(&'a StyledNode<'a>, String, Vec<GlyphRun>),
+    /// The content of a replaced element, filling its content box as `object-fit` says: its
+    /// image, or the glyph runs of its alternative text when the image failed to load.
+    Image(&'a StyledNode<'a>, Option<Arc<Image>>, Vec<GlyphRun>),
+    /// The marker of a list item, with its text and glyph runs. An outside marker is a child
+    /// of the list item, while an inside one starts its inline content.
+    Marker(&'a StyledNode<'a>, String, Vec<GlyphRun>),
 }
 
 impl Dimensions {
@@ -88,33 +123,169 @@ impl Region {
     }
 }
 
-pub fn layout_tree<'a>(node: &'a StyledNode<'a>, mut contaning_block: Dimensions) -> LayoutBox<'a> {
+pub fn layout_tree<'a>(node: &'a StyledNode<'a>, contaning_block: Dimensions) -> LayoutBox<'a> {
+    layout_tree_with_images(node, &ImageMap::new(), contaning_block)
+}
+
+/// Lays out a tree whose images are looked up in `images` by their `src` attribute.
+pub fn layout_tree_with_images<'a>(
+    node: &'a StyledNode<'a>,
+    images: &ImageMap,
+    mut contaning_block: Dimensions,
+) -> LayoutBox<'a> {
+    // Percentage heights of the root refer to the viewport.
+    let viewport_height = contaning_block.content.height;
     contaning_block.content.height = 0.0;
 
-    let mut root_box = build_layout_tree(node);
-    root_box.layout(contaning_block);
+    let mut root_box = build_layout_tree(node, false, &mut Counters::default(), images);
+    match root_box.box_type {
+        BoxType::InlineNode(_) => {
+            let mut anonymous_block = LayoutBox::new(BoxType::AnonymousBlock(node));
+            anonymous_block.children.push(root_box);
+            root_box = anonymous_block;
+            root_box.layout(contaning_block);
+        }
+        _ => root_box.layout_block(contaning_block, Some(viewport_height)),
+    }
+
+    // Fixed boxes, and absolute ones without a positioned ancestor, are positioned against
+    // the viewport.
+    let viewport = Rect {
+        height: viewport_height,
+        ..contaning_block.content
+    };
+    let absolute = !root_box.is_positioned_container();
+    root_box.layout_out_of_flow_descendants(viewport, absolute, true);
     root_box
 }
 
-fn build_layout_tree<'a>(style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
+/// Builds the box tree of a node. `blockify` is set for flex and grid items, which are
+/// block-level whatever their display. `counters` are those in scope before the node, in
+/// document order. Replaced elements take their content from `images`.
+fn build_layout_tree<'a>(
+    style_node: &'a StyledNode<'a>,
+    blockify: bool,
+    counters: &mut Counters,
+    images: &ImageMap,
+) -> LayoutBox<'a> {
+    counters.enter(style_node);
+    let replaced = replaced::is_replaced_element(style_node);
+    let out_of_flow = matches!(
+        style_node.position(),
+        style::Position::Absolute | style::Position::Fixed
+    );
+    let floated = style_node.float() != style::Float::None;
     let mut root = LayoutBox::new(match style_node.display() {
-        Display::Block => BoxType::BlockNode(style_node),
+        Display::Block
+        | Display::ListItem
+        | Display::FlowRoot
+        | Display::InlineBlock
+        | Display::Flex
+        | Display::InlineFlex
+        | Display::Grid
+        | Display::InlineGrid
+        | Display::TableCaption => BoxType::BlockNode(style_node),
+        // Tables are wrapped in a box that takes their place in the flow.
+        Display::Table | Display::InlineTable => {
+            let wrapper = table::build_table_wrapper(style_node, counters, images);
+            counters.leave();
+            return wrapper;
+        }
+        // Table parts only get their role inside a table.
+        Display::TableRowGroup
+        | Display::TableHeaderGroup
+        | Display::TableFooterGroup
+        | Display::TableRow
+        | Display::TableCell
+        | Display::TableColumnGroup
+        | Display::TableColumn => BoxType::BlockNode(style_node),
+        // Absolutely positioned and floating boxes are blockified, and replaced elements are
+        // atomic.
+        Display::Inline if out_of_flow || floated || blockify || replaced => {
+            BoxType::BlockNode(style_node)
+        }
         Display::Inline => BoxType::InlineNode(style_node),
         Display::None => panic!("Root node has display: none."),
     });
 
+    if replaced {
+        root.children
+            .push(replaced::build_replaced_content(style_node, images));
+        counters.leave();
+        return root;
+    }
+
+    if style_node.display() == Display::ListItem {
+        if let Some(marker) = list::build_marker(style_node, counters) {
+            match style_node.list_style_position() {
+                ListStylePosition::Outside => root.children.push(marker),
+                ListStylePosition::Inside => root.get_inline_container().children.push(marker),
+            }
+        }
+    }
+
+    // Out-of-flow children stay where they would have been in the flow, which gives their
+    // static position, but take no space there. Floats stay among the lines they float in.
+    let flex_container = matches!(
+        style_node.display(),
+        Display::Flex | Display::InlineFlex | Display::Grid | Display::InlineGrid
+    );
     for child in &style_node.children {
         match child.display() {
-            Display::Block => root.children.push(build_layout_tree(child)),
-            Display::Inline => root
+            Display::None => {}
+            // Each child element of a flex or grid container is an item, while runs of text
+            // are wrapped in anonymous ones.
+            _ if flex_container && matches!(child.node.typ, NodeType::Element(_)) => root
+                .children
+                .push(build_layout_tree(child, true, counters, images)),
+            // Table parts outside of a table are wrapped in an anonymous one.
+            Display::TableRowGroup
+            | Display::TableHeaderGroup
+            | Display::TableFooterGroup
+            | Display::TableRow
+            | Display::TableCell
+            | Display::TableColumnGroup
+            | Display::TableColumn => {
+                if !matches!(
+                    root.children.last(),
+                    Some(&LayoutBox {
+                        box_type: BoxType::AnonymousTable(_),
+                        ..
+                    })
+                ) {
+                    root.children
+                        .push(LayoutBox::new(BoxType::AnonymousTable(style_node)));
+                }
+                table::add_table_child(root.children.last_mut().unwrap(), child, counters, images);
+            }
+            Display::Block
+            | Display::ListItem
+            | Display::FlowRoot
+            | Display::Flex
+            | Display::Grid
+            | Display::Table
+            | Display::TableCaption => {
+                let child_box = build_layout_tree(child, false, counters, images);
+                if (child_box.is_out_of_flow() || child_box.is_float()) && root.in_inline_content()
+                {
+                    root.get_inline_container().children.push(child_box);
+                } else {
+                    root.children.push(child_box);
+                }
+            }
+            Display::Inline
+            | Display::InlineBlock
+            | Display::InlineFlex
+            | Display::InlineGrid
+            | Display::InlineTable => root
                 .get_inline_container()
                 .children
-                .push(build_layout_tree(child)),
-            Display::None => {}
+                .push(build_layout_tree(child, false, counters, images)),
         }
     }
 
-    return root;
+    counters.leave();
+    root
 }
 
 impl<'a> LayoutBox<'a> {
@@ -128,29 +299,122 @@ impl<'a> LayoutBox<'a> {
 
     fn layout(&mut self, containing_block: Dimensions) {
         match self.box_type {
-            BoxType::BlockNode(_) => self.layout_block(containing_block),
+            BoxType::BlockNode(_) => self.layout_block(containing_block, None),
             BoxType::InlineNode(_) => self.layout_inline(containing_block),
-            BoxType::AnonymousBlock => self.layout_anonymous_block(containing_block),
+            BoxType::AnonymousBlock(_) => {
+                self.layout_anonymous_block(containing_block, &mut FloatContext::default())
+            }
+            BoxType::Table(_) | BoxType::AnonymousTable(_) => {
+                self.layout_table_box(containing_block, None)
+            }
+            BoxType::LineBox { .. } | BoxType::TextRun(..) => {
+                unreachable!("Line boxes are built by their inline formatting context")
+            }
+            BoxType::Marker(..) => unreachable!("Markers are placed by their list item"),
+            BoxType::Image(..) => unreachable!("Images are placed by their replaced element"),
+        }
+    }
+
+    /// Whether the box is absolutely positioned, and so taken out of the normal flow.
+    pub(crate) fn is_out_of_flow(&self) -> bool {
+        match self.box_type {
+            BoxType::BlockNode(style) => {
+                matches!(
+                    style.position(),
+                    style::Position::Absolute | style::Position::Fixed
+                )
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the box is an inline-level container, like an inline-block, or an inline
+    /// replaced element, laid out as an atomic inline.
+    pub(crate) fn is_inline_block(&self) -> bool {
+        match self.box_type {
+            BoxType::BlockNode(style) => {
+                let atomic = match style.display() {
+                    Display::InlineBlock
+                    | Display::InlineFlex
+                    | Display::InlineGrid
+                    | Display::InlineTable => true,
+                    Display::Inline => self.is_replaced(),
+                    _ => false,
+                };
+                atomic && !self.is_out_of_flow() && !self.is_float()
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the box wraps a table box and its captions.
+    pub(crate) fn is_table_wrapper(&self) -> bool {
+        match self.box_type {
+            BoxType::BlockNode(style) => {
+                matches!(style.display(), Display::Table | Display::InlineTable)
+            }
+            _ => false,
+        }
+    }
+
+    /// Looks up a border width or padding of a block box. Anonymous boxes and table wrappers
+    /// have none, the table box inside a wrapper taking the table's.
+    pub(crate) fn edge_value(&self, name: &str, fallback: &str) -> Value {
+        let zero = Length(0.0, Px);
+        match self.box_type {
+            BoxType::BlockNode(style) if !self.is_table_wrapper() => {
+                style.lookup(name, fallback, &zero)
+            }
+            _ => zero,
         }
     }
 
     fn get_style_node(&self) -> &'a StyledNode<'a> {
         match self.box_type {
-            BoxType::BlockNode(node) | BoxType::InlineNode(node) => node,
-            BoxType::AnonymousBlock => panic!("Anonymous block box has no style node"),
+            BoxType::BlockNode(node)
+            | BoxType::InlineNode(node)
+            | BoxType::Table(node)
+            | BoxType::TextRun(node, ..)
+            | BoxType::Marker(node, ..)
+            | BoxType::Image(node, ..) => node,
+            BoxType::AnonymousBlock(_) => panic!("Anonymous block box has no style node"),
+            BoxType::AnonymousTable(_) => panic!("Anonymous table box has no style node"),
+            BoxType::LineBox { .. } => panic!("Line box has no style node"),
+        }
+    }
+
+    /// Whether children added now would follow inline content.
+    fn in_inline_content(&self) -> bool {
+        match self.box_type {
+            BoxType::BlockNode(_) => matches!(
+                self.children.last(),
+                Some(&LayoutBox {
+                    box_type: BoxType::AnonymousBlock(_),
+                    ..
+                })
+            ),
+            _ => true,
         }
     }
 
     fn get_inline_container(&mut self) -> &mut LayoutBox<'a> {
         match self.box_type {
-            BoxType::InlineNode(_) | BoxType::AnonymousBlock => self,
-            BoxType::BlockNode(_) => {
+            BoxType::InlineNode(_) | BoxType::AnonymousBlock(_) => self,
+            BoxType::LineBox { .. }
+            | BoxType::TextRun(..)
+            | BoxType::Marker(..)
+            | BoxType::Image(..)
+            | BoxType::Table(_)
+            | BoxType::AnonymousTable(_) => unreachable!(),
+            BoxType::BlockNode(style) => {
                 match self.children.last() {
                     Some(&LayoutBox {
-                        box_type: BoxType::AnonymousBlock,
+                        box_type: BoxType::AnonymousBlock(_),
                         ..
                     }) => {}
-                    _ => self.children.push(LayoutBox::new(BoxType::AnonymousBlock)),
+                    _ => self
+                        .children
+                        .push(LayoutBox::new(BoxType::AnonymousBlock(style))),
                 }
                 self.children.last_mut().unwrap()
             }
@@ -182,3 +446,62 @@ impl Dimensions {
         self.border_box().expanded_by(self.margin)
     }
 }
+
+/// Layout trees built from markup, for the tests of the layout modules.
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::{layout_tree_with_images, BoxType, Dimensions, LayoutBox, Rect};
+    use crate::css::media::{Device, MediaType};
+    use crate::css::{self, Stylesheet};
+    use crate::html::{self, NodeType};
+    use crate::raster::ImageMap;
+    use crate::style::style_tree;
+    use crate::text::testing::{add_test_font, TEST_FAMILY};
+
+    /// Lays out `html` for print in `viewport`, styled by the default stylesheet and `css`,
+    /// with text in the bundled test font unless `css` says otherwise.
+    pub(crate) fn layout_html(html: &str, css: &str, viewport: Rect) -> LayoutBox<'static> {
+        layout_html_with_images(html, css, &ImageMap::new(), viewport)
+    }
+
+    /// Like `layout_html`, with `images` for the `<img>` elements. The document and its
+    /// styles are leaked, so the tree can outlive the call.
+    pub(crate) fn layout_html_with_images(
+        html: &str,
+        css: &str,
+        images: &ImageMap,
+        viewport: Rect,
+    ) -> LayoutBox<'static> {
+        add_test_font();
+        let document = Box::leak(Box::new(html::parse(html.to_string())));
+        let mut stylesheet = Stylesheet::default_style();
+        stylesheet.merge(css::parse(format!("* {{ font-family: {}; }}", TEST_FAMILY)));
+        stylesheet.merge(css::parse(css.to_string()));
+        let stylesheet = Box::leak(Box::new(stylesheet));
+        let device = Device::new(MediaType::Print, viewport);
+        let styled = Box::leak(Box::new(style_tree(
+            &document.root_node,
+            stylesheet,
+            &device,
+        )));
+        layout_tree_with_images(styled, images, Dimensions::new(viewport))
+    }
+
+    /// The first block box of an element with `class`, in tree order.
+    pub(crate) fn find<'b>(
+        layout_box: &'b LayoutBox<'b>,
+        class: &str,
+    ) -> Option<&'b LayoutBox<'b>> {
+        if let BoxType::BlockNode(style) = layout_box.box_type {
+            if let NodeType::Element(ref elem) = style.node.typ {
+                if elem.classes().contains(class) {
+                    return Some(layout_box);
+                }
+            }
+        }
+        layout_box
+            .children
+            .iter()
+            .find_map(|child| find(child, class))
+    }
+}
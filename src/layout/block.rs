@@ -1,115 +1,327 @@
-use crate::style::Position;
+use crate::style::{Clear, Display, Overflow, Position};
+
+use super::float::FloatContext;
+use super::{BoxType, Dimensions, Keyword, LayoutBox, Length, Px};
+use crate::css::Value;
+
+/// A set of adjoining vertical margins, which collapse into a single margin: the largest
+/// positive one plus the most negative one.
+#[derive(Debug, Default, Clone, Copy)]
+struct CollapsedMargin {
+    positive: f32,
+    negative: f32,
+}
+
+/// The margins a block exposes to its parent once its children's margins have collapsed
+/// with its own.
+struct CollapsibleMargins {
+    top: CollapsedMargin,
+    bottom: CollapsedMargin,
+    /// The block is empty and its top and bottom margins collapse with each other.
+    collapses_through: bool,
+}
+
+impl CollapsedMargin {
+    fn new(margin: f32) -> Self {
+        CollapsedMargin::default().adjoin_px(margin)
+    }
+
+    fn adjoin(self, other: CollapsedMargin) -> Self {
+        CollapsedMargin {
+            positive: self.positive.max(other.positive),
+            negative: self.negative.min(other.negative),
+        }
+    }
 
-use super::{Dimensions, Keyword, LayoutBox, Length, Px};
+    fn adjoin_px(self, margin: f32) -> Self {
+        CollapsedMargin {
+            positive: self.positive.max(margin),
+            negative: self.negative.min(margin),
+        }
+    }
+
+    fn resolve(self) -> f32 {
+        self.positive + self.negative
+    }
+}
 
 impl<'a> LayoutBox<'a> {
-    pub(crate) fn layout_block(&mut self, containing_block: Dimensions) {
+    /// Lays out a block on its own, like the root box. It establishes a new block formatting
+    /// context, so the margins of its children don't collapse with its own. `cb_height` is
+    /// the height of the containing block, when it doesn't depend on its contents.
+    pub(crate) fn layout_block(&mut self, containing_block: Dimensions, cb_height: Option<f32>) {
+        self.layout_block_in_flow(
+            containing_block,
+            cb_height,
+            true,
+            &mut FloatContext::default(),
+        );
+    }
+
+    /// Lays out a block positioned from its own top margin, and returns its margins after
+    /// collapsing them with its children's, for the parent to place it with. Unless the
+    /// block is a formatting context root, its contents flow around the parent's `floats`.
+    fn layout_block_in_flow(
+        &mut self,
+        containing_block: Dimensions,
+        cb_height: Option<f32>,
+        formatting_context_root: bool,
+        floats: &mut FloatContext,
+    ) -> CollapsibleMargins {
         self.calculate_block_width(containing_block);
 
         self.calculate_block_position(containing_block);
 
-        self.layout_block_children();
+        let height = self.specified_height(cb_height);
+        let mut own_floats = FloatContext::default();
+        let floats = if formatting_context_root {
+            &mut own_floats
+        } else {
+            floats
+        };
+        let margins = self.layout_block_children(height, formatting_context_root, floats);
+
+        let d = &self.dimensions;
+        let collapse_bottom = !formatting_context_root
+            && height.is_none()
+            && d.border.bottom == 0.0
+            && d.padding.bottom == 0.0;
+        if !collapse_bottom {
+            // Margins left over at the end of the children stay inside this block.
+            self.dimensions.content.height += margins.bottom.resolve();
+        }
+        if formatting_context_root {
+            self.contain_floats(floats);
+        }
+
+        self.calculate_block_height(height, cb_height);
+
+        // Relative offsets move the box once it's laid out, without affecting the flow.
+        let (dx, dy) = self.relative_offset(containing_block.content.width, cb_height);
+        self.translate(dx, dy);
+
+        if self.is_positioned_container() {
+            self.layout_out_of_flow_descendants(self.dimensions.padding_box(), true, false);
+        }
+
+        let d = &self.dimensions;
+        let collapses_through = margins.collapses_through
+            && d.content.height == 0.0
+            && d.border.top == 0.0
+            && d.padding.top == 0.0
+            && d.border.bottom == 0.0
+            && d.padding.bottom == 0.0;
+        if collapses_through {
+            let margin = margins
+                .top
+                .adjoin(margins.bottom)
+                .adjoin_px(self.dimensions.margin.bottom);
+            return CollapsibleMargins {
+                top: margin,
+                bottom: margin,
+                collapses_through,
+            };
+        }
+
+        CollapsibleMargins {
+            top: margins.top,
+            bottom: if collapse_bottom {
+                margins.bottom.adjoin_px(self.dimensions.margin.bottom)
+            } else {
+                CollapsedMargin::new(self.dimensions.margin.bottom)
+            },
+            collapses_through,
+        }
+    }
 
-        self.calculate_block_height();
+    /// Lays out the children of a block that establishes a block formatting context, once its
+    /// width and position are known, and sets its height.
+    pub(crate) fn layout_block_contents(&mut self, height: Option<f32>, cb_height: Option<f32>) {
+        let mut floats = FloatContext::default();
+        let margins = self.layout_block_children(height, true, &mut floats);
+        self.dimensions.content.height += margins.bottom.resolve();
+        self.contain_floats(&floats);
+        self.calculate_block_height(height, cb_height);
     }
 
-    fn calculate_block_width(&mut self, containing_block: Dimensions) {
-        let style = self.get_style_node();
+    /// Grows the content height of a formatting context root to include its floats.
+    fn contain_floats(&mut self, floats: &FloatContext) {
+        if let Some(bottom) = floats.bottom() {
+            let content = &mut self.dimensions.content;
+            content.height = content.height.max(bottom - content.y);
+        }
+    }
 
-        let auto = Keyword("auto".to_string());
-        let mut width = style.value("width").unwrap_or_else(|| auto.clone());
+    /// Whether the block establishes a new block formatting context for its contents.
+    fn establishes_formatting_context(&self) -> bool {
+        let style = self.get_style_node();
+        if let Display::FlowRoot
+        | Display::Flex
+        | Display::InlineFlex
+        | Display::Grid
+        | Display::InlineGrid
+        | Display::Table
+        | Display::InlineTable = style.display()
+        {
+            return true;
+        }
+        match style.overflow() {
+            Overflow::Visible | Overflow::Clip => false,
+            Overflow::Hidden | Overflow::Scroll | Overflow::Auto => true,
+        }
+    }
 
+    /// The top margin the block will expose once collapsed with those of its first
+    /// children. It's known before the block is laid out, so that the block can be placed
+    /// where its lines will wrap around the right floats.
+    fn estimate_top_margin(&self, cb_width: f32) -> CollapsedMargin {
+        let style = self.get_style_node();
         let zero = Length(0.0, Px);
-
-        let mut margin_left = style.lookup("margin-left", "margin", &zero);
-        let mut margin_right = style.lookup("margin-right", "margin", &zero);
-
-        let border_left = style.lookup("border-left-width", "border-width", &zero);
-        let border_right = style.lookup("border-right-width", "border-width", &zero);
-
-        let padding_left = style.lookup("padding-left", "padding", &zero);
-        let padding_right = style.lookup("padding-right", "padding", &zero);
-
-        let total: f32 = [
-            &margin_left,
-            &margin_right,
-            &border_left,
-            &border_right,
-            &padding_left,
-            &padding_right,
-            &width,
-        ]
-        .iter()
-        .map(|v| v.to_px())
-        .sum();
-
-        if width != auto && total > containing_block.content.width {
-            if margin_left == auto {
-                margin_left = Length(0.0, Px);
-            }
-            if margin_right == auto {
-                margin_right = Length(0.0, Px);
+        let edge =
+            |name: &str, fallback: &str| style.lookup(name, fallback, &zero).resolve(cb_width);
+
+        let margin = CollapsedMargin::new(edge("margin-top", "margin"));
+        if self.establishes_formatting_context()
+            || self
+                .edge_value("border-top-width", "border-width")
+                .resolve(cb_width)
+                != 0.0
+            || self.edge_value("padding-top", "padding").resolve(cb_width) != 0.0
+        {
+            return margin;
+        }
+        match self.children.iter().find(|child| {
+            !child.is_out_of_flow()
+                && !child.is_float()
+                && !matches!(child.box_type, BoxType::Marker(..))
+        }) {
+            Some(child)
+                if matches!(child.box_type, BoxType::BlockNode(_))
+                    && child.get_style_node().clear() == Clear::None =>
+            {
+                margin.adjoin(child.estimate_top_margin(cb_width))
             }
+            _ => margin,
         }
+    }
 
-        let underflow = containing_block.content.width - total;
+    /// Computes the used width and horizontal margins following CSS 2.1 §10.3.3, then
+    /// applies `max-width` and `min-width` as in §10.4.
+    fn calculate_block_width(&mut self, containing_block: Dimensions) {
+        let style = self.get_style_node();
+        let cb_width = containing_block.content.width;
 
-        match (width == auto, margin_left == auto, margin_right == auto) {
-            (false, false, false) => margin_right = Length(margin_right.to_px() + underflow, Px),
-            (false, false, true) => margin_left = Length(underflow, Px),
-            (false, true, false) => margin_right = Length(underflow, Px),
-            (false, true, true) => {
-                margin_left = Length(underflow / 2.0, Px);
-                margin_right = Length(underflow / 2.0, Px);
-            }
-            (true, _, _) => {
-                if margin_left == auto {
-                    margin_left = Length(0.0, Px);
-                }
-                if margin_right == auto {
-                    margin_right = Length(0.0, Px);
-                }
+        let zero = Length(0.0, Px);
 
-                if underflow >= 0.0 {
-                    width = Length(underflow, Px);
-                } else {
-                    width = Length(0.0, Px);
-                    margin_right = Length(margin_right.to_px() + underflow, Px);
-                }
+        let margin_left = style.lookup("margin-left", "margin", &zero);
+        let margin_right = style.lookup("margin-right", "margin", &zero);
+
+        let border_left = self
+            .edge_value("border-left-width", "border-width")
+            .resolve(cb_width);
+        let border_right = self
+            .edge_value("border-right-width", "border-width")
+            .resolve(cb_width);
+
+        let padding_left = self.edge_value("padding-left", "padding").resolve(cb_width);
+        let padding_right = self
+            .edge_value("padding-right", "padding")
+            .resolve(cb_width);
+
+        let edges = border_left + border_right + padding_left + padding_right;
+        let content_width = |name: &str| {
+            let value = style.value(name)?;
+            if let Keyword(_) = value {
+                return None;
             }
+            Some(self.content_size(value.resolve(cb_width), edges))
+        };
+
+        // Floats and inline-blocks shrink to fit, and keep their margins whatever the space
+        // left, auto ones being zero.
+        let shrink_to_fit = self.is_float() || self.is_inline_block();
+        let used_margin = |value: &Value| match value {
+            Keyword(_) => 0.0,
+            _ => value.resolve(cb_width),
+        };
+        let mut width = content_width("width");
+        if self.is_replaced() && width.is_none() {
+            width = Some(self.replaced_width());
         }
+        if self.is_table_wrapper() {
+            // A table is never narrower than its contents, and an auto width shrinks to fit.
+            let sizes = self.content_intrinsic_sizes();
+            let available =
+                cb_width - used_margin(&margin_left) - used_margin(&margin_right) - edges;
+            width = Some(match width {
+                Some(width) => width.max(sizes.min_content),
+                None => sizes.shrink_to_fit(available),
+            });
+        } else if shrink_to_fit && width.is_none() {
+            let available =
+                cb_width - used_margin(&margin_left) - used_margin(&margin_right) - edges;
+            width = Some(self.content_intrinsic_sizes().shrink_to_fit(available));
+        }
+        let solve = |width: Option<f32>| {
+            if shrink_to_fit {
+                (
+                    width.unwrap_or(0.0),
+                    (used_margin(&margin_left), used_margin(&margin_right)),
+                )
+            } else {
+                solve_block_width(width, &margin_left, &margin_right, edges, cb_width)
+            }
+        };
+        let mut used = solve(width);
+        if let Some(max_width) = content_width("max-width") {
+            if used.0 > max_width {
+                used = solve(Some(max_width));
+            }
+        }
+        let min_width = content_width("min-width").unwrap_or(0.0);
+        if used.0 < min_width {
+            used = solve(Some(min_width));
+        }
+        let (width, margins) = used;
 
         let d = &mut self.dimensions;
-        d.content.width = width.to_px();
+        d.content.width = width;
 
-        d.padding.left = padding_left.to_px();
-        d.padding.right = padding_right.to_px();
+        d.padding.left = padding_left;
+        d.padding.right = padding_right;
 
-        d.border.left = border_left.to_px();
-        d.border.right = border_right.to_px();
+        d.border.left = border_left;
+        d.border.right = border_right;
 
-        d.margin.left = margin_left.to_px();
-        d.margin.right = margin_right.to_px();
+        d.margin.left = margins.0;
+        d.margin.right = margins.1;
     }
 
     fn calculate_block_position(&mut self, containing_block: Dimensions) {
         let style = self.get_style_node();
-        let d = &mut self.dimensions;
+        // Vertical margins and padding are percentages of the containing block's width too.
+        let cb_width = containing_block.content.width;
+        let edge = |name: &str, fallback: &str| self.edge_value(name, fallback).resolve(cb_width);
+        let border_top = edge("border-top-width", "border-width");
+        let border_bottom = edge("border-bottom-width", "border-width");
+        let padding_top = edge("padding-top", "padding");
+        let padding_bottom = edge("padding-bottom", "padding");
 
         let zero = Length(0.0, Px);
+        let d = &mut self.dimensions;
 
-        d.margin.top = style.lookup("margin-top", "margin", &zero).to_px();
-        d.margin.bottom = style.lookup("margin-bottom", "margin", &zero).to_px();
+        d.margin.top = style
+            .lookup("margin-top", "margin", &zero)
+            .resolve(cb_width);
+        d.margin.bottom = style
+            .lookup("margin-bottom", "margin", &zero)
+            .resolve(cb_width);
 
-        d.border.top = style
-            .lookup("border-top-width", "border-width", &zero)
-            .to_px();
-        d.border.bottom = style
-            .lookup("border-bottom-width", "border-width", &zero)
-            .to_px();
+        d.border.top = border_top;
+        d.border.bottom = border_bottom;
 
-        d.padding.top = style.lookup("padding-top", "padding", &zero).to_px();
-        d.padding.bottom = style.lookup("padding-bottom", "padding", &zero).to_px();
+        d.padding.top = padding_top;
+        d.padding.bottom = padding_bottom;
 
         d.content.x = containing_block.content.x + d.margin.left + d.border.left + d.padding.left;
 
@@ -118,8 +330,12 @@ impl<'a> LayoutBox<'a> {
             + d.margin.top
             + d.border.top
             + d.padding.top;
+    }
 
-        if let Position::Relative = style.position() {
+    /// The offset of a relatively positioned box from its place in the flow.
+    pub(crate) fn relative_offset(&self, cb_width: f32, cb_height: Option<f32>) -> (f32, f32) {
+        let style = self.get_style_node();
+        if let Position::Relative | Position::Sticky = style.position() {
             let auto = Keyword("auto".to_string());
 
             let mut left = style.value_or("left", &auto);
@@ -129,32 +345,375 @@ impl<'a> LayoutBox<'a> {
 
             match (left == auto, right == auto) {
                 (true, true) => left = Length(0.0, Px),
-                (true, false) => left = Length(-right.to_px(), Px),
+                (true, false) => left = Length(-right.resolve(cb_width), Px),
                 _ => {}
             }
 
+            // Percentages of an auto height containing block compute to 0.
+            let cb_height = cb_height.unwrap_or(0.0);
             match (top == auto, bottom == auto) {
                 (true, true) => top = Length(0.0, Px),
-                (true, false) => top = Length(-bottom.to_px(), Px),
+                (true, false) => top = Length(-bottom.resolve(cb_height), Px),
                 _ => {}
             }
 
-            d.content.x += left.to_px();
-            d.content.y += top.to_px();
+            return (left.resolve(cb_width), top.resolve(cb_height));
         }
+        (0.0, 0.0)
     }
 
-    fn layout_block_children(&mut self) {
+    /// Stacks the children vertically, collapsing adjoining margins. Returns this block's top
+    /// margin, collapsed with the children's margins adjoining it, and the margins pending
+    /// after the last child. `collapses_through` is set when no child separates the two.
+    /// `height` is this block's content height when it doesn't depend on the children, and
+    /// serves as the base of their percentage heights. Floats are placed in `floats`.
+    fn layout_block_children(
+        &mut self,
+        height: Option<f32>,
+        formatting_context_root: bool,
+        floats: &mut FloatContext,
+    ) -> CollapsibleMargins {
+        if self.is_replaced() {
+            self.layout_replaced_content(height);
+            return CollapsibleMargins {
+                top: CollapsedMargin::new(self.dimensions.margin.top),
+                bottom: CollapsedMargin::default(),
+                collapses_through: false,
+            };
+        }
+        if self.is_flex_container() || self.is_grid_container() {
+            if self.is_flex_container() {
+                self.layout_flex_items(height);
+            } else {
+                self.layout_grid_items(height);
+            }
+            return CollapsibleMargins {
+                top: CollapsedMargin::new(self.dimensions.margin.top),
+                bottom: CollapsedMargin::default(),
+                collapses_through: false,
+            };
+        }
+
         let d = &mut self.dimensions;
+        let mut top = CollapsedMargin::new(d.margin.top);
+        // Until some content or an edge separates them, the children's margins collapse
+        // with this block's top margin.
+        let mut at_top = !formatting_context_root && d.border.top == 0.0 && d.padding.top == 0.0;
+        let mut pending = CollapsedMargin::default();
+        let zero = Length(0.0, Px);
+
         for child in &mut self.children {
-            child.layout(*d);
-            d.content.height += child.dimensions.margin_box().height;
+            match child.box_type {
+                _ if child.is_out_of_flow() => {
+                    // Only the static position is known until the containing block is laid
+                    // out.
+                    let gap = if at_top { 0.0 } else { pending.resolve() };
+                    child.dimensions.content.x = d.content.x;
+                    child.dimensions.content.y = d.content.y + d.content.height + gap;
+                }
+                _ if child.is_float() => {
+                    let gap = if at_top { 0.0 } else { pending.resolve() };
+                    child.layout_float(*d, height);
+                    child.place_float(d.content.y + d.content.height + gap, d.content, floats);
+                }
+                BoxType::BlockNode(style) => {
+                    let root = child.establishes_formatting_context();
+                    // The child is placed where its margins are expected to collapse to, so
+                    // that its contents flow around the floats from where they'll stay.
+                    let estimated_gap = if at_top {
+                        0.0
+                    } else {
+                        pending
+                            .adjoin(child.estimate_top_margin(d.content.width))
+                            .resolve()
+                    };
+                    let mut y = d.content.y + d.content.height + estimated_gap;
+                    let clearance = floats.clearance(style.clear()).filter(|&bottom| bottom > y);
+                    if let Some(bottom) = clearance {
+                        y = bottom;
+                    }
+
+                    let mut containing_block = *d;
+                    let margin_top = style
+                        .lookup("margin-top", "margin", &zero)
+                        .resolve(d.content.width);
+                    containing_block.content.height = y - margin_top - d.content.y;
+                    if root {
+                        // A formatting context root doesn't overlap the floats beside it.
+                        let (left, right) =
+                            floats.available(y, 0.0, d.content.x, d.content.x + d.content.width);
+                        containing_block.content.x = left;
+                        containing_block.content.width = (right - left).max(0.0);
+                    }
+                    let first_float = floats.len();
+                    let margins =
+                        child.layout_block_in_flow(containing_block, height, root, floats);
+
+                    if let Some(bottom) = clearance {
+                        // Clearance keeps the child's margins from collapsing with the ones
+                        // before it.
+                        d.content.height =
+                            bottom - d.content.y + child.dimensions.border_box().height;
+                        pending = margins.bottom;
+                        at_top = false;
+                        continue;
+                    }
+
+                    if at_top {
+                        top = top.adjoin(margins.top);
+                    } else {
+                        pending = pending.adjoin(margins.top);
+                    }
+                    if margins.collapses_through {
+                        if at_top {
+                            top = top.adjoin(margins.bottom);
+                        } else {
+                            pending = pending.adjoin(margins.bottom);
+                        }
+                        continue;
+                    }
+
+                    // Margins the estimate missed move the child after all.
+                    let gap = if at_top { 0.0 } else { pending.resolve() };
+                    if gap != estimated_gap {
+                        child.translate(0.0, gap - estimated_gap);
+                        floats.translate_from(first_float, gap - estimated_gap);
+                    }
+                    d.content.height += gap + child.dimensions.border_box().height;
+                    pending = margins.bottom;
+                    at_top = false;
+                }
+                // Outside markers are placed beside the first line once it's laid out.
+                BoxType::Marker(..) => {}
+                BoxType::Table(_) | BoxType::AnonymousTable(_) => {
+                    let gap = if at_top { 0.0 } else { pending.resolve() };
+                    let mut containing_block = *d;
+                    containing_block.content.height += gap;
+                    child.layout_table_box(containing_block, height);
+                    d.content.height += gap + child.dimensions.margin_box().height;
+                    pending = CollapsedMargin::default();
+                    at_top = false;
+                }
+                _ => {
+                    let gap = if at_top { 0.0 } else { pending.resolve() };
+                    let mut containing_block = *d;
+                    containing_block.content.height += gap;
+                    child.layout_anonymous_block(containing_block, floats);
+                    // An anonymous block without any line box takes no space.
+                    let empty = !child
+                        .children
+                        .iter()
+                        .any(|line| matches!(line.box_type, BoxType::LineBox { .. }));
+                    if !empty {
+                        d.content.height += gap + child.dimensions.margin_box().height;
+                        pending = CollapsedMargin::default();
+                        at_top = false;
+                    }
+                }
+            }
+        }
+
+        self.place_outside_marker();
+        CollapsibleMargins {
+            top,
+            bottom: pending,
+            collapses_through: at_top,
         }
     }
 
-    fn calculate_block_height(&mut self) {
-        if let Some(Length(h, Px)) = self.get_style_node().value("height") {
-            self.dimensions.content.height = h;
+    /// The content height set by `height`, `min-height` and `max-height`, or `None` when it
+    /// depends on the contents.
+    fn specified_height(&self, cb_height: Option<f32>) -> Option<f32> {
+        let height = self.content_height("height", cb_height)?;
+        Some(self.clamp_height(height, cb_height))
+    }
+
+    fn calculate_block_height(&mut self, height: Option<f32>, cb_height: Option<f32>) {
+        self.dimensions.content.height = match height {
+            Some(height) => height,
+            None => self.clamp_height(self.dimensions.content.height, cb_height),
+        };
+    }
+
+    /// Applies `max-height` then `min-height`. Percentages of a containing block whose height
+    /// depends on its contents are ignored.
+    pub(crate) fn clamp_height(&self, mut height: f32, cb_height: Option<f32>) -> f32 {
+        if let Some(max_height) = self.content_height("max-height", cb_height) {
+            height = height.min(max_height);
+        }
+        if let Some(min_height) = self.content_height("min-height", cb_height) {
+            height = height.max(min_height);
+        }
+        height
+    }
+
+    /// Resolves a vertical size property to a content height. The heights of a table apply
+    /// to its table box rather than to its wrapper.
+    pub(crate) fn content_height(&self, name: &str, cb_height: Option<f32>) -> Option<f32> {
+        if self.is_table_wrapper() {
+            return None;
+        }
+        let value = self.get_style_node().value(name)?;
+        let height = match value {
+            Keyword(_) => return None,
+            Length(..) => value.to_px(),
+            _ => value.resolve(cb_height?),
+        };
+        let d = &self.dimensions;
+        Some(self.content_size(
+            height,
+            d.border.top + d.border.bottom + d.padding.top + d.padding.bottom,
+        ))
+    }
+
+    /// Converts a specified size to a content size, taking `box-sizing` into account.
+    /// `edges` is the sum of the padding and border widths along the same axis.
+    pub(crate) fn content_size(&self, size: f32, edges: f32) -> f32 {
+        match self.get_style_node().value("box-sizing") {
+            Some(Keyword(ref sizing)) if sizing == "border-box" => (size - edges).max(0.0),
+            _ => size,
         }
     }
 }
+
+/// Solves the horizontal constraint of CSS 2.1 §10.3.3 for a content `width` (`None` when
+/// auto), returning the used width and left and right margins.
+fn solve_block_width(
+    width: Option<f32>,
+    margin_left: &Value,
+    margin_right: &Value,
+    edges: f32,
+    cb_width: f32,
+) -> (f32, (f32, f32)) {
+    let auto = Keyword("auto".to_string());
+    let mut margin_left = if *margin_left == auto {
+        None
+    } else {
+        Some(margin_left.resolve(cb_width))
+    };
+    let mut margin_right = if *margin_right == auto {
+        None
+    } else {
+        Some(margin_right.resolve(cb_width))
+    };
+
+    let total =
+        edges + width.unwrap_or(0.0) + margin_left.unwrap_or(0.0) + margin_right.unwrap_or(0.0);
+
+    if width.is_some() && total > cb_width {
+        margin_left = margin_left.or(Some(0.0));
+        margin_right = margin_right.or(Some(0.0));
+    }
+
+    let underflow = cb_width - total;
+
+    match (width, margin_left, margin_right) {
+        (Some(width), Some(left), Some(right)) => (width, (left, right + underflow)),
+        (Some(width), Some(left), None) => (width, (left, underflow)),
+        (Some(width), None, Some(right)) => (width, (underflow, right)),
+        (Some(width), None, None) => (width, (underflow / 2.0, underflow / 2.0)),
+        (None, left, right) => {
+            let (left, right) = (left.unwrap_or(0.0), right.unwrap_or(0.0));
+            if underflow >= 0.0 {
+                (underflow, (left, right))
+            } else {
+                (0.0, (left, right + underflow))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layout::testing::{find, layout_html};
+    use crate::layout::Rect;
+
+    #[test]
+    fn test_margin_collapsing() {
+        let root = layout_html(
+            "<div>\
+             <div class=\"outer\"><h1 class=\"h1\">a</h1><p class=\"p\">b</p><div class=\"empty\"></div><p class=\"negative\">c</p></div>\
+             <div class=\"bordered\"><p class=\"d\">d</p></div>\
+             </div>",
+            ".outer { margin-top: 10px; }
+             .empty { margin: 30px 0; }
+             .negative { margin-top: -5px; }
+             .bordered { border-top-width: 1px; }
+             h1, p { line-height: 20px; }",
+            Rect::new(0.0, 0.0, 400.0, 600.0),
+        );
+        let near = |a: f32, b: f32| (a - b).abs() < 0.01;
+        let border_box = |class: &str| find(&root, class).unwrap().dimensions.border_box();
+        let top = |class: &str| border_box(class).y;
+        let bottom = |class: &str| {
+            let border_box = border_box(class);
+            border_box.y + border_box.height
+        };
+
+        // The first child's top margin collapses with its parent's.
+        let outer = border_box("outer");
+        assert!(near(outer.y, 21.44));
+        assert!(near(top("h1"), outer.y));
+        assert!(near(bottom("h1") - top("h1"), 20.0));
+        // Adjacent siblings.
+        assert!(near(top("p") - bottom("h1"), 21.44));
+        // An empty block collapses through, and negative margins are subtracted.
+        assert!(near(top("negative") - bottom("p"), 30.0 - 5.0));
+        // The last child's bottom margin collapses with its parent's, and then with the top
+        // margin of the next sibling, which a border keeps from its own first child.
+        assert!(near(outer.y + outer.height, bottom("negative")));
+        let bordered = border_box("bordered");
+        assert!(near(bordered.y - bottom("negative"), 17.92));
+        let p = border_box("d");
+        assert!(near(p.y - bordered.y, 1.0 + 17.92));
+        // The root establishes a block formatting context and contains every margin.
+        assert!(near(root.dimensions.content.height, p.y + p.height + 17.92));
+        assert!(near(
+            root.dimensions.content.height,
+            21.44 + 20.0 + 21.44 + 20.0 + 25.0 + 20.0 + 17.92 + 1.0 + 17.92 + 20.0 + 17.92
+        ));
+    }
+
+    #[test]
+    fn test_block_sizing() {
+        let root = layout_html(
+            "<div class=\"root\">\
+             <div class=\"half\"><div class=\"fill\"></div></div>\
+             <div class=\"sized\"></div>\
+             <div class=\"clamped\"></div>\
+             <div class=\"centered\"></div>\
+             </div>",
+            ".root { height: 50%; }
+             .half { width: 50%; height: 50%; padding: 10%; }
+             .fill { height: 100%; }
+             .sized { box-sizing: border-box; width: 100px; height: 50px; padding-left: 10px; padding-right: 10px; border-width: 5px; }
+             .clamped { width: 90%; max-width: 300px; min-width: 320px; min-height: 20%; }
+             .centered { max-width: 100px; margin: 0 auto; }",
+            Rect::new(0.0, 0.0, 400.0, 600.0),
+        );
+        let dimensions = |class: &str| find(&root, class).unwrap().dimensions;
+
+        assert_eq!(root.dimensions.content.height, 300.0);
+
+        let half = dimensions("half");
+        assert_eq!(half.content.width, 200.0);
+        assert_eq!(half.content.height, 150.0);
+        assert_eq!(half.padding.top, 40.0);
+        assert_eq!(half.padding.left, 40.0);
+        assert_eq!(dimensions("fill").content.height, 150.0);
+
+        let sized = dimensions("sized");
+        assert_eq!(sized.border_box().width, 100.0);
+        assert_eq!(sized.content.width, 70.0);
+        assert_eq!(sized.content.height, 40.0);
+
+        let clamped = dimensions("clamped");
+        assert_eq!(clamped.content.width, 320.0);
+        assert_eq!(clamped.content.height, 60.0);
+
+        let centered = dimensions("centered");
+        assert_eq!(centered.content.width, 100.0);
+        assert_eq!(centered.margin.left, 150.0);
+        assert_eq!(centered.margin.right, 150.0);
+    }
+}
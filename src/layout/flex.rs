@@ -0,0 +1,784 @@
+use super::float::FloatContext;
+use super::{BoxType, Dimensions, EdgeSizes, Keyword, LayoutBox, Length, Position, Px, Rect};
+use crate::css::Value;
+use crate::style::{
+    ContentDistribution, Display, FlexDirection, FlexWrap, ItemAlignment, StyledNode,
+};
+use std::ops::Range;
+
+/// The start and end margins of an item along an axis, `None` when auto.
+pub(crate) type AxisMargins = (Option<f32>, Option<f32>);
+
+/// A flex item while the flex layout algorithm runs. Sizes are content sizes, along the axes
+/// of the container.
+struct FlexItem {
+    /// Index of the item among the container's children.
+    index: usize,
+    grow: f32,
+    shrink: f32,
+    base_size: f32,
+    hypothetical_main: f32,
+    min_main: f32,
+    max_main: f32,
+    /// Margins at the main start and end, `None` when auto.
+    main_margins: AxisMargins,
+    cross_margins: AxisMargins,
+    /// Borders and padding along each axis.
+    main_edges: f32,
+    cross_edges: f32,
+    main: f32,
+    frozen: bool,
+    cross: f32,
+    /// Whether the cross size is set by the item's style rather than by its contents.
+    definite_cross: bool,
+    min_cross: f32,
+    max_cross: f32,
+    align: ItemAlignment,
+    /// Distance from the cross start margin edge to the first baseline.
+    baseline: f32,
+    /// Offsets of the margin box from the container's content box.
+    main_position: f32,
+    cross_position: f32,
+}
+
+/// A line of flex items, and where it sits along the cross axis.
+struct FlexLine {
+    items: Range<usize>,
+    cross: f32,
+    position: f32,
+    /// Distance from the cross start of the line to the baseline shared by its items.
+    baseline: f32,
+}
+
+impl FlexItem {
+    /// Margins, borders and padding along the main axis, auto margins counting as zero.
+    fn main_extra(&self) -> f32 {
+        self.main_margins.0.unwrap_or(0.0) + self.main_margins.1.unwrap_or(0.0) + self.main_edges
+    }
+
+    fn outer_cross(&self) -> f32 {
+        self.cross
+            + self.cross_margins.0.unwrap_or(0.0)
+            + self.cross_margins.1.unwrap_or(0.0)
+            + self.cross_edges
+    }
+
+    fn stretches(&self) -> bool {
+        self.align == ItemAlignment::Stretch
+            && !self.definite_cross
+            && self.cross_margins.0.is_some()
+            && self.cross_margins.1.is_some()
+    }
+}
+
+impl<'a> LayoutBox<'a> {
+    pub(crate) fn is_flex_container(&self) -> bool {
+        match self.box_type {
+            BoxType::BlockNode(style) => {
+                matches!(style.display(), Display::Flex | Display::InlineFlex)
+            }
+            _ => false,
+        }
+    }
+
+    /// Lays out the children of a flex container following CSS Flexbox §9, once its width and
+    /// position are known, and sets its content height. `height` is that height when it
+    /// doesn't depend on the items.
+    pub(crate) fn layout_flex_items(&mut self, height: Option<f32>) {
+        let style = self.get_style_node();
+        let direction = style.flex_direction();
+        let row = matches!(direction, FlexDirection::Row | FlexDirection::RowReverse);
+        let reverse = matches!(
+            direction,
+            FlexDirection::RowReverse | FlexDirection::ColumnReverse
+        );
+        let wrap = style.flex_wrap();
+        let content = self.dimensions.content;
+        let width = content.width;
+
+        let gap = |name: &str, base: Option<f32>| match style.value(name) {
+            Some(Keyword(_)) | None => 0.0,
+            Some(value @ Length(..)) => value.to_px(),
+            Some(value) => base.map_or(0.0, |base| value.resolve(base)),
+        };
+        let (main_gap, cross_gap) = if row {
+            (gap("column-gap", Some(width)), gap("row-gap", height))
+        } else {
+            (gap("row-gap", height), gap("column-gap", Some(width)))
+        };
+        let (main_size, cross_size) = if row {
+            (Some(width), height)
+        } else {
+            (height, Some(width))
+        };
+
+        // Out-of-flow children are positioned from the start of the content box.
+        let mut indices = Vec::new();
+        for (index, child) in self.children.iter_mut().enumerate() {
+            if child.is_out_of_flow() {
+                child.dimensions.content.x = content.x;
+                child.dimensions.content.y = content.y;
+            } else {
+                indices.push(index);
+            }
+        }
+        indices.sort_by_key(|&index| self.children[index].order());
+
+        let align_items = style.align_items();
+        let mut items: Vec<FlexItem> = indices
+            .into_iter()
+            .map(|index| self.children[index].flex_item(index, row, width, height, align_items))
+            .collect();
+
+        // Items are collected into lines, then flexed to fill them.
+        let mut lines = Vec::new();
+        let mut start = 0;
+        let mut used = 0.0;
+        for (i, item) in items.iter().enumerate() {
+            let outer = item.hypothetical_main + item.main_extra();
+            if let (FlexWrap::Wrap | FlexWrap::WrapReverse, Some(available)) = (wrap, main_size) {
+                if i > start && used + main_gap + outer > available {
+                    lines.push(start..i);
+                    start = i;
+                    used = 0.0;
+                }
+            }
+            used += if i > start { main_gap + outer } else { outer };
+        }
+        lines.push(start..items.len());
+
+        let mut container_main: f32 = 0.0;
+        for range in &lines {
+            let line = &mut items[range.clone()];
+            let gaps = main_gap * range.len().saturating_sub(1) as f32;
+            let available = match main_size {
+                Some(size) => size - gaps,
+                None => line
+                    .iter()
+                    .map(|item| item.hypothetical_main + item.main_extra())
+                    .sum(),
+            };
+            resolve_flexible_lengths(line, available);
+            let used: f32 = line.iter().map(|item| item.main + item.main_extra()).sum();
+            container_main = container_main.max(used + gaps);
+        }
+        let container_main = main_size.unwrap_or(container_main);
+
+        // With the main sizes known, rows measure the heights of their items.
+        if row {
+            for item in &mut items {
+                let child = &self.children[item.index];
+                let (measured, baseline) = child.measure_item(item.main, height);
+                if !item.definite_cross {
+                    item.cross = measured.min(item.max_cross).max(item.min_cross);
+                }
+                let d = &child.dimensions;
+                let before = item.cross_margins.0.unwrap_or(0.0) + d.border.top + d.padding.top;
+                item.baseline = match baseline {
+                    Some(baseline) => before + baseline,
+                    None => item.outer_cross(),
+                };
+            }
+        }
+
+        let single_line = wrap == FlexWrap::NoWrap;
+        let mut lines: Vec<FlexLine> = lines
+            .into_iter()
+            .map(|range| {
+                let line = &items[range.clone()];
+                let baseline = |item: &&FlexItem| row && item.align == ItemAlignment::Baseline;
+                let ascent = line
+                    .iter()
+                    .filter(baseline)
+                    .map(|item| item.baseline)
+                    .fold(0.0, f32::max);
+                let descent = line
+                    .iter()
+                    .filter(baseline)
+                    .map(|item| item.outer_cross() - item.baseline)
+                    .fold(0.0, f32::max);
+                let cross = line
+                    .iter()
+                    .map(FlexItem::outer_cross)
+                    .fold(ascent + descent, f32::max);
+                FlexLine {
+                    items: range,
+                    cross: match cross_size {
+                        Some(size) if single_line => size,
+                        _ => cross,
+                    },
+                    position: 0.0,
+                    baseline: ascent,
+                }
+            })
+            .collect();
+
+        // `align-content` distributes the free space along the cross axis between lines.
+        let cross_gaps = cross_gap * lines.len().saturating_sub(1) as f32;
+        let lines_cross: f32 = lines.iter().map(|line| line.cross).sum::<f32>() + cross_gaps;
+        let container_cross = cross_size.unwrap_or(lines_cross);
+        let mut free = container_cross - lines_cross;
+        let mut distribution = style.align_content();
+        if single_line {
+            distribution = ContentDistribution::Start;
+        } else if distribution == ContentDistribution::Stretch && free > 0.0 {
+            let share = free / lines.len() as f32;
+            for line in &mut lines {
+                line.cross += share;
+            }
+            free = 0.0;
+        }
+        let (mut position, between) = distribute(distribution, free, lines.len());
+        for line in &mut lines {
+            line.position = position;
+            position += line.cross + cross_gap + between;
+        }
+
+        for line in &lines {
+            let range = line.items.clone();
+            let count = range.len();
+            let gaps = main_gap * count.saturating_sub(1) as f32;
+            let line_items = &mut items[range];
+
+            // Auto margins along the main axis take the free space before `justify-content`.
+            let used: f32 = line_items
+                .iter()
+                .map(|item| item.main + item.main_extra())
+                .sum();
+            let mut free = container_main - used - gaps;
+            let auto_margins = line_items
+                .iter()
+                .map(|item| {
+                    item.main_margins.0.is_none() as usize + item.main_margins.1.is_none() as usize
+                })
+                .sum::<usize>();
+            let mut auto_margin = 0.0;
+            if free > 0.0 && auto_margins > 0 {
+                auto_margin = free / auto_margins as f32;
+                free = 0.0;
+            }
+            let (mut position, between) = distribute(style.justify_content(), free, count);
+            for item in line_items.iter_mut() {
+                let start = item.main_margins.0.unwrap_or(auto_margin);
+                let end = item.main_margins.1.unwrap_or(auto_margin);
+                item.main_margins = (Some(start), Some(end));
+                item.main_position = position;
+                position += item.main + item.main_extra() + main_gap + between;
+                if reverse {
+                    item.main_position =
+                        container_main - item.main_position - item.main - item.main_extra();
+                }
+
+                if item.stretches() {
+                    let cross = line.cross
+                        - item.cross_margins.0.unwrap_or(0.0)
+                        - item.cross_margins.1.unwrap_or(0.0)
+                        - item.cross_edges;
+                    item.cross = cross.min(item.max_cross).max(item.min_cross);
+                }
+
+                // Auto margins along the cross axis take the free space before alignment.
+                let free = line.cross - item.outer_cross();
+                let offset = match item.cross_margins {
+                    (None, None) => {
+                        let margin = free.max(0.0) / 2.0;
+                        item.cross_margins = (Some(margin), Some(margin));
+                        0.0
+                    }
+                    (None, end) => {
+                        item.cross_margins = (Some(free.max(0.0)), end);
+                        0.0
+                    }
+                    (start, None) => {
+                        item.cross_margins = (start, Some(free.max(0.0)));
+                        0.0
+                    }
+                    _ => match item.align {
+                        ItemAlignment::Start | ItemAlignment::Stretch => 0.0,
+                        ItemAlignment::End => free,
+                        ItemAlignment::Center => free / 2.0,
+                        ItemAlignment::Baseline if row => line.baseline - item.baseline,
+                        ItemAlignment::Baseline => 0.0,
+                    },
+                };
+                item.cross_position = line.position + offset;
+                if wrap == FlexWrap::WrapReverse {
+                    item.cross_position =
+                        container_cross - item.cross_position - item.outer_cross();
+                }
+            }
+        }
+
+        for item in &items {
+            let child = &mut self.children[item.index];
+            let (main_start, main_end) =
+                (item.main_margins.0.unwrap(), item.main_margins.1.unwrap());
+            let (cross_start, cross_end) = (
+                item.cross_margins.0.unwrap_or(0.0),
+                item.cross_margins.1.unwrap_or(0.0),
+            );
+            let d = &mut child.dimensions;
+            let (x, y, item_width, item_height) = if row {
+                d.margin.left = main_start;
+                d.margin.right = main_end;
+                d.margin.top = cross_start;
+                d.margin.bottom = cross_end;
+                (
+                    item.main_position,
+                    item.cross_position,
+                    item.main,
+                    item.cross,
+                )
+            } else {
+                d.margin.top = main_start;
+                d.margin.bottom = main_end;
+                d.margin.left = cross_start;
+                d.margin.right = cross_end;
+                (
+                    item.cross_position,
+                    item.main_position,
+                    item.cross,
+                    item.main,
+                )
+            };
+            let x = content.x + x + d.margin.left + d.border.left + d.padding.left;
+            let y = content.y + y + d.margin.top + d.border.top + d.padding.top;
+            child.layout_item(
+                Position::new(x, y),
+                item_width,
+                Some(item_height),
+                width,
+                height,
+            );
+        }
+
+        self.dimensions.content.height = match height {
+            Some(height) => height,
+            None if row => container_cross,
+            None => container_main,
+        };
+    }
+
+    /// Collects what the flex layout algorithm needs to know about an item in a container
+    /// `width` wide and `height` high, and sets its borders, padding and non-auto margins.
+    fn flex_item(
+        &mut self,
+        index: usize,
+        row: bool,
+        width: f32,
+        height: Option<f32>,
+        align_items: ItemAlignment,
+    ) -> FlexItem {
+        let style = match self.box_type {
+            BoxType::BlockNode(style) => Some(style),
+            _ => None,
+        };
+        let (horizontal, vertical) = self.set_item_edges(width);
+        let d = &self.dimensions;
+        let horizontal_edges = d.border.left + d.padding.left + d.border.right + d.padding.right;
+        let vertical_edges = d.border.top + d.padding.top + d.border.bottom + d.padding.bottom;
+
+        let align = style
+            .and_then(StyledNode::align_self)
+            .unwrap_or(align_items);
+        let number = |name: &str, default: f32| match style.and_then(|style| style.value(name)) {
+            Some(Value::Number(n)) => n,
+            _ => default,
+        };
+        let size = |name: &str, base: Option<f32>, edges: f32| self.item_size(name, base, edges);
+        let clips = match style.and_then(|style| style.value("overflow")) {
+            Some(Keyword(ref overflow)) => overflow != "visible",
+            _ => false,
+        };
+
+        let mut item = FlexItem {
+            index,
+            grow: number("flex-grow", 0.0),
+            shrink: number("flex-shrink", 1.0),
+            base_size: 0.0,
+            hypothetical_main: 0.0,
+            min_main: 0.0,
+            max_main: f32::INFINITY,
+            main_margins: if row { horizontal } else { vertical },
+            cross_margins: if row { vertical } else { horizontal },
+            main_edges: if row {
+                horizontal_edges
+            } else {
+                vertical_edges
+            },
+            cross_edges: if row {
+                vertical_edges
+            } else {
+                horizontal_edges
+            },
+            main: 0.0,
+            frozen: false,
+            cross: 0.0,
+            definite_cross: false,
+            min_cross: 0.0,
+            max_cross: f32::INFINITY,
+            align,
+            baseline: 0.0,
+            main_position: 0.0,
+            cross_position: 0.0,
+        };
+
+        let (main_name, cross_name) = if row {
+            ("width", "height")
+        } else {
+            ("height", "width")
+        };
+        let (main_base, cross_base) = if row {
+            (Some(width), height)
+        } else {
+            (height, Some(width))
+        };
+        let (main_edges, cross_edges) = (item.main_edges, item.cross_edges);
+
+        item.min_cross =
+            size(&format!("min-{}", cross_name), cross_base, cross_edges).unwrap_or(0.0);
+        item.max_cross =
+            size(&format!("max-{}", cross_name), cross_base, cross_edges).unwrap_or(f32::INFINITY);
+        let specified_cross = size(cross_name, cross_base, cross_edges);
+        item.definite_cross = specified_cross.is_some();
+        if !row {
+            // A column's items get their width first, which their heights depend on.
+            let available = width
+                - item.cross_margins.0.unwrap_or(0.0)
+                - item.cross_margins.1.unwrap_or(0.0)
+                - cross_edges;
+            item.cross = match specified_cross {
+                Some(cross) => cross,
+                None if item.stretches() => available,
+                None => self.content_intrinsic_sizes().shrink_to_fit(available),
+            }
+            .min(item.max_cross)
+            .max(item.min_cross);
+        } else if let Some(cross) = specified_cross {
+            item.cross = cross.min(item.max_cross).max(item.min_cross);
+        }
+
+        // The size the item's contents give it along the main axis.
+        let cross = item.cross;
+        let content_size = |min: bool| {
+            if row {
+                let sizes = self.content_intrinsic_sizes();
+                if min {
+                    sizes.min_content
+                } else {
+                    sizes.max_content
+                }
+            } else {
+                self.measure_item(cross, height).0
+            }
+        };
+
+        let specified_main = size(main_name, main_base, main_edges);
+        let basis = style.and_then(|style| style.value("flex-basis"));
+        let base_size = match basis {
+            Some(Keyword(ref keyword)) if keyword == "content" => None,
+            Some(Keyword(_)) | None => specified_main,
+            Some(basis @ Length(..)) => Some(self.content_size(basis.to_px(), main_edges)),
+            Some(basis) => main_base.map(|base| self.content_size(basis.resolve(base), main_edges)),
+        };
+        item.base_size = base_size.unwrap_or_else(|| content_size(false));
+
+        item.max_main =
+            size(&format!("max-{}", main_name), main_base, main_edges).unwrap_or(f32::INFINITY);
+        item.min_main = match size(&format!("min-{}", main_name), main_base, main_edges) {
+            Some(min) => min,
+            // The automatic minimum size keeps the item from shrinking below its contents.
+            None if clips => 0.0,
+            None => {
+                let min = content_size(true);
+                specified_main
+                    .map_or(min, |size| size.min(min))
+                    .min(item.max_main)
+            }
+        };
+        item.hypothetical_main = item.base_size.min(item.max_main).max(item.min_main);
+        item
+    }
+
+    /// Sets the borders, padding and margins of a flex or grid item in a container `width`
+    /// wide, auto margins counting as zero. Returns its horizontal and vertical margins, `None`
+    /// when auto.
+    pub(crate) fn set_item_edges(&mut self, width: f32) -> (AxisMargins, AxisMargins) {
+        let style = match self.box_type {
+            BoxType::BlockNode(style) => Some(style),
+            _ => None,
+        };
+        let zero = Length(0.0, Px);
+        let value = |name: &str, fallback: &str| {
+            style.map_or(zero.clone(), |style| style.lookup(name, fallback, &zero))
+        };
+        // Margins and padding are percentages of the container's width along both axes.
+        let margin = |name: &str| match value(name, "margin") {
+            Keyword(ref keyword) if keyword == "auto" => None,
+            margin => Some(margin.resolve(width)),
+        };
+        let edges = |side: &str| {
+            (
+                self.edge_value(&format!("border-{}-width", side), "border-width")
+                    .resolve(width),
+                self.edge_value(&format!("padding-{}", side), "padding")
+                    .resolve(width),
+            )
+        };
+
+        let (left, right, top, bottom) =
+            (edges("left"), edges("right"), edges("top"), edges("bottom"));
+        let d = &mut self.dimensions;
+        d.border = EdgeSizes {
+            left: left.0,
+            right: right.0,
+            top: top.0,
+            bottom: bottom.0,
+        };
+        d.padding = EdgeSizes {
+            left: left.1,
+            right: right.1,
+            top: top.1,
+            bottom: bottom.1,
+        };
+        let horizontal = (margin("margin-left"), margin("margin-right"));
+        let vertical = (margin("margin-top"), margin("margin-bottom"));
+        d.margin = EdgeSizes {
+            left: horizontal.0.unwrap_or(0.0),
+            right: horizontal.1.unwrap_or(0.0),
+            top: vertical.0.unwrap_or(0.0),
+            bottom: vertical.1.unwrap_or(0.0),
+        };
+        (horizontal, vertical)
+    }
+
+    /// Resolves a size property of a flex or grid item to a content size along an axis whose
+    /// borders and padding add up to `edges`. `None` for `auto` and `none`, and for
+    /// percentages of an indefinite `base`.
+    pub(crate) fn item_size(&self, name: &str, base: Option<f32>, edges: f32) -> Option<f32> {
+        let value = match self.box_type {
+            BoxType::BlockNode(style) => style.value(name)?,
+            _ => return None,
+        };
+        let size = match value {
+            Keyword(_) => return None,
+            Length(..) => value.to_px(),
+            _ => value.resolve(base?),
+        };
+        Some(self.content_size(size, edges))
+    }
+
+    pub(crate) fn order(&self) -> i32 {
+        match self.box_type {
+            BoxType::BlockNode(style) => match style.value("order") {
+                Some(Value::Number(order)) => order as i32,
+                _ => 0,
+            },
+            _ => 0,
+        }
+    }
+
+    /// The content height of a flex or grid item laid out `width` wide, and the distance from
+    /// its content top to its first baseline. The layout is done on a copy of the item.
+    pub(crate) fn measure_item(&self, width: f32, cb_height: Option<f32>) -> (f32, Option<f32>) {
+        let mut probe = self.clone();
+        probe.layout_item(Position::default(), width, None, width, cb_height);
+        let baseline = probe.first_line_baseline();
+        (probe.dimensions.content.height, baseline)
+    }
+
+    /// Lays out a flex or grid item with the top left corner of its content box at `origin`,
+    /// `width` wide and `height` high, or as high as its contents when `None`. `cb_width` and
+    /// `cb_height` are the container's content width and height.
+    pub(crate) fn layout_item(
+        &mut self,
+        origin: Position,
+        width: f32,
+        height: Option<f32>,
+        cb_width: f32,
+        cb_height: Option<f32>,
+    ) {
+        let content = Rect::new(origin.x, origin.y, width, 0.0);
+        if let BoxType::AnonymousBlock(_) = self.box_type {
+            let containing_block = Dimensions::new(content);
+            self.layout_anonymous_block(containing_block, &mut FloatContext::default());
+            if let Some(height) = height {
+                self.dimensions.content.height = height;
+            }
+            return;
+        }
+
+        self.dimensions.content = content;
+        // Items establish a formatting context for their contents.
+        self.layout_block_contents(height, cb_height);
+
+        let (dx, dy) = self.relative_offset(cb_width, cb_height);
+        self.translate(dx, dy);
+        if self.is_positioned_container() {
+            self.layout_out_of_flow_descendants(self.dimensions.padding_box(), true, false);
+        }
+    }
+}
+
+/// Resolves the main sizes of the items on a line to fill `available`, growing or shrinking
+/// them by their flex factors, as in CSS Flexbox §9.7.
+fn resolve_flexible_lengths(items: &mut [FlexItem], available: f32) {
+    let used: f32 = items
+        .iter()
+        .map(|item| item.hypothetical_main + item.main_extra())
+        .sum();
+    let growing = used < available;
+    for item in items.iter_mut() {
+        item.main = item.hypothetical_main;
+        let factor = if growing { item.grow } else { item.shrink };
+        item.frozen = factor == 0.0
+            || (growing && item.base_size > item.hypothetical_main)
+            || (!growing && item.base_size < item.hypothetical_main);
+    }
+
+    let free_space = |items: &[FlexItem]| {
+        available
+            - items
+                .iter()
+                .map(|item| {
+                    let size = if item.frozen {
+                        item.main
+                    } else {
+                        item.base_size
+                    };
+                    size + item.main_extra()
+                })
+                .sum::<f32>()
+    };
+    let initial_free = free_space(items);
+    let mut violations = vec![0.0; items.len()];
+    while items.iter().any(|item| !item.frozen) {
+        let mut free = free_space(items);
+        let factors: f32 = items
+            .iter()
+            .filter(|item| !item.frozen)
+            .map(|item| if growing { item.grow } else { item.shrink })
+            .sum();
+        // Factors adding up to less than one only take their share of the free space.
+        if factors < 1.0 && (initial_free * factors).abs() < free.abs() {
+            free = initial_free * factors;
+        }
+
+        let scaled_shrink: f32 = items
+            .iter()
+            .filter(|item| !item.frozen)
+            .map(|item| item.shrink * item.base_size)
+            .sum();
+        for item in items.iter_mut().filter(|item| !item.frozen) {
+            item.main = if growing && factors > 0.0 {
+                item.base_size + free * item.grow / factors
+            } else if !growing && scaled_shrink > 0.0 {
+                item.base_size + free * item.shrink * item.base_size / scaled_shrink
+            } else {
+                item.base_size
+            };
+        }
+
+        let mut total_violation = 0.0;
+        for (item, violation) in items.iter_mut().zip(violations.iter_mut()) {
+            if item.frozen {
+                continue;
+            }
+            let clamped = item.main.min(item.max_main).max(item.min_main).max(0.0);
+            *violation = clamped - item.main;
+            total_violation += *violation;
+            item.main = clamped;
+        }
+        for (item, &violation) in items.iter_mut().zip(violations.iter()) {
+            item.frozen |= total_violation == 0.0
+                || (total_violation > 0.0 && violation > 0.0)
+                || (total_violation < 0.0 && violation < 0.0);
+        }
+    }
+}
+
+/// The offset of the first box and the extra space between boxes when `free` space is
+/// distributed among `count` boxes. Negative free space overflows from the start, or from
+/// both sides for the centering distributions.
+pub(crate) fn distribute(distribution: ContentDistribution, free: f32, count: usize) -> (f32, f32) {
+    match distribution {
+        ContentDistribution::Start | ContentDistribution::Stretch => (0.0, 0.0),
+        ContentDistribution::End => (free, 0.0),
+        ContentDistribution::Center => (free / 2.0, 0.0),
+        ContentDistribution::SpaceBetween if free > 0.0 && count > 1 => {
+            (0.0, free / (count - 1) as f32)
+        }
+        ContentDistribution::SpaceBetween => (0.0, 0.0),
+        ContentDistribution::SpaceAround if free > 0.0 => {
+            let space = free / count as f32;
+            (space / 2.0, space)
+        }
+        ContentDistribution::SpaceEvenly if free > 0.0 => {
+            let space = free / (count + 1) as f32;
+            (space, space)
+        }
+        ContentDistribution::SpaceAround | ContentDistribution::SpaceEvenly => (free / 2.0, 0.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layout::testing::{find, layout_html};
+    use crate::layout::Rect;
+
+    #[test]
+    fn test_flex_layout() {
+        let root = layout_html(
+            "<div>\
+             <div class=\"row\"><div class=\"fixed\"></div><div class=\"grow\"></div><div class=\"grow2\"></div></div>\
+             <div class=\"center\"><div class=\"box\"></div></div>\
+             <div class=\"column\"><div class=\"short\"></div><div class=\"tall\"></div></div>\
+             <div class=\"wrap\"><div class=\"w1\"></div><div class=\"w2\"></div><div class=\"w3 first\"></div></div>\
+             <div class=\"auto\"><div class=\"push\"></div></div>\
+             </div>",
+            ".row { display: flex; height: 50px; gap: 10px; }
+             .fixed { width: 100px; }
+             .grow { flex: 1; }
+             .grow2 { flex: 2; }
+             .center { display: flex; justify-content: center; align-items: center; height: 100px; }
+             .box { width: 50px; height: 20px; }
+             .column { display: flex; flex-direction: column-reverse; }
+             .short { height: 10px; }
+             .tall { height: 20px; }
+             .wrap { display: flex; flex-wrap: wrap; width: 250px; }
+             .w1, .w2, .w3 { width: 100px; height: 30px; }
+             .first { order: -1; height: 40px; }
+             .auto { display: flex; }
+             .push { margin-left: auto; width: 50px; height: 10px; }",
+            Rect::new(0.0, 0.0, 400.0, 600.0),
+        );
+        let rect = |class: &str| find(&root, class).unwrap().dimensions.content;
+        let near = |a: f32, b: f32| (a - b).abs() < 0.01;
+
+        // Free space goes to the items by their grow factors, and items stretch to the line.
+        let (fixed, grow, grow2) = (rect("fixed"), rect("grow"), rect("grow2"));
+        assert_eq!((fixed.x, fixed.width, fixed.height), (0.0, 100.0, 50.0));
+        assert!(near(grow.width, 280.0 / 3.0));
+        assert!(near(grow2.width, 2.0 * 280.0 / 3.0));
+        assert!(near(grow.x, 110.0));
+        assert!(near(grow2.x, 120.0 + 280.0 / 3.0));
+
+        let center = rect("center");
+        let item = rect("box");
+        assert_eq!((item.x, item.y - center.y), (175.0, 40.0));
+
+        let column = rect("column");
+        assert_eq!(column.height, 30.0);
+        assert_eq!(rect("short").y - column.y, 20.0);
+        assert_eq!(rect("tall").y, column.y);
+
+        // The reordered item comes first, and the last one wraps onto a second line.
+        let wrap = rect("wrap");
+        assert_eq!(rect("first").x, 0.0);
+        assert_eq!(rect("w1").x, 100.0);
+        assert_eq!((rect("w2").x, rect("w2").y - wrap.y), (0.0, 40.0));
+        assert_eq!(wrap.height, 70.0);
+
+        assert_eq!(rect("push").x, 350.0);
+    }
+}
@@ -0,0 +1,227 @@
+use super::{BoxType, Dimensions, LayoutBox, Rect};
+use crate::style::{Clear, Float};
+
+/// The margin box of a float placed in a block formatting context.
+struct Exclusion {
+    side: Float,
+    rect: Rect,
+}
+
+/// The floats placed so far in a block formatting context, which its line boxes and the
+/// formatting context roots that follow them must not overlap. Positions are absolute.
+#[derive(Default)]
+pub struct FloatContext {
+    floats: Vec<Exclusion>,
+}
+
+impl FloatContext {
+    pub(crate) fn len(&self) -> usize {
+        self.floats.len()
+    }
+
+    /// Moves the floats placed after the first `start` ones, when the box holding them is
+    /// moved after its layout.
+    pub(crate) fn translate_from(&mut self, start: usize, dy: f32) {
+        for float in &mut self.floats[start..] {
+            float.rect.y += dy;
+        }
+    }
+
+    /// The left and right edges of the space the floats leave between `left` and `right`,
+    /// over the band `height` tall starting at `y`.
+    pub(crate) fn available(&self, y: f32, height: f32, left: f32, right: f32) -> (f32, f32) {
+        let mut edges = (left, right);
+        for float in &self.floats {
+            let rect = float.rect;
+            let overlaps = if height > 0.0 {
+                rect.y < y + height && y < rect.y + rect.height
+            } else {
+                rect.y <= y && y < rect.y + rect.height
+            };
+            if !overlaps {
+                continue;
+            }
+            match float.side {
+                Float::Left => edges.0 = edges.0.max(rect.x + rect.width),
+                Float::Right => edges.1 = edges.1.min(rect.x),
+                Float::None => {}
+            }
+        }
+        edges
+    }
+
+    /// The highest bottom edge of a float below `y`, where more space may free up.
+    pub(crate) fn next_bottom(&self, y: f32) -> Option<f32> {
+        self.floats
+            .iter()
+            .map(|float| float.rect.y + float.rect.height)
+            .filter(|&bottom| bottom > y)
+            .fold(None, |next, bottom| {
+                Some(next.map_or(bottom, |n: f32| n.min(bottom)))
+            })
+    }
+
+    /// The bottom edge of the floats a box with `clear` must be placed below.
+    pub(crate) fn clearance(&self, clear: Clear) -> Option<f32> {
+        self.floats
+            .iter()
+            .filter(|float| match clear {
+                Clear::None => false,
+                Clear::Left => float.side == Float::Left,
+                Clear::Right => float.side == Float::Right,
+                Clear::Both => true,
+            })
+            .map(|float| float.rect.y + float.rect.height)
+            .fold(None, |lowest, bottom| {
+                Some(lowest.map_or(bottom, |l: f32| l.max(bottom)))
+            })
+    }
+
+    /// The bottom edge of the lowest float, which the root of the formatting context grows
+    /// to contain.
+    pub(crate) fn bottom(&self) -> Option<f32> {
+        self.clearance(Clear::Both)
+    }
+
+    /// Places a margin box `width` by `height` on its `side`, as high as possible from `y`
+    /// and then as far to that side as possible, following CSS 2.1 §9.5.1. Returns the
+    /// position of its top left corner.
+    fn place(
+        &mut self,
+        side: Float,
+        width: f32,
+        height: f32,
+        y: f32,
+        left: f32,
+        right: f32,
+    ) -> (f32, f32) {
+        // A float isn't placed higher than the floats before it.
+        let mut y = self.floats.last().map_or(y, |last| y.max(last.rect.y));
+        let (x, y) = loop {
+            let (start, end) = self.available(y, height, left, right);
+            let unobstructed = start <= left && end >= right;
+            if end - start >= width || unobstructed {
+                match side {
+                    Float::Right => break (end - width, y),
+                    _ => break (start, y),
+                }
+            }
+            match self.next_bottom(y) {
+                Some(bottom) => y = bottom,
+                None => break (start, y),
+            }
+        };
+        self.floats.push(Exclusion {
+            side,
+            rect: Rect::new(x, y, width, height),
+        });
+        (x, y)
+    }
+}
+
+impl<'a> LayoutBox<'a> {
+    /// Whether the box floats: it's taken out of the flow, and the line boxes next to it are
+    /// shortened around it.
+    pub(crate) fn is_float(&self) -> bool {
+        match self.box_type {
+            BoxType::BlockNode(style) => style.float() != Float::None && !self.is_out_of_flow(),
+            _ => false,
+        }
+    }
+
+    /// Lays out a float at the top left of its containing block. Its contents establish a
+    /// block formatting context, and an auto width shrinks to fit.
+    pub(crate) fn layout_float(&mut self, containing_block: Dimensions, cb_height: Option<f32>) {
+        let mut containing_block = containing_block;
+        containing_block.content.height = 0.0;
+        self.layout_block(containing_block, cb_height);
+    }
+
+    /// Moves a laid out float to the first place it fits within `containing_block`, no
+    /// higher than `y` and below the floats it clears, and records it in `floats`.
+    pub(crate) fn place_float(
+        &mut self,
+        y: f32,
+        containing_block: Rect,
+        floats: &mut FloatContext,
+    ) {
+        let style = self.get_style_node();
+        let y = match floats.clearance(style.clear()) {
+            Some(clearance) => y.max(clearance),
+            None => y,
+        };
+        // A relative offset moves the float from where it's placed, and doesn't move its
+        // exclusion.
+        let (dx, dy) = self.relative_offset(containing_block.width, None);
+        let margin_box = self.dimensions.margin_box();
+        let (x, y) = floats.place(
+            style.float(),
+            margin_box.width,
+            margin_box.height,
+            y,
+            containing_block.x,
+            containing_block.x + containing_block.width,
+        );
+        self.translate(x + dx - margin_box.x, y + dy - margin_box.y);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layout::testing::{find, layout_html};
+    use crate::layout::{BoxType, LayoutBox, Rect};
+
+    fn line_boxes<'b>(layout_box: &'b LayoutBox<'b>) -> Vec<Rect> {
+        layout_box
+            .children
+            .iter()
+            .flat_map(|child| child.children.iter())
+            .filter(|line| matches!(line.box_type, BoxType::LineBox { .. }))
+            .map(|line| line.dimensions.content)
+            .collect()
+    }
+
+    #[test]
+    fn test_floats() {
+        let root = layout_html(
+            &format!(
+                "<div>\
+             <div class=\"left\"></div>\
+             <div class=\"right\"></div>\
+             <p class=\"short\">text</p>\
+             <div class=\"cleared\"></div>\
+             <p class=\"long\">{}</p>\
+             <div class=\"root\"><span class=\"inner\">float</span></div>\
+             </div>",
+                "word ".repeat(60)
+            ),
+            ".left { float: left; width: 100px; height: 70px; }
+             .right { float: right; width: 50px; height: 120px; }
+             .cleared { clear: left; height: 10px; }
+             .root { display: flow-root; }
+             .inner { float: right; height: 30px; }",
+            Rect::new(0.0, 0.0, 400.0, 600.0),
+        );
+        let rect = |class: &str| find(&root, class).unwrap().dimensions.border_box();
+
+        assert_eq!((rect("left").x, rect("left").y), (0.0, 0.0));
+        assert_eq!((rect("right").x, rect("right").y), (350.0, 0.0));
+
+        // Lines are shortened around the floats beside them.
+        let short = line_boxes(find(&root, "short").unwrap());
+        assert_eq!((short[0].x, short[0].width), (100.0, 250.0));
+
+        // Clearance puts the box below the left float only.
+        assert_eq!(rect("cleared").y, 70.0);
+        let long = line_boxes(find(&root, "long").unwrap());
+        assert_eq!((long[0].x, long[0].width), (0.0, 350.0));
+        let last = long.last().unwrap();
+        assert_eq!((last.x, last.width), (0.0, 400.0));
+
+        // A formatting context root contains its floats, which shrink to fit their contents.
+        let inner = rect("inner");
+        assert!(inner.width > 0.0 && inner.width < 400.0);
+        assert_eq!(inner.x + inner.width, 400.0);
+        assert!((rect("root").height - 30.0).abs() < 0.01);
+    }
+}
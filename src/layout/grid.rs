@@ -0,0 +1,1130 @@
+use super::flex::{distribute, AxisMargins};
+use super::intrinsic::IntrinsicSizes;
+use super::{BoxType, Keyword, LayoutBox, Length, Position};
+use crate::css::Value;
+use crate::style::{ContentDistribution, Display, GridAutoFlow, ItemAlignment, StyledNode};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// The most tracks a grid has along an axis, and the furthest line an item can be placed
+/// on. Larger repetitions and line numbers are clamped, as browsers do, so that a stray
+/// `repeat(100000000, 1px)` doesn't lay out a track for each.
+const MAX_TRACKS: usize = 10000;
+
+/// One end of a track sizing function.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Breadth {
+    Fixed(f32),
+    /// A share of the free space, in `fr`. Only valid as a maximum.
+    Flex(f32),
+    Auto,
+    MinContent,
+    MaxContent,
+    /// As large as the contents, up to a limit. Only valid as a maximum.
+    FitContent(f32),
+}
+
+/// The sizing function of a track, `minmax(min, max)`.
+#[derive(Debug, Clone, Copy)]
+struct TrackSize {
+    min: Breadth,
+    max: Breadth,
+}
+
+/// A row or column while its size is resolved.
+#[derive(Debug, Clone, Copy)]
+struct Track {
+    size: TrackSize,
+    base: f32,
+    /// The growth limit, infinite until the contents give one.
+    limit: f32,
+    /// Set for the tracks of `repeat(auto-fit, ...)`, which collapse when they are empty.
+    collapsible: bool,
+}
+
+/// A grid item and the area it occupies, as ranges of track indices.
+struct GridItem {
+    /// Index of the item among the container's children.
+    index: usize,
+    rows: Range<usize>,
+    columns: Range<usize>,
+}
+
+/// The value of a placement property like `grid-row-start`.
+enum GridLine {
+    Auto,
+    /// A line number, counted from the end of the explicit grid when negative.
+    Line(i32),
+    Span(usize),
+    /// A named area, or one of its `-start` and `-end` lines.
+    Name(String),
+}
+
+/// The named areas of `grid-template-areas`, with their rows and columns.
+type Areas = HashMap<String, [Range<usize>; 2]>;
+
+/// Where an item goes along one axis: its start track when definite, and the number of
+/// tracks it spans.
+type Placement = (Option<usize>, usize);
+
+impl Track {
+    fn new(size: TrackSize, collapsible: bool) -> Self {
+        Track {
+            size,
+            base: 0.0,
+            limit: f32::INFINITY,
+            collapsible,
+        }
+    }
+
+    fn is_flexible(&self) -> bool {
+        matches!(self.size.max, Breadth::Flex(_))
+    }
+}
+
+impl Breadth {
+    fn is_intrinsic(self) -> bool {
+        matches!(
+            self,
+            Breadth::Auto | Breadth::MinContent | Breadth::MaxContent | Breadth::FitContent(_)
+        )
+    }
+}
+
+impl<'a> LayoutBox<'a> {
+    pub(crate) fn is_grid_container(&self) -> bool {
+        match self.box_type {
+            BoxType::BlockNode(style) => {
+                matches!(style.display(), Display::Grid | Display::InlineGrid)
+            }
+            _ => false,
+        }
+    }
+
+    /// Lays out the children of a grid container following CSS Grid §8 to §11, once its
+    /// width and position are known, and sets its content height. `height` is that height
+    /// when it doesn't depend on the items. Baseline alignment falls back to `start`.
+    pub(crate) fn layout_grid_items(&mut self, height: Option<f32>) {
+        let style = self.get_style_node();
+        let content = self.dimensions.content;
+        let width = content.width;
+        let column_gap = grid_gap(style, "column-gap", Some(width));
+        let row_gap = grid_gap(style, "row-gap", height);
+
+        // Out-of-flow children are positioned from the start of the content box.
+        for child in &mut self.children {
+            if child.is_out_of_flow() {
+                child.dimensions.content.x = content.x;
+                child.dimensions.content.y = content.y;
+            }
+        }
+
+        let (items, mut columns, mut rows) = self.place_grid_items(Some(width), height);
+
+        let justify_content = style.justify_content();
+        let contributions: Vec<_> = items
+            .iter()
+            .map(|item| {
+                let sizes = self.children[item.index].intrinsic_sizes();
+                (item.columns.clone(), sizes)
+            })
+            .collect();
+        size_tracks(
+            &mut columns,
+            &contributions,
+            Some(width),
+            column_gap,
+            justify_content == ContentDistribution::Stretch,
+        );
+        let column_positions = track_positions(&columns, Some(width), column_gap, justify_content);
+
+        // With the columns known, the heights of the items at their widths size the rows.
+        let justify_items = style.justify_items();
+        let align_items = style.align_items();
+        let contributions: Vec<_> = items
+            .iter()
+            .map(|item| {
+                let area_width = area_size(&columns, &column_positions, &item.columns);
+                let child = &mut self.children[item.index];
+                let (_, height, _) =
+                    child.fit_grid_item(area_width, None, justify_items, align_items);
+                let d = &child.dimensions;
+                let outer = height
+                    + d.margin.top
+                    + d.border.top
+                    + d.padding.top
+                    + d.padding.bottom
+                    + d.border.bottom
+                    + d.margin.bottom;
+                (item.rows.clone(), IntrinsicSizes::fixed(outer))
+            })
+            .collect();
+        let align_content = style.align_content();
+        size_tracks(
+            &mut rows,
+            &contributions,
+            height,
+            row_gap,
+            align_content == ContentDistribution::Stretch,
+        );
+        let row_positions = track_positions(&rows, height, row_gap, align_content);
+
+        for item in &items {
+            let area_width = area_size(&columns, &column_positions, &item.columns);
+            let area_height = area_size(&rows, &row_positions, &item.rows);
+            let child = &mut self.children[item.index];
+            let (item_width, item_height, offset) =
+                child.fit_grid_item(area_width, Some(area_height), justify_items, align_items);
+            let d = &child.dimensions;
+            let x = content.x
+                + column_positions[item.columns.start]
+                + offset.x
+                + d.margin.left
+                + d.border.left
+                + d.padding.left;
+            let y = content.y
+                + row_positions[item.rows.start]
+                + offset.y
+                + d.margin.top
+                + d.border.top
+                + d.padding.top;
+            child.layout_item(
+                Position::new(x, y),
+                item_width,
+                Some(item_height),
+                area_width,
+                Some(area_height),
+            );
+        }
+
+        self.dimensions.content.height = match height {
+            Some(height) => height,
+            None => {
+                let gaps = row_gap * rows.len().saturating_sub(1) as f32;
+                rows.iter().map(|row| row.base).sum::<f32>() + gaps
+            }
+        };
+    }
+
+    /// The intrinsic widths of a grid container: its columns sized to the min-content and
+    /// max-content contributions of its items.
+    pub(crate) fn grid_intrinsic_sizes(&self) -> IntrinsicSizes {
+        let style = self.get_style_node();
+        let gap = grid_gap(style, "column-gap", None);
+        let (items, mut columns, _) = self.place_grid_items(None, None);
+        let contributions: Vec<_> = items
+            .iter()
+            .map(|item| {
+                let sizes = self.children[item.index].intrinsic_sizes();
+                (item.columns.clone(), sizes)
+            })
+            .collect();
+
+        let mut min_columns = columns.clone();
+        size_tracks(&mut min_columns, &contributions, Some(0.0), gap, false);
+        size_tracks(&mut columns, &contributions, None, gap, false);
+        let gaps = gap * columns.len().saturating_sub(1) as f32;
+        let sum = |tracks: &[Track]| tracks.iter().map(|track| track.base).sum::<f32>() + gaps;
+        IntrinsicSizes {
+            min_content: sum(&min_columns),
+            max_content: sum(&columns),
+        }
+    }
+
+    /// Places the in-flow items of a grid container in a container `width` by `height`,
+    /// when known, following CSS Grid §8.5. Returns the items, in `order`, with the columns
+    /// and rows of the grid.
+    fn place_grid_items(
+        &self,
+        width: Option<f32>,
+        height: Option<f32>,
+    ) -> (Vec<GridItem>, Vec<Track>, Vec<Track>) {
+        let style = self.get_style_node();
+        let (areas, area_rows, area_columns) = template_areas(style);
+        let template_rows = track_list(
+            style.value("grid-template-rows"),
+            height,
+            grid_gap(style, "row-gap", height),
+        );
+        let template_columns = track_list(
+            style.value("grid-template-columns"),
+            width,
+            grid_gap(style, "column-gap", width),
+        );
+        let explicit = [
+            template_rows.len().max(area_rows),
+            template_columns.len().max(area_columns),
+        ];
+
+        let mut indices: Vec<usize> = (0..self.children.len())
+            .filter(|&index| !self.children[index].is_out_of_flow())
+            .collect();
+        indices.sort_by_key(|&index| self.children[index].order());
+        let placements: Vec<[Placement; 2]> = indices
+            .iter()
+            .map(|&index| match self.children[index].box_type {
+                BoxType::BlockNode(style) => [
+                    resolve_placement(style, "grid-row", 0, &areas, explicit[0]),
+                    resolve_placement(style, "grid-column", 1, &areas, explicit[1]),
+                ],
+                _ => [(None, 1), (None, 1)],
+            })
+            .collect();
+
+        // Items fill the tracks across the flow, and new tracks are added along it.
+        let (flow, dense) = style.grid_auto_flow();
+        let (along, across) = match flow {
+            GridAutoFlow::Row => (0, 1),
+            GridAutoFlow::Column => (1, 0),
+        };
+        let across_count = placements
+            .iter()
+            .map(|placement| match placement[across] {
+                (Some(start), span) => start + span,
+                (None, span) => span,
+            })
+            .fold(explicit[across], usize::max);
+        let mut grid = Occupancy::default();
+        let mut placed: Vec<Option<[usize; 2]>> = vec![None; placements.len()];
+
+        // Items with a definite position along both axes go first.
+        for (i, placement) in placements.iter().enumerate() {
+            if let (Some(a), Some(c)) = (placement[along].0, placement[across].0) {
+                grid.mark(a, placement[along].1, c, placement[across].1);
+                placed[i] = Some([a, c]);
+            }
+        }
+
+        // Then those with a definite position along the flow, in the first gap left in it.
+        let mut cursors: HashMap<usize, usize> = HashMap::new();
+        for (i, placement) in placements.iter().enumerate() {
+            if let (Some(a), None) = (placement[along].0, placement[across].0) {
+                let (span_a, span_c) = (placement[along].1, placement[across].1);
+                let start = if dense {
+                    0
+                } else {
+                    cursors.get(&a).copied().unwrap_or(0)
+                };
+                let c = (start..)
+                    .take_while(|c| c + span_c <= across_count)
+                    .find(|&c| grid.fits(a, span_a, c, span_c))
+                    .unwrap_or(start);
+                grid.mark(a, span_a, c, span_c);
+                cursors.insert(a, c + span_c);
+                placed[i] = Some([a, c]);
+            }
+        }
+
+        // The rest follow an auto-placement cursor.
+        let (mut cursor_a, mut cursor_c) = (0, 0);
+        for (i, placement) in placements.iter().enumerate() {
+            if placed[i].is_some() {
+                continue;
+            }
+            if dense {
+                cursor_a = 0;
+                cursor_c = 0;
+            }
+            let (span_a, span_c) = (placement[along].1, placement[across].1);
+            match placement[across].0 {
+                Some(c) => {
+                    if c < cursor_c {
+                        cursor_a += 1;
+                    }
+                    cursor_c = c;
+                    while !grid.fits(cursor_a, span_a, c, span_c) {
+                        cursor_a += 1;
+                    }
+                }
+                None => loop {
+                    if cursor_c + span_c > across_count {
+                        cursor_a += 1;
+                        cursor_c = 0;
+                    } else if grid.fits(cursor_a, span_a, cursor_c, span_c) {
+                        break;
+                    } else {
+                        cursor_c += 1;
+                    }
+                },
+            }
+            grid.mark(cursor_a, span_a, cursor_c, span_c);
+            placed[i] = Some([cursor_a, cursor_c]);
+        }
+
+        let mut items: Vec<GridItem> = indices
+            .into_iter()
+            .zip(placements)
+            .zip(placed)
+            .map(|((index, placement), start)| {
+                let start = start.unwrap();
+                let mut ranges = [0..0, 0..0];
+                ranges[along] = start[0]..start[0] + placement[along].1;
+                ranges[across] = start[1]..start[1] + placement[across].1;
+                let [rows, columns] = ranges;
+                GridItem {
+                    index,
+                    rows,
+                    columns,
+                }
+            })
+            .collect();
+
+        // Tracks past the explicit grid are sized by `grid-auto-rows` and `grid-auto-columns`.
+        let extend = |template: Vec<Track>, count: usize, name: &str, base: Option<f32>| {
+            let auto_sizes = implicit_track_sizes(style.value(name), base);
+            let explicit = template.len();
+            let mut tracks = template;
+            while tracks.len() < count {
+                let size = auto_sizes[(tracks.len() - explicit) % auto_sizes.len()];
+                tracks.push(Track::new(size, false));
+            }
+            tracks
+        };
+        let row_count = items
+            .iter()
+            .map(|item| item.rows.end)
+            .fold(explicit[0], usize::max);
+        let column_count = items
+            .iter()
+            .map(|item| item.columns.end)
+            .fold(explicit[1], usize::max);
+        let mut rows = extend(template_rows, row_count, "grid-auto-rows", height);
+        let mut columns = extend(template_columns, column_count, "grid-auto-columns", width);
+
+        collapse_empty_tracks(
+            &mut rows,
+            items.iter_mut().map(|item| &mut item.rows).collect(),
+        );
+        collapse_empty_tracks(
+            &mut columns,
+            items.iter_mut().map(|item| &mut item.columns).collect(),
+        );
+        (items, columns, rows)
+    }
+
+    /// Sizes a grid item in an area `area_width` wide and `area_height` high, when known, and
+    /// sets its edges. Returns its content width and height, and the offset of its margin box
+    /// in the area given by its alignment.
+    fn fit_grid_item(
+        &mut self,
+        area_width: f32,
+        area_height: Option<f32>,
+        justify_items: ItemAlignment,
+        align_items: ItemAlignment,
+    ) -> (f32, f32, Position) {
+        let (horizontal, vertical) = self.set_item_edges(area_width);
+        let style = match self.box_type {
+            BoxType::BlockNode(style) => Some(style),
+            _ => None,
+        };
+        let justify = style
+            .and_then(StyledNode::justify_self)
+            .unwrap_or(justify_items);
+        let align = style
+            .and_then(StyledNode::align_self)
+            .unwrap_or(align_items);
+        let d = &self.dimensions;
+        let horizontal_edges = d.border.left + d.padding.left + d.border.right + d.padding.right;
+        let vertical_edges = d.border.top + d.padding.top + d.border.bottom + d.padding.bottom;
+
+        let available = area_width
+            - horizontal.0.unwrap_or(0.0)
+            - horizontal.1.unwrap_or(0.0)
+            - horizontal_edges;
+        let min_width = self
+            .item_size("min-width", Some(area_width), horizontal_edges)
+            .unwrap_or(0.0);
+        let max_width = self
+            .item_size("max-width", Some(area_width), horizontal_edges)
+            .unwrap_or(f32::INFINITY);
+        let width = match self.item_size("width", Some(area_width), horizontal_edges) {
+            Some(width) => width,
+            None if stretches(justify, horizontal) => available,
+            None => self.content_intrinsic_sizes().shrink_to_fit(available),
+        }
+        .min(max_width)
+        .max(min_width)
+        .max(0.0);
+
+        let height_size = |name: &str| self.item_size(name, area_height, vertical_edges);
+        let min_height = height_size("min-height").unwrap_or(0.0);
+        let max_height = height_size("max-height").unwrap_or(f32::INFINITY);
+        let stretched = area_height
+            .filter(|_| stretches(align, vertical))
+            .map(|height| {
+                height - vertical.0.unwrap_or(0.0) - vertical.1.unwrap_or(0.0) - vertical_edges
+            });
+        let height = match (height_size("height"), stretched) {
+            (Some(height), _) | (None, Some(height)) => height,
+            (None, None) => self.measure_item(width, area_height).0,
+        }
+        .min(max_height)
+        .max(min_height)
+        .max(0.0);
+
+        let (left, right, dx) = align_in_area(available - width, horizontal, justify);
+        let (top, bottom, dy) = match area_height {
+            Some(area_height) => {
+                let free = area_height
+                    - vertical.0.unwrap_or(0.0)
+                    - vertical.1.unwrap_or(0.0)
+                    - vertical_edges
+                    - height;
+                align_in_area(free, vertical, align)
+            }
+            None => (vertical.0.unwrap_or(0.0), vertical.1.unwrap_or(0.0), 0.0),
+        };
+        let d = &mut self.dimensions;
+        d.margin.left = left;
+        d.margin.right = right;
+        d.margin.top = top;
+        d.margin.bottom = bottom;
+        (width, height, Position::new(dx, dy))
+    }
+}
+
+/// The cells of the grid taken by the items placed so far, indexed along the flow, then
+/// across it.
+#[derive(Default)]
+struct Occupancy {
+    cells: Vec<Vec<bool>>,
+}
+
+impl Occupancy {
+    fn fits(&self, along: usize, span_along: usize, across: usize, span_across: usize) -> bool {
+        (along..along + span_along).all(|a| match self.cells.get(a) {
+            Some(line) => (across..across + span_across).all(|c| line.get(c) != Some(&true)),
+            None => true,
+        })
+    }
+
+    fn mark(&mut self, along: usize, span_along: usize, across: usize, span_across: usize) {
+        if self.cells.len() < along + span_along {
+            self.cells.resize(along + span_along, Vec::new());
+        }
+        for line in &mut self.cells[along..along + span_along] {
+            if line.len() < across + span_across {
+                line.resize(across + span_across, false);
+            }
+            for cell in &mut line[across..across + span_across] {
+                *cell = true;
+            }
+        }
+    }
+}
+
+/// Whether an item with its `margins` along an axis, `None` when auto, stretches to fill
+/// its area when its size is auto.
+fn stretches(alignment: ItemAlignment, margins: AxisMargins) -> bool {
+    alignment == ItemAlignment::Stretch && margins.0.is_some() && margins.1.is_some()
+}
+
+/// The used start and end margins of an item along an axis, and the offset of its margin
+/// box in its area, when `free` space is left beside its margin box. Auto margins take up
+/// the free space before the alignment applies.
+fn align_in_area(free: f32, margins: AxisMargins, alignment: ItemAlignment) -> (f32, f32, f32) {
+    match margins {
+        (None, None) => {
+            let margin = free.max(0.0) / 2.0;
+            (margin, margin, 0.0)
+        }
+        (None, Some(end)) => (free.max(0.0), end, 0.0),
+        (Some(start), None) => (start, free.max(0.0), 0.0),
+        (Some(start), Some(end)) => (
+            start,
+            end,
+            match alignment {
+                ItemAlignment::End => free,
+                ItemAlignment::Center => free / 2.0,
+                _ => 0.0,
+            },
+        ),
+    }
+}
+
+/// The `row-gap` or `column-gap` of a grid container, whose percentages refer to `base`.
+fn grid_gap(style: &StyledNode, name: &str, base: Option<f32>) -> f32 {
+    match style.value(name) {
+        Some(Keyword(_)) | None => 0.0,
+        Some(value @ Length(..)) => value.to_px(),
+        Some(value) => base.map_or(0.0, |base| value.resolve(base)),
+    }
+}
+
+/// The components of a value that may be a list, none for `none`.
+fn components(value: Option<Value>) -> Vec<Value> {
+    match value {
+        Some(Value::List(values)) => values,
+        Some(Keyword(ref keyword)) if keyword == "none" => Vec::new(),
+        Some(value) => vec![value],
+        None => Vec::new(),
+    }
+}
+
+/// Reads one end of a track sizing function, with percentages of `base`.
+fn breadth(value: &Value, base: Option<f32>) -> Breadth {
+    match *value {
+        Value::Flex(f) => Breadth::Flex(f),
+        Length(..) => Breadth::Fixed(value.to_px()),
+        Value::Percentage(_) | Value::Calc(_) => {
+            base.map_or(Breadth::Auto, |base| Breadth::Fixed(value.resolve(base)))
+        }
+        Keyword(ref keyword) if keyword == "min-content" => Breadth::MinContent,
+        Keyword(ref keyword) if keyword == "max-content" => Breadth::MaxContent,
+        _ => Breadth::Auto,
+    }
+}
+
+/// Reads a track size: a breadth, `minmax()` or `fit-content()`. A flexible breadth on its
+/// own has an automatic minimum.
+fn track_size(value: &Value, base: Option<f32>) -> TrackSize {
+    match *value {
+        Value::Function(ref name, ref args) if name == "minmax" && args.len() == 2 => TrackSize {
+            min: match breadth(&args[0], base) {
+                Breadth::Flex(_) => Breadth::Auto,
+                min => min,
+            },
+            max: breadth(&args[1], base),
+        },
+        Value::Function(ref name, ref args) if name == "fit-content" && args.len() == 1 => {
+            let limit = match breadth(&args[0], base) {
+                Breadth::Fixed(limit) => limit,
+                _ => f32::INFINITY,
+            };
+            TrackSize {
+                min: Breadth::Auto,
+                max: Breadth::FitContent(limit),
+            }
+        }
+        _ => match breadth(value, base) {
+            Breadth::Flex(f) => TrackSize {
+                min: Breadth::Auto,
+                max: Breadth::Flex(f),
+            },
+            breadth => TrackSize {
+                min: breadth,
+                max: breadth,
+            },
+        },
+    }
+}
+
+/// The size a track takes for sure, used to count how many fit in a repetition.
+fn fixed_size(size: &TrackSize) -> Option<f32> {
+    match (size.min, size.max) {
+        (_, Breadth::Fixed(max)) => Some(max),
+        (Breadth::Fixed(min), _) => Some(min),
+        _ => None,
+    }
+}
+
+/// The explicit tracks of a `grid-template-rows` or `grid-template-columns` value along an
+/// axis `available` long, with percentages of it. `repeat(auto-fill, ...)` and
+/// `repeat(auto-fit, ...)` repeat their tracks as many times as fit.
+fn track_list(value: Option<Value>, available: Option<f32>, gap: f32) -> Vec<Track> {
+    let components = components(value);
+    let mut tracks = Vec::new();
+    for component in &components {
+        match *component {
+            Value::Function(ref name, ref args) if name == "repeat" && args.len() == 2 => {
+                let repeated: Vec<TrackSize> = components_of(&args[1])
+                    .iter()
+                    .map(|value| track_size(value, available))
+                    .collect();
+                let (count, collapsible) = match args[0] {
+                    Value::Number(n) => (n.max(1.0) as usize, false),
+                    Keyword(ref keyword) => (
+                        auto_repetitions(&components, &repeated, available, gap),
+                        keyword == "auto-fit",
+                    ),
+                    _ => (1, false),
+                };
+                for _ in 0..count.min(MAX_TRACKS) {
+                    if tracks.len() + repeated.len() > MAX_TRACKS {
+                        break;
+                    }
+                    for size in &repeated {
+                        tracks.push(Track::new(*size, collapsible));
+                    }
+                }
+            }
+            ref value => tracks.push(Track::new(track_size(value, available), false)),
+        }
+    }
+    tracks
+}
+
+fn components_of(value: &Value) -> Vec<Value> {
+    components(Some(value.clone()))
+}
+
+/// How many times the `repeated` tracks of an automatic repetition fit beside the other
+/// tracks of the list, at least once.
+fn auto_repetitions(
+    components: &[Value],
+    repeated: &[TrackSize],
+    available: Option<f32>,
+    gap: f32,
+) -> usize {
+    let available = match available {
+        Some(available) => available,
+        None => return 1,
+    };
+    let others: f32 = components
+        .iter()
+        .filter(|value| !matches!(value, Value::Function(ref name, _) if name == "repeat"))
+        .map(|value| fixed_size(&track_size(value, Some(available))).unwrap_or(0.0) + gap)
+        .sum();
+    let repetition: f32 = repeated
+        .iter()
+        .map(|size| fixed_size(size).unwrap_or(0.0) + gap)
+        .sum();
+    if repetition <= gap * repeated.len() as f32 {
+        return 1;
+    }
+    (((available - others + gap) / repetition).floor() as usize).max(1)
+}
+
+/// The sizes of the implicit tracks, cycled through from the end of the explicit grid.
+fn implicit_track_sizes(value: Option<Value>, base: Option<f32>) -> Vec<TrackSize> {
+    let sizes: Vec<TrackSize> = components(value)
+        .iter()
+        .map(|value| track_size(value, base))
+        .collect();
+    if sizes.is_empty() {
+        vec![TrackSize {
+            min: Breadth::Auto,
+            max: Breadth::Auto,
+        }]
+    } else {
+        sizes
+    }
+}
+
+/// Removes the empty tracks of `repeat(auto-fit, ...)`, and renumbers the `ranges` of tracks
+/// the items occupy.
+fn collapse_empty_tracks(tracks: &mut Vec<Track>, ranges: Vec<&mut Range<usize>>) {
+    let keep: Vec<bool> = (0..tracks.len())
+        .map(|i| !tracks[i].collapsible || ranges.iter().any(|range| range.contains(&i)))
+        .collect();
+    let mut new_index = vec![0];
+    for &kept in &keep {
+        new_index.push(new_index.last().unwrap() + kept as usize);
+    }
+    for range in ranges {
+        *range = new_index[range.start]..new_index[range.end];
+    }
+    let mut keep = keep.into_iter();
+    tracks.retain(|_| keep.next().unwrap());
+}
+
+/// The grid areas named in `grid-template-areas`, with the number of rows and columns the
+/// template has.
+fn template_areas(style: &StyledNode) -> (Areas, usize, usize) {
+    let mut areas: Areas = HashMap::new();
+    let (mut rows, mut columns) = (0, 0);
+    for (row, value) in components(style.value("grid-template-areas"))
+        .iter()
+        .enumerate()
+    {
+        let string = match *value {
+            Value::Str(ref string) => string,
+            _ => continue,
+        };
+        rows = row + 1;
+        for (column, name) in string.split_whitespace().enumerate() {
+            columns = columns.max(column + 1);
+            // Runs of dots are unnamed cells.
+            if name.chars().all(|c| c == '.') {
+                continue;
+            }
+            let area = areas
+                .entry(name.to_string())
+                .or_insert([row..row + 1, column..column + 1]);
+            area[0].start = area[0].start.min(row);
+            area[0].end = area[0].end.max(row + 1);
+            area[1].start = area[1].start.min(column);
+            area[1].end = area[1].end.max(column + 1);
+        }
+    }
+    (areas, rows, columns)
+}
+
+fn grid_line(value: Option<Value>) -> GridLine {
+    let limit = MAX_TRACKS as f32;
+    match value {
+        Some(Value::Number(n)) => GridLine::Line(n.clamp(-limit, limit) as i32),
+        Some(Keyword(ref keyword)) if keyword == "auto" => GridLine::Auto,
+        Some(Keyword(ref keyword)) if keyword == "span" => GridLine::Span(1),
+        Some(Keyword(name)) => GridLine::Name(name),
+        Some(Value::List(values)) => {
+            let number = values.iter().find_map(|value| match *value {
+                Value::Number(n) => Some(n.clamp(-limit, limit) as i32),
+                _ => None,
+            });
+            if values.contains(&Keyword("span".to_string())) {
+                GridLine::Span(number.unwrap_or(1).max(1) as usize)
+            } else {
+                number.map_or(GridLine::Auto, GridLine::Line)
+            }
+        }
+        _ => GridLine::Auto,
+    }
+}
+
+/// Resolves the start and end lines of an item along an axis, `grid-row` or `grid-column`,
+/// where `explicit` tracks make the explicit grid. Lines before the grid are clamped to its
+/// start.
+fn resolve_placement(
+    style: &StyledNode,
+    property: &str,
+    axis: usize,
+    areas: &Areas,
+    explicit: usize,
+) -> Placement {
+    let start = grid_line(style.value(&format!("{}-start", property)));
+    let end = grid_line(style.value(&format!("{}-end", property)));
+    let line = |line: &GridLine, is_end: bool| -> Option<i32> {
+        match *line {
+            GridLine::Line(n) if n > 0 => Some(n - 1),
+            GridLine::Line(n) if n < 0 => Some(explicit as i32 + 1 + n),
+            GridLine::Name(ref name) => {
+                let area = |name: &str| areas.get(name).map(|area| &area[axis]);
+                if let Some(range) = area(name) {
+                    let line = if is_end { range.end } else { range.start };
+                    Some(line as i32)
+                } else if let Some(range) = name.strip_suffix("-start").and_then(area) {
+                    Some(range.start as i32)
+                } else {
+                    name.strip_suffix("-end")
+                        .and_then(area)
+                        .map(|range| range.end as i32)
+                }
+            }
+            _ => None,
+        }
+    };
+    let span = |line: &GridLine| match *line {
+        GridLine::Span(n) => Some(n),
+        _ => None,
+    };
+    match (line(&start, false), line(&end, true)) {
+        (Some(s), Some(e)) => {
+            let (s, e) = (s.min(e), s.max(e));
+            (Some(s.max(0) as usize), (e - s).max(1) as usize)
+        }
+        (Some(s), None) => (Some(s.max(0) as usize), span(&end).unwrap_or(1)),
+        (None, Some(e)) => {
+            let span = span(&start).unwrap_or(1);
+            (Some((e - span as i32).max(0) as usize), span)
+        }
+        (None, None) => (None, span(&start).or_else(|| span(&end)).unwrap_or(1)),
+    }
+}
+
+/// Sizes `tracks` from the `contributions` of the items spanning them, following CSS Grid
+/// §11.4 to §11.8. `available` is the space the tracks and `gap`s fill, `None` to size them
+/// to their max-content contributions. `stretch` grows auto tracks into the free space.
+fn size_tracks(
+    tracks: &mut [Track],
+    contributions: &[(Range<usize>, IntrinsicSizes)],
+    available: Option<f32>,
+    gap: f32,
+    stretch: bool,
+) {
+    for track in tracks.iter_mut() {
+        track.base = match track.size.min {
+            Breadth::Fixed(min) => min,
+            _ => 0.0,
+        };
+        track.limit = match track.size.max {
+            Breadth::Fixed(max) => max.max(track.base),
+            _ => f32::INFINITY,
+        };
+    }
+    let gaps = |count: usize| gap * count.saturating_sub(1) as f32;
+
+    // Items spanning fewer tracks size them first.
+    let mut sorted: Vec<&(Range<usize>, IntrinsicSizes)> = contributions.iter().collect();
+    sorted.sort_by_key(|(range, _)| range.len());
+    for (range, sizes) in sorted {
+        let spanned = &mut tracks[range.clone()];
+        let used =
+            |spanned: &[Track]| spanned.iter().map(|t| t.base).sum::<f32>() + gaps(range.len());
+
+        // Items spanning flexible tracks only grow the base sizes of those.
+        if spanned.iter().any(Track::is_flexible) {
+            let extra = sizes.min_content - used(spanned);
+            grow_equally(spanned, extra, |track| {
+                track.is_flexible() && track.size.min.is_intrinsic()
+            });
+            continue;
+        }
+
+        let min_contribution = match (range.len(), spanned[0].size.min) {
+            (1, Breadth::MaxContent) => sizes.max_content,
+            _ => sizes.min_content,
+        };
+        let extra = min_contribution - used(spanned);
+        grow_equally(spanned, extra, |track| track.size.min.is_intrinsic());
+
+        let max_contribution = match (range.len(), spanned[0].size.max) {
+            (1, Breadth::MinContent) => sizes.min_content,
+            _ => sizes.max_content,
+        };
+        let limits: f32 = spanned
+            .iter()
+            .map(|track| {
+                if track.limit.is_finite() {
+                    track.limit
+                } else {
+                    track.base
+                }
+            })
+            .sum();
+        let targets = spanned
+            .iter()
+            .filter(|track| track.size.max.is_intrinsic())
+            .count();
+        let extra = max_contribution - limits - gaps(range.len());
+        for track in spanned
+            .iter_mut()
+            .filter(|track| track.size.max.is_intrinsic())
+        {
+            let limit = if track.limit.is_finite() {
+                track.limit
+            } else {
+                track.base
+            };
+            track.limit = limit + extra.max(0.0) / targets as f32;
+            if let Breadth::FitContent(cap) = track.size.max {
+                track.limit = track.limit.min(cap.max(track.base));
+            }
+        }
+    }
+    for track in tracks.iter_mut() {
+        if !track.limit.is_finite() || track.limit < track.base {
+            track.limit = track.base;
+        }
+    }
+
+    let total_gaps = gaps(tracks.len());
+    let used = |tracks: &[Track]| tracks.iter().map(|t| t.base).sum::<f32>() + total_gaps;
+
+    // The free space grows the tracks up to their limits.
+    match available {
+        Some(available) => {
+            let mut free = available - used(tracks);
+            while free > 0.01 {
+                let growable = tracks
+                    .iter()
+                    .filter(|track| !track.is_flexible() && track.base < track.limit)
+                    .count();
+                if growable == 0 {
+                    break;
+                }
+                let share = free / growable as f32;
+                for track in tracks
+                    .iter_mut()
+                    .filter(|track| !track.is_flexible() && track.base < track.limit)
+                {
+                    let grown = share.min(track.limit - track.base);
+                    track.base += grown;
+                    free -= grown;
+                }
+            }
+        }
+        None => {
+            for track in tracks.iter_mut().filter(|track| !track.is_flexible()) {
+                track.base = track.limit;
+            }
+        }
+    }
+
+    // Flexible tracks share what is left, in proportion to their factors.
+    let factor = |track: &Track| match track.size.max {
+        Breadth::Flex(f) => f,
+        _ => 0.0,
+    };
+    match available {
+        Some(available) => {
+            let mut flexible: Vec<bool> = tracks.iter().map(Track::is_flexible).collect();
+            let fr_size = loop {
+                let inflexible: f32 = tracks
+                    .iter()
+                    .zip(&flexible)
+                    .filter(|(_, &flexible)| !flexible)
+                    .map(|(track, _)| track.base)
+                    .sum();
+                let factors: f32 = tracks
+                    .iter()
+                    .zip(&flexible)
+                    .filter(|(_, &flexible)| flexible)
+                    .map(|(track, _)| factor(track))
+                    .sum();
+                let fr_size = (available - total_gaps - inflexible) / factors.max(1.0);
+                // A track whose base size is already larger is treated as inflexible.
+                let mut changed = false;
+                for (track, flexible) in tracks.iter().zip(flexible.iter_mut()) {
+                    if *flexible && track.base > fr_size * factor(track) {
+                        *flexible = false;
+                        changed = true;
+                    }
+                }
+                if !changed {
+                    break fr_size;
+                }
+            };
+            for (track, flexible) in tracks.iter_mut().zip(flexible) {
+                if flexible {
+                    track.base = fr_size * factor(track);
+                }
+            }
+        }
+        None => {
+            let mut fr_size: f32 = tracks
+                .iter()
+                .filter(|track| track.is_flexible())
+                .map(|track| track.base / factor(track).max(1.0))
+                .fold(0.0, f32::max);
+            for (range, sizes) in contributions {
+                let factors: f32 = tracks[range.clone()].iter().map(factor).sum();
+                if factors > 0.0 {
+                    fr_size = fr_size.max(sizes.max_content / factors.max(1.0));
+                }
+            }
+            for track in tracks.iter_mut().filter(|track| track.is_flexible()) {
+                track.base = track.base.max(fr_size * factor(track));
+            }
+        }
+    }
+
+    // Auto tracks stretch into any space still free.
+    if let (true, Some(available)) = (stretch, available) {
+        let free = available - used(tracks);
+        let auto = tracks
+            .iter()
+            .filter(|track| track.size.max == Breadth::Auto)
+            .count();
+        if free > 0.0 && auto > 0 {
+            for track in tracks
+                .iter_mut()
+                .filter(|track| track.size.max == Breadth::Auto)
+            {
+                track.base += free / auto as f32;
+            }
+        }
+    }
+}
+
+/// Shares `extra` space equally between the base sizes of the tracks that `grows` accepts.
+fn grow_equally<F: Fn(&Track) -> bool>(tracks: &mut [Track], extra: f32, grows: F) {
+    let count = tracks.iter().filter(|track| grows(track)).count();
+    if extra <= 0.0 || count == 0 {
+        return;
+    }
+    for track in tracks.iter_mut().filter(|track| grows(track)) {
+        track.base += extra / count as f32;
+    }
+}
+
+/// The offsets of the tracks from the start of the content box, once `distribution` has
+/// shared the space `available` for them.
+fn track_positions(
+    tracks: &[Track],
+    available: Option<f32>,
+    gap: f32,
+    distribution: ContentDistribution,
+) -> Vec<f32> {
+    let used = tracks.iter().map(|track| track.base).sum::<f32>()
+        + gap * tracks.len().saturating_sub(1) as f32;
+    let free = available.map_or(0.0, |available| available - used);
+    let (mut position, between) = distribute(distribution, free, tracks.len());
+    tracks
+        .iter()
+        .map(|track| {
+            let start = position;
+            position += track.base + gap + between;
+            start
+        })
+        .collect()
+}
+
+/// The size of the area covering a `range` of tracks, with the gaps between them.
+fn area_size(tracks: &[Track], positions: &[f32], range: &Range<usize>) -> f32 {
+    let last = range.end - 1;
+    positions[last] + tracks[last].base - positions[range.start]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layout::testing::{find, layout_html};
+    use crate::layout::Rect;
+
+    #[test]
+    fn test_grid_layout() {
+        let root = layout_html(
+            "<div>\
+             <div class=\"grid\">\
+             <div class=\"a\">a</div><div class=\"b\">b</div><div class=\"c\">c</div>\
+             </div>\
+             <div class=\"areas\">\
+             <div class=\"main\">main</div><div class=\"head\">head</div>\
+             <div class=\"side\">side</div>\
+             </div>\
+             <div class=\"centered\"><div class=\"first\"></div><div class=\"second\"></div></div>\
+             </div>",
+            ".grid { display: grid; width: 400px; gap: 10px; grid-template-columns: 100px 1fr 3fr; grid-template-rows: 50px auto; }
+             .a { grid-column: 2 / span 2; }
+             .areas { display: grid; width: 300px; grid-template-columns: repeat(auto-fill, minmax(100px, 1fr)); grid-template-areas: \"head head\" \"side main\"; }
+             .head { grid-area: head; }
+             .main { grid-area: main; }
+             .side { grid-area: side; width: 40px; justify-self: center; }
+             .centered { display: grid; width: 300px; height: 100px; grid-template-columns: 50px 50px; grid-auto-rows: 20px; justify-content: center; align-content: end; }",
+            Rect::new(0.0, 0.0, 800.0, 600.0),
+        );
+        let rect = |class: &str| find(&root, class).unwrap().dimensions.margin_box();
+
+        // Flexible columns share what the fixed one and the gaps leave.
+        let a = rect("a");
+        assert_eq!((a.x, a.y, a.height), (110.0, 0.0, 50.0));
+        assert!((a.width - 290.0).abs() < 0.01);
+        // Auto-placed items flow around it onto the next row.
+        let (b, c) = (rect("b"), rect("c"));
+        assert_eq!((b.x, b.y, b.width), (0.0, 60.0, 100.0));
+        assert!((c.x - 110.0).abs() < 0.01 && (c.width - 70.0).abs() < 0.01);
+        assert_eq!(c.y, 60.0);
+
+        // As many columns as fit are repeated, and named areas place the items.
+        let areas = rect("areas");
+        let head = rect("head");
+        assert_eq!((head.x, head.y - areas.y, head.width), (0.0, 0.0, 200.0));
+        let main = rect("main");
+        assert_eq!((main.x, main.width), (100.0, 100.0));
+        assert_eq!(main.y, rect("side").y);
+        let side = find(&root, "side").unwrap().dimensions.border_box();
+        assert_eq!((side.x, side.width), (30.0, 40.0));
+
+        // The tracks are aligned in the container.
+        let centered = rect("centered");
+        let second = rect("second");
+        assert_eq!((second.x, second.y - centered.y), (150.0, 80.0));
+        assert_eq!(rect("first").x, 100.0);
+    }
+
+    #[test]
+    fn test_track_limit() {
+        let root = layout_html(
+            "<div class=\"grid\"><div class=\"last\"></div><div class=\"far\"></div></div>",
+            ".grid { display: grid; width: 400px; grid-template-columns: repeat(100000000, 1px); grid-auto-rows: 10px; }
+             .last { grid-column: -2; }
+             .far { grid-row: 1; grid-column: 100000000; }",
+            Rect::new(0.0, 0.0, 800.0, 600.0),
+        );
+        let rect = |class: &str| find(&root, class).unwrap().dimensions.margin_box();
+
+        // The repetition stops at the track limit, and lines past it are clamped to it.
+        assert_eq!((rect("last").x, rect("last").width), (9999.0, 1.0));
+        assert_eq!(rect("far").x, 9999.0);
+    }
+}
@@ -1,96 +1,1246 @@
-use crate::{css::Value, html::NodeType, text::calc_text_region};
+use crate::html::NodeType;
+use crate::style::{LineHeight, Overflow, StyledNode, TextAlign, TextOverflow, VerticalAlign};
+use crate::text::{calc_text_region, font_metrics, shape, FontMetrics, GlyphRun};
 
-use super::{BoxType, Dimensions, LayoutBox, Length, Px};
+use super::float::FloatContext;
+use super::{BoxType, Dimensions, Keyword, LayoutBox, Length, Px, Rect};
+use unicode_linebreak::{linebreaks, BreakOpportunity};
+
+/// Marks the end of text cut off by `text-overflow: ellipsis`.
+const ELLIPSIS: &str = "\u{2026}";
+
+/// Stands in for atomic inlines in the text that line breaking runs over.
+const OBJECT_REPLACEMENT: char = '\u{FFFC}';
+
+/// A piece of inline content that can't be split any further.
+enum InlineItem<'a> {
+    /// Start of an inline box; its left margin, border and padding take up line space.
+    Open(&'a StyledNode<'a>),
+    Close(&'a StyledNode<'a>),
+    Text {
+        style: &'a StyledNode<'a>,
+        text: String,
+        /// The glyph runs the text is shaped into, which the text run box is painted with.
+        runs: Vec<GlyphRun>,
+        width: f32,
+        /// Width of the trailing collapsible space, which hangs at the end of a line.
+        trailing_space: f32,
+    },
+    Atomic(LayoutBox<'a>),
+    /// An absolutely positioned box, which only needs its static position from the line.
+    OutOfFlow(LayoutBox<'a>),
+    /// A float, placed at the top of the line it's on if it fits there, or below it.
+    Float(LayoutBox<'a>),
+    /// A line may (or must) break after the preceding items.
+    Break(BreakOpportunity),
+}
+
+/// The inline boxes that continue from one line to the next.
+type OpenBoxes<'a> = Vec<&'a StyledNode<'a>>;
+
+/// The font and line height of an inline box, which determine its layout bounds.
+struct InlineMetrics {
+    font_size: f32,
+    font: FontMetrics,
+    line_height: f32,
+}
+
+/// Vertical extent of the layout bounds on a line, relative to its baseline.
+#[derive(Clone, Copy)]
+struct LineBounds {
+    top: f32,
+    bottom: f32,
+}
+
+/// Heights of the subtrees aligned with the top and the bottom of the line box, which
+/// don't take part in baseline alignment.
+#[derive(Default)]
+struct EdgeHeights {
+    top: f32,
+    bottom: f32,
+}
 
 impl<'a> LayoutBox<'a> {
-    pub(crate) fn layout_inline(&mut self, containing_block: Dimensions) {
-        self.calculate_width();
+    pub(crate) fn layout_inline(&mut self, _containing_block: Dimensions) {
+        unreachable!("Inline boxes are laid out by their inline formatting context");
+    }
 
-        self.calculate_dimentions();
+    /// Lays out an inline formatting context: breaks the inline children into line boxes
+    /// that fit the containing block, fragmenting inline boxes that span several lines.
+    /// Lines are shortened around the `floats` of the block formatting context.
+    pub(crate) fn layout_anonymous_block(
+        &mut self,
+        containing_block: Dimensions,
+        floats: &mut FloatContext,
+    ) {
+        let style = match self.box_type {
+            BoxType::AnonymousBlock(style) => style,
+            _ => unreachable!(),
+        };
+        let d = &mut self.dimensions;
+        d.content.x = containing_block.content.x;
+        d.content.y = containing_block.content.y + containing_block.content.height;
+        d.content.width = containing_block.content.width;
+        d.content.height = 0.0;
 
-        self.calculate_position(containing_block);
+        let mut items = Vec::new();
+        let mut text = String::new();
+        for child in std::mem::take(&mut self.children) {
+            collect_inline_items(child, &mut items, &mut text);
+        }
+        let own_block = Dimensions {
+            content: Rect {
+                height: 0.0,
+                ..self.dimensions.content
+            },
+            ..Default::default()
+        };
+        for item in &mut items {
+            match *item {
+                InlineItem::Atomic(ref mut atomic) => atomic.layout(own_block),
+                InlineItem::Float(ref mut float) => float.layout_float(own_block, None),
+                _ => {}
+            }
+        }
+        let items = split_at_break_opportunities(items, &text);
 
-        self.layout_children();
+        // Floats are placed against the space a line of the block's own line height needs.
+        let strut_height = InlineMetrics::new(style).line_height;
+        let mut open_boxes = OpenBoxes::new();
+        let mut line = Vec::new();
+        let mut line_width = 0.0;
+        let mut band = self.line_band(floats, strut_height);
+        let mut deferred = Vec::new();
+        for (chunk, mandatory) in chunks(items) {
+            let mut content = Vec::new();
+            for item in chunk {
+                match item {
+                    InlineItem::Float(float) => {
+                        let width = float.dimensions.margin_box().width;
+                        if line_width == 0.0 || line_width + width <= band.1 - band.0 {
+                            self.place_line_float(float, floats);
+                            band = self.line_band(floats, strut_height);
+                        } else {
+                            deferred.push(float);
+                        }
+                    }
+                    item => content.push(item),
+                }
+            }
+
+            let width: f32 = content.iter().map(item_width).sum();
+            let hanging = content
+                .iter()
+                .rev()
+                .find_map(|item| match *item {
+                    InlineItem::Text { trailing_space, .. } => Some(trailing_space),
+                    _ => None,
+                })
+                .unwrap_or(0.0);
+            if !line.is_empty() && line_width + width - hanging > band.1 - band.0 {
+                self.push_line_box(std::mem::take(&mut line), band, &mut open_boxes, false);
+                line_width = 0.0;
+                band = self.start_line(&mut deferred, floats, strut_height);
+            }
+            if line.is_empty() {
+                // A line too narrow for its first piece of content moves down past floats.
+                while width - hanging > band.1 - band.0 {
+                    let content = self.dimensions.content;
+                    match floats.next_bottom(content.y + content.height) {
+                        Some(bottom) => {
+                            self.dimensions.content.height = bottom - content.y;
+                            band = self.line_band(floats, strut_height);
+                        }
+                        None => break,
+                    }
+                }
+            }
+
+            line_width += width;
+            line.extend(content);
+            if mandatory && !line.is_empty() {
+                self.push_line_box(std::mem::take(&mut line), band, &mut open_boxes, true);
+                line_width = 0.0;
+                band = self.start_line(&mut deferred, floats, strut_height);
+            }
+        }
+        if !line.is_empty() {
+            self.push_line_box(line, band, &mut open_boxes, true);
+        }
+        self.start_line(&mut deferred, floats, strut_height);
     }
 
-    fn calculate_width(&mut self) {
-        match self.box_type {
-            BoxType::InlineNode(ref style) => match style.node.typ {
-                NodeType::Text(ref text) => {
-                    let font_size = style.value_or("font-size", &Value::Length(16.0, Px));
-                    let region = calc_text_region(text.clone(), font_size.to_px());
-                    self.dimensions.content.width = region.width;
-                    self.dimensions.content.height = region.height;
+    /// The left and right edges of the space the floats leave to the next line.
+    fn line_band(&self, floats: &FloatContext, line_height: f32) -> (f32, f32) {
+        let content = self.dimensions.content;
+        floats.available(
+            content.y + content.height,
+            line_height,
+            content.x,
+            content.x + content.width,
+        )
+    }
+
+    /// Places a float at the top of the current line, or below if it doesn't fit there.
+    fn place_line_float(&mut self, mut float: LayoutBox<'a>, floats: &mut FloatContext) {
+        let content = self.dimensions.content;
+        float.place_float(content.y + content.height, content, floats);
+        self.children.push(float);
+    }
+
+    /// Places the floats that didn't fit on the previous line, and returns the space left
+    /// to the next one.
+    fn start_line(
+        &mut self,
+        deferred: &mut Vec<LayoutBox<'a>>,
+        floats: &mut FloatContext,
+        line_height: f32,
+    ) -> (f32, f32) {
+        for float in deferred.drain(..) {
+            self.place_line_float(float, floats);
+        }
+        self.line_band(floats, line_height)
+    }
+
+    /// Appends a line box holding `items` below the previous lines, between the left and
+    /// right edges of `band`. `last` tells whether the line ends a paragraph, either at a
+    /// forced break or at the end of the content.
+    fn push_line_box(
+        &mut self,
+        items: Vec<InlineItem<'a>>,
+        band: (f32, f32),
+        open_boxes: &mut OpenBoxes<'a>,
+        last: bool,
+    ) {
+        // A line holding nothing but out-of-flow boxes isn't generated.
+        if items
+            .iter()
+            .all(|item| matches!(item, InlineItem::OutOfFlow(_) | InlineItem::Break(_)))
+        {
+            let content = self.dimensions.content;
+            for item in items {
+                if let InlineItem::OutOfFlow(mut layout_box) = item {
+                    layout_box.dimensions.content.x = band.0;
+                    layout_box.dimensions.content.y = content.y + content.height;
+                    self.children.push(layout_box);
                 }
-                _ => {}
-            },
-            _ => {}
+            }
+            return;
+        }
+
+        let line_box = self.build_line_box(items, band, open_boxes, last);
+        self.dimensions.content.height += line_box.dimensions.content.height;
+        self.children.push(line_box);
+    }
+
+    fn build_line_box(
+        &self,
+        mut items: Vec<InlineItem<'a>>,
+        band: (f32, f32),
+        open_boxes: &mut OpenBoxes<'a>,
+        last: bool,
+    ) -> LayoutBox<'a> {
+        let style = match self.box_type {
+            BoxType::AnonymousBlock(style) => style,
+            _ => unreachable!(),
+        };
+
+        // Collapsible spaces at the end of a line are removed.
+        if let Some(InlineItem::Text {
+            text,
+            runs,
+            width,
+            trailing_space,
+            ..
+        }) = items
+            .iter_mut()
+            .rev()
+            .find(|item| matches!(item, InlineItem::Text { .. }))
+        {
+            text.truncate(text.trim_end_matches(' ').len());
+            truncate_runs(runs, text.len());
+            *width -= *trailing_space;
+            *trailing_space = 0.0;
+        }
+
+        let mut text_align = style.text_align();
+        if text_align == TextAlign::Justify {
+            // The last line of a paragraph isn't stretched.
+            text_align = TextAlign::Left;
+            if !last {
+                let free = band.1 - band.0 - items.iter().map(item_width).sum::<f32>();
+                items = justify(items, free);
+            }
+        }
+
+        let mut line_box = LayoutBox::new(BoxType::LineBox { baseline: 0.0 });
+        line_box.dimensions.content.x = band.0;
+        line_box.dimensions.content.width = (band.1 - band.0).max(0.0);
+        line_box.dimensions.content.y = self.dimensions.content.y + self.dimensions.content.height;
+
+        // Fragments of the boxes opened on a previous line, without their start edges.
+        let mut stack: Vec<LayoutBox<'a>> = open_boxes
+            .iter()
+            .map(|style| {
+                let mut fragment = LayoutBox::new(BoxType::InlineNode(style));
+                fragment.set_inline_edges(false, false);
+                fragment
+            })
+            .collect();
+        for item in items {
+            match item {
+                InlineItem::Open(style) => {
+                    let mut fragment = LayoutBox::new(BoxType::InlineNode(style));
+                    fragment.set_inline_edges(true, false);
+                    stack.push(fragment);
+                    open_boxes.push(style);
+                }
+                InlineItem::Close(_) => {
+                    let mut fragment = stack.pop().unwrap();
+                    fragment.set_inline_edges(false, true);
+                    open_boxes.pop();
+                    append_fragment(&mut stack, &mut line_box, fragment);
+                }
+                InlineItem::Text {
+                    style,
+                    text,
+                    runs,
+                    width,
+                    ..
+                } => {
+                    let mut fragment = LayoutBox::new(BoxType::TextRun(style, text, runs));
+                    fragment.dimensions.content.width = width;
+                    append_fragment(&mut stack, &mut line_box, fragment);
+                }
+                InlineItem::Atomic(layout_box) | InlineItem::OutOfFlow(layout_box) => {
+                    append_fragment(&mut stack, &mut line_box, layout_box)
+                }
+                InlineItem::Break(_) => {}
+                InlineItem::Float(_) => unreachable!("Floats are placed before the line is built"),
+            }
+        }
+        while let Some(fragment) = stack.pop() {
+            append_fragment(&mut stack, &mut line_box, fragment);
+        }
+
+        let origin = line_box.dimensions.content;
+        let mut x = origin.x;
+        for fragment in &mut line_box.children {
+            fragment.place_inline_fragment(&mut x);
+        }
+        // Content cut off by the end of a clipping block is marked with an ellipsis.
+        if x > origin.x + origin.width
+            && style.overflow() != Overflow::Visible
+            && style.text_overflow() == TextOverflow::Ellipsis
+        {
+            line_box.ellipsize(style, origin.x + origin.width);
+            x = origin.x;
+            for fragment in &mut line_box.children {
+                fragment.place_inline_fragment(&mut x);
+            }
+        }
+        let free = (origin.x + origin.width - x).max(0.0);
+        let offset = match text_align {
+            TextAlign::Left | TextAlign::Justify => 0.0,
+            TextAlign::Right => free,
+            TextAlign::Center => free / 2.0,
+        };
+
+        // Every line starts with a zero width strut carrying the block's font and line height.
+        let strut = InlineMetrics::new(style);
+        let mut bounds = strut.layout_bounds();
+        let mut edges = EdgeHeights::default();
+        for fragment in &mut line_box.children {
+            fragment.translate(offset, 0.0);
+            fragment.align_vertically(0.0, &strut, &mut bounds, &mut edges);
+        }
+
+        let baseline_height = bounds.bottom - bounds.top;
+        let height = baseline_height.max(edges.top).max(edges.bottom);
+        // Boxes aligned on the baseline stick to the top of the line, unless a taller box
+        // aligned with the bottom pushes them down.
+        let top = if edges.bottom > baseline_height.max(edges.top) {
+            origin.y + height - baseline_height
+        } else {
+            origin.y
+        };
+        for fragment in &mut line_box.children {
+            fragment.finish_vertical_alignment(top - bounds.top, origin.y, origin.y + height);
         }
+
+        line_box.dimensions.content.height = height;
+        line_box.box_type = BoxType::LineBox {
+            baseline: top - bounds.top - origin.y,
+        };
+        line_box
     }
 
-    fn calculate_dimentions(&mut self) {
+    /// Sets the horizontal edges of an inline box fragment. Only the first fragment of a box
+    /// gets its left edge, and only the last its right edge.
+    fn set_inline_edges(&mut self, start: bool, end: bool) {
         let style = self.get_style_node();
         let zero = Length(0.0, Px);
-
         let d = &mut self.dimensions;
 
-        d.padding.left = style.lookup("padding-left", "padding", &zero).to_px();
-        d.padding.right = style.lookup("padding-right", "padding", &zero).to_px();
+        if start {
+            d.padding.left = style.lookup("padding-left", "padding", &zero).to_px();
+            d.border.left = style
+                .lookup("border-left-width", "border-width", &zero)
+                .to_px();
+            d.margin.left = style.lookup("margin-left", "margin", &zero).to_px();
+        }
+        if end {
+            d.padding.right = style.lookup("padding-right", "padding", &zero).to_px();
+            d.border.right = style
+                .lookup("border-right-width", "border-width", &zero)
+                .to_px();
+            d.margin.right = style.lookup("margin-right", "margin", &zero).to_px();
+        }
+
         d.padding.top = style.lookup("padding-top", "padding", &zero).to_px();
         d.padding.bottom = style.lookup("padding-bottom", "padding", &zero).to_px();
-
-        d.border.left = style
-            .lookup("border-left-width", "border-width", &zero)
-            .to_px();
-        d.border.right = style
-            .lookup("border-right-width", "border-width", &zero)
-            .to_px();
         d.border.top = style
             .lookup("border-top-width", "border-width", &zero)
             .to_px();
         d.border.bottom = style
             .lookup("border-bottom-width", "border-width", &zero)
             .to_px();
+    }
 
-        d.margin.left = style.lookup("margin-left", "margin", &zero).to_px();
-        d.margin.right = style.lookup("margin-right", "margin", &zero).to_px();
+    /// Positions a fragment and its descendants horizontally, starting at `x`.
+    fn place_inline_fragment(&mut self, x: &mut f32) {
+        if self.is_out_of_flow() {
+            self.dimensions.content.x = *x;
+            return;
+        }
+        match self.box_type {
+            BoxType::InlineNode(_) => {
+                let d = &mut self.dimensions;
+                *x += d.margin.left + d.border.left + d.padding.left;
+                d.content.x = *x;
+
+                for child in &mut self.children {
+                    child.place_inline_fragment(x);
+                }
+
+                let d = &mut self.dimensions;
+                d.content.width = *x - d.content.x;
+                *x += d.padding.right + d.border.right + d.margin.right;
+            }
+            BoxType::TextRun(..) => {
+                self.dimensions.content.x = *x;
+                *x += self.dimensions.content.width;
+            }
+            _ => {
+                let margin_box = self.dimensions.margin_box();
+                self.translate(*x - margin_box.x, 0.0);
+                *x += margin_box.width;
+            }
+        }
     }
 
-    fn calculate_position(&mut self, containing_block: Dimensions) {
-        let d = &mut self.dimensions;
+    /// Truncates the content of a placed line fragment that overflows `right` and ends it
+    /// with an ellipsis, in the font of `style` if no text is cut. Everything after the
+    /// ellipsis is dropped. Returns whether the ellipsis was placed.
+    fn ellipsize(&mut self, style: &'a StyledNode<'a>, right: f32) -> bool {
+        let mut placed = false;
+        let mut children = Vec::new();
+        for mut child in std::mem::take(&mut self.children) {
+            if placed {
+                continue;
+            }
+            if child.is_out_of_flow() {
+                children.push(child);
+                continue;
+            }
+            match child.box_type {
+                BoxType::InlineNode(inline_style) => placed = child.ellipsize(inline_style, right),
+                BoxType::TextRun(text_style, ref mut text, ref mut runs) => {
+                    let font = text_style.font();
+                    let limit = right - calc_text_region(ELLIPSIS, &font).width;
+                    let x = child.dimensions.content.x;
+                    if x + child.dimensions.content.width > limit {
+                        while !text.is_empty() && x + calc_text_region(text, &font).width > limit {
+                            text.pop();
+                        }
+                        text.truncate(text.trim_end_matches(' ').len());
+                        text.push_str(ELLIPSIS);
+                        *runs = shape(text, &font);
+                        child.dimensions.content.width = runs.iter().map(GlyphRun::width).sum();
+                        placed = true;
+                    }
+                }
+                _ => {
+                    let limit = right - calc_text_region(ELLIPSIS, &style.font()).width;
+                    let margin_box = child.dimensions.margin_box();
+                    if margin_box.x + margin_box.width > limit {
+                        // An atomic inline that doesn't fit is hidden in favour of the ellipsis.
+                        let runs = shape(ELLIPSIS, &style.font());
+                        child = LayoutBox::new(BoxType::TextRun(style, ELLIPSIS.to_string(), runs));
+                        child.dimensions.content.width = right - limit;
+                        placed = true;
+                    }
+                }
+            }
+            children.push(child);
+        }
+        self.children = children;
+        placed
+    }
 
-        d.content.x = containing_block.content.x
-            + containing_block.content.width
-            + d.padding.left
-            + d.border.left
-            + d.margin.left;
-        d.content.y = containing_block.content.y + containing_block.content.height;
+    fn vertical_align(&self) -> VerticalAlign {
+        match self.box_type {
+            // Text always sits on the baseline of its inline box.
+            BoxType::TextRun(..) => VerticalAlign::Baseline,
+            _ => self.get_style_node().vertical_align(),
+        }
     }
 
-    fn layout_children(&mut self) {
-        let mut max_height = 0.0f32;
+    /// Positions a fragment relative to the baseline of the line, given the baseline of its
+    /// parent inline box, and grows the line's `bounds` to include it. Fragments aligned with
+    /// the edges of the line are positioned relative to the line's top or bottom instead, and
+    /// only their height is recorded.
+    fn align_vertically(
+        &mut self,
+        parent_baseline: f32,
+        parent: &InlineMetrics,
+        bounds: &mut LineBounds,
+        edges: &mut EdgeHeights,
+    ) {
+        if self.is_out_of_flow() {
+            return;
+        }
+        let align = self.vertical_align();
+        if let VerticalAlign::Top | VerticalAlign::Bottom = align {
+            let mut own = LineBounds {
+                top: f32::INFINITY,
+                bottom: f32::NEG_INFINITY,
+            };
+            let mut nested = EdgeHeights::default();
+            self.align_on_baseline(0.0, &mut own, &mut nested);
+            own.include(0.0, nested.top);
+            own.include(-nested.bottom, 0.0);
 
-        for child in &mut self.children {
-            child.layout(self.dimensions);
+            let height = own.bottom - own.top;
+            if align == VerticalAlign::Top {
+                self.translate(0.0, -own.top);
+                edges.top = edges.top.max(height);
+            } else {
+                self.translate(0.0, -own.bottom);
+                edges.bottom = edges.bottom.max(height);
+            }
+            return;
+        }
+
+        let own = self.own_layout_bounds();
+        let shift = match align {
+            VerticalAlign::Baseline | VerticalAlign::Top | VerticalAlign::Bottom => 0.0,
+            VerticalAlign::Sub => parent.font_size / 5.0,
+            VerticalAlign::Super => -parent.font_size / 3.0,
+            VerticalAlign::TextTop => -parent.font.ascent - own.top,
+            VerticalAlign::TextBottom => parent.font.descent - own.bottom,
+            VerticalAlign::Middle => -parent.font.x_height / 2.0 - (own.top + own.bottom) / 2.0,
+            VerticalAlign::Length(ref value) => {
+                -value.resolve(InlineMetrics::new(self.get_style_node()).line_height)
+            }
+        };
+        self.align_on_baseline(parent_baseline + shift, bounds, edges);
+    }
 
-            self.dimensions.content.width += child.dimensions.margin_box().width;
-            max_height = max_height.max(child.dimensions.content.height);
+    /// Layout bounds of the fragment itself relative to its baseline, without descendants.
+    fn own_layout_bounds(&self) -> LineBounds {
+        match self.box_type {
+            BoxType::InlineNode(style) | BoxType::TextRun(style, ..) => {
+                InlineMetrics::new(style).layout_bounds()
+            }
+            _ => {
+                let margin_box = self.dimensions.margin_box();
+                let bottom = margin_box.y + margin_box.height;
+                let baseline = self.inline_block_baseline().unwrap_or(bottom);
+                LineBounds {
+                    top: margin_box.y - baseline,
+                    bottom: bottom - baseline,
+                }
+            }
         }
+    }
+
+    /// The baseline of an inline-block, which is that of its last line box. Atomic inlines
+    /// without one, or whose overflow isn't visible, have theirs at their bottom margin edge.
+    /// An inline table has the baseline of its first row.
+    fn inline_block_baseline(&self) -> Option<f32> {
+        if !self.is_inline_block() {
+            return None;
+        }
+        if self.is_table_wrapper() {
+            return self.first_line_baseline();
+        }
+        match self.get_style_node().value("overflow") {
+            Some(Keyword(ref overflow)) if overflow != "visible" => None,
+            _ => self.last_line_baseline(),
+        }
+    }
+
+    /// The baseline of the first line box in the flow of a block container.
+    pub(crate) fn first_line_baseline(&self) -> Option<f32> {
+        self.children.iter().find_map(|child| match child.box_type {
+            BoxType::LineBox { baseline } => Some(child.dimensions.content.y + baseline),
+            BoxType::AnonymousBlock(_) => child.first_line_baseline(),
+            BoxType::BlockNode(_) | BoxType::Table(_) | BoxType::AnonymousTable(_)
+                if !child.is_out_of_flow() && !child.is_float() =>
+            {
+                child.first_line_baseline()
+            }
+            _ => None,
+        })
+    }
 
-        self.dimensions.content.height = max_height.max(self.dimensions.content.height);
+    /// The baseline of the last line box in the flow of a block container.
+    fn last_line_baseline(&self) -> Option<f32> {
+        self.children
+            .iter()
+            .rev()
+            .find_map(|child| match child.box_type {
+                BoxType::LineBox { baseline } => Some(child.dimensions.content.y + baseline),
+                BoxType::AnonymousBlock(_) => child.last_line_baseline(),
+                BoxType::BlockNode(_) | BoxType::Table(_) | BoxType::AnonymousTable(_)
+                    if !child.is_out_of_flow() && !child.is_float() =>
+                {
+                    child.last_line_baseline()
+                }
+                _ => None,
+            })
     }
 
-    pub(crate) fn layout_anonymous_block(&mut self, mut containing_block: Dimensions) {
-        let mut max_height = 0.0f32;
-        containing_block.content.width = 0.0;
+    fn align_on_baseline(
+        &mut self,
+        baseline: f32,
+        bounds: &mut LineBounds,
+        edges: &mut EdgeHeights,
+    ) {
+        let own = self.own_layout_bounds();
+        bounds.include(baseline + own.top, baseline + own.bottom);
+        match self.box_type {
+            BoxType::InlineNode(style) | BoxType::TextRun(style, ..) => {
+                // The content area is the em box of the font, whatever the line height.
+                let metrics = InlineMetrics::new(style);
+                let d = &mut self.dimensions;
+                d.content.y = baseline - metrics.font.ascent;
+                d.content.height = metrics.font.ascent + metrics.font.descent;
+                for child in &mut self.children {
+                    child.align_vertically(baseline, &metrics, bounds, edges);
+                }
+            }
+            _ => {
+                let margin_box = self.dimensions.margin_box();
+                self.translate(0.0, baseline + own.top - margin_box.y);
+            }
+        }
+    }
 
+    /// Moves fragments from baseline-relative positions to their place on the line.
+    fn finish_vertical_alignment(&mut self, dy: f32, line_top: f32, line_bottom: f32) {
+        if self.is_out_of_flow() {
+            self.dimensions.content.y = line_top;
+            return;
+        }
+        match self.vertical_align() {
+            VerticalAlign::Top => self.translate(0.0, line_top),
+            VerticalAlign::Bottom => self.translate(0.0, line_bottom),
+            _ => match self.box_type {
+                BoxType::InlineNode(_) => {
+                    self.dimensions.content.y += dy;
+                    for child in &mut self.children {
+                        child.finish_vertical_alignment(dy, line_top, line_bottom);
+                    }
+                }
+                _ => self.translate(0.0, dy),
+            },
+        }
+    }
+
+    /// Moves a laid out box and all of its descendants.
+    pub(crate) fn translate(&mut self, dx: f32, dy: f32) {
+        self.dimensions.content.x += dx;
+        self.dimensions.content.y += dy;
         for child in &mut self.children {
-            child.layout(containing_block);
+            child.translate(dx, dy);
+        }
+    }
+}
+
+impl InlineMetrics {
+    fn new(style: &StyledNode) -> Self {
+        let font_size = style.font_size();
+        let font = font_metrics(&style.font());
+        let line_height = match style.line_height() {
+            LineHeight::Normal => font.line_spacing,
+            LineHeight::Number(n) => n * font_size,
+            LineHeight::Length(px) => px,
+        };
+        Self {
+            font_size,
+            font,
+            line_height,
+        }
+    }
+
+    /// The font's content area plus half the leading above and below it.
+    fn layout_bounds(&self) -> LineBounds {
+        let half_leading = (self.line_height - (self.font.ascent + self.font.descent)) / 2.0;
+        LineBounds {
+            top: -self.font.ascent - half_leading,
+            bottom: self.font.descent + half_leading,
+        }
+    }
+}
+
+impl LineBounds {
+    fn include(&mut self, top: f32, bottom: f32) {
+        self.top = self.top.min(top);
+        self.bottom = self.bottom.max(bottom);
+    }
+}
+
+/// Distributes the free space of a line among its spaces, splitting its text into words.
+fn justify(items: Vec<InlineItem>, free: f32) -> Vec<InlineItem> {
+    let spaces: usize = items
+        .iter()
+        .map(|item| match *item {
+            InlineItem::Text { ref text, .. } => text.matches(' ').count(),
+            _ => 0,
+        })
+        .sum();
+    if spaces == 0 || free <= 0.0 {
+        return items;
+    }
+
+    let extra = free / spaces as f32;
+    let mut result = Vec::new();
+    for item in items {
+        match item {
+            InlineItem::Text { style, text, .. } => {
+                for word in text.split_inclusive(' ') {
+                    let mut item = text_item(style, word);
+                    if let InlineItem::Text { ref mut width, .. } = item {
+                        *width += extra * word.matches(' ').count() as f32;
+                    }
+                    result.push(item);
+                }
+            }
+            item => result.push(item),
+        }
+    }
+    result
+}
+
+fn append_fragment<'a>(
+    stack: &mut [LayoutBox<'a>],
+    line_box: &mut LayoutBox<'a>,
+    fragment: LayoutBox<'a>,
+) {
+    match stack.last_mut() {
+        Some(parent) => parent.children.push(fragment),
+        None => line_box.children.push(fragment),
+    }
+}
+
+/// Flattens an inline subtree, appending its text to `text` with white space collapsed.
+fn collect_inline_items<'a>(
+    layout_box: LayoutBox<'a>,
+    items: &mut Vec<InlineItem<'a>>,
+    text: &mut String,
+) {
+    let style = match layout_box.box_type {
+        BoxType::InlineNode(style) => style,
+        BoxType::Marker(style, ref marker, _) => {
+            push_text(style, marker, items, text);
+            return;
+        }
+        _ if layout_box.is_out_of_flow() => {
+            items.push(InlineItem::OutOfFlow(layout_box));
+            return;
+        }
+        _ if layout_box.is_float() => {
+            items.push(InlineItem::Float(layout_box));
+            return;
+        }
+        _ if layout_box.is_inline_block() => {
+            items.push(InlineItem::Atomic(layout_box));
+            text.push(OBJECT_REPLACEMENT);
+            return;
+        }
+        _ => {
+            // Block-level boxes inside inline content sit on a line of their own.
+            items.push(InlineItem::Break(BreakOpportunity::Mandatory));
+            items.push(InlineItem::Atomic(layout_box));
+            items.push(InlineItem::Break(BreakOpportunity::Mandatory));
+            text.push(OBJECT_REPLACEMENT);
+            return;
+        }
+    };
+
+    match style.node.typ {
+        NodeType::Text(ref data) => push_text(style, data, items, text),
+        NodeType::Element(ref elem) if elem.tag_name == "br" => {
+            // A line feed, so that UAX #14 sees the forced break.
+            text.push('\n');
+            items.push(InlineItem::Text {
+                style,
+                text: "\n".to_string(),
+                runs: Vec::new(),
+                width: 0.0,
+                trailing_space: 0.0,
+            });
+        }
+        NodeType::Element(_) => {
+            items.push(InlineItem::Open(style));
+            for child in layout_box.children {
+                collect_inline_items(child, items, text);
+            }
+            items.push(InlineItem::Close(style));
+        }
+    }
+}
+
+/// Appends a text item, with white space collapsed, and its text to `text`.
+fn push_text<'a>(
+    style: &'a StyledNode<'a>,
+    data: &str,
+    items: &mut Vec<InlineItem<'a>>,
+    text: &mut String,
+) {
+    let collapsed = collapse_white_space(data, text.is_empty() || text.ends_with(' '));
+    text.push_str(&collapsed);
+    items.push(InlineItem::Text {
+        style,
+        text: collapsed,
+        runs: Vec::new(),
+        width: 0.0,
+        trailing_space: 0.0,
+    });
+}
+
+/// Splits text items at the UAX #14 break opportunities of the whole formatting context's
+/// text, and measures the resulting pieces.
+fn split_at_break_opportunities<'a>(items: Vec<InlineItem<'a>>, text: &str) -> Vec<InlineItem<'a>> {
+    // The end of the text is always a mandatory break, which isn't ours to take unless the
+    // text ends with a forced break; the empty line after that is a line of its own.
+    let forced_end = text.ends_with(is_forced_break);
+    let mut breaks = linebreaks(text)
+        .filter(|&(i, _)| i < text.len() || forced_end)
+        .peekable();
+
+    let mut result = Vec::new();
+    let mut offset = 0;
+    let mut last_style = None;
+    for item in items {
+        let (style, data) = match item {
+            InlineItem::Text { style, text, .. } => (style, text),
+            InlineItem::Atomic(_) => {
+                offset += OBJECT_REPLACEMENT.len_utf8();
+                result.push(item);
+                while let Some((_, opportunity)) = breaks.next_if(|&(i, _)| i <= offset) {
+                    result.push(InlineItem::Break(opportunity));
+                }
+                continue;
+            }
+            item => {
+                result.push(item);
+                continue;
+            }
+        };
+
+        // Breaks at the very end of an item go right after it, ahead of any `Open`s.
+        let end = offset + data.len();
+        let mut start = offset;
+        while let Some((i, opportunity)) = breaks.next_if(|&(i, _)| i <= end) {
+            if i > start {
+                result.push(text_item(style, &data[start - offset..i - offset]));
+                start = i;
+            }
+            result.push(InlineItem::Break(opportunity));
+        }
+        if end > start {
+            result.push(text_item(style, &data[start - offset..]));
+        }
+        offset = end;
+        last_style = Some(style);
+    }
+    if let (true, Some(style)) = (forced_end, last_style) {
+        result.push(text_item(style, ""));
+    }
+    result
+}
+
+/// Whether `c` forces a line break, as the characters of the UAX #14 classes BK, CR, LF and
+/// NL do.
+fn is_forced_break(c: char) -> bool {
+    matches!(
+        c,
+        '\n' | '\r' | '\u{b}' | '\u{c}' | '\u{85}' | '\u{2028}' | '\u{2029}'
+    )
+}
+
+fn text_item<'a>(style: &'a StyledNode<'a>, text: &str) -> InlineItem<'a> {
+    // Forced breaks end lines without taking up space on them.
+    let text = text.trim_end_matches(is_forced_break);
+    let runs = shape(text, &style.font());
+    let width = runs.iter().map(GlyphRun::width).sum();
+    let trailing_space = advance_from(&runs, text.trim_end_matches(' ').len());
+
+    InlineItem::Text {
+        style,
+        text: text.to_string(),
+        runs,
+        width,
+        trailing_space,
+    }
+}
+
+/// The advance of the glyphs `runs` draws for their text from byte `start` on.
+fn advance_from(runs: &[GlyphRun], start: usize) -> f32 {
+    let mut offset = 0;
+    let mut advance = 0.0;
+    for run in runs {
+        advance += run
+            .glyphs
+            .iter()
+            .filter(|glyph| offset + glyph.cluster >= start)
+            .map(|glyph| glyph.x_advance)
+            .sum::<f32>();
+        offset += run.text.len();
+    }
+    advance
+}
+
+/// Drops the glyphs of `runs` for their text from byte `len` on, and that text.
+fn truncate_runs(runs: &mut Vec<GlyphRun>, len: usize) {
+    let mut offset = 0;
+    for run in runs.iter_mut() {
+        let end = len.saturating_sub(offset).min(run.text.len());
+        offset += run.text.len();
+        run.text.truncate(end);
+        run.glyphs.retain(|glyph| glyph.cluster < end);
+    }
+    runs.retain(|run| !run.text.is_empty());
+}
+
+/// Groups items into chunks that can't be broken inside. The flag tells whether the line
+/// must break after the chunk.
+fn chunks(items: Vec<InlineItem>) -> Vec<(Vec<InlineItem>, bool)> {
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut opportunity = None;
+    for item in items {
+        match item {
+            InlineItem::Break(BreakOpportunity::Mandatory) => {
+                opportunity = Some(BreakOpportunity::Mandatory)
+            }
+            InlineItem::Break(o) => {
+                opportunity = opportunity.or(Some(o));
+            }
+            // Closing boxes stick to the content before them.
+            InlineItem::Close(_) => chunk.push(item),
+            _ => {
+                if let Some(o) = opportunity.take() {
+                    chunks.push((chunk, o == BreakOpportunity::Mandatory));
+                    chunk = Vec::new();
+                }
+                chunk.push(item);
+            }
+        }
+    }
+    if !chunk.is_empty() {
+        chunks.push((chunk, opportunity == Some(BreakOpportunity::Mandatory)));
+    }
+    chunks
+}
+
+fn item_width(item: &InlineItem) -> f32 {
+    match *item {
+        InlineItem::Open(style) => inline_edges(style).0,
+        InlineItem::Close(style) => inline_edges(style).1,
+        InlineItem::Text { width, .. } => width,
+        InlineItem::Atomic(ref atomic) => atomic.dimensions.margin_box().width,
+        InlineItem::OutOfFlow(_) | InlineItem::Float(_) | InlineItem::Break(_) => 0.0,
+    }
+}
+
+/// The widths of the margin, border and padding at the start and at the end of an inline box.
+pub(crate) fn inline_edges(style: &StyledNode) -> (f32, f32) {
+    let zero = Length(0.0, Px);
+    let start = [
+        style.lookup("margin-left", "margin", &zero),
+        style.lookup("border-left-width", "border-width", &zero),
+        style.lookup("padding-left", "padding", &zero),
+    ];
+    let end = [
+        style.lookup("margin-right", "margin", &zero),
+        style.lookup("border-right-width", "border-width", &zero),
+        style.lookup("padding-right", "padding", &zero),
+    ];
+    (
+        start.iter().map(|v| v.to_px()).sum(),
+        end.iter().map(|v| v.to_px()).sum(),
+    )
+}
+
+/// Collapses each sequence of white space into a single space. `space` tells whether the
+/// preceding text ends with a space, in which case leading white space is dropped.
+pub(crate) fn collapse_white_space(data: &str, mut space: bool) -> String {
+    let mut collapsed = String::new();
+    for c in data.chars() {
+        if c.is_whitespace() {
+            if !space {
+                collapsed.push(' ');
+            }
+            space = true;
+        } else {
+            collapsed.push(c);
+            space = false;
+        }
+    }
+    collapsed
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::display::{build_display_list, DisplayCommand};
+    use crate::layout::testing::{find, layout_html};
+    use crate::layout::{BoxType, LayoutBox, Rect};
+
+    fn collect_text(layout_box: &LayoutBox, runs: &mut Vec<String>) {
+        if let BoxType::TextRun(_, ref text, _) = layout_box.box_type {
+            runs.push(text.clone());
+        }
+        for child in &layout_box.children {
+            collect_text(child, runs);
+        }
+    }
+
+    /// The text of each line box under `layout_box`.
+    fn line_texts(layout_box: &LayoutBox) -> Vec<String> {
+        if let BoxType::LineBox { .. } = layout_box.box_type {
+            let mut runs = Vec::new();
+            collect_text(layout_box, &mut runs);
+            return vec![runs.concat()];
+        }
+        layout_box.children.iter().flat_map(line_texts).collect()
+    }
+
+    /// The first line box under `layout_box`.
+    fn first_line<'b>(layout_box: &'b LayoutBox<'b>) -> &'b LayoutBox<'b> {
+        match layout_box.box_type {
+            BoxType::LineBox { .. } => layout_box,
+            _ => first_line(&layout_box.children[0]),
+        }
+    }
+
+    /// Checks that each text run box is shaped into glyph runs of its text, which fill it.
+    fn check_glyph_runs(layout_box: &LayoutBox) {
+        if let BoxType::TextRun(_, ref text, ref runs) = layout_box.box_type {
+            let shaped: String = runs.iter().map(|run| run.text.as_str()).collect();
+            assert_eq!(&shaped, text);
+            let width: f32 = runs.iter().map(|run| run.width()).sum();
+            assert_eq!(width, layout_box.dimensions.content.width);
+        }
+        for child in &layout_box.children {
+            check_glyph_runs(child);
+        }
+    }
+
+    // The test font's letters are 8px wide at 16px, its spaces 4px, and its ascent and
+    // descent 12.8px and 3.2px.
+
+    #[test]
+    fn test_line_breaking() {
+        let root = layout_html(
+            "<p>The quick brown fox jumps over the lazy dog and keeps running \
+             <em>far   away\n from </em>here</p>",
+            "p { line-height: 20px; }",
+            Rect::new(0.0, 0.0, 120.0, 600.0),
+        );
+
+        let anonymous_block = &root.children[0];
+        assert!(matches!(
+            anonymous_block.box_type,
+            BoxType::AnonymousBlock(_)
+        ));
+        assert_eq!(
+            line_texts(anonymous_block),
+            [
+                "The quick brown",
+                "fox jumps over",
+                "the lazy dog and",
+                "keeps running",
+                "far away from",
+                "here"
+            ]
+        );
+        let lines = &anonymous_block.children;
+        for (i, line) in lines.iter().enumerate() {
+            assert!(matches!(line.box_type, BoxType::LineBox { .. }));
+            assert_eq!(
+                line.dimensions.content.y,
+                anonymous_block.dimensions.content.y + 20.0 * i as f32
+            );
+            assert_eq!(line.dimensions.content.height, 20.0);
+        }
+        // "the lazy dog and" fills 116px of the 120px.
+        let last = lines[2].children.last().unwrap().dimensions.content;
+        assert_eq!(last.x + last.width, 116.0);
+        assert_eq!(anonymous_block.dimensions.content.height, 120.0);
+
+        // Trailing spaces leave the glyph runs with the text, and the text is painted with
+        // the glyph runs it was laid out with.
+        check_glyph_runs(&root);
+        let mut runs = Vec::new();
+        collect_text(&root, &mut runs);
+        let painted: String = build_display_list(&root)
+            .into_iter()
+            .filter_map(|command| match command {
+                DisplayCommand::Glyphs(run, ..) => Some(run.text),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(painted, runs.concat());
+    }
+
+    #[test]
+    fn test_trailing_forced_break() {
+        let root = layout_html(
+            "<p>one<br />two<br /></p>",
+            "p { line-height: 20px; }",
+            Rect::new(0.0, 0.0, 800.0, 600.0),
+        );
+
+        let lines = &root.children[0].children;
+        assert_eq!(line_texts(&root), ["one", "two", ""]);
+        for line in lines {
+            assert_eq!(line.dimensions.content.height, 20.0);
+        }
+        let mut runs = Vec::new();
+        collect_text(&lines[2], &mut runs);
+        assert_eq!(runs, [""]);
+    }
+
+    #[test]
+    fn test_line_alignment() {
+        let root = layout_html(
+            "<div>\
+             <p class=\"center\">hi</p>\
+             <p class=\"right\">hi</p>\
+             <p class=\"aligned\">a<span class=\"top\">b</span><span class=\"super\">c</span></p>\
+             </div>",
+            ".center { text-align: center; line-height: 30px; }
+             .right { text-align: end; }
+             .aligned { line-height: 20px; }
+             .top { vertical-align: top; line-height: 50px; }
+             .super { vertical-align: super; }",
+            Rect::new(0.0, 0.0, 400.0, 600.0),
+        );
+        let line = |class| first_line(find(&root, class).unwrap());
+
+        // "hi" is 16px wide.
+        let centered = line("center");
+        assert_eq!(centered.dimensions.content.height, 30.0);
+        assert_eq!(centered.children[0].dimensions.content.x, 192.0);
+        assert_eq!(line("right").children[0].dimensions.content.x, 384.0);
+
+        // The top-aligned box sets the line height, its content area in the middle of its
+        // line height. The superscript, raised by a third of an em, is the highest of the
+        // rest, its content area 2px below the line top within its 20px line height.
+        let aligned = line("aligned");
+        let line_top = aligned.dimensions.content.y;
+        assert_eq!(aligned.dimensions.content.height, 50.0);
+        let text = aligned.children[0].dimensions.content;
+        let top = aligned.children[1].dimensions.content;
+        let sup = aligned.children[2].dimensions.content;
+        let near = |a: f32, b: f32| (a - b).abs() < 0.01;
+        assert_eq!(top.y - line_top, 17.0);
+        assert!(near(sup.y - line_top, 2.0));
+        assert!(near(text.y, sup.y + 16.0 / 3.0));
+    }
+
+    #[test]
+    fn test_inline_block() {
+        let root = layout_html(
+            "<p>text <span class=\"block\"><div>a</div>b</span><span class=\"clipped\">c</span> end</p>",
+            "p { line-height: 20px; }
+             .block { display: inline-block; padding-bottom: 10px; }
+             .clipped { display: inline-block; overflow: hidden; }",
+            Rect::new(0.0, 0.0, 400.0, 600.0),
+        );
+
+        // Both boxes sit on the line with the text around them, as atomic inlines, as wide
+        // as their widest line.
+        let lines = &root.children[0].children;
+        assert_eq!(lines.len(), 1);
+        let line = &lines[0];
+        let fragments = &line.children;
+        assert_eq!(fragments.len(), 4);
+        let text = fragments[0].dimensions.content;
+        let block = find(&root, "block").unwrap();
+        let clipped = find(&root, "clipped").unwrap().dimensions.margin_box();
+        assert_eq!(text.width, 36.0);
+        assert_eq!(block.dimensions.margin_box().x, 36.0);
+        assert_eq!(block.dimensions.content.width, 8.0);
+        assert_eq!(clipped.x, 44.0);
+        assert_eq!(fragments[3].dimensions.content.x, 52.0);
+
+        // The inline-block's last line sits on the baseline of the line; the clipped one's
+        // bottom margin edge does.
+        let baseline = match line.box_type {
+            BoxType::LineBox { baseline } => line.dimensions.content.y + baseline,
+            _ => unreachable!(),
+        };
+        let mut runs = Vec::new();
+        collect_text(block, &mut runs);
+        assert_eq!(runs, ["a", "b"]);
+        let last_line = block.children.last().unwrap().children.last().unwrap();
+        let b = last_line.children[0].dimensions.content;
+        let near = |a: f32, b: f32| (a - b).abs() < 0.01;
+        assert!(near(b.y, text.y));
+        assert!(near(clipped.y + clipped.height, baseline));
+        // The block's baseline is 34.8px down its 50px margin box, below the text's 14.8px.
+        assert!(near(line.dimensions.content.height, 50.0));
+    }
+
+    #[test]
+    fn test_text_overflow() {
+        let root = layout_html(
+            "<div class=\"box\">Supercalifragilistic <em>expialidocious</em></div>",
+            ".box { width: 100px; height: 20px; overflow: hidden; text-overflow: ellipsis; }",
+            Rect::new(0.0, 0.0, 800.0, 600.0),
+        );
 
-            containing_block.content.width += child.dimensions.margin_box().width;
-            max_height = max_height.max(child.dimensions.content.height);
+        // Ten letters and the 16px ellipsis fit in the 100px.
+        assert_eq!(
+            line_texts(&root),
+            ["Supercalif\u{2026}", "expialidoc\u{2026}"]
+        );
+        let lines = &root.children[0].children;
+        for line in lines {
+            let mut end = 0.0;
+            for fragment in &line.children {
+                let margin_box = fragment.dimensions.margin_box();
+                end = margin_box.x + margin_box.width;
+            }
+            assert_eq!(end, 96.0);
         }
+        check_glyph_runs(&root);
 
-        self.dimensions.content.height = max_height;
+        let list = build_display_list(&root);
+        let clip = list
+            .iter()
+            .position(
+                |command| matches!(command, DisplayCommand::PushClip(rect) if rect.height == 20.0),
+            )
+            .unwrap();
+        assert!(matches!(list.last(), Some(DisplayCommand::PopClip)));
+        assert!(list[clip..]
+            .iter()
+            .any(|command| matches!(command, DisplayCommand::Glyphs(..))));
     }
 }
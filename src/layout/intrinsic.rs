@@ -0,0 +1,255 @@
+use super::inline::{collapse_white_space, inline_edges};
+use super::{BoxType, LayoutBox, Length, Px};
+use crate::html::NodeType;
+use crate::style::{FlexDirection, FlexWrap, StyledNode};
+use crate::text::calc_text_region;
+use unicode_linebreak::linebreaks;
+
+/// The widths a box takes when laid out as narrow as possible without overflowing, and
+/// without breaking lines anywhere but at forced breaks.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IntrinsicSizes {
+    pub min_content: f32,
+    pub max_content: f32,
+}
+
+impl IntrinsicSizes {
+    pub(crate) fn fixed(width: f32) -> Self {
+        Self {
+            min_content: width,
+            max_content: width,
+        }
+    }
+
+    fn max(self, other: IntrinsicSizes) -> Self {
+        Self {
+            min_content: self.min_content.max(other.min_content),
+            max_content: self.max_content.max(other.max_content),
+        }
+    }
+
+    fn map<F: Fn(f32) -> f32>(self, f: F) -> Self {
+        Self {
+            min_content: f(self.min_content),
+            max_content: f(self.max_content),
+        }
+    }
+
+    /// The width a box shrinks to when `available` is the space it may take.
+    pub fn shrink_to_fit(self, available: f32) -> f32 {
+        self.max_content.min(self.min_content.max(available))
+    }
+}
+
+/// Accumulates the intrinsic sizes of inline content.
+#[derive(Default)]
+struct InlineMeasure {
+    sizes: IntrinsicSizes,
+    /// Width of the current line, when breaking only at forced breaks.
+    line: f32,
+    /// Width of the content since the last break opportunity.
+    word: f32,
+    /// Whether the text so far ends with a collapsible space.
+    space: bool,
+}
+
+impl<'a> LayoutBox<'a> {
+    /// Measures the margin box of a box that hasn't been laid out yet. Percentages, which
+    /// depend on the containing block, count as zero.
+    pub(crate) fn intrinsic_sizes(&self) -> IntrinsicSizes {
+        let style = match self.box_type {
+            BoxType::BlockNode(style) => style,
+            _ => return self.content_intrinsic_sizes(),
+        };
+
+        let zero = Length(0.0, Px);
+        let edges: f32 = [
+            self.edge_value("border-left-width", "border-width"),
+            self.edge_value("border-right-width", "border-width"),
+            self.edge_value("padding-left", "padding"),
+            self.edge_value("padding-right", "padding"),
+        ]
+        .iter()
+        .map(|v| v.to_px())
+        .sum();
+        let margins: f32 = [
+            style.lookup("margin-left", "margin", &zero),
+            style.lookup("margin-right", "margin", &zero),
+        ]
+        .iter()
+        .map(|v| v.to_px())
+        .sum();
+        let content_width = |name: &str| match style.value(name) {
+            Some(value @ Length(..)) => Some(self.content_size(value.to_px(), edges)),
+            _ => None,
+        };
+
+        let mut sizes = match content_width("width") {
+            // A table is never narrower than its contents.
+            Some(width) if self.is_table_wrapper() => {
+                IntrinsicSizes::fixed(width.max(self.content_intrinsic_sizes().min_content))
+            }
+            Some(width) => IntrinsicSizes::fixed(width),
+            None => self.content_intrinsic_sizes(),
+        };
+        if let Some(max_width) = content_width("max-width") {
+            sizes = sizes.map(|width| width.min(max_width));
+        }
+        if let Some(min_width) = content_width("min-width") {
+            sizes = sizes.map(|width| width.max(min_width));
+        }
+        sizes.map(|width| width + edges + margins)
+    }
+
+    /// Measures the contents of a box, without its own margins, borders and padding.
+    pub(crate) fn content_intrinsic_sizes(&self) -> IntrinsicSizes {
+        match self.box_type {
+            BoxType::BlockNode(_) if self.is_replaced() => {
+                IntrinsicSizes::fixed(self.replaced_width())
+            }
+            BoxType::BlockNode(_) if self.is_grid_container() => self.grid_intrinsic_sizes(),
+            // Captions don't widen a table beyond their min-content width.
+            BoxType::BlockNode(_) if self.is_table_wrapper() => self
+                .children
+                .iter()
+                .map(|child| match child.box_type {
+                    BoxType::Table(_) => child.table_intrinsic_sizes(),
+                    _ => IntrinsicSizes::fixed(child.intrinsic_sizes().min_content),
+                })
+                .fold(IntrinsicSizes::default(), IntrinsicSizes::max),
+            BoxType::Table(_) | BoxType::AnonymousTable(_) => self.table_intrinsic_sizes(),
+            BoxType::BlockNode(style) if self.is_flex_container() => {
+                let items = self.children.iter().filter(|child| !child.is_out_of_flow());
+                let row = matches!(
+                    style.flex_direction(),
+                    FlexDirection::Row | FlexDirection::RowReverse
+                );
+                if !row {
+                    return items
+                        .map(|item| item.intrinsic_sizes())
+                        .fold(IntrinsicSizes::default(), IntrinsicSizes::max);
+                }
+                // Items on a row sit side by side, unless they may wrap onto lines of their own.
+                let wraps = style.flex_wrap() != FlexWrap::NoWrap;
+                items.map(|item| item.intrinsic_sizes()).fold(
+                    IntrinsicSizes::default(),
+                    |sizes, item| IntrinsicSizes {
+                        min_content: if wraps {
+                            sizes.min_content.max(item.min_content)
+                        } else {
+                            sizes.min_content + item.min_content
+                        },
+                        max_content: sizes.max_content + item.max_content,
+                    },
+                )
+            }
+            BoxType::BlockNode(_) => self
+                .children
+                .iter()
+                .filter(|child| !child.is_out_of_flow())
+                .map(|child| child.intrinsic_sizes())
+                .fold(IntrinsicSizes::default(), IntrinsicSizes::max),
+            BoxType::AnonymousBlock(_) | BoxType::InlineNode(_) => {
+                let mut measure = InlineMeasure {
+                    space: true,
+                    ..Default::default()
+                };
+                for child in &self.children {
+                    measure.add(child);
+                }
+                measure.sizes
+            }
+            // Outside markers hang beside their list item; inside ones are measured as text.
+            BoxType::Marker(..) => IntrinsicSizes::default(),
+            BoxType::Image(..) => unreachable!("Images are measured by their replaced element"),
+            BoxType::LineBox { .. } | BoxType::TextRun(..) => {
+                unreachable!("Intrinsic sizes are measured before layout")
+            }
+        }
+    }
+}
+
+impl InlineMeasure {
+    fn add(&mut self, layout_box: &LayoutBox) {
+        match layout_box.box_type {
+            BoxType::InlineNode(style) => match style.node.typ {
+                NodeType::Text(ref data) => self.add_text(style, data),
+                NodeType::Element(ref elem) if elem.tag_name == "br" => self.break_line(),
+                NodeType::Element(_) => {
+                    let (start, end) = inline_edges(style);
+                    self.add_width(start);
+                    for child in &layout_box.children {
+                        self.add(child);
+                    }
+                    self.add_width(end);
+                }
+            },
+            BoxType::Marker(style, ref text, _) => self.add_text(style, text),
+            _ if layout_box.is_out_of_flow() => {}
+            _ if layout_box.is_inline_block() => {
+                // Atomic inlines can't be broken, but lines may break on either side.
+                let sizes = layout_box.intrinsic_sizes();
+                self.line += sizes.max_content;
+                self.sizes = self.sizes.max(IntrinsicSizes {
+                    min_content: sizes.min_content,
+                    max_content: self.line,
+                });
+                self.word = 0.0;
+                self.space = false;
+            }
+            _ if layout_box.is_float() => {
+                // Floats sit beside the line, so they add to its width without breaking it.
+                let sizes = layout_box.intrinsic_sizes();
+                self.sizes.min_content = self.sizes.min_content.max(sizes.min_content);
+                self.line += sizes.max_content;
+                self.sizes.max_content = self.sizes.max_content.max(self.line);
+            }
+            _ => {
+                // Block-level boxes inside inline content sit on a line of their own.
+                self.break_line();
+                self.sizes = self.sizes.max(layout_box.intrinsic_sizes());
+            }
+        }
+    }
+
+    fn add_width(&mut self, width: f32) {
+        self.line += width;
+        self.word += width;
+        self.sizes = self.sizes.max(IntrinsicSizes {
+            min_content: self.word,
+            max_content: self.line,
+        });
+    }
+
+    fn break_line(&mut self) {
+        self.line = 0.0;
+        self.word = 0.0;
+        self.space = true;
+    }
+
+    fn add_text(&mut self, style: &StyledNode, data: &str) {
+        let text = collapse_white_space(data, self.space);
+        if text.is_empty() {
+            return;
+        }
+        self.space = text.ends_with(' ');
+
+        let font = style.font();
+        let mut start = 0;
+        for (end, _) in linebreaks(&text) {
+            let segment = &text[start..end];
+            let width = calc_text_region(segment, &font).width;
+            // Trailing spaces hang at the end of a line, so they don't count for min-content.
+            let trailing_space =
+                width - calc_text_region(segment.trim_end_matches(' '), &font).width;
+            self.add_width(width - trailing_space);
+            self.line += trailing_space;
+
+            // The end of the text isn't a break opportunity unless it ends with a space.
+            if end < text.len() || segment.ends_with(' ') {
+                self.word = 0.0;
+            }
+            start = end;
+        }
+    }
+}
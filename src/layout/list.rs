@@ -0,0 +1,373 @@
+use super::{BoxType, Keyword, LayoutBox, Rect};
+use crate::css::Value;
+use crate::style::{Display, StyledNode};
+use crate::text::{font_metrics, shape, GlyphRun};
+
+/// The counter that numbers list items.
+const LIST_ITEM: &str = "list-item";
+
+/// A counter in scope, as set up by `counter-reset`, or by the first increment or set of a
+/// counter that isn't in scope.
+#[derive(Debug)]
+struct Counter {
+    name: String,
+    value: i32,
+    /// Set by `reversed()`, which makes list items count down.
+    reversed: bool,
+    /// Depth of the element that created the counter. The counter is in scope for it, its
+    /// descendants and its following siblings.
+    depth: usize,
+}
+
+/// The CSS counters in scope at some point of a walk through the tree in document order.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    depth: usize,
+    /// Innermost last.
+    counters: Vec<Counter>,
+}
+
+impl Counters {
+    /// Enters an element, applying its `counter-reset`, then its `counter-increment` and
+    /// `counter-set`. List items also increment the `list-item` counter, unless they
+    /// increment it themselves.
+    pub(crate) fn enter(&mut self, style: &StyledNode) {
+        self.depth += 1;
+        for (name, value, reversed) in counter_changes(style.value("counter-reset")) {
+            let value = match value {
+                Some(value) => value,
+                // A reversed counter starts from the number of times its children step it.
+                None if reversed => {
+                    1 - style
+                        .children
+                        .iter()
+                        .filter_map(|child| self.increment(child, &name, true))
+                        .sum::<i32>()
+                }
+                None => 0,
+            };
+            self.reset(&name, value, reversed);
+        }
+
+        let increments = counter_changes(style.value("counter-increment"));
+        for (name, value, _) in &increments {
+            self.counter(name).value += value.unwrap_or(1);
+        }
+        if !increments.iter().any(|change| change.0 == LIST_ITEM) {
+            if let Some(value) = self.increment(style, LIST_ITEM, self.is_reversed(LIST_ITEM)) {
+                self.counter(LIST_ITEM).value += value;
+            }
+        }
+
+        for (name, value, _) in counter_changes(style.value("counter-set")) {
+            self.counter(&name).value = value.unwrap_or(0);
+        }
+    }
+
+    /// Leaves the element entered last, ending the scope of the counters its children
+    /// created.
+    pub(crate) fn leave(&mut self) {
+        let depth = self.depth;
+        self.counters.retain(|counter| counter.depth <= depth);
+        self.depth -= 1;
+    }
+
+    /// The value of the innermost counter called `name`, or 0 when none is in scope.
+    pub(crate) fn value(&self, name: &str) -> i32 {
+        self.find(name).map_or(0, |counter| counter.value)
+    }
+
+    fn find(&self, name: &str) -> Option<&Counter> {
+        self.counters
+            .iter()
+            .rev()
+            .find(|counter| counter.name == name)
+    }
+
+    fn is_reversed(&self, name: &str) -> bool {
+        matches!(self.find(name), Some(counter) if counter.reversed)
+    }
+
+    /// Creates a counter on the current element, replacing one created by a previous sibling.
+    fn reset(&mut self, name: &str, value: i32, reversed: bool) {
+        let depth = self.depth;
+        self.counters
+            .retain(|counter| !(counter.name == name && counter.depth == depth));
+        self.counters.push(Counter {
+            name: name.to_string(),
+            value,
+            reversed,
+            depth,
+        });
+    }
+
+    /// The innermost counter called `name`, created with a value of 0 when none is in scope.
+    fn counter(&mut self, name: &str) -> &mut Counter {
+        match self
+            .counters
+            .iter()
+            .rposition(|counter| counter.name == name)
+        {
+            Some(index) => &mut self.counters[index],
+            None => {
+                self.reset(name, 0, false);
+                self.counters.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// How much an element steps the counter called `name`, when it does. List items step
+    /// `list-item` by one, down when the counter is `reversed`.
+    fn increment(&self, style: &StyledNode, name: &str, reversed: bool) -> Option<i32> {
+        let explicit = counter_changes(style.value("counter-increment"))
+            .into_iter()
+            .find(|change| change.0 == name);
+        match explicit {
+            Some((_, value, _)) => Some(value.unwrap_or(1)),
+            None if name == LIST_ITEM && style.display() == Display::ListItem => {
+                Some(if reversed { -1 } else { 1 })
+            }
+            None => None,
+        }
+    }
+}
+
+/// Reads a `counter-reset`, `counter-increment` or `counter-set` value into the counters it
+/// names, with their values when given and whether they are `reversed()`.
+fn counter_changes(value: Option<Value>) -> Vec<(String, Option<i32>, bool)> {
+    let values = match value {
+        Some(Value::List(values)) => values,
+        Some(value) => vec![value],
+        None => return Vec::new(),
+    };
+    let mut changes: Vec<(String, Option<i32>, bool)> = Vec::new();
+    for value in values {
+        match value {
+            Keyword(ref name) if name == "none" => {}
+            Keyword(name) => changes.push((name, None, false)),
+            Value::Function(ref function, ref args) if function == "reversed" => {
+                if let Some(Keyword(name)) = args.first() {
+                    changes.push((name.clone(), None, true));
+                }
+            }
+            Value::Number(n) => {
+                if let Some(change) = changes.last_mut() {
+                    change.1 = Some(n as i32);
+                }
+            }
+            _ => {}
+        }
+    }
+    changes
+}
+
+/// Builds the marker box of a list item from its `list-style-type` and the value of the
+/// `list-item` counter, or `None` for `list-style-type: none`.
+pub(crate) fn build_marker<'a>(
+    style: &'a StyledNode<'a>,
+    counters: &Counters,
+) -> Option<LayoutBox<'a>> {
+    let text = match style.value("list-style-type") {
+        Some(Keyword(ref name)) if name == "none" => return None,
+        Some(Value::Str(text)) => text,
+        Some(Keyword(name)) => marker_text(&name, counters.value(LIST_ITEM)),
+        _ => marker_text("disc", counters.value(LIST_ITEM)),
+    };
+    let runs = shape(&text, &style.font());
+    Some(LayoutBox::new(BoxType::Marker(style, text, runs)))
+}
+
+const LOWER_ALPHA: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPER_ALPHA: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const LOWER_GREEK: &str = "αβγδεζηθικλμνξοπρστυφχψω";
+const HIRAGANA: &str =
+    "あいうえおかきくけこさしすせそたちつてとなにぬねのはひふへほまみむめもやゆよらりるれろわをん";
+const KATAKANA: &str =
+    "アイウエオカキクケコサシスセソタチツテトナニヌネノハヒフヘホマミムメモヤユヨラリルレロワヲン";
+const HIRAGANA_IROHA: &str = "いろはにほへとちりぬるをわかよたれそつねならむうゐのおくやまけふこえてあさきゆめみしゑひもせす";
+const KATAKANA_IROHA: &str = "イロハニホヘトチリヌルヲワカヨタレソツネナラムウヰノオクヤマケフコエテアサキユメミシヱヒモセス";
+
+/// Formats the marker of a list item numbered `n` in one of the predefined counter styles
+/// of CSS Counter Styles §6, with its suffix.
+fn marker_text(list_style_type: &str, n: i32) -> String {
+    let suffix = match list_style_type {
+        "disc" | "circle" | "square" => " ",
+        "cjk-decimal" | "hiragana" | "katakana" | "hiragana-iroha" | "katakana-iroha" => "、",
+        _ => ". ",
+    };
+    format!("{}{}", counter_text(list_style_type, n), suffix)
+}
+
+/// Represents `n` in one of the predefined counter styles of CSS Counter Styles §6, as
+/// `counter()` does. Unknown styles, and numbers outside the range of a style, fall back to
+/// `decimal`.
+pub(crate) fn counter_text(list_style_type: &str, n: i32) -> String {
+    let representation = match list_style_type {
+        "disc" => return "•".to_string(),
+        "circle" => return "◦".to_string(),
+        "square" => return "▪".to_string(),
+        "decimal-leading-zero" if (0..10).contains(&n) => Some(format!("0{}", n)),
+        "lower-roman" => roman(n, false),
+        "upper-roman" => roman(n, true),
+        "lower-alpha" | "lower-latin" => alphabetic(n, LOWER_ALPHA),
+        "upper-alpha" | "upper-latin" => alphabetic(n, UPPER_ALPHA),
+        "lower-greek" => alphabetic(n, LOWER_GREEK),
+        "cjk-decimal" => numeric(n, "〇一二三四五六七八九"),
+        "hiragana" => alphabetic(n, HIRAGANA),
+        "katakana" => alphabetic(n, KATAKANA),
+        "hiragana-iroha" => alphabetic(n, HIRAGANA_IROHA),
+        "katakana-iroha" => alphabetic(n, KATAKANA_IROHA),
+        _ => None,
+    };
+    representation.unwrap_or_else(|| n.to_string())
+}
+
+/// Writes `n` with the positional `digits` of a numeric counter style, from zero up.
+fn numeric(n: i32, digits: &str) -> Option<String> {
+    let digits: Vec<char> = digits.chars().collect();
+    let mut value = n.unsigned_abs() as usize;
+    let mut representation = Vec::new();
+    loop {
+        representation.push(digits[value % digits.len()]);
+        value /= digits.len();
+        if value == 0 {
+            break;
+        }
+    }
+    if n < 0 {
+        representation.push('-');
+    }
+    Some(representation.iter().rev().collect())
+}
+
+/// Writes `n` with the `letters` of an alphabetic counter style, which has no zero: after
+/// the last letter comes the first one twice. Only defined from 1.
+fn alphabetic(n: i32, letters: &str) -> Option<String> {
+    if n < 1 {
+        return None;
+    }
+    let letters: Vec<char> = letters.chars().collect();
+    let mut value = n as usize;
+    let mut representation = Vec::new();
+    while value > 0 {
+        value -= 1;
+        representation.push(letters[value % letters.len()]);
+        value /= letters.len();
+    }
+    Some(representation.iter().rev().collect())
+}
+
+/// Writes `n` in roman numerals, which go from 1 to 3999.
+fn roman(n: i32, upper: bool) -> Option<String> {
+    const SYMBOLS: [(i32, &str); 13] = [
+        (1000, "m"),
+        (900, "cm"),
+        (500, "d"),
+        (400, "cd"),
+        (100, "c"),
+        (90, "xc"),
+        (50, "l"),
+        (40, "xl"),
+        (10, "x"),
+        (9, "ix"),
+        (5, "v"),
+        (4, "iv"),
+        (1, "i"),
+    ];
+    if !(1..4000).contains(&n) {
+        return None;
+    }
+    let mut value = n;
+    let mut representation = String::new();
+    for &(weight, symbol) in SYMBOLS.iter() {
+        while value >= weight {
+            representation.push_str(symbol);
+            value -= weight;
+        }
+    }
+    Some(if upper {
+        representation.to_uppercase()
+    } else {
+        representation
+    })
+}
+
+impl<'a> LayoutBox<'a> {
+    /// Places the outside marker of a list item, once the item is laid out, so that it ends
+    /// where the item's border box starts, on the baseline of its first line. Without a line,
+    /// it sits at the top of the item.
+    pub(crate) fn place_outside_marker(&mut self) {
+        let baseline = self.first_line_baseline();
+        let d = self.dimensions;
+        for child in &mut self.children {
+            if let BoxType::Marker(style, _, ref runs) = child.box_type {
+                let metrics = font_metrics(&style.font());
+                let width = runs.iter().map(GlyphRun::width).sum::<f32>();
+                let baseline = baseline.unwrap_or(d.content.y + metrics.ascent);
+                child.dimensions.content = Rect::new(
+                    d.border_box().x - width,
+                    baseline - metrics.ascent,
+                    width,
+                    metrics.ascent + metrics.descent,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::marker_text;
+    use crate::layout::testing::{find, layout_html};
+    use crate::layout::{BoxType, LayoutBox, Rect};
+
+    fn collect_markers<'b>(layout_box: &'b LayoutBox<'b>, markers: &mut Vec<&'b LayoutBox<'b>>) {
+        if let BoxType::Marker(..) = layout_box.box_type {
+            markers.push(layout_box);
+        }
+        for child in &layout_box.children {
+            collect_markers(child, markers);
+        }
+    }
+
+    #[test]
+    fn test_list_markers() {
+        assert_eq!(marker_text("lower-roman", 1994), "mcmxciv. ");
+        assert_eq!(marker_text("upper-alpha", 28), "AB. ");
+        assert_eq!(marker_text("cjk-decimal", 105), "一〇五、");
+        assert_eq!(marker_text("hiragana", 2), "い、");
+        assert_eq!(marker_text("lower-alpha", 0), "0. ");
+
+        let root = layout_html(
+            "<div>\
+             <ol start=\"3\"><li class=\"first\">a</li><li>b</li><li value=\"10\">c</li><li>d</li></ol>\
+             <ol reversed=\"\"><li>a</li><li>b</li><li>c</li></ol>\
+             <ul><li>a<ul><li>b</li></ul></li></ul>\
+             <div class=\"sections\"><p class=\"section\">a</p><p class=\"section\">b</p></div>\
+             </div>",
+            ".sections { counter-reset: list-item 4; }
+             .section { display: list-item; list-style-type: upper-roman; counter-increment: list-item 2; }",
+            Rect::new(0.0, 0.0, 800.0, 600.0),
+        );
+        let mut markers = Vec::new();
+        collect_markers(&root, &mut markers);
+        let texts: Vec<&str> = markers
+            .iter()
+            .map(|marker| match marker.box_type {
+                BoxType::Marker(_, ref text, _) => text.as_str(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            texts,
+            ["3. ", "4. ", "10. ", "11. ", "3. ", "2. ", "1. ", "• ", "◦ ", "VI. ", "VIII. "]
+        );
+
+        // Outside markers end where their list item starts, at the width of their glyphs.
+        let first = find(&root, "first").unwrap().dimensions.border_box();
+        let marker = markers[0].dimensions.content;
+        assert_eq!(marker.width, 20.0);
+        assert_eq!(marker.x + marker.width, first.x);
+        assert_eq!(markers[2].dimensions.content.width, 28.0);
+    }
+}
@@ -0,0 +1,364 @@
+use super::table::table_role;
+use super::{BoxType, LayoutBox, Rect};
+use crate::style::{Break, Display};
+
+/// How far below a page edge a position must be to count as past it.
+const EPSILON: f32 = 0.01;
+
+/// Splits a laid out tree across pages of `page_height`, pushing content that would straddle
+/// a page edge down to the next page, and returns the number of pages. Page `i` shows the
+/// part of the tree from `i * page_height` down. Lines and table rows break between each
+/// other, honouring `orphans` and `widows`; replaced elements, flex and grid containers and
+/// floats move as a whole when they fit on a page, as do boxes with `break-inside: avoid`.
+/// Forced breaks come from `break-before` and `break-after`. Out-of-flow boxes are left
+/// where they are.
+pub fn paginate(root: &mut LayoutBox, page_height: f32) -> usize {
+    paginate_with(root, |_| page_height)
+}
+
+/// Like `paginate`, for pages whose heights `page_height` gives by index. Each page shows the
+/// part of the tree below those the pages before it show.
+pub fn paginate_with<F: Fn(usize) -> f32>(root: &mut LayoutBox, page_height: F) -> usize {
+    if page_height(0) <= 0.0 {
+        return 1;
+    }
+    let mut paginator = Paginator {
+        page_height: &page_height,
+        started: false,
+    };
+    paginator.paginate_box(root);
+    let bottom = root.dimensions.margin_box().y + root.dimensions.margin_box().height;
+    paginator.page_index(bottom - EPSILON) + 1
+}
+
+struct Paginator<'h> {
+    page_height: &'h dyn Fn(usize) -> f32,
+    /// Whether content was placed yet. Forced breaks before the first content are ignored,
+    /// not to leave the first page blank.
+    started: bool,
+}
+
+impl<'h> Paginator<'h> {
+    fn page_index(&self, y: f32) -> usize {
+        let mut page = 0;
+        let mut top = 0.0;
+        loop {
+            let height = (self.page_height)(page);
+            // Pages without height end the document.
+            if y < top + height || height <= 0.0 {
+                return page;
+            }
+            top += height;
+            page += 1;
+        }
+    }
+
+    fn page_top(&self, page: usize) -> f32 {
+        (0..page).map(|page| (self.page_height)(page)).sum()
+    }
+
+    /// The top of the page after the one `y` is on.
+    fn next_page_top(&self, y: f32) -> f32 {
+        self.page_top(self.page_index(y) + 1)
+    }
+
+    fn next_page_height(&self, y: f32) -> f32 {
+        (self.page_height)(self.page_index(y) + 1)
+    }
+
+    fn at_page_top(&self, y: f32) -> bool {
+        y - self.page_top(self.page_index(y + EPSILON)) < EPSILON
+    }
+
+    /// Whether a rectangle crosses a page edge, while it could fit on a page of its own.
+    fn straddles(&self, rect: Rect) -> bool {
+        rect.height > 0.0
+            && rect.height <= (self.page_height)(self.page_index(rect.y + EPSILON))
+            && self.page_index(rect.y + EPSILON) != self.page_index(rect.y + rect.height - EPSILON)
+    }
+
+    /// How far down a forced break moves content at `y`: to the top of the next page, or of
+    /// the one after it for a break to a left or right page. The first page is a right one.
+    fn forced_break(&self, y: f32, kind: Break) -> f32 {
+        let mut page = self.page_index(y + EPSILON);
+        if !self.at_page_top(y) {
+            page += 1;
+        }
+        match (kind, page % 2) {
+            (Break::Left, 0) | (Break::Right, 1) => page += 1,
+            _ => {}
+        }
+        (self.page_top(page) - y).max(0.0)
+    }
+
+    /// Paginates the contents of a box whose top is in place, and returns how much taller it
+    /// got.
+    fn paginate_box(&mut self, layout_box: &mut LayoutBox) -> f32 {
+        match layout_box.box_type {
+            BoxType::AnonymousBlock(_) => self.paginate_lines(layout_box),
+            BoxType::Table(_) | BoxType::AnonymousTable(_) => self.paginate_rows(layout_box),
+            BoxType::BlockNode(_) if !layout_box.is_monolithic() => {
+                self.paginate_blocks(layout_box)
+            }
+            _ => {
+                self.started = true;
+                0.0
+            }
+        }
+    }
+
+    fn paginate_blocks(&mut self, parent: &mut LayoutBox) -> f32 {
+        let mut shift = 0.0;
+        let mut previous: Option<(usize, Break)> = None;
+        for index in 0..parent.children.len() {
+            let child = &parent.children[index];
+            if child.is_out_of_flow() || matches!(child.box_type, BoxType::Marker(..)) {
+                continue;
+            }
+            parent.children[index].translate(0.0, shift);
+
+            let child = &parent.children[index];
+            let (before, after, inside) = match child.box_type {
+                BoxType::BlockNode(style) => (
+                    style.break_before(),
+                    style.break_after(),
+                    style.break_inside(),
+                ),
+                _ => (Break::Auto, Break::Auto, Break::Auto),
+            };
+            let forced = match (previous.map_or(Break::Auto, |(_, after)| after), before) {
+                (Break::Left, _) | (_, Break::Left) => Some(Break::Left),
+                (Break::Right, _) | (_, Break::Right) => Some(Break::Right),
+                (Break::Page, _) | (_, Break::Page) => Some(Break::Page),
+                _ => None,
+            };
+            let avoid = matches!(previous, Some((_, Break::Avoid))) || before == Break::Avoid;
+
+            let rect = child.dimensions.border_box();
+            let mut moved = 0.0;
+            match forced {
+                Some(kind) if self.started => moved = self.forced_break(rect.y, kind),
+                _ if self.straddles(rect) && (child.is_monolithic() || inside == Break::Avoid) => {
+                    moved = self.next_page_top(rect.y) - rect.y;
+                    // A box that mustn't be parted from the previous one takes it along.
+                    if let Some((previous, _)) = previous.filter(|_| avoid) {
+                        let top = parent.children[previous].dimensions.border_box().y;
+                        if !self.at_page_top(top)
+                            && self.page_index(top + EPSILON) == self.page_index(rect.y + EPSILON)
+                            && rect.y + rect.height - top <= self.next_page_height(top)
+                        {
+                            let taken = self.next_page_top(top) - top;
+                            parent.children[previous].translate(0.0, taken);
+                            moved = taken;
+                        }
+                    }
+                }
+                _ => {}
+            }
+            let child = &mut parent.children[index];
+            child.translate(0.0, moved);
+            shift += moved;
+            shift += self.paginate_box(child);
+            previous = Some((index, after));
+        }
+        parent.dimensions.content.height += shift;
+        parent.place_outside_marker();
+        shift
+    }
+
+    /// Breaks the lines of an inline formatting context across pages, leaving at least
+    /// `orphans` lines of it at the bottom of a page and `widows` at the top of the next.
+    fn paginate_lines(&mut self, block: &mut LayoutBox) -> f32 {
+        let style = match block.box_type {
+            BoxType::AnonymousBlock(style) => style,
+            _ => return 0.0,
+        };
+        let (orphans, widows) = (style.line_count("orphans"), style.line_count("widows"));
+        let lines: Vec<usize> = (0..block.children.len())
+            .filter(|&index| matches!(block.children[index].box_type, BoxType::LineBox { .. }))
+            .collect();
+        let line_top =
+            |block: &LayoutBox, line: usize| block.children[lines[line]].dimensions.content.y;
+
+        let mut shift = 0.0;
+        // The first line on the current page.
+        let mut start = 0;
+        for line in 0..lines.len() {
+            let rect = block.children[lines[line]].dimensions.content;
+            if !self.straddles(rect) {
+                self.started = true;
+                continue;
+            }
+
+            let mut first = line;
+            if lines.len() - line < widows {
+                first = line
+                    .saturating_sub(widows - (lines.len() - line))
+                    .max(start + orphans)
+                    .min(line);
+            }
+            // Too few lines would be left behind: the whole paragraph moves when it can.
+            if first - start < orphans && start == 0 {
+                first = 0;
+            }
+            let top = line_top(block, first);
+            if first != line && self.at_page_top(top) {
+                first = line;
+            }
+
+            let top = line_top(block, first);
+            let moved = self.next_page_top(rect.y) - top;
+            for child in &mut block.children {
+                if child.dimensions.content.y >= top && !child.is_out_of_flow() {
+                    child.translate(0.0, moved);
+                }
+            }
+            shift += moved;
+            start = first;
+            self.started = true;
+        }
+        block.dimensions.content.height += shift;
+        shift
+    }
+
+    /// Breaks a table box between its rows, repeating its header group at the top of each
+    /// page the table continues on.
+    fn paginate_rows(&mut self, table: &mut LayoutBox) -> f32 {
+        let header = table
+            .children
+            .iter()
+            .position(|child| table_role(child) == Display::TableHeaderGroup);
+        let mut rows: Vec<(usize, Option<usize>)> = Vec::new();
+        for (index, child) in table.children.iter().enumerate() {
+            match table_role(child) {
+                Display::TableRow => rows.push((index, None)),
+                Display::TableColumn | Display::TableColumnGroup => {}
+                _ => rows.extend((0..child.children.len()).map(|row| (index, Some(row)))),
+            }
+        }
+        let row_top = |table: &LayoutBox, (index, row): (usize, Option<usize>)| match row {
+            Some(row) => table.children[index].children[row].dimensions.content.y,
+            None => table.children[index].dimensions.content.y,
+        };
+        rows.sort_by(|&a, &b| row_top(table, a).partial_cmp(&row_top(table, b)).unwrap());
+
+        // How far below the top of the header the rows after it start.
+        let header_offset = header.and_then(|header| {
+            let body = rows.iter().find(|path| path.0 != header)?;
+            Some(row_top(table, *body) - table.children[header].dimensions.content.y)
+        });
+
+        let mut shift = 0.0;
+        let mut repeated = Vec::new();
+        for (index, row) in rows {
+            let row_box = match row {
+                Some(row) => &mut table.children[index].children[row],
+                None => &mut table.children[index],
+            };
+            row_box.translate(0.0, shift);
+            let rect = row_box.dimensions.content;
+            if Some(index) != header && self.straddles(rect) {
+                let page_top = self.next_page_top(rect.y);
+                let mut top = page_top;
+                if let (Some(header), Some(offset)) = (header, header_offset) {
+                    if offset + rect.height <= self.next_page_height(rect.y) {
+                        top += offset;
+                        let mut header = table.children[header].clone();
+                        let y = header.dimensions.content.y;
+                        header.translate(0.0, page_top - y);
+                        repeated.push(header);
+                    }
+                }
+                let row_box = match row {
+                    Some(row) => &mut table.children[index].children[row],
+                    None => &mut table.children[index],
+                };
+                row_box.translate(0.0, top - rect.y);
+                shift += top - rect.y;
+            }
+            self.started = true;
+        }
+
+        // Row groups and columns cover their rows again.
+        for child in &mut table.children {
+            match table_role(child) {
+                Display::TableRowGroup | Display::TableHeaderGroup | Display::TableFooterGroup => {
+                    if let (Some(first), Some(last)) =
+                        (child.children.first(), child.children.last())
+                    {
+                        let top = first.dimensions.content.y;
+                        let last = last.dimensions.content;
+                        child.dimensions.content.y = top;
+                        child.dimensions.content.height = last.y + last.height - top;
+                    }
+                }
+                Display::TableColumn | Display::TableColumnGroup => {
+                    child.dimensions.content.height += shift;
+                    for column in &mut child.children {
+                        column.dimensions.content.height += shift;
+                    }
+                }
+                _ => {}
+            }
+        }
+        table.children.extend(repeated);
+        table.dimensions.content.height += shift;
+        shift
+    }
+}
+
+impl<'a> LayoutBox<'a> {
+    /// Whether a box moves to the next page as a whole rather than breaking inside.
+    fn is_monolithic(&self) -> bool {
+        matches!(self.box_type, BoxType::BlockNode(_))
+            && (self.is_replaced()
+                || self.is_flex_container()
+                || self.is_grid_container()
+                || self.is_float())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::paginate;
+    use crate::layout::testing::{find, layout_html};
+    use crate::layout::{BoxType, LayoutBox, Rect};
+
+    fn line_tops(layout_box: &LayoutBox) -> Vec<f32> {
+        let mut tops = Vec::new();
+        for child in &layout_box.children {
+            match child.box_type {
+                BoxType::LineBox { .. } => tops.push(child.dimensions.content.y),
+                _ => tops.extend(line_tops(child)),
+            }
+        }
+        tops
+    }
+
+    #[test]
+    fn test_pagination() {
+        let mut root = layout_html(
+            "<body>\
+             <div class=\"monolithic\"></div>\
+             <div class=\"forced\"></div>\
+             <div class=\"lines\">a<br />b<br />c<br />d</div>\
+             </body>",
+            "body { margin: 0; font-size: 10px; line-height: 10px; }
+             .monolithic { display: flex; height: 40px; margin-top: 80px; }
+             .forced { break-before: page; height: 65px; }",
+            Rect::new(0.0, 0.0, 800.0, 100.0),
+        );
+        let pages = paginate(&mut root, 100.0);
+
+        // The flex container would straddle the first page edge, so it starts the second
+        // page; the forced break starts the third.
+        let monolithic = find(&root, "monolithic").unwrap().dimensions.content;
+        assert_eq!(monolithic.y, 100.0);
+        let forced = find(&root, "forced").unwrap().dimensions.content;
+        assert_eq!(forced.y, 200.0);
+        // Three lines fit below it, but the third follows to leave two widows.
+        let tops = line_tops(find(&root, "lines").unwrap());
+        assert_eq!(tops, vec![265.0, 275.0, 300.0, 310.0]);
+        assert_eq!(pages, 4);
+    }
+}
@@ -0,0 +1,308 @@
+use super::{BoxType, EdgeSizes, Keyword, LayoutBox, Length, Px, Rect};
+use crate::css::Value;
+use crate::style::Position;
+
+/// One axis of the constraint of CSS 2.1 §10.3.7 and §10.6.4 that absolutely positioned boxes
+/// satisfy: `start + margin_start + edges + size + margin_end + end` equals the size of the
+/// containing block. `None` stands for `auto`.
+#[derive(Clone, Copy)]
+struct AxisConstraint {
+    start: Option<f32>,
+    margin_start: Option<f32>,
+    size: Option<f32>,
+    margin_end: Option<f32>,
+    end: Option<f32>,
+    /// Borders and padding.
+    edges: f32,
+    containing_block: f32,
+    /// Offset of the margin edge the box would have had in the normal flow.
+    static_start: f32,
+}
+
+/// The used start offset, margins and content size along an axis.
+struct AxisSolution {
+    start: f32,
+    margin_start: f32,
+    size: f32,
+    margin_end: f32,
+}
+
+impl<'a> LayoutBox<'a> {
+    /// Whether the box is the containing block of its absolutely positioned descendants.
+    pub(crate) fn is_positioned_container(&self) -> bool {
+        match self.box_type {
+            BoxType::BlockNode(style) => style.position() != Position::Static,
+            _ => false,
+        }
+    }
+
+    /// Lays out the out-of-flow descendants whose containing block is `containing_block`:
+    /// the absolutely positioned ones without a closer positioned ancestor when `absolute`
+    /// is set, and the fixed ones when `fixed` is.
+    pub(crate) fn layout_out_of_flow_descendants(
+        &mut self,
+        containing_block: Rect,
+        absolute: bool,
+        fixed: bool,
+    ) {
+        for child in &mut self.children {
+            if child.is_out_of_flow() {
+                let position = child.get_style_node().position();
+                if (absolute && position == Position::Absolute)
+                    || (fixed && position == Position::Fixed)
+                {
+                    child.layout_absolute(containing_block);
+                }
+            }
+
+            let absolute = absolute && !child.is_positioned_container();
+            if absolute || fixed {
+                child.layout_out_of_flow_descendants(containing_block, absolute, fixed);
+            }
+        }
+    }
+
+    /// Lays out an absolutely positioned box against the padding box of its containing block.
+    /// Its content position holds its static position on entry.
+    fn layout_absolute(&mut self, containing_block: Rect) {
+        let style = self.get_style_node();
+        let cb_width = containing_block.width;
+        let cb_height = containing_block.height;
+        let static_position = self.dimensions.content;
+
+        let auto = Keyword("auto".to_string());
+        let zero = Length(0.0, Px);
+        let used = |value: Value, base: f32| {
+            if value == auto {
+                None
+            } else {
+                Some(value.resolve(base))
+            }
+        };
+        let offset = |name: &str, base: f32| used(style.value_or(name, &auto), base);
+        let margin = |name: &str| used(style.lookup(name, "margin", &zero), cb_width);
+        let edge = |name: &str, fallback: &str| self.edge_value(name, fallback).resolve(cb_width);
+        let border = EdgeSizes {
+            left: edge("border-left-width", "border-width"),
+            right: edge("border-right-width", "border-width"),
+            top: edge("border-top-width", "border-width"),
+            bottom: edge("border-bottom-width", "border-width"),
+        };
+        let padding = EdgeSizes {
+            left: edge("padding-left", "padding"),
+            right: edge("padding-right", "padding"),
+            top: edge("padding-top", "padding"),
+            bottom: edge("padding-bottom", "padding"),
+        };
+
+        let d = &mut self.dimensions;
+        d.border = border;
+        d.padding = padding;
+
+        let horizontal_edges = d.border.left + d.border.right + d.padding.left + d.padding.right;
+        let vertical_edges = d.border.top + d.border.bottom + d.padding.top + d.padding.bottom;
+        let content_width = |name: &str| {
+            let value = style.value(name)?;
+            if let Keyword(_) = value {
+                return None;
+            }
+            Some(self.content_size(value.resolve(cb_width), horizontal_edges))
+        };
+
+        let horizontal = AxisConstraint {
+            start: offset("left", cb_width),
+            margin_start: margin("margin-left"),
+            size: content_width("width"),
+            margin_end: margin("margin-right"),
+            end: offset("right", cb_width),
+            edges: horizontal_edges,
+            containing_block: cb_width,
+            static_start: static_position.x - containing_block.x,
+        };
+        let sizes = self.content_intrinsic_sizes();
+        let shrink_to_fit = |available: f32| sizes.shrink_to_fit(available);
+        let mut solution = horizontal.solve(&shrink_to_fit);
+        if let Some(max_width) = content_width("max-width") {
+            if solution.size > max_width {
+                solution = horizontal.with_size(max_width).solve(&shrink_to_fit);
+            }
+        }
+        let min_width = content_width("min-width").unwrap_or(0.0);
+        if solution.size < min_width {
+            solution = horizontal.with_size(min_width).solve(&shrink_to_fit);
+        }
+
+        let d = &mut self.dimensions;
+        d.margin.left = solution.margin_start;
+        d.margin.right = solution.margin_end;
+        d.content.width = solution.size;
+        d.content.x = containing_block.x
+            + solution.start
+            + solution.margin_start
+            + d.border.left
+            + d.padding.left;
+
+        let vertical = AxisConstraint {
+            start: offset("top", cb_height),
+            margin_start: margin("margin-top"),
+            size: self.content_height("height", Some(cb_height)),
+            margin_end: margin("margin-bottom"),
+            end: offset("bottom", cb_height),
+            edges: vertical_edges,
+            containing_block: cb_height,
+            static_start: static_position.y - containing_block.y,
+        };
+
+        // The contents are laid out at the top of the containing block, then moved to where
+        // the constraint puts the box. A height given by `top` and `bottom` is as definite as
+        // an explicit one.
+        let height = match vertical {
+            AxisConstraint {
+                start: Some(top),
+                size: None,
+                end: Some(bottom),
+                ..
+            } => Some(
+                cb_height
+                    - top
+                    - bottom
+                    - vertical.margin_start.unwrap_or(0.0)
+                    - vertical.margin_end.unwrap_or(0.0)
+                    - vertical_edges,
+            ),
+            _ => vertical.size,
+        }
+        .map(|height| self.clamp_height(height.max(0.0), Some(cb_height)));
+        self.dimensions.content.y = containing_block.y;
+        self.layout_block_contents(height, Some(cb_height));
+
+        let content_height = self.dimensions.content.height;
+        let mut solution = vertical.solve(&|_| content_height);
+        let clamped = self.clamp_height(solution.size, Some(cb_height));
+        if clamped != solution.size {
+            solution = vertical.with_size(clamped).solve(&|_| clamped);
+        }
+
+        let d = &mut self.dimensions;
+        d.margin.top = solution.margin_start;
+        d.margin.bottom = solution.margin_end;
+        d.content.height = solution.size;
+        let y = containing_block.y
+            + solution.start
+            + solution.margin_start
+            + d.border.top
+            + d.padding.top;
+        self.translate(0.0, y - containing_block.y);
+
+        self.layout_out_of_flow_descendants(self.dimensions.padding_box(), true, false);
+    }
+}
+
+impl AxisConstraint {
+    fn with_size(&self, size: f32) -> Self {
+        AxisConstraint {
+            size: Some(size),
+            ..*self
+        }
+    }
+
+    /// Solves the constraint. `auto_size` gives the size of the box when it depends on its
+    /// contents, from the space available to it.
+    fn solve(&self, auto_size: &dyn Fn(f32) -> f32) -> AxisSolution {
+        let mut start = self.start;
+        if start.is_none() && self.end.is_none() {
+            start = Some(self.static_start);
+        }
+
+        if let (Some(start), Some(size), Some(end)) = (start, self.size, self.end) {
+            let remaining = self.containing_block - start - size - end - self.edges;
+            let (margin_start, margin_end) = match (self.margin_start, self.margin_end) {
+                (None, None) if remaining < 0.0 => (0.0, remaining),
+                (None, None) => (remaining / 2.0, remaining / 2.0),
+                (None, Some(margin_end)) => (remaining - margin_end, margin_end),
+                (Some(margin_start), None) => (margin_start, remaining - margin_start),
+                // Over-constrained: the end offset is ignored.
+                (Some(margin_start), Some(margin_end)) => (margin_start, margin_end),
+            };
+            return AxisSolution {
+                start,
+                margin_start,
+                size,
+                margin_end,
+            };
+        }
+
+        let margin_start = self.margin_start.unwrap_or(0.0);
+        let margin_end = self.margin_end.unwrap_or(0.0);
+        let taken = margin_start + margin_end + self.edges;
+        let (start, size) = match (start, self.size, self.end) {
+            (Some(start), Some(size), _) => (start, size),
+            (Some(start), None, Some(end)) => (
+                start,
+                (self.containing_block - start - end - taken).max(0.0),
+            ),
+            (Some(start), None, None) => (start, auto_size(self.containing_block - start - taken)),
+            (None, size, Some(end)) => {
+                let available = self.containing_block - end - taken;
+                let size = size.unwrap_or_else(|| auto_size(available));
+                (available - size, size)
+            }
+            (None, _, None) => unreachable!(),
+        };
+        AxisSolution {
+            start,
+            margin_start,
+            size,
+            margin_end,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layout::testing::{find, layout_html};
+    use crate::layout::Rect;
+
+    #[test]
+    fn test_absolute_positioning() {
+        let root = layout_html(
+            "<div>\
+             <div class=\"container\">\
+             <p>text <span class=\"inline\">x</span></p>\
+             <div class=\"corner\"></div>\
+             <div class=\"centered\"></div>\
+             </div>\
+             <div class=\"fixed\"></div>\
+             <div class=\"after\"></div>\
+             </div>",
+            ".container { position: relative; margin-left: 20px; padding: 10px; width: 300px; height: 200px; }
+             .inline { position: absolute; top: 5px; }
+             .corner { position: absolute; right: 0; bottom: 0; width: 50px; height: 40px; }
+             .centered { position: absolute; left: 0; right: 0; width: 100px; margin: 0 auto; }
+             .fixed { position: fixed; left: 10%; top: 0; bottom: 0; width: 20px; }
+             .after { height: 10px; }",
+            Rect::new(0.0, 0.0, 400.0, 600.0),
+        );
+        let rect = |class: &str| find(&root, class).unwrap().dimensions.content;
+
+        // Out-of-flow boxes take no space in the flow.
+        assert_eq!(rect("after").y, 220.0);
+
+        let corner = rect("corner");
+        assert_eq!((corner.x, corner.y), (290.0, 180.0));
+
+        let centered = find(&root, "centered").unwrap().dimensions;
+        assert_eq!(centered.margin.left, 110.0);
+        assert_eq!(centered.content.x, 130.0);
+
+        // Offsets left auto keep the static position, and auto widths shrink to fit.
+        let inline = rect("inline");
+        assert_eq!(inline.y, 5.0);
+        assert!(inline.x > 30.0);
+        assert!(inline.width > 0.0 && inline.width < 300.0);
+
+        let fixed = rect("fixed");
+        assert_eq!((fixed.x, fixed.y), (40.0, 0.0));
+        assert_eq!(fixed.height, 600.0);
+    }
+}
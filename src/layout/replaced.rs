@@ -0,0 +1,215 @@
+use super::{BoxType, Keyword, LayoutBox, Rect};
+use crate::css::Value::{self, Percentage};
+use crate::html::NodeType;
+use crate::raster::ImageMap;
+use crate::style::{ObjectFit, StyledNode};
+use crate::text::{font_metrics, shape, GlyphRun};
+
+/// Whether an element is replaced by external content instead of rendering its children.
+pub(crate) fn is_replaced_element(style: &StyledNode) -> bool {
+    matches!(style.node.typ, NodeType::Element(ref elem) if elem.tag_name == "img")
+}
+
+/// Builds the box holding the content of a replaced element: its image, when it loaded, or
+/// else its alternative text, shaped.
+pub(crate) fn build_replaced_content<'a>(
+    style: &'a StyledNode<'a>,
+    images: &ImageMap,
+) -> LayoutBox<'a> {
+    let image = match style.node.typ {
+        NodeType::Element(ref elem) => elem.attrs.get("src").and_then(|src| images.get(src)),
+        NodeType::Text(_) => None,
+    };
+    let runs = match image {
+        Some(_) => Vec::new(),
+        None => shape(alt_text(style), &style.font()),
+    };
+    LayoutBox::new(BoxType::Image(style, image.cloned(), runs))
+}
+
+/// The text shown in place of an image that failed to load.
+fn alt_text<'a>(style: &'a StyledNode) -> &'a str {
+    match style.node.typ {
+        NodeType::Element(ref elem) => elem.attrs.get("alt").map_or("", String::as_str),
+        NodeType::Text(_) => "",
+    }
+}
+
+impl<'a> LayoutBox<'a> {
+    /// Whether the box is that of a replaced element, holding an image box as its content.
+    pub(crate) fn is_replaced(&self) -> bool {
+        if let BoxType::BlockNode(_) = self.box_type {
+            if let Some(BoxType::Image(..)) = self.children.first().map(|child| &child.box_type) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// The natural width and height of the content of a replaced element. A broken image
+    /// is as large as its alternative text.
+    fn natural_size(&self) -> (f32, f32) {
+        match self.children[0].box_type {
+            BoxType::Image(_, Some(ref image), _) => (image.width as f32, image.height as f32),
+            BoxType::Image(style, None, ref runs) => {
+                if runs.is_empty() {
+                    return (0.0, 0.0);
+                }
+                let metrics = font_metrics(&style.font());
+                (
+                    runs.iter().map(GlyphRun::width).sum(),
+                    metrics.ascent + metrics.descent,
+                )
+            }
+            _ => unreachable!("Replaced elements hold an image box"),
+        }
+    }
+
+    /// The width of a replaced element with an auto `width`, following CSS 2.1 §10.3.2: the
+    /// one its specified height gives with its aspect ratio, or else its natural width.
+    pub(crate) fn replaced_width(&self) -> f32 {
+        let (width, height) = self.natural_size();
+        match self.content_height("height", None) {
+            Some(specified) if height > 0.0 => specified * width / height,
+            _ => width,
+        }
+    }
+
+    /// The height of a replaced element with an auto `height`, once its width is known,
+    /// keeping its aspect ratio as in CSS 2.1 §10.6.2.
+    fn replaced_height(&self) -> f32 {
+        let (width, height) = self.natural_size();
+        if width > 0.0 {
+            self.dimensions.content.width * height / width
+        } else {
+            height
+        }
+    }
+
+    /// Sets the content height of a replaced element, unless `height` is given, then sizes
+    /// and positions its content in the content box following `object-fit` and
+    /// `object-position`. Content overflowing the box is clipped when painted.
+    pub(crate) fn layout_replaced_content(&mut self, height: Option<f32>) {
+        let height = height.unwrap_or_else(|| self.clamp_height(self.replaced_height(), None));
+        self.dimensions.content.height = height;
+
+        let content = self.dimensions.content;
+        let (width, height) = self.natural_size();
+        let style = self.get_style_node();
+        let fit = style.object_fit();
+        let (width, height) = if width > 0.0 && height > 0.0 && fit != ObjectFit::Fill {
+            let contain = (content.width / width).min(content.height / height);
+            let scale = match fit {
+                ObjectFit::Contain => contain,
+                ObjectFit::Cover => (content.width / width).max(content.height / height),
+                ObjectFit::ScaleDown => contain.min(1.0),
+                _ => 1.0,
+            };
+            (width * scale, height * scale)
+        } else {
+            (content.width, content.height)
+        };
+
+        let (x, y) = object_position(style);
+        self.children[0].dimensions.content = Rect::new(
+            content.x + x.resolve(content.width - width),
+            content.y + y.resolve(content.height - height),
+            width,
+            height,
+        );
+    }
+}
+
+/// The horizontal and vertical offsets of `object-position`, percentages being those of
+/// the space the content leaves in the box.
+fn object_position(style: &StyledNode) -> (Value, Value) {
+    let values = match style.value("object-position") {
+        Some(Value::List(values)) => values,
+        Some(value) => vec![value],
+        None => Vec::new(),
+    };
+    let (mut x, mut y) = (values.first(), values.get(1));
+    // Keywords may come in either order.
+    let is_keyword = |value: Option<&Value>, keywords: &[&str]| match value {
+        Some(Keyword(ref keyword)) => keywords.contains(&keyword.as_str()),
+        _ => false,
+    };
+    if is_keyword(x, &["top", "bottom"]) || is_keyword(y, &["left", "right"]) {
+        std::mem::swap(&mut x, &mut y);
+    }
+
+    let offset = |value: Option<&Value>| match value {
+        Some(Keyword(ref keyword)) => match keyword.as_str() {
+            "left" | "top" => Percentage(0.0),
+            "right" | "bottom" => Percentage(100.0),
+            _ => Percentage(50.0),
+        },
+        Some(value) => value.clone(),
+        None => Percentage(50.0),
+    };
+    (offset(x), offset(y))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layout::testing::{find, layout_html_with_images};
+    use crate::layout::{BoxType, Rect};
+    use crate::raster::{Image, ImageMap};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_replaced_elements() {
+        let mut images = ImageMap::new();
+        let image = Image {
+            width: 40,
+            height: 10,
+            data: vec![0; 40 * 10 * 4],
+        };
+        images.insert("a.png".to_string(), Arc::new(image));
+        let root = layout_html_with_images(
+            "<div>\
+             <img class=\"natural\" src=\"a.png\" />\
+             <img class=\"sized\" src=\"a.png\" height=\"20\" />\
+             <img class=\"cover\" src=\"a.png\" />\
+             <img class=\"contain\" src=\"a.png\" />\
+             <img class=\"broken\" src=\"missing.png\" alt=\"Missing\" />\
+             </div>",
+            ".cover, .contain { display: block; width: 50px; height: 50px; }
+             .cover { object-fit: cover; object-position: top left; }
+             .contain { object-fit: contain; }",
+            &images,
+            Rect::new(0.0, 0.0, 800.0, 600.0),
+        );
+
+        let natural = find(&root, "natural").unwrap().dimensions.content;
+        assert_eq!((natural.width, natural.height), (40.0, 10.0));
+        let sized = find(&root, "sized").unwrap().dimensions.content;
+        assert_eq!((sized.width, sized.height), (80.0, 20.0));
+        // Inline images sit side by side on the baseline.
+        assert_eq!(sized.x, natural.x + natural.width);
+        assert_eq!(sized.y + sized.height, natural.y + natural.height);
+
+        let cover = find(&root, "cover").unwrap();
+        let content = cover.dimensions.content;
+        let object = cover.children[0].dimensions.content;
+        assert_eq!((object.width, object.height), (200.0, 50.0));
+        assert_eq!((object.x, object.y), (content.x, content.y));
+
+        let contain = find(&root, "contain").unwrap();
+        let content = contain.dimensions.content;
+        let object = contain.children[0].dimensions.content;
+        assert_eq!((object.width, object.height), (50.0, 12.5));
+        assert_eq!((object.x, object.y), (content.x, content.y + 18.75));
+
+        // A broken image is as wide as its alternative text, shaped in seven 8px glyphs.
+        let broken = find(&root, "broken").unwrap();
+        match broken.children[0].box_type {
+            BoxType::Image(_, None, ref runs) => {
+                let text: String = runs.iter().map(|run| run.text.as_str()).collect();
+                assert_eq!(text, "Missing");
+            }
+            _ => panic!("expected a broken image"),
+        }
+        assert_eq!(broken.dimensions.content.width, 56.0);
+    }
+}
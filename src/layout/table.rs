@@ -0,0 +1,1061 @@
+use super::intrinsic::IntrinsicSizes;
+use super::list::Counters;
+use super::{build_layout_tree, BoxType, EdgeSizes, Keyword, LayoutBox, Length, Position, Px};
+use super::{Dimensions, Rect};
+use crate::css::Value;
+use crate::html::NodeType;
+use crate::raster::ImageMap;
+use crate::style::{Display, StyledNode, VerticalAlign};
+use std::ops::Range;
+
+/// Where a row is: its index among the children of the table box, and among those of its
+/// row group when it's in one.
+type RowPath = (usize, Option<usize>);
+
+/// A `<col>` or `<colgroup>` box: its index among the children of the table box, and among
+/// those of its column group for a column in one, with the columns it spans.
+type ColumnBox = (usize, Option<usize>, Range<usize>);
+
+/// The borders and padding of a cell or table.
+type BoxEdges = (EdgeSizes, EdgeSizes);
+
+/// A cell and the slots it spans, as ranges of row and column indices.
+struct TableCell {
+    row: RowPath,
+    /// Index of the cell among the children of its row.
+    index: usize,
+    rows: Range<usize>,
+    columns: Range<usize>,
+}
+
+/// The rows of a table in display order, and the cells covering its slots.
+#[derive(Default)]
+struct TableGrid {
+    rows: Vec<RowPath>,
+    cells: Vec<TableCell>,
+    /// The cell covering each slot, by row then column.
+    slots: Vec<Vec<Option<usize>>>,
+    columns: usize,
+}
+
+/// What the cells and columns of a column ask for in the automatic table layout.
+#[derive(Debug, Default, Clone, Copy)]
+struct ColumnSizes {
+    min: f32,
+    max: f32,
+    percentage: Option<f32>,
+    /// Set when a column or one of its cells has a fixed width.
+    fixed: bool,
+}
+
+/// Builds the block-level wrapper box of a table, holding its captions around its table box.
+pub(crate) fn build_table_wrapper<'a>(
+    style: &'a StyledNode<'a>,
+    counters: &mut Counters,
+    images: &ImageMap,
+) -> LayoutBox<'a> {
+    let mut wrapper = LayoutBox::new(BoxType::BlockNode(style));
+    let mut table = LayoutBox::new(BoxType::Table(style));
+    let mut bottom_captions = Vec::new();
+    for child in &style.children {
+        match child.display() {
+            Display::None => {}
+            Display::TableCaption => {
+                let caption = build_layout_tree(child, false, counters, images);
+                match child.value("caption-side") {
+                    Some(Keyword(ref side)) if side == "bottom" => bottom_captions.push(caption),
+                    _ => wrapper.children.push(caption),
+                }
+            }
+            _ => add_table_child(&mut table, child, counters, images),
+        }
+    }
+    wrapper.children.push(table);
+    wrapper.children.extend(bottom_captions);
+    wrapper
+}
+
+/// Adds a child to a table box, wrapping what isn't a row group, a row or a column in
+/// anonymous rows and cells.
+pub(crate) fn add_table_child<'a>(
+    table: &mut LayoutBox<'a>,
+    child: &'a StyledNode<'a>,
+    counters: &mut Counters,
+    images: &ImageMap,
+) {
+    match child.display() {
+        Display::None => {}
+        Display::TableRowGroup | Display::TableHeaderGroup | Display::TableFooterGroup => {
+            counters.enter(child);
+            let mut group = LayoutBox::new(BoxType::BlockNode(child));
+            for row in &child.children {
+                add_group_child(&mut group, row, counters, images);
+            }
+            counters.leave();
+            table.children.push(group);
+        }
+        Display::TableColumnGroup => {
+            // Only columns are kept in a column group.
+            let mut group = LayoutBox::new(BoxType::BlockNode(child));
+            for column in &child.children {
+                if column.display() == Display::TableColumn {
+                    group
+                        .children
+                        .push(LayoutBox::new(BoxType::BlockNode(column)));
+                }
+            }
+            table.children.push(group);
+        }
+        Display::TableColumn => table
+            .children
+            .push(LayoutBox::new(BoxType::BlockNode(child))),
+        _ => add_group_child(table, child, counters, images),
+    }
+}
+
+/// Adds a child to a table or row group box, wrapping what isn't a row in an anonymous one.
+fn add_group_child<'a>(
+    group: &mut LayoutBox<'a>,
+    child: &'a StyledNode<'a>,
+    counters: &mut Counters,
+    images: &ImageMap,
+) {
+    match child.display() {
+        Display::None => {}
+        Display::TableRow => {
+            counters.enter(child);
+            let mut row = LayoutBox::new(BoxType::BlockNode(child));
+            for cell in &child.children {
+                add_row_child(&mut row, cell, counters, images);
+            }
+            counters.leave();
+            group.children.push(row);
+        }
+        _ => {
+            if !matches!(
+                group.children.last(),
+                Some(&LayoutBox {
+                    box_type: BoxType::AnonymousBlock(_),
+                    ..
+                })
+            ) {
+                let style = parent_style(group);
+                group
+                    .children
+                    .push(LayoutBox::new(BoxType::AnonymousBlock(style)));
+            }
+            add_row_child(group.children.last_mut().unwrap(), child, counters, images);
+        }
+    }
+}
+
+/// Adds a child to a row box. Block-level children become cells of their own, and inline
+/// content is wrapped in an anonymous cell.
+fn add_row_child<'a>(
+    row: &mut LayoutBox<'a>,
+    child: &'a StyledNode<'a>,
+    counters: &mut Counters,
+    images: &ImageMap,
+) {
+    match child.display() {
+        Display::None => {}
+        Display::TableCell => row
+            .children
+            .push(build_layout_tree(child, false, counters, images)),
+        Display::Inline
+        | Display::InlineBlock
+        | Display::InlineFlex
+        | Display::InlineGrid
+        | Display::InlineTable => {
+            if !matches!(
+                row.children.last(),
+                Some(&LayoutBox {
+                    box_type: BoxType::AnonymousBlock(_),
+                    ..
+                })
+            ) {
+                let style = parent_style(row);
+                row.children
+                    .push(LayoutBox::new(BoxType::AnonymousBlock(style)));
+            }
+            let cell = row.children.last_mut().unwrap();
+            let child_box = build_layout_tree(child, false, counters, images);
+            cell.get_inline_container().children.push(child_box);
+        }
+        _ => row
+            .children
+            .push(build_layout_tree(child, true, counters, images)),
+    }
+}
+
+/// The style anonymous children of a table part inherit from.
+fn parent_style<'a>(layout_box: &LayoutBox<'a>) -> &'a StyledNode<'a> {
+    match layout_box.box_type {
+        BoxType::BlockNode(style)
+        | BoxType::AnonymousBlock(style)
+        | BoxType::Table(style)
+        | BoxType::AnonymousTable(style) => style,
+        _ => unreachable!("Only table parts have table children"),
+    }
+}
+
+/// Reads the `span`, `colspan` or `rowspan` attribute of a table part.
+fn span_attribute(layout_box: &LayoutBox, name: &str) -> Option<usize> {
+    match layout_box.box_type {
+        BoxType::BlockNode(style) => match style.node.typ {
+            NodeType::Element(ref elem) => elem.attrs.get(name)?.trim().parse().ok(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The display a child of a table box plays, anonymous rows being rows.
+pub(crate) fn table_role(layout_box: &LayoutBox) -> Display {
+    match layout_box.box_type {
+        BoxType::BlockNode(style) => style.display(),
+        _ => Display::TableRow,
+    }
+}
+
+/// The borders and padding a box gets from its style, percentages being of `base` or zero
+/// when it's unknown.
+fn box_edges(layout_box: &LayoutBox, base: Option<f32>) -> BoxEdges {
+    let style = match layout_box.box_type {
+        BoxType::BlockNode(style) if !layout_box.is_table_wrapper() => style,
+        BoxType::Table(style) => style,
+        _ => return BoxEdges::default(),
+    };
+    let zero = Length(0.0, Px);
+    let edge = |name: String, fallback: &str| {
+        let value = style.lookup(&name, fallback, &zero);
+        base.map_or(value.to_px(), |base| value.resolve(base))
+    };
+    let sides = |edge: &dyn Fn(&str) -> f32| EdgeSizes {
+        left: edge("left"),
+        right: edge("right"),
+        top: edge("top"),
+        bottom: edge("bottom"),
+    };
+    (
+        sides(&|side| edge(format!("border-{}-width", side), "border-width")),
+        sides(&|side| edge(format!("padding-{}", side), "padding")),
+    )
+}
+
+/// The spacing around and between `count` rows or columns.
+fn spacing_around(spacing: f32, count: usize) -> f32 {
+    match count {
+        0 => 0.0,
+        count => spacing * (count + 1) as f32,
+    }
+}
+
+fn horizontal(edges: &BoxEdges) -> f32 {
+    let (border, padding) = edges;
+    border.left + border.right + padding.left + padding.right
+}
+
+fn vertical(edges: &BoxEdges) -> f32 {
+    let (border, padding) = edges;
+    border.top + border.bottom + padding.top + padding.bottom
+}
+
+impl TableGrid {
+    /// Places the cells of a row, given the index of the first row past its row group.
+    fn add_row(&mut self, path: RowPath, row: &LayoutBox, group_end: usize) {
+        let r = self.rows.len();
+        self.rows.push(path);
+        if self.slots.len() < group_end {
+            self.slots.resize(group_end, Vec::new());
+        }
+
+        let mut column = 0;
+        for (index, cell) in row.children.iter().enumerate() {
+            if cell.is_out_of_flow() {
+                continue;
+            }
+            while matches!(self.slots[r].get(column), Some(Some(_))) {
+                column += 1;
+            }
+            let colspan = span_attribute(cell, "colspan").unwrap_or(1).clamp(1, 1000);
+            // A zero rowspan spans the rest of the row group.
+            let end = match span_attribute(cell, "rowspan").unwrap_or(1) {
+                0 => group_end,
+                rowspan => (r + rowspan).min(group_end),
+            };
+            let columns = column..column + colspan;
+            for slots in &mut self.slots[r..end] {
+                if slots.len() < columns.end {
+                    slots.resize(columns.end, None);
+                }
+                for slot in &mut slots[columns.clone()] {
+                    *slot = Some(self.cells.len());
+                }
+            }
+            self.cells.push(TableCell {
+                row: path,
+                index,
+                rows: r..end,
+                columns: columns.clone(),
+            });
+            column = columns.end;
+        }
+    }
+
+    fn slot(&self, row: usize, column: usize) -> Option<usize> {
+        *self.slots.get(row)?.get(column)?
+    }
+}
+
+impl<'a> LayoutBox<'a> {
+    fn row(&self, (index, row): RowPath) -> &LayoutBox<'a> {
+        match row {
+            Some(row) => &self.children[index].children[row],
+            None => &self.children[index],
+        }
+    }
+
+    fn row_mut(&mut self, (index, row): RowPath) -> &mut LayoutBox<'a> {
+        match row {
+            Some(row) => &mut self.children[index].children[row],
+            None => &mut self.children[index],
+        }
+    }
+
+    fn cell(&self, cell: &TableCell) -> &LayoutBox<'a> {
+        &self.row(cell.row).children[cell.index]
+    }
+
+    fn cell_mut(&mut self, cell: &TableCell) -> &mut LayoutBox<'a> {
+        &mut self.row_mut(cell.row).children[cell.index]
+    }
+
+    /// The style of a table box, which an anonymous table doesn't have.
+    fn table_style(&self) -> Option<&'a StyledNode<'a>> {
+        match self.box_type {
+            BoxType::Table(style) => Some(style),
+            _ => None,
+        }
+    }
+
+    fn collapses_borders(&self) -> bool {
+        match parent_style(self).value("border-collapse") {
+            Some(Keyword(ref collapse)) => collapse == "collapse",
+            _ => false,
+        }
+    }
+
+    /// The horizontal and vertical spacing between cells, and around them.
+    fn border_spacing(&self) -> (f32, f32) {
+        if self.collapses_borders() {
+            return (0.0, 0.0);
+        }
+        match parent_style(self).value("border-spacing") {
+            Some(Value::List(values)) => (
+                values.first().map_or(0.0, Value::to_px),
+                values.get(1).map_or(0.0, Value::to_px),
+            ),
+            Some(value) => (value.to_px(), value.to_px()),
+            None => (0.0, 0.0),
+        }
+    }
+
+    /// Whether the columns are sized by `table-layout: fixed`, which only applies to tables
+    /// with a width.
+    fn is_fixed_layout(&self) -> bool {
+        let style = match self.table_style() {
+            Some(style) => style,
+            None => return false,
+        };
+        let fixed =
+            matches!(style.value("table-layout"), Some(Keyword(ref layout)) if layout == "fixed");
+        let auto_width = matches!(style.value("width"), None | Some(Keyword(_)));
+        fixed && !auto_width
+    }
+
+    /// Places the cells of a table box in its slots. Header groups come first and footer
+    /// groups last, whatever their place among the rows.
+    fn table_grid(&self) -> TableGrid {
+        let mut children: Vec<usize> = (0..self.children.len())
+            .filter(|&index| {
+                !matches!(
+                    table_role(&self.children[index]),
+                    Display::TableColumn | Display::TableColumnGroup
+                )
+            })
+            .collect();
+        children.sort_by_key(|&index| match table_role(&self.children[index]) {
+            Display::TableHeaderGroup => 0,
+            Display::TableFooterGroup => 2,
+            _ => 1,
+        });
+
+        // Consecutive rows outside of any row group form one.
+        let mut groups: Vec<Vec<RowPath>> = Vec::new();
+        let mut in_group = false;
+        for index in children {
+            if table_role(&self.children[index]) == Display::TableRow {
+                if !in_group {
+                    groups.push(Vec::new());
+                }
+                groups.last_mut().unwrap().push((index, None));
+                in_group = true;
+            } else {
+                let rows = 0..self.children[index].children.len();
+                groups.push(rows.map(|row| (index, Some(row))).collect());
+                in_group = false;
+            }
+        }
+
+        let mut grid = TableGrid::default();
+        for group in groups {
+            let end = grid.rows.len() + group.len();
+            for path in group {
+                grid.add_row(path, self.row(path), end);
+            }
+        }
+        let columns = self.column_boxes().iter().map(|column| column.2.end).max();
+        grid.columns = grid
+            .slots
+            .iter()
+            .map(Vec::len)
+            .chain(columns)
+            .max()
+            .unwrap_or(0);
+        grid
+    }
+
+    /// The column and column group boxes of a table box, in order.
+    fn column_boxes(&self) -> Vec<ColumnBox> {
+        let mut boxes = Vec::new();
+        let mut start = 0;
+        for (index, child) in self.children.iter().enumerate() {
+            match table_role(child) {
+                Display::TableColumn => {
+                    let end = start + span_attribute(child, "span").unwrap_or(1).max(1);
+                    boxes.push((index, None, start..end));
+                    start = end;
+                }
+                Display::TableColumnGroup => {
+                    let group_start = start;
+                    let position = boxes.len();
+                    for (column, col) in child.children.iter().enumerate() {
+                        let end = start + span_attribute(col, "span").unwrap_or(1).max(1);
+                        boxes.push((index, Some(column), start..end));
+                        start = end;
+                    }
+                    if child.children.is_empty() {
+                        start += span_attribute(child, "span").unwrap_or(1).max(1);
+                    }
+                    boxes.insert(position, (index, None, group_start..start));
+                }
+                _ => {}
+            }
+        }
+        boxes
+    }
+
+    /// The widths `<col>` and `<colgroup>` elements give to the columns, a column's own
+    /// width taking precedence over its group's.
+    fn specified_column_widths(&self, count: usize) -> Vec<Option<Value>> {
+        let mut widths = vec![None; count];
+        for (index, column, columns) in self.column_boxes() {
+            let width = match column {
+                Some(column) => self.children[index].children[column].get_style_node(),
+                None => self.children[index].get_style_node(),
+            }
+            .value("width");
+            if let Some(width @ Length(..)) | Some(width @ Value::Percentage(_)) = width {
+                for slot in widths.iter_mut().take(columns.end).skip(columns.start) {
+                    *slot = Some(width.clone());
+                }
+            }
+        }
+        widths
+    }
+
+    /// The borders and padding of a table box and of its cells. With collapsing borders,
+    /// each border between two cells is as wide as the widest of the two, half of it going to
+    /// each side, and a table has no padding. Percentages are of `base`.
+    fn table_edges(&self, grid: &TableGrid, base: Option<f32>) -> (BoxEdges, Vec<BoxEdges>) {
+        let mut table = box_edges(self, base);
+        let mut cells: Vec<BoxEdges> = grid
+            .cells
+            .iter()
+            .map(|cell| box_edges(self.cell(cell), base))
+            .collect();
+        if !self.collapses_borders() {
+            return (table, cells);
+        }
+
+        let own: Vec<EdgeSizes> = cells.iter().map(|edges| edges.0).collect();
+        let widest = |slots: &mut dyn Iterator<Item = Option<usize>>,
+                      side: fn(&EdgeSizes) -> f32,
+                      outer: f32| {
+            slots.fold(outer, |width, slot| {
+                slot.map_or(width, |cell| width.max(side(&own[cell])))
+            })
+        };
+        let rows = grid.rows.len();
+        let mut table_border = table.0;
+        for (edges, cell) in cells.iter_mut().zip(&grid.cells) {
+            let (start, end) = (cell.columns.start, cell.columns.end);
+            let collapsed = EdgeSizes {
+                left: match start {
+                    0 => table.0.left,
+                    _ => widest(
+                        &mut cell.rows.clone().map(|row| grid.slot(row, start - 1)),
+                        |border| border.right,
+                        0.0,
+                    ),
+                },
+                right: if end == grid.columns {
+                    table.0.right
+                } else {
+                    widest(
+                        &mut cell.rows.clone().map(|row| grid.slot(row, end)),
+                        |border| border.left,
+                        0.0,
+                    )
+                },
+                top: match cell.rows.start {
+                    0 => table.0.top,
+                    row => widest(
+                        &mut cell
+                            .columns
+                            .clone()
+                            .map(|column| grid.slot(row - 1, column)),
+                        |border| border.bottom,
+                        0.0,
+                    ),
+                },
+                bottom: if cell.rows.end == rows {
+                    table.0.bottom
+                } else {
+                    widest(
+                        &mut cell
+                            .columns
+                            .clone()
+                            .map(|column| grid.slot(cell.rows.end, column)),
+                        |border| border.top,
+                        0.0,
+                    )
+                },
+            };
+            let border = &mut edges.0;
+            border.left = border.left.max(collapsed.left);
+            border.right = border.right.max(collapsed.right);
+            border.top = border.top.max(collapsed.top);
+            border.bottom = border.bottom.max(collapsed.bottom);
+            if start == 0 {
+                table_border.left = table_border.left.max(border.left);
+            }
+            if end == grid.columns {
+                table_border.right = table_border.right.max(border.right);
+            }
+            if cell.rows.start == 0 {
+                table_border.top = table_border.top.max(border.top);
+            }
+            if cell.rows.end == rows {
+                table_border.bottom = table_border.bottom.max(border.bottom);
+            }
+            border.left /= 2.0;
+            border.right /= 2.0;
+            border.top /= 2.0;
+            border.bottom /= 2.0;
+        }
+        table.0 = EdgeSizes {
+            left: table_border.left / 2.0,
+            right: table_border.right / 2.0,
+            top: table_border.top / 2.0,
+            bottom: table_border.bottom / 2.0,
+        };
+        table.1 = EdgeSizes::default();
+        (table, cells)
+    }
+
+    /// Gathers the widths the columns and their cells ask for, following CSS 2.1 §17.5.2.2.
+    /// Cells spanning several columns spread what the columns lack over them, in proportion
+    /// to their max-content widths.
+    fn column_sizes(&self, grid: &TableGrid, edges: &[BoxEdges], spacing: f32) -> Vec<ColumnSizes> {
+        let mut columns = vec![ColumnSizes::default(); grid.columns];
+        for (column, width) in columns
+            .iter_mut()
+            .zip(self.specified_column_widths(grid.columns))
+        {
+            match width {
+                Some(Value::Percentage(percentage)) => column.percentage = Some(percentage),
+                Some(width) => {
+                    column.max = width.to_px();
+                    column.fixed = true;
+                }
+                None => {}
+            }
+        }
+
+        let mut order: Vec<usize> = (0..grid.cells.len()).collect();
+        order.sort_by_key(|&index| grid.cells[index].columns.len());
+        for index in order {
+            let cell = &grid.cells[index];
+            let cell_box = self.cell(cell);
+            let edges = horizontal(&edges[index]);
+            let content = cell_box.content_intrinsic_sizes();
+            let mut min = content.min_content + edges;
+            let mut max = content.max_content + edges;
+            let width = cell_box.item_size("width", None, edges);
+            if let Some(width) = width {
+                min = min.max(width + edges);
+                max = min.max(width + edges);
+            }
+            let percentage = match cell_box.box_type {
+                BoxType::BlockNode(style) => match style.value("width") {
+                    Some(Value::Percentage(percentage)) => Some(percentage),
+                    _ => None,
+                },
+                _ => None,
+            };
+
+            let spanned = &mut columns[cell.columns.clone()];
+            if let [column] = spanned {
+                column.min = column.min.max(min);
+                column.max = column.max.max(max);
+                column.fixed |= width.is_some();
+                if let Some(percentage) = percentage {
+                    column.percentage =
+                        Some(column.percentage.map_or(percentage, |p| p.max(percentage)));
+                }
+                continue;
+            }
+            let gaps = spacing * (spanned.len() - 1) as f32;
+            let weights: Vec<f32> = spanned.iter().map(|column| column.max).collect();
+            let excess_min = min - gaps - spanned.iter().map(|column| column.min).sum::<f32>();
+            let excess_max = max - gaps - spanned.iter().map(|column| column.max).sum::<f32>();
+            for (column, share) in spanned
+                .iter_mut()
+                .zip(shares(&weights, excess_min.max(0.0)))
+            {
+                column.min += share;
+            }
+            for (column, share) in spanned
+                .iter_mut()
+                .zip(shares(&weights, excess_max.max(0.0)))
+            {
+                column.max += share;
+            }
+        }
+        for column in &mut columns {
+            column.max = column.max.max(column.min);
+        }
+        columns
+    }
+
+    /// The widths of the columns in the fixed table layout: those of the columns, or else of
+    /// the cells of the first row, when they have one. Percentages are of `base`.
+    fn fixed_column_widths(
+        &self,
+        grid: &TableGrid,
+        edges: &[BoxEdges],
+        base: Option<f32>,
+    ) -> Vec<Option<f32>> {
+        let resolve = |value: Value| base.map_or(value.to_px(), |base| value.resolve(base));
+        let mut widths: Vec<Option<f32>> = self
+            .specified_column_widths(grid.columns)
+            .into_iter()
+            .map(|width| width.map(resolve))
+            .collect();
+        for (cell, edges) in grid.cells.iter().zip(edges) {
+            if cell.rows.start != 0 {
+                continue;
+            }
+            let edges = horizontal(edges);
+            let cell_box = self.cell(cell);
+            let width = match cell_box.item_size("width", base, edges) {
+                Some(width) => width + edges,
+                None => continue,
+            };
+            let share = width / cell.columns.len() as f32;
+            for column in &mut widths[cell.columns.clone()] {
+                column.get_or_insert(share);
+            }
+        }
+        widths
+    }
+
+    /// The intrinsic widths of the border box of a table box.
+    pub(crate) fn table_intrinsic_sizes(&self) -> IntrinsicSizes {
+        let grid = self.table_grid();
+        let (table, cells) = self.table_edges(&grid, None);
+        let (spacing, _) = self.border_spacing();
+        let outside = horizontal(&table) + spacing_around(spacing, grid.columns);
+        if self.is_fixed_layout() {
+            let widths = self.fixed_column_widths(&grid, &cells, None);
+            return IntrinsicSizes::fixed(widths.iter().flatten().sum::<f32>() + outside);
+        }
+        let columns = self.column_sizes(&grid, &cells, spacing);
+        IntrinsicSizes {
+            min_content: columns.iter().map(|column| column.min).sum::<f32>() + outside,
+            max_content: columns.iter().map(|column| column.max).sum::<f32>() + outside,
+        }
+    }
+
+    /// Lays out a table box below the contents of `containing_block`. The table box of a
+    /// table fills its wrapper, which is sized beforehand, while an anonymous table shrinks to
+    /// fit. `cb_height` is the height percentages are of, when known.
+    pub(crate) fn layout_table_box(
+        &mut self,
+        containing_block: Dimensions,
+        cb_height: Option<f32>,
+    ) {
+        let cb = containing_block.content;
+        let grid = self.table_grid();
+        let (table, edges) = self.table_edges(&grid, Some(cb.width));
+        let (h_spacing, v_spacing) = self.border_spacing();
+
+        let border_box_width = match self.box_type {
+            BoxType::Table(_) => cb.width,
+            _ => self.table_intrinsic_sizes().shrink_to_fit(cb.width),
+        };
+        let d = &mut self.dimensions;
+        d.border = table.0;
+        d.padding = table.1;
+        d.margin = EdgeSizes::default();
+        d.content.x = cb.x + d.border.left + d.padding.left;
+        d.content.y = cb.y + cb.height + d.border.top + d.padding.top;
+        d.content.width = (border_box_width - horizontal(&table)).max(0.0);
+        let content = d.content;
+
+        // Column widths.
+        let available = content.width - spacing_around(h_spacing, grid.columns);
+        let widths = if self.is_fixed_layout() {
+            share_fixed_widths(
+                &self.fixed_column_widths(&grid, &edges, Some(content.width)),
+                available,
+            )
+        } else {
+            distribute_widths(&self.column_sizes(&grid, &edges, h_spacing), available)
+        };
+        let mut column_x = vec![content.x + h_spacing];
+        for width in &widths {
+            column_x.push(column_x.last().unwrap() + width + h_spacing);
+        }
+        let span_width =
+            |columns: &Range<usize>| column_x[columns.end] - column_x[columns.start] - h_spacing;
+
+        // Row heights, from the heights of the cells at their widths.
+        let mut heights: Vec<f32> = grid
+            .rows
+            .iter()
+            .map(|&path| self.row(path).item_size("height", None, 0.0).unwrap_or(0.0))
+            .collect();
+        let mut ascents = vec![0.0f32; grid.rows.len()];
+        let mut descents = vec![0.0f32; grid.rows.len()];
+        let mut outer_heights = Vec::with_capacity(grid.cells.len());
+        for (cell, cell_edges) in grid.cells.iter().zip(&edges) {
+            let width = (span_width(&cell.columns) - horizontal(cell_edges)).max(0.0);
+            let cell_box = self.cell_mut(cell);
+            let d = &mut cell_box.dimensions;
+            d.border = cell_edges.0;
+            d.padding = cell_edges.1;
+            d.margin = EdgeSizes::default();
+            let (height, baseline) = cell_box.measure_item(width, None);
+            let height = cell_box
+                .item_size("height", None, vertical(cell_edges))
+                .map_or(height, |specified| height.max(specified));
+            let outer = height + vertical(cell_edges);
+            outer_heights.push(outer);
+            // Baseline aligned cells line up on the first row they span.
+            let row = cell.rows.start;
+            let single_row = cell.rows.len() == 1;
+            if cell_vertical_align(cell_box) == VerticalAlign::Baseline {
+                let ascent = cell_edges.0.top + cell_edges.1.top + baseline.unwrap_or(height);
+                ascents[row] = ascents[row].max(ascent);
+                if single_row {
+                    descents[row] = descents[row].max(outer - ascent);
+                }
+            }
+            if single_row {
+                heights[row] = heights[row].max(outer);
+            }
+        }
+        for (height, (ascent, descent)) in heights.iter_mut().zip(ascents.iter().zip(&descents)) {
+            *height = height.max(ascent + descent);
+        }
+        let mut order: Vec<usize> = (0..grid.cells.len()).collect();
+        order.sort_by_key(|&index| grid.cells[index].rows.len());
+        for index in order {
+            let rows = grid.cells[index].rows.clone();
+            if rows.len() < 2 {
+                continue;
+            }
+            let gaps = v_spacing * (rows.len() - 1) as f32;
+            let excess = outer_heights[index] - gaps - heights[rows.clone()].iter().sum::<f32>();
+            if excess > 0.0 {
+                for height in &mut heights[rows.clone()] {
+                    *height += excess / rows.len() as f32;
+                }
+            }
+        }
+
+        // A table taller than its rows shares the extra height among them.
+        let mut content_height =
+            spacing_around(v_spacing, grid.rows.len()) + heights.iter().sum::<f32>();
+        if let Some(height) = self
+            .table_style()
+            .and_then(|_| self.content_height("height", cb_height))
+        {
+            if height > content_height && !heights.is_empty() {
+                let extra = (height - content_height) / heights.len() as f32;
+                for row in &mut heights {
+                    *row += extra;
+                }
+            }
+            content_height = content_height.max(height);
+        }
+        self.dimensions.content.height = content_height;
+        let mut row_y = vec![content.y + v_spacing];
+        for height in &heights {
+            row_y.push(row_y.last().unwrap() + height + v_spacing);
+        }
+        let span_height = |rows: &Range<usize>| row_y[rows.end] - row_y[rows.start] - v_spacing;
+
+        for (cell, cell_edges) in grid.cells.iter().zip(&edges) {
+            let (border, padding) = cell_edges;
+            let width = (span_width(&cell.columns) - horizontal(cell_edges)).max(0.0);
+            let height = (span_height(&cell.rows) - vertical(cell_edges)).max(0.0);
+            let origin = Position::new(
+                column_x[cell.columns.start] + border.left + padding.left,
+                row_y[cell.rows.start] + border.top + padding.top,
+            );
+            let row = cell.rows.start;
+            let cell_box = self.cell_mut(cell);
+            cell_box.layout_item(origin, width, None, content.width, None);
+
+            // The contents are aligned in the cell, which fills its slots.
+            let natural = cell_box.dimensions.content.height;
+            let dy = match cell_vertical_align(cell_box) {
+                VerticalAlign::Top => 0.0,
+                VerticalAlign::Bottom => height - natural,
+                VerticalAlign::Middle => (height - natural) / 2.0,
+                _ => {
+                    let baseline = cell_box
+                        .first_line_baseline()
+                        .map_or(natural, |baseline| baseline - origin.y);
+                    ascents[row] - border.top - padding.top - baseline
+                }
+            };
+            for child in &mut cell_box.children {
+                child.translate(0.0, dy);
+            }
+            cell_box.dimensions.content.height = height;
+        }
+
+        // Rows, row groups and columns cover their slots, for their backgrounds.
+        let table_width = column_x[grid.columns] - column_x[0] - h_spacing;
+        for (r, &path) in grid.rows.iter().enumerate() {
+            let rect = Rect::new(column_x[0], row_y[r], table_width.max(0.0), heights[r]);
+            let row = self.row_mut(path);
+            row.dimensions.content = rect;
+            for cell in &mut row.children {
+                if cell.is_out_of_flow() {
+                    cell.dimensions.content.x = rect.x;
+                    cell.dimensions.content.y = rect.y;
+                }
+            }
+        }
+        for index in 0..self.children.len() {
+            let group = &self.children[index];
+            if !matches!(
+                table_role(group),
+                Display::TableRowGroup | Display::TableHeaderGroup | Display::TableFooterGroup
+            ) {
+                continue;
+            }
+            let rows: Vec<usize> = grid
+                .rows
+                .iter()
+                .enumerate()
+                .filter(|(_, path)| path.0 == index)
+                .map(|(r, _)| r)
+                .collect();
+            let y = rows.first().map_or(content.y, |&r| row_y[r]);
+            let bottom = rows.last().map_or(content.y, |&r| row_y[r] + heights[r]);
+            self.children[index].dimensions.content =
+                Rect::new(column_x[0], y, table_width.max(0.0), bottom - y);
+        }
+        let rows_height = row_y[grid.rows.len()] - row_y[0] - v_spacing;
+        for (index, column, columns) in self.column_boxes() {
+            let end = columns.end.min(grid.columns);
+            let start = columns.start.min(end);
+            let x = column_x[start];
+            let rect = Rect::new(
+                x,
+                row_y[0],
+                (column_x[end] - x - h_spacing).max(0.0),
+                rows_height.max(0.0),
+            );
+            match column {
+                Some(column) => self.children[index].children[column].dimensions.content = rect,
+                None => self.children[index].dimensions.content = rect,
+            }
+        }
+    }
+}
+
+/// How a cell aligns its contents vertically; anonymous cells sit on the baseline.
+fn cell_vertical_align(cell: &LayoutBox) -> VerticalAlign {
+    match cell.box_type {
+        BoxType::BlockNode(style) => style.vertical_align(),
+        _ => VerticalAlign::Baseline,
+    }
+}
+
+/// Splits `amount` in proportion to `weights`, or equally when they are all zero.
+fn shares(weights: &[f32], amount: f32) -> Vec<f32> {
+    let total: f32 = weights.iter().sum();
+    weights
+        .iter()
+        .map(|weight| {
+            if total > 0.0 {
+                amount * weight / total
+            } else {
+                amount / weights.len() as f32
+            }
+        })
+        .collect()
+}
+
+/// Distributes `available` among the columns of the automatic table layout. Columns get
+/// their min-content width, then percentage columns their percentage of `available`, then
+/// the others grow toward their max-content width. What is left goes to the columns without
+/// a fixed width, or else to the others, in proportion to their widths.
+fn distribute_widths(columns: &[ColumnSizes], available: f32) -> Vec<f32> {
+    let mut widths: Vec<f32> = columns.iter().map(|column| column.min).collect();
+    let mut free = available - widths.iter().sum::<f32>();
+    if free <= 0.0 {
+        return widths;
+    }
+
+    for (width, column) in widths.iter_mut().zip(columns) {
+        if let Some(percentage) = column.percentage {
+            let grow = (available * percentage / 100.0 - *width).max(0.0).min(free);
+            *width += grow;
+            free -= grow;
+        }
+    }
+
+    let growable: Vec<usize> = (0..columns.len())
+        .filter(|&i| columns[i].percentage.is_none())
+        .collect();
+    let wanted: f32 = growable.iter().map(|&i| columns[i].max - widths[i]).sum();
+    if wanted > 0.0 {
+        let ratio = (free / wanted).min(1.0);
+        for &i in &growable {
+            let grow = (columns[i].max - widths[i]) * ratio;
+            widths[i] += grow;
+            free -= grow;
+        }
+    }
+    if free <= 0.0 {
+        return widths;
+    }
+
+    let auto: Vec<usize> = growable
+        .iter()
+        .copied()
+        .filter(|&i| !columns[i].fixed)
+        .collect();
+    let targets = if !auto.is_empty() {
+        auto
+    } else if !growable.is_empty() {
+        growable
+    } else {
+        (0..columns.len()).collect()
+    };
+    let weights: Vec<f32> = targets.iter().map(|&i| widths[i]).collect();
+    for (&i, share) in targets.iter().zip(shares(&weights, free)) {
+        widths[i] += share;
+    }
+    widths
+}
+
+/// Distributes `available` among the columns of the fixed table layout: the columns
+/// without a width share what the others leave, or those all grow in proportion to their
+/// widths.
+fn share_fixed_widths(specified: &[Option<f32>], available: f32) -> Vec<f32> {
+    let fixed: f32 = specified.iter().flatten().sum();
+    let auto = specified.iter().filter(|width| width.is_none()).count();
+    let free = (available - fixed).max(0.0);
+    if auto > 0 {
+        return specified
+            .iter()
+            .map(|width| width.unwrap_or(free / auto as f32))
+            .collect();
+    }
+    let weights: Vec<f32> = specified.iter().flatten().copied().collect();
+    shares(&weights, free)
+        .into_iter()
+        .zip(&weights)
+        .map(|(share, width)| width + share)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layout::testing::{find, layout_html};
+    use crate::layout::Rect;
+
+    #[test]
+    fn test_table_layout() {
+        let root = layout_html(
+            "<div>\
+             <table class=\"fixed\" width=\"300\" cellpadding=\"5\">\
+             <caption class=\"caption\">t</caption>\
+             <tr><td class=\"a\" width=\"100\">a</td><td class=\"b\">b</td></tr>\
+             <tr><td class=\"c\" colspan=\"2\">c</td></tr>\
+             </table>\
+             <table class=\"collapse\">\
+             <tr><td class=\"d\" rowspan=\"2\">d</td><td class=\"e\">e</td></tr>\
+             <tr><td class=\"f\">f</td></tr>\
+             </table>\
+             </div>",
+            ".fixed { table-layout: fixed; border-spacing: 10px; border-width: 2px; }
+             .collapse { border-collapse: collapse; border-width: 4px; }
+             .collapse td { border-width: 2px; padding: 0; height: 20px; }
+             .d { width: 50px; }",
+            Rect::new(0.0, 0.0, 800.0, 600.0),
+        );
+        let cell = |class: &str| find(&root, class).unwrap().dimensions;
+
+        // The caption sits above the table box, in a wrapper as wide as the table.
+        let wrapper = find(&root, "fixed").unwrap();
+        assert_eq!(wrapper.dimensions.content.width, 300.0);
+        let table = wrapper.children[1].dimensions;
+        let caption = cell("caption").margin_box();
+        assert_eq!(table.border_box().y, caption.y + caption.height);
+        assert_eq!(table.content.width, 296.0);
+
+        // The first column takes the width of its cell, the second what's left.
+        let (a, b, c) = (cell("a"), cell("b"), cell("c"));
+        assert_eq!(a.padding.left, 5.0);
+        assert_eq!(a.border_box().x, table.content.x + 10.0);
+        assert_eq!(a.border_box().width, 110.0);
+        assert_eq!(b.border_box().x, a.border_box().x + 120.0);
+        assert_eq!(b.border_box().width, 156.0);
+        assert_eq!(c.border_box().width, 276.0);
+        let below_a = a.border_box().y + a.border_box().height + 10.0;
+        assert_eq!(c.border_box().y, below_a);
+
+        // Collapsed borders are shared by neighbouring cells, and by cells and the table.
+        let collapse = find(&root, "collapse").unwrap().children[0].dimensions;
+        let (d, e, f) = (cell("d"), cell("e"), cell("f"));
+        assert_eq!(collapse.border.left, 2.0);
+        assert_eq!((d.border.left, d.border.right), (2.0, 1.0));
+        assert_eq!(d.border_box().width, 53.0);
+        assert_eq!(e.border_box().x, d.border_box().x + d.border_box().width);
+        assert_eq!((e.border.top, e.border.bottom), (2.0, 1.0));
+        assert_eq!(f.border_box().y, e.border_box().y + e.border_box().height);
+        let rows_height = e.border_box().height + f.border_box().height;
+        assert_eq!(d.border_box().height, rows_height);
+    }
+}
@@ -2,8 +2,18 @@ pub mod css;
 pub mod display;
 pub mod html;
 pub mod layout;
+pub mod loader;
+pub mod page;
+pub mod raster;
 pub mod style;
 pub mod text;
 
+extern crate base64;
 extern crate cairo;
 extern crate clap;
+extern crate fontconfig_sys;
+extern crate image;
+#[cfg(feature = "parallel")]
+extern crate rayon;
+extern crate unicode_linebreak;
+extern crate url;
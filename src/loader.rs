@@ -0,0 +1,262 @@
+use crate::css::media::Device;
+use crate::css::{self, Stylesheet};
+use crate::html::Document;
+use crate::raster::{Image, ImageMap};
+use crate::text::font_face::{self, FontFace, FontSource};
+use crate::text::{is_font, local_font};
+use std::fs;
+use std::sync::Arc;
+use url::Url;
+
+/// Returns the `file:` URL of a local file, relative to the working directory.
+pub fn file_url(path: &str) -> Result<Url, String> {
+    let path = fs::canonicalize(path).map_err(|e| e.to_string())?;
+    Url::from_file_path(&path).map_err(|_| format!("invalid file path {}", path.display()))
+}
+
+pub fn fetch(url: &Url) -> Result<String, String> {
+    String::from_utf8(fetch_bytes(url)?).map_err(|e| e.to_string())
+}
+
+/// Reads the contents of a local file or of a `data:` URL.
+pub fn fetch_bytes(url: &Url) -> Result<Vec<u8>, String> {
+    match url.scheme() {
+        "file" => {
+            let path = url
+                .to_file_path()
+                .map_err(|_| format!("invalid file URL {}", url))?;
+            fs::read(path).map_err(|e| e.to_string())
+        }
+        "data" => decode_data_url(url),
+        scheme => Err(format!("unsupported scheme {}", scheme)),
+    }
+}
+
+/// Decodes the payload of a `data:[<media type>][;base64],<data>` URL.
+fn decode_data_url(url: &Url) -> Result<Vec<u8>, String> {
+    let path = url.path();
+    let comma = path
+        .find(',')
+        .ok_or_else(|| format!("invalid data URL {}", url))?;
+    let data = percent_decode(&path[comma + 1..]);
+    if path[..comma].ends_with(";base64") {
+        let data: Vec<u8> = data
+            .into_iter()
+            .filter(|c| !c.is_ascii_whitespace())
+            .collect();
+        base64::decode(&data).map_err(|e| e.to_string())
+    } else {
+        Ok(data)
+    }
+}
+
+fn percent_decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                result.push(byte);
+                i += 3;
+            }
+            None => {
+                result.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    result
+}
+
+/// Loads and decodes the images of a document. Images that fail to load are left out, to
+/// be shown by their alternative text, and why is added to `warnings`.
+pub fn load_images(
+    document: &Document,
+    document_url: &Url,
+    warnings: &mut Vec<String>,
+) -> ImageMap {
+    let base_url = document.base_url(document_url);
+    let mut images = ImageMap::new();
+
+    for src in document.collect_image_sources() {
+        if images.contains_key(&src) {
+            continue;
+        }
+        let image = base_url
+            .join(&src)
+            .map_err(|e| e.to_string())
+            .and_then(|url| fetch_bytes(&url))
+            .and_then(|bytes| Image::decode(&bytes));
+        match image {
+            Ok(image) => {
+                images.insert(src, Arc::new(image));
+            }
+            Err(e) => warnings.push(format!("Failed to load image {}: {}", src, e)),
+        }
+    }
+
+    images
+}
+
+/// Loads the fonts of the `@font-face` rules that apply to `device`, each from the first of
+/// its sources that loads. Rules none of whose sources load are left out; each source that
+/// fails is added to `warnings`.
+pub fn load_font_faces(
+    stylesheet: &Stylesheet,
+    device: &Device,
+    warnings: &mut Vec<String>,
+) -> Vec<FontFace> {
+    let mut faces = Vec::new();
+
+    for rule in &stylesheet.font_faces {
+        if !rule.applies_to(device) {
+            continue;
+        }
+        let loaded = font_face::sources(rule)
+            .iter()
+            .find_map(|source| match load_font(source) {
+                Ok(font) => Some(font),
+                Err(e) => {
+                    warnings.push(format!("Failed to load font {:?}: {}", source, e));
+                    None
+                }
+            });
+        if let Some((data, index)) = loaded {
+            faces.extend(FontFace::from_rule(rule, data, index));
+        }
+    }
+
+    faces
+}
+
+/// Reads a font file and finds the index of the face in it.
+fn load_font(source: &FontSource) -> Result<(Vec<u8>, isize), String> {
+    let (data, index) = match *source {
+        FontSource::Url(ref url) => {
+            let url = Url::parse(url).map_err(|e| e.to_string())?;
+            (fetch_bytes(&url)?, 0)
+        }
+        FontSource::Local(ref name) => {
+            let (path, index) = local_font(name).ok_or("no such font installed")?;
+            (fs::read(path).map_err(|e| e.to_string())?, index)
+        }
+    };
+    if !is_font(&data, index) {
+        return Err("unsupported font format".to_string());
+    }
+    Ok((data, index))
+}
+
+/// Loads the linked and inline stylesheets of a document in cascade order,
+/// following their `@import` rules. Stylesheets that fail to load and cyclic imports are left
+/// out, and added to `warnings`.
+pub fn load_stylesheets(
+    document: &Document,
+    document_url: &Url,
+    warnings: &mut Vec<String>,
+) -> Stylesheet {
+    let mut stylesheet = Stylesheet::default();
+
+    for css_url in document.collect_css_urls(document_url) {
+        if let Some(linked) = load_stylesheet(&css_url, &mut Vec::new(), warnings) {
+            stylesheet.merge(linked);
+        }
+    }
+
+    let base_url = document.base_url(document_url);
+    for css_source in document.collect_inline_styles() {
+        let mut inline = css::parse(css_source);
+        inline.resolve_urls(&base_url);
+        stylesheet.merge(resolve_imports(inline, &mut Vec::new(), warnings));
+    }
+
+    stylesheet
+}
+
+/// `loading` holds the stylesheets currently being imported, to break `@import` cycles.
+fn load_stylesheet(
+    url: &Url,
+    loading: &mut Vec<Url>,
+    warnings: &mut Vec<String>,
+) -> Option<Stylesheet> {
+    if loading.contains(url) {
+        warnings.push(format!("Skipping cyclic @import of {}", url));
+        return None;
+    }
+
+    let source = match fetch(url) {
+        Ok(source) => source,
+        Err(e) => {
+            warnings.push(format!("Failed to load {}: {}", url, e));
+            return None;
+        }
+    };
+    let mut stylesheet = css::parse(source);
+    stylesheet.resolve_urls(url);
+
+    loading.push(url.clone());
+    let stylesheet = resolve_imports(stylesheet, loading, warnings);
+    loading.pop();
+
+    Some(stylesheet)
+}
+
+fn resolve_imports(
+    mut stylesheet: Stylesheet,
+    loading: &mut Vec<Url>,
+    warnings: &mut Vec<String>,
+) -> Stylesheet {
+    let mut result = Stylesheet::default();
+
+    for import in stylesheet.imports.drain(..) {
+        let url = match Url::parse(&import.url) {
+            Ok(url) => url,
+            Err(e) => {
+                warnings.push(format!("Failed to load {}: {}", import.url, e));
+                continue;
+            }
+        };
+        if let Some(mut imported) = load_stylesheet(&url, loading, warnings) {
+            imported.add_media(&import.media);
+            result.merge(imported);
+        }
+    }
+
+    result.merge(stylesheet);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_stylesheet, Url};
+    use std::fs;
+
+    #[test]
+    fn test_import_warnings() {
+        let dir = std::env::temp_dir().join(format!("shigi-loader-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.css"), "@import url(b.css); p { color: red; }").unwrap();
+        fs::write(
+            dir.join("b.css"),
+            "@import 'a.css'; @import 'missing.css'; @import url(http://[);",
+        )
+        .unwrap();
+        let url = Url::from_file_path(dir.join("a.css")).unwrap();
+
+        let mut warnings = Vec::new();
+        let stylesheet = load_stylesheet(&url, &mut Vec::new(), &mut warnings).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(stylesheet.rules.len(), 1);
+        assert_eq!(warnings.len(), 3);
+        assert!(warnings[0].starts_with("Skipping cyclic @import of file://"));
+        assert!(warnings[1].starts_with("Failed to load file://"));
+        assert!(warnings[2].starts_with("Failed to load http://["));
+    }
+}
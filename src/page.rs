@@ -0,0 +1,415 @@
+use crate::css::media::Device;
+use crate::css::{Color, ComputeContext, PageRule, Stylesheet, Value};
+use crate::layout::list::counter_text;
+use crate::layout::{EdgeSizes, Position, Rect};
+use crate::style::{self, declared_values, PropertyMap};
+use crate::text::{font_metrics, shape, Font, GlyphRun};
+
+/// Named page sizes of CSS Paged Media §7.1, in portrait orientation, in millimetres.
+const PAGE_SIZES: [(&str, f32, f32); 10] = [
+    ("a5", 148.0, 210.0),
+    ("a4", 210.0, 297.0),
+    ("a3", 297.0, 420.0),
+    ("b5", 176.0, 250.0),
+    ("b4", 250.0, 353.0),
+    ("jis-b5", 182.0, 257.0),
+    ("jis-b4", 257.0, 364.0),
+    ("letter", 215.9, 279.4),
+    ("legal", 215.9, 355.6),
+    ("ledger", 279.4, 431.8),
+];
+
+/// The margin boxes of a page, in the order they are painted.
+const MARGIN_BOXES: [&str; 16] = [
+    "top-left-corner",
+    "top-left",
+    "top-center",
+    "top-right",
+    "top-right-corner",
+    "right-top",
+    "right-middle",
+    "right-bottom",
+    "bottom-right-corner",
+    "bottom-right",
+    "bottom-center",
+    "bottom-left",
+    "bottom-left-corner",
+    "left-bottom",
+    "left-middle",
+    "left-top",
+];
+
+/// The pages of a document as the `@page` rules of a stylesheet style them. The first page is
+/// a right one, after which left and right pages alternate, so there are only three kinds.
+#[derive(Debug)]
+pub struct PageStyles {
+    first: Page,
+    left: Page,
+    right: Page,
+}
+
+/// A page box: its size, its margins, and the boxes in them. Content is laid out in the page
+/// area inside the margins.
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub width: f32,
+    pub height: f32,
+    pub margin: EdgeSizes,
+    pub margin_boxes: Vec<MarginBox>,
+}
+
+/// A box in the margin of a page, generated by a rule like `@top-center` with a `content`.
+/// Its text is laid out once the page it is on is known.
+#[derive(Debug, Clone)]
+pub struct MarginBox {
+    pub name: &'static str,
+    pub rect: Rect,
+    /// The glyph runs of the text, painted one after the other.
+    pub runs: Vec<GlyphRun>,
+    /// Where the first run starts, on the baseline.
+    pub origin: Position,
+    values: PropertyMap,
+}
+
+impl PageStyles {
+    /// Cascades the `@page` rules that apply to `device`. Pages are as large as the device
+    /// unless `size` says otherwise.
+    pub fn new(stylesheet: &Stylesheet, device: &Device) -> Self {
+        let mut rules: Vec<(usize, &PageRule)> = stylesheet
+            .pages
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| rule.applies_to(device))
+            .collect();
+        rules.sort_by_key(|&(order, rule)| (rule.selector.specificity(), order));
+        let rules: Vec<&PageRule> = rules.into_iter().map(|(_, rule)| rule).collect();
+
+        let page = |index| Page::new(&rules, index, (device.width, device.height));
+        Self {
+            first: page(0),
+            left: page(1),
+            right: page(2),
+        }
+    }
+
+    /// The page at `index`, from 0.
+    pub fn page(&self, index: usize) -> &Page {
+        match (index, index % 2) {
+            (0, _) => &self.first,
+            (_, 1) => &self.left,
+            _ => &self.right,
+        }
+    }
+
+    /// The page at `index`, from 0, of `count`, with the text of its margin boxes laid out.
+    pub fn layout_page(&self, index: usize, count: usize) -> Page {
+        let mut page = self.page(index).clone();
+        for margin_box in &mut page.margin_boxes {
+            margin_box.layout_text(index + 1, count);
+        }
+        page
+    }
+}
+
+impl Page {
+    fn new(rules: &[&PageRule], index: usize, default_size: (f32, f32)) -> Self {
+        let rules: Vec<&PageRule> = rules
+            .iter()
+            .copied()
+            .filter(|rule| rule.selector.matches(index))
+            .collect();
+        let values = declared_values(rules.iter().flat_map(|rule| &rule.declarations));
+
+        let (width, height) = page_size(values.get("size"), default_size);
+        let margin = |name: &str, base: f32| {
+            let value = values.get(name).or_else(|| values.get("margin"));
+            match value {
+                Some(value @ Value::Length(..)) | Some(value @ Value::Percentage(_)) => {
+                    value.compute(&ComputeContext::default()).resolve(base)
+                }
+                _ => 0.0,
+            }
+        };
+        let margin = EdgeSizes {
+            top: margin("margin-top", height),
+            right: margin("margin-right", width),
+            bottom: margin("margin-bottom", height),
+            left: margin("margin-left", width),
+        };
+
+        let mut margin_boxes = Vec::new();
+        for &name in MARGIN_BOXES.iter() {
+            let declarations = rules.iter().flat_map(|rule| {
+                rule.margin_rules
+                    .iter()
+                    .filter(move |(rule_name, _)| rule_name == name)
+                    .flat_map(|(_, declarations)| declarations)
+            });
+            let values = declared_values(declarations);
+            match values.get("content") {
+                None | Some(Value::Keyword(_)) => continue,
+                Some(_) => {}
+            }
+            margin_boxes.push(MarginBox {
+                name,
+                rect: margin_box_rect(name, width, height, margin),
+                runs: Vec::new(),
+                origin: Position::default(),
+                values,
+            });
+        }
+
+        Self {
+            width,
+            height,
+            margin,
+            margin_boxes,
+        }
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0.0, 0.0, self.width, self.height)
+    }
+
+    /// The area inside the margins, where the content of the page goes.
+    pub fn page_area(&self) -> Rect {
+        Rect::new(
+            self.margin.left,
+            self.margin.top,
+            (self.width - self.margin.left - self.margin.right).max(0.0),
+            (self.height - self.margin.top - self.margin.bottom).max(0.0),
+        )
+    }
+}
+
+/// The width and height `size` gives a page: `auto`, an orientation, a named size with an
+/// optional orientation, or one or two lengths.
+fn page_size(value: Option<&Value>, default_size: (f32, f32)) -> (f32, f32) {
+    let values = match value {
+        Some(Value::List(values)) => values.iter().collect(),
+        Some(value) => vec![value],
+        None => Vec::new(),
+    };
+    let mut size = default_size;
+    let mut orientation = None;
+    let mut lengths = Vec::new();
+    for value in values {
+        match value {
+            Value::Keyword(keyword) => match keyword.to_ascii_lowercase().as_str() {
+                "portrait" | "landscape" => orientation = Some(keyword.to_ascii_lowercase()),
+                name => {
+                    if let Some(&(_, width, height)) = PAGE_SIZES.iter().find(|s| s.0 == name) {
+                        let mm = |f: f32| f * 96.0 / 25.4;
+                        size = (mm(width), mm(height));
+                    }
+                }
+            },
+            Value::Length(..) => lengths.push(value.to_px()),
+            _ => {}
+        }
+    }
+    match lengths[..] {
+        [side] => size = (side, side),
+        [width, height, ..] => size = (width, height),
+        _ => {}
+    }
+    let (short, long) = (size.0.min(size.1), size.0.max(size.1));
+    match orientation.as_deref() {
+        Some("portrait") => (short, long),
+        Some("landscape") => (long, short),
+        _ => size,
+    }
+}
+
+/// Where a margin box is on a page, as in CSS Paged Media §5.3 but with fixed widths: the
+/// corners fill the corners of the page, and the boxes along each side share the space
+/// between them equally.
+fn margin_box_rect(name: &str, width: f32, height: f32, margin: EdgeSizes) -> Rect {
+    let inner_width = width - margin.left - margin.right;
+    let inner_height = height - margin.top - margin.bottom;
+    let column = |i: f32| margin.left + inner_width * i / 3.0;
+    let row = |i: f32| margin.top + inner_height * i / 3.0;
+    let (right, bottom) = (width - margin.right, height - margin.bottom);
+    let (x, y, w, h) = match name {
+        "top-left-corner" => (0.0, 0.0, margin.left, margin.top),
+        "top-right-corner" => (right, 0.0, margin.right, margin.top),
+        "bottom-left-corner" => (0.0, bottom, margin.left, margin.bottom),
+        "bottom-right-corner" => (right, bottom, margin.right, margin.bottom),
+        "top-left" => (column(0.0), 0.0, inner_width / 3.0, margin.top),
+        "top-center" => (column(1.0), 0.0, inner_width / 3.0, margin.top),
+        "top-right" => (column(2.0), 0.0, inner_width / 3.0, margin.top),
+        "bottom-left" => (column(0.0), bottom, inner_width / 3.0, margin.bottom),
+        "bottom-center" => (column(1.0), bottom, inner_width / 3.0, margin.bottom),
+        "bottom-right" => (column(2.0), bottom, inner_width / 3.0, margin.bottom),
+        "left-top" => (0.0, row(0.0), margin.left, inner_height / 3.0),
+        "left-middle" => (0.0, row(1.0), margin.left, inner_height / 3.0),
+        "left-bottom" => (0.0, row(2.0), margin.left, inner_height / 3.0),
+        "right-top" => (right, row(0.0), margin.right, inner_height / 3.0),
+        "right-middle" => (right, row(1.0), margin.right, inner_height / 3.0),
+        "right-bottom" => (right, row(2.0), margin.right, inner_height / 3.0),
+        _ => (0.0, 0.0, 0.0, 0.0),
+    };
+    Rect::new(x, y, w.max(0.0), h.max(0.0))
+}
+
+impl MarginBox {
+    /// The text `content` gives the box on page `number`, counted from 1, of `count`.
+    /// `counter(page)` and `counter(pages)` are the only counters.
+    pub fn text(&self, number: usize, count: usize) -> String {
+        let values = match self.values.get("content") {
+            Some(Value::List(values)) => values.iter().collect(),
+            Some(value) => vec![value],
+            None => Vec::new(),
+        };
+        let mut text = String::new();
+        for value in values {
+            match value {
+                Value::Str(s) => text.push_str(s),
+                Value::Function(name, args) if name == "counter" => {
+                    let n = match args.first() {
+                        Some(Value::Keyword(counter)) if counter == "page" => number,
+                        Some(Value::Keyword(counter)) if counter == "pages" => count,
+                        _ => 0,
+                    };
+                    let style = match args.get(1) {
+                        Some(Value::Keyword(style)) => style.as_str(),
+                        _ => "decimal",
+                    };
+                    text.push_str(&counter_text(style, n as i32));
+                }
+                _ => {}
+            }
+        }
+        text
+    }
+
+    /// Shapes the text of the box on page `number`, counted from 1, of `count`, and aligns
+    /// it in the box on one line.
+    fn layout_text(&mut self, number: usize, count: usize) {
+        let font = self.font();
+        let metrics = font_metrics(&font);
+        self.runs = shape(&self.text(number, count), &font);
+        let width = self.runs.iter().map(GlyphRun::width).sum::<f32>();
+        let height = metrics.ascent + metrics.descent;
+        let (horizontal, vertical) = self.alignment();
+        self.origin = Position {
+            x: self.rect.x + (self.rect.width - width) * horizontal,
+            y: self.rect.y + (self.rect.height - height) * vertical + metrics.ascent,
+        };
+    }
+
+    /// The font of the text, whose size is relative to the default one.
+    pub fn font(&self) -> Font {
+        let mut font = style::font(&self.values);
+        font.size = match self.values.get("font-size") {
+            Some(value @ Value::Length(..)) => value.compute(&ComputeContext::default()).to_px(),
+            _ => ComputeContext::default().font_size,
+        };
+        font
+    }
+
+    pub fn color(&self) -> Color {
+        match self.values.get("color") {
+            Some(&Value::ColorValue(color)) => color,
+            _ => Color::new(0, 0, 0, 255),
+        }
+    }
+
+    /// Where the text goes in the box, horizontally then vertically, as a fraction of the
+    /// space it leaves. `text-align` and `vertical-align` override the defaults of the box:
+    /// toward the page area, and in the middle.
+    pub fn alignment(&self) -> (f32, f32) {
+        let keyword = |name: &str| match self.values.get(name) {
+            Some(Value::Keyword(keyword)) => Some(keyword.as_str()),
+            _ => None,
+        };
+        let horizontal = match keyword("text-align") {
+            Some("left") => 0.0,
+            Some("center") => 0.5,
+            Some("right") => 1.0,
+            _ => match self.name {
+                "top-left" | "bottom-left" | "top-right-corner" | "bottom-right-corner" => 0.0,
+                "top-right" | "bottom-right" | "top-left-corner" | "bottom-left-corner" => 1.0,
+                _ => 0.5,
+            },
+        };
+        let vertical = match keyword("vertical-align") {
+            Some("top") => 0.0,
+            Some("middle") => 0.5,
+            Some("bottom") => 1.0,
+            _ => match self.name {
+                "left-top" | "right-top" => 0.0,
+                "left-bottom" | "right-bottom" => 1.0,
+                _ => 0.5,
+            },
+        };
+        (horizontal, vertical)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PageStyles;
+    use crate::css;
+    use crate::css::media::{Device, MediaType};
+    use crate::layout::Rect;
+    use crate::text::testing::add_test_font;
+    use crate::text::GlyphRun;
+
+    #[test]
+    fn test_page_styles() {
+        let stylesheet = css::parse(
+            "@page { size: A4 landscape; margin: 10mm; }
+             @page :first { margin-top: 20mm; }
+             @page :left {
+                 @bottom-left { content: counter(page) \" / \" counter(pages); font-family: Shigi Test; }
+             }
+             @page :right {
+                 @bottom-right { content: \"Page \" counter(page, upper-roman); font-size: 8pt; }
+             }
+             @page chapter { margin: 0; }
+             @media screen { @page { margin: 0; } }"
+                .to_string(),
+        );
+        let device = Device::new(MediaType::Print, Rect::new(0.0, 0.0, 800.0, 600.0));
+        let pages = PageStyles::new(&stylesheet, &device);
+        let mm = |f: f32| f * 96.0 / 25.4;
+
+        let first = pages.page(0);
+        assert_eq!((first.width, first.height), (mm(297.0), mm(210.0)));
+        assert_eq!(first.margin.top, mm(20.0));
+        assert_eq!(first.margin.left, mm(10.0));
+        let area = first.page_area();
+        assert_eq!(area.height, mm(210.0) - mm(20.0) - mm(10.0));
+
+        assert_eq!(pages.page(1).margin.top, mm(10.0));
+        let left = &pages.page(1).margin_boxes;
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].text(2, 7), "2 / 7");
+        assert_eq!(left[0].rect.y, mm(200.0));
+        assert_eq!(left[0].alignment(), (0.0, 0.5));
+
+        // The text of a margin box is shaped once its page is known, and aligned in the box.
+        add_test_font();
+        let page = pages.layout_page(1, 7);
+        let margin_box = &page.margin_boxes[0];
+        let text: String = margin_box
+            .runs
+            .iter()
+            .map(|run| run.text.as_str())
+            .collect();
+        assert_eq!(text, "2 / 7");
+        let width: f32 = margin_box.runs.iter().map(GlyphRun::width).sum();
+        assert_eq!(width, 32.0);
+        let rect = margin_box.rect;
+        assert_eq!(margin_box.origin.x, rect.x);
+        let baseline = rect.y + (rect.height - 16.0) / 2.0 + 12.8;
+        assert!((margin_box.origin.y - baseline).abs() < 0.01);
+        assert!(pages.page(1).margin_boxes[0].runs.is_empty());
+
+        let right = &pages.page(2).margin_boxes;
+        assert_eq!(right[0].name, "bottom-right");
+        assert_eq!(right[0].text(3, 7), "Page III");
+        assert_eq!(right[0].font().size, 8.0 * 96.0 / 72.0);
+    }
+}
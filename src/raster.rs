@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A decoded image, as rows of premultiplied ARGB32 pixels in native byte order, the way
+/// cairo draws them.
+#[derive(Debug, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// The images of a document, keyed by the `src` attribute that refers to them.
+pub type ImageMap = HashMap<String, Arc<Image>>;
+
+impl Image {
+    /// Decodes a PNG, JPEG, GIF or WebP image, recognised by its signature. Only the first
+    /// frame of an animation is kept.
+    pub fn decode(bytes: &[u8]) -> Result<Image, String> {
+        let rgba = image::load_from_memory(bytes)
+            .map_err(|e| e.to_string())?
+            .to_rgba8();
+        let (width, height) = rgba.dimensions();
+
+        let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+        for pixel in rgba.pixels() {
+            let [r, g, b, a] = pixel.0;
+            let premultiply = |c: u8| (c as u32 * a as u32 + 127) / 255;
+            let argb =
+                (a as u32) << 24 | premultiply(r) << 16 | premultiply(g) << 8 | premultiply(b);
+            data.extend_from_slice(&argb.to_ne_bytes());
+        }
+        Ok(Image {
+            width,
+            height,
+            data,
+        })
+    }
+}
@@ -1,27 +1,208 @@
-use crate::css::{Rule, Selector, SimpleSelector, Specificity, Stylesheet, Value};
+pub mod bloom;
+pub mod rule_map;
+pub mod sharing;
+
+use crate::css::media::Device;
+use crate::css::{
+    Color, ComputeContext, Declaration, Selector, SimpleSelector, Stylesheet, Unit, Value,
+};
 use crate::html::{ElementData, Node, NodeType};
+use crate::text::Font;
+use bloom::AncestorFilter;
+use rule_map::{MatchedRule, RuleMap};
+use sharing::StyleSharingCache;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 pub type PropertyMap = HashMap<String, Value>;
 
 #[derive(Debug)]
 pub struct StyledNode<'a> {
     pub node: &'a Node,
-    pub specified_values: PropertyMap,
+    pub specified_values: Arc<PropertyMap>,
     pub children: Vec<StyledNode<'a>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Display {
     Inline,
     Block,
+    /// A block that generates a marker box, numbered by the `list-item` counter.
+    ListItem,
+    /// A block that establishes a new block formatting context for its contents.
+    FlowRoot,
+    /// A block container laid out as an atomic box on a line.
+    InlineBlock,
+    Flex,
+    InlineFlex,
+    Grid,
+    InlineGrid,
+    Table,
+    InlineTable,
+    TableRowGroup,
+    TableHeaderGroup,
+    TableFooterGroup,
+    TableRow,
+    TableCell,
+    TableColumnGroup,
+    TableColumn,
+    TableCaption,
     None,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Position {
     Static,
     Relative,
+    Absolute,
+    Fixed,
+    /// Without scrolling, sticky boxes are only offset like relative ones.
+    Sticky,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Float {
+    None,
+    Left,
+    Right,
+}
+
+/// The sides of earlier floats a box must be placed below.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Clear {
+    None,
+    Left,
+    Right,
+    Both,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlexDirection {
+    Row,
+    RowReverse,
+    Column,
+    ColumnReverse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlexWrap {
+    NoWrap,
+    Wrap,
+    WrapReverse,
+}
+
+/// The axis along which auto-placed grid items fill the grid.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GridAutoFlow {
+    Row,
+    Column,
+}
+
+/// How free space is distributed between and around the boxes, or the lines, along an axis:
+/// the values of `justify-content` and `align-content`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContentDistribution {
+    Start,
+    End,
+    Center,
+    SpaceBetween,
+    SpaceAround,
+    SpaceEvenly,
+    Stretch,
+}
+
+/// How a box is aligned in the space set aside for it: the values of `align-items` and
+/// `align-self`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ItemAlignment {
+    Start,
+    End,
+    Center,
+    Baseline,
+    Stretch,
+}
+
+/// Where the marker of a list item goes: on the first line of the item, or beside it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListStylePosition {
+    Inside,
+    Outside,
+}
+
+/// How content overflowing the padding box of a block is shown. Anything but `visible`
+/// clips it; `clip` alone doesn't make the block a formatting context root.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    Visible,
+    Hidden,
+    Clip,
+    Scroll,
+    Auto,
+}
+
+/// How the content of a replaced element is sized to fit its content box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObjectFit {
+    Fill,
+    Contain,
+    Cover,
+    None,
+    ScaleDown,
+}
+
+/// A page break asked for before, after or inside a box. `Left` and `Right` force a break
+/// to the next left or right page.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Break {
+    Auto,
+    Avoid,
+    Page,
+    Left,
+    Right,
+}
+
+/// What marks inline content cut off at the end edge of a line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextOverflow {
+    Clip,
+    Ellipsis,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextAlign {
+    Left,
+    Right,
+    Center,
+    Justify,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineHeight {
+    Normal,
+    /// A multiple of the element's font size.
+    Number(f32),
+    Length(f32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerticalAlign {
+    Baseline,
+    Sub,
+    Super,
+    TextTop,
+    TextBottom,
+    Middle,
+    Top,
+    Bottom,
+    /// A length, or a percentage of the line height, to raise the baseline by.
+    Length(Value),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FontStyle {
+    Normal,
+    Italic,
+    Oblique,
 }
 
 impl<'a> StyledNode<'a> {
@@ -42,6 +223,23 @@ impl<'a> StyledNode<'a> {
         match self.value("display") {
             Some(Value::Keyword(s)) => match &*s {
                 "block" => Display::Block,
+                "list-item" => Display::ListItem,
+                "flow-root" => Display::FlowRoot,
+                "inline-block" => Display::InlineBlock,
+                "flex" => Display::Flex,
+                "inline-flex" => Display::InlineFlex,
+                "grid" => Display::Grid,
+                "inline-grid" => Display::InlineGrid,
+                "table" => Display::Table,
+                "inline-table" => Display::InlineTable,
+                "table-row-group" => Display::TableRowGroup,
+                "table-header-group" => Display::TableHeaderGroup,
+                "table-footer-group" => Display::TableFooterGroup,
+                "table-row" => Display::TableRow,
+                "table-cell" => Display::TableCell,
+                "table-column-group" => Display::TableColumnGroup,
+                "table-column" => Display::TableColumn,
+                "table-caption" => Display::TableCaption,
                 "none" => Display::None,
                 _ => Display::Inline,
             },
@@ -54,56 +252,582 @@ impl<'a> StyledNode<'a> {
             Some(Value::Keyword(s)) => match &*s {
                 "static" => Position::Static,
                 "relative" => Position::Relative,
+                "absolute" => Position::Absolute,
+                "fixed" => Position::Fixed,
+                "sticky" => Position::Sticky,
                 _ => Position::Static,
             },
             _ => Position::Static,
         }
     }
+
+    pub fn float(&self) -> Float {
+        match self.value("float") {
+            Some(Value::Keyword(s)) => match &*s {
+                "left" => Float::Left,
+                "right" => Float::Right,
+                _ => Float::None,
+            },
+            _ => Float::None,
+        }
+    }
+
+    pub fn clear(&self) -> Clear {
+        match self.value("clear") {
+            Some(Value::Keyword(s)) => match &*s {
+                "left" => Clear::Left,
+                "right" => Clear::Right,
+                "both" => Clear::Both,
+                _ => Clear::None,
+            },
+            _ => Clear::None,
+        }
+    }
+
+    pub fn flex_direction(&self) -> FlexDirection {
+        match self.value("flex-direction") {
+            Some(Value::Keyword(s)) => match &*s {
+                "row-reverse" => FlexDirection::RowReverse,
+                "column" => FlexDirection::Column,
+                "column-reverse" => FlexDirection::ColumnReverse,
+                _ => FlexDirection::Row,
+            },
+            _ => FlexDirection::Row,
+        }
+    }
+
+    pub fn flex_wrap(&self) -> FlexWrap {
+        match self.value("flex-wrap") {
+            Some(Value::Keyword(s)) => match &*s {
+                "wrap" => FlexWrap::Wrap,
+                "wrap-reverse" => FlexWrap::WrapReverse,
+                _ => FlexWrap::NoWrap,
+            },
+            _ => FlexWrap::NoWrap,
+        }
+    }
+
+    /// `normal` behaves as `stretch`, which is `start` in a flex container.
+    pub fn justify_content(&self) -> ContentDistribution {
+        self.content_distribution("justify-content", ContentDistribution::Stretch)
+    }
+
+    /// `normal` behaves as `stretch`.
+    pub fn align_content(&self) -> ContentDistribution {
+        self.content_distribution("align-content", ContentDistribution::Stretch)
+    }
+
+    fn content_distribution(&self, name: &str, normal: ContentDistribution) -> ContentDistribution {
+        match self.value(name) {
+            Some(Value::Keyword(s)) => match &*s {
+                "start" | "flex-start" | "left" => ContentDistribution::Start,
+                "end" | "flex-end" | "right" => ContentDistribution::End,
+                "center" => ContentDistribution::Center,
+                "space-between" => ContentDistribution::SpaceBetween,
+                "space-around" => ContentDistribution::SpaceAround,
+                "space-evenly" => ContentDistribution::SpaceEvenly,
+                "stretch" => ContentDistribution::Stretch,
+                _ => normal,
+            },
+            _ => normal,
+        }
+    }
+
+    /// `normal` behaves as `stretch`.
+    pub fn align_items(&self) -> ItemAlignment {
+        self.item_alignment("align-items")
+            .unwrap_or(ItemAlignment::Stretch)
+    }
+
+    /// `None` for `auto`, which defers to the parent's `align-items`.
+    pub fn align_self(&self) -> Option<ItemAlignment> {
+        self.item_alignment("align-self")
+    }
+
+    /// `normal` and `legacy` behave as `stretch`.
+    pub fn justify_items(&self) -> ItemAlignment {
+        self.item_alignment("justify-items")
+            .unwrap_or(ItemAlignment::Stretch)
+    }
+
+    /// `None` for `auto`, which defers to the parent's `justify-items`.
+    pub fn justify_self(&self) -> Option<ItemAlignment> {
+        self.item_alignment("justify-self")
+    }
+
+    /// The direction auto-placed grid items flow in, and whether holes left earlier in the
+    /// grid are filled (`dense`).
+    pub fn grid_auto_flow(&self) -> (GridAutoFlow, bool) {
+        let keywords = match self.value("grid-auto-flow") {
+            Some(Value::List(values)) => values,
+            Some(value) => vec![value],
+            None => Vec::new(),
+        };
+        let has = |name: &str| keywords.contains(&Value::Keyword(name.to_string()));
+        let flow = if has("column") {
+            GridAutoFlow::Column
+        } else {
+            GridAutoFlow::Row
+        };
+        (flow, has("dense"))
+    }
+
+    fn item_alignment(&self, name: &str) -> Option<ItemAlignment> {
+        match self.value(name) {
+            Some(Value::Keyword(s)) => match &*s {
+                "start" | "flex-start" | "self-start" | "left" => Some(ItemAlignment::Start),
+                "end" | "flex-end" | "self-end" | "right" => Some(ItemAlignment::End),
+                "center" => Some(ItemAlignment::Center),
+                "baseline" | "first" => Some(ItemAlignment::Baseline),
+                "stretch" | "normal" | "legacy" => Some(ItemAlignment::Stretch),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// `start` and `end` are resolved against `direction`.
+    pub fn text_align(&self) -> TextAlign {
+        let rtl = self.value("direction") == Some(Value::Keyword("rtl".to_string()));
+        match self.value("text-align") {
+            Some(Value::Keyword(s)) => match &*s {
+                "left" => TextAlign::Left,
+                "right" => TextAlign::Right,
+                "center" => TextAlign::Center,
+                "justify" => TextAlign::Justify,
+                "end" if !rtl => TextAlign::Right,
+                "end" => TextAlign::Left,
+                _ if rtl => TextAlign::Right,
+                _ => TextAlign::Left,
+            },
+            _ if rtl => TextAlign::Right,
+            _ => TextAlign::Left,
+        }
+    }
+
+    pub fn line_height(&self) -> LineHeight {
+        match self.value("line-height") {
+            Some(Value::Number(n)) => LineHeight::Number(n),
+            Some(Value::Length(px, Unit::Px)) => LineHeight::Length(px),
+            _ => LineHeight::Normal,
+        }
+    }
+
+    pub fn vertical_align(&self) -> VerticalAlign {
+        match self.value("vertical-align") {
+            Some(Value::Keyword(s)) => match &*s {
+                "sub" => VerticalAlign::Sub,
+                "super" => VerticalAlign::Super,
+                "text-top" => VerticalAlign::TextTop,
+                "text-bottom" => VerticalAlign::TextBottom,
+                "middle" => VerticalAlign::Middle,
+                "top" => VerticalAlign::Top,
+                "bottom" => VerticalAlign::Bottom,
+                _ => VerticalAlign::Baseline,
+            },
+            Some(value @ Value::Length(..))
+            | Some(value @ Value::Percentage(_))
+            | Some(value @ Value::Calc(_)) => VerticalAlign::Length(value),
+            _ => VerticalAlign::Baseline,
+        }
+    }
+
+    pub fn list_style_position(&self) -> ListStylePosition {
+        match self.value("list-style-position") {
+            Some(Value::Keyword(ref s)) if s == "inside" => ListStylePosition::Inside,
+            _ => ListStylePosition::Outside,
+        }
+    }
+
+    /// The stack level of a box, or `None` for `auto`.
+    pub fn z_index(&self) -> Option<i32> {
+        match self.value("z-index") {
+            Some(Value::Number(n)) => Some(n as i32),
+            _ => None,
+        }
+    }
+
+    pub fn overflow(&self) -> Overflow {
+        match self.value("overflow") {
+            Some(Value::Keyword(s)) => match &*s {
+                "hidden" => Overflow::Hidden,
+                "clip" => Overflow::Clip,
+                "scroll" => Overflow::Scroll,
+                "auto" => Overflow::Auto,
+                _ => Overflow::Visible,
+            },
+            _ => Overflow::Visible,
+        }
+    }
+
+    pub fn object_fit(&self) -> ObjectFit {
+        match self.value("object-fit") {
+            Some(Value::Keyword(s)) => match &*s {
+                "contain" => ObjectFit::Contain,
+                "cover" => ObjectFit::Cover,
+                "none" => ObjectFit::None,
+                "scale-down" => ObjectFit::ScaleDown,
+                _ => ObjectFit::Fill,
+            },
+            _ => ObjectFit::Fill,
+        }
+    }
+
+    pub fn break_before(&self) -> Break {
+        self.page_break("break-before", "page-break-before")
+    }
+
+    pub fn break_after(&self) -> Break {
+        self.page_break("break-after", "page-break-after")
+    }
+
+    pub fn break_inside(&self) -> Break {
+        match self.page_break("break-inside", "page-break-inside") {
+            Break::Avoid => Break::Avoid,
+            _ => Break::Auto,
+        }
+    }
+
+    /// Reads a break property, or else its legacy `page-break-*` alias.
+    fn page_break(&self, name: &str, legacy_name: &str) -> Break {
+        match self.value(name).or_else(|| self.value(legacy_name)) {
+            Some(Value::Keyword(s)) => match &*s {
+                "avoid" | "avoid-page" => Break::Avoid,
+                "page" | "always" => Break::Page,
+                "left" | "verso" => Break::Left,
+                "right" | "recto" => Break::Right,
+                _ => Break::Auto,
+            },
+            _ => Break::Auto,
+        }
+    }
+
+    /// The minimum number of lines of a paragraph left at the bottom of a page by `orphans`,
+    /// or at the top of the next one by `widows`.
+    pub fn line_count(&self, name: &str) -> usize {
+        match self.value(name) {
+            Some(Value::Number(n)) if n >= 1.0 => n as usize,
+            _ => 2,
+        }
+    }
+
+    pub fn text_overflow(&self) -> TextOverflow {
+        match self.value("text-overflow") {
+            Some(Value::Keyword(ref s)) if s == "ellipsis" => TextOverflow::Ellipsis,
+            _ => TextOverflow::Clip,
+        }
+    }
+
+    pub fn font_size(&self) -> f32 {
+        self.value_or("font-size", &Value::Length(16.0, Unit::Px))
+            .to_px()
+    }
+
+    pub fn font(&self) -> Font {
+        font(&self.specified_values)
+    }
+
+    /// The text color, black by default.
+    pub fn color(&self) -> Color {
+        match self.value("color") {
+            Some(Value::ColorValue(color)) => color,
+            _ => Color::new(0, 0, 0, 255),
+        }
+    }
 }
 
-pub fn style_tree<'a>(root: &'a Node, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
-    let specified_values = match root.typ {
-        NodeType::Element(ref elem) => specified_values(elem, stylesheet),
-        NodeType::Text(_) => HashMap::new(),
+/// The font the `font-*` properties among `values` describe.
+pub(crate) fn font(values: &PropertyMap) -> Font {
+    let size = match values.get("font-size") {
+        Some(size) => size.to_px(),
+        None => ComputeContext::default().font_size,
     };
-    StyledNode {
-        node: root,
-        children: root
-            .children
+    let family = match values.get("font-family") {
+        Some(Value::List(values)) => &values[..],
+        Some(value) => std::slice::from_ref(value),
+        None => &[],
+    };
+    // Families are separated by commas, and unquoted names may be several identifiers.
+    let mut families = Vec::new();
+    for names in family.split(|value| *value == Value::Keyword(",".to_string())) {
+        let name = names
             .iter()
-            .map(|child| child_style_tree(child, stylesheet, &specified_values))
-            .collect(),
-        specified_values,
+            .filter_map(|value| match *value {
+                Value::Keyword(ref s) | Value::Str(ref s) => Some(s.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        if !name.is_empty() {
+            families.push(name);
+        }
+    }
+    let style = match values.get("font-style") {
+        Some(Value::Keyword(ref s)) if s == "italic" => FontStyle::Italic,
+        Some(Value::Keyword(ref s)) if s == "oblique" => FontStyle::Oblique,
+        _ => FontStyle::Normal,
+    };
+    Font {
+        families,
+        size,
+        weight: values
+            .get("font-weight")
+            .map_or(400.0, |value| font_weight(value, 400.0)),
+        style,
+        stretch: values.get("font-stretch").map_or(100.0, font_stretch),
     }
 }
 
-fn child_style_tree<'a>(
+/// The percentage of the normal width a `font-stretch` value stands for.
+pub(crate) fn font_stretch(value: &Value) -> f32 {
+    match *value {
+        Value::Percentage(p) => p,
+        Value::Keyword(ref s) => match s.as_str() {
+            "ultra-condensed" => 50.0,
+            "extra-condensed" => 62.5,
+            "condensed" => 75.0,
+            "semi-condensed" => 87.5,
+            "semi-expanded" => 112.5,
+            "expanded" => 125.0,
+            "extra-expanded" => 150.0,
+            "ultra-expanded" => 200.0,
+            _ => 100.0,
+        },
+        _ => 100.0,
+    }
+}
+
+/// The numeric weight a `font-weight` value stands for, `bolder` and `lighter` being relative
+/// to the inherited weight `parent`.
+pub(crate) fn font_weight(value: &Value, parent: f32) -> f32 {
+    match *value {
+        Value::Number(n) => n.clamp(1.0, 1000.0),
+        Value::Keyword(ref s) => match s.as_str() {
+            "bold" => 700.0,
+            "bolder" if parent < 350.0 => 400.0,
+            "bolder" if parent < 550.0 => 700.0,
+            "bolder" => parent.max(900.0),
+            "lighter" if parent < 550.0 => parent.min(100.0),
+            "lighter" if parent < 750.0 => 400.0,
+            "lighter" => 700.0,
+            _ => 400.0,
+        },
+        _ => 400.0,
+    }
+}
+
+pub fn style_tree<'a>(
     root: &'a Node,
     stylesheet: &'a Stylesheet,
-    parent_values: &PropertyMap,
+    device: &Device,
 ) -> StyledNode<'a> {
-    let mut values = inherited_values(parent_values);
-    let specified_values = match root.typ {
-        NodeType::Element(ref elem) => specified_values(elem, stylesheet),
-        NodeType::Text(_) => HashMap::new(),
+    style_tree_with(root, stylesheet, device, false)
+}
+
+/// Like `style_tree`, but styles sibling subtrees in parallel. The result is identical.
+#[cfg(feature = "parallel")]
+pub fn par_style_tree<'a>(
+    root: &'a Node,
+    stylesheet: &'a Stylesheet,
+    device: &Device,
+) -> StyledNode<'a> {
+    style_tree_with(root, stylesheet, device, true)
+}
+
+fn style_tree_with<'a>(
+    root: &'a Node,
+    stylesheet: &'a Stylesheet,
+    device: &Device,
+    parallel: bool,
+) -> StyledNode<'a> {
+    let rules = RuleMap::new(stylesheet, device);
+    let mut context = StyleContext {
+        rules: &rules,
+        filter: AncestorFilter::new(),
+        ancestors: Vec::new(),
+        parallel,
     };
-    values.extend(specified_values);
+    let cx = ComputeContext {
+        viewport_width: device.width,
+        viewport_height: device.height,
+        ..Default::default()
+    };
+    context.style_node(root, &HashMap::new(), cx, &mut StyleSharingCache::new())
+}
+
+/// State carried down the tree while styling it.
+struct StyleContext<'r, 'a> {
+    rules: &'r RuleMap<'a>,
+    filter: AncestorFilter,
+    ancestors: Vec<&'a ElementData>,
+    #[cfg_attr(not(feature = "parallel"), allow(dead_code))]
+    parallel: bool,
+}
 
-    StyledNode {
-        node: root,
-        children: root
-            .children
+impl<'r, 'a> StyleContext<'r, 'a> {
+    /// `sharing` caches the styles of `node`'s previous siblings.
+    fn style_node(
+        &mut self,
+        node: &'a Node,
+        parent_values: &PropertyMap,
+        mut cx: ComputeContext,
+        sharing: &mut StyleSharingCache<'a>,
+    ) -> StyledNode<'a> {
+        let values = match node.typ {
+            NodeType::Element(ref elem) => match sharing.lookup(elem) {
+                Some(values) => values,
+                None => {
+                    let values = Arc::new(self.cascade(elem, parent_values, cx));
+                    sharing.insert(elem, values.clone());
+                    values
+                }
+            },
+            NodeType::Text(_) => sharing.text_values(|| inherited_values(parent_values)),
+        };
+        if let Some(font_size) = values.get("font-size") {
+            cx.font_size = font_size.to_px();
+        }
+        if self.ancestors.is_empty() {
+            cx.root_font_size = cx.font_size;
+        }
+
+        if let NodeType::Element(ref elem) = node.typ {
+            self.filter.push(elem);
+            self.ancestors.push(elem);
+        }
+        let children = self.style_children(node, &values, cx);
+        if let NodeType::Element(ref elem) = node.typ {
+            self.filter.pop(elem);
+            self.ancestors.pop();
+        }
+
+        StyledNode {
+            node,
+            specified_values: values,
+            children,
+        }
+    }
+
+    fn style_children(
+        &mut self,
+        node: &'a Node,
+        values: &PropertyMap,
+        cx: ComputeContext,
+    ) -> Vec<StyledNode<'a>> {
+        #[cfg(feature = "parallel")]
+        {
+            if self.parallel && node.children.len() > 1 {
+                return self.par_style_children(node, values, cx);
+            }
+        }
+
+        let mut sharing = StyleSharingCache::new();
+        node.children
             .iter()
-            .map(|child| child_style_tree(child, stylesheet, &values))
-            .collect(),
-        specified_values: values,
+            .map(|child| self.style_node(child, values, cx, &mut sharing))
+            .collect()
+    }
+
+    /// Hands the children to rayon, each split getting its own copy of the ancestor state.
+    #[cfg(feature = "parallel")]
+    fn par_style_children(
+        &self,
+        node: &'a Node,
+        values: &PropertyMap,
+        cx: ComputeContext,
+    ) -> Vec<StyledNode<'a>> {
+        use rayon::prelude::*;
+
+        node.children
+            .par_iter()
+            .map_init(
+                || (self.fork(), StyleSharingCache::new()),
+                |(context, sharing), child| context.style_node(child, values, cx, sharing),
+            )
+            .collect()
+    }
+
+    #[cfg(feature = "parallel")]
+    fn fork(&self) -> Self {
+        StyleContext {
+            rules: self.rules,
+            filter: self.filter.clone(),
+            ancestors: self.ancestors.clone(),
+            parallel: self.parallel,
+        }
+    }
+
+    fn cascade(
+        &self,
+        elem: &ElementData,
+        parent_values: &PropertyMap,
+        mut cx: ComputeContext,
+    ) -> PropertyMap {
+        let mut values = inherited_values(parent_values);
+        let rules = self
+            .rules
+            .matching_rules(elem, &self.ancestors, &self.filter);
+        let mut specified = presentational_hints(elem, &self.ancestors);
+        specified.extend(specified_values(rules));
+        compute_values(&mut specified, &mut cx);
+        if let Some(weight) = specified.get_mut("font-weight") {
+            let parent = match parent_values.get("font-weight") {
+                Some(&Value::Number(n)) => n,
+                _ => 400.0,
+            };
+            *weight = Value::Number(font_weight(weight, parent));
+        }
+        values.extend(specified);
+        values
+    }
+}
+
+/// Turns specified values into computed values. `cx` holds the parent's font size on entry
+/// and this element's on return.
+fn compute_values(values: &mut PropertyMap, cx: &mut ComputeContext) {
+    if let Some(font_size) = values.get("font-size") {
+        let parent_font_size = cx.font_size;
+        cx.font_size = match *font_size {
+            Value::Length(..) | Value::Calc(_) => font_size.compute(cx).resolve(parent_font_size),
+            Value::Percentage(_) => font_size.resolve(parent_font_size),
+            _ => parent_font_size,
+        };
+        values.insert("font-size".into(), Value::Length(cx.font_size, Unit::Px));
+    }
+
+    // Percentages of the font size are inherited as lengths, unlike plain numbers.
+    if let Some(line_height) = values.get_mut("line-height") {
+        if let Value::Percentage(_) | Value::Calc(_) = *line_height {
+            *line_height = Value::Length(line_height.compute(cx).resolve(cx.font_size), Unit::Px);
+        }
+    }
+
+    for value in values.values_mut() {
+        *value = value.compute(cx);
     }
 }
 
 fn inherited_values(parent_values: &PropertyMap) -> PropertyMap {
     let mut values = HashMap::new();
 
-    let inherited_decl_names = ["font-size"];
+    let inherited_decl_names = [
+        "color",
+        "font-size",
+        "font-family",
+        "font-weight",
+        "font-style",
+        "font-stretch",
+        "line-height",
+        "text-align",
+        "direction",
+        "border-collapse",
+        "border-spacing",
+        "caption-side",
+        "list-style-type",
+        "list-style-position",
+        "orphans",
+        "widows",
+    ];
 
     for decl_name in inherited_decl_names {
         if let Some(value) = parent_values.get(decl_name) {
@@ -114,68 +838,291 @@ fn inherited_values(parent_values: &PropertyMap) -> PropertyMap {
     values
 }
 
-fn specified_values(elem: &ElementData, stylesheet: &Stylesheet) -> PropertyMap {
+fn specified_values(rules: Vec<MatchedRule>) -> PropertyMap {
+    declared_values(rules.iter().flat_map(|(_, rule)| &rule.declarations))
+}
+
+/// The values that declarations, from lowest to highest precedence, give to the properties
+/// they set, with shorthands expanded.
+pub(crate) fn declared_values<'d>(
+    declarations: impl Iterator<Item = &'d Declaration>,
+) -> PropertyMap {
     let mut values = HashMap::new();
-    let mut rules = matching_rules(elem, stylesheet);
-
-    rules.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
-    for (_, rule) in rules {
-        for decl in &rule.declarations {
-            match decl.name.as_str() {
-                "margin" => match decl.values.len() {
-                    1 => {
-                        values.insert("margin".into(), decl.values[0].clone());
-                    }
-                    2 => {
-                        values.insert("margin-top".into(), decl.values[0].clone());
-                        values.insert("margin-bottom".into(), decl.values[0].clone());
-                        values.insert("margin-left".into(), decl.values[1].clone());
-                        values.insert("margin-right".into(), decl.values[1].clone());
-                    }
-                    3 => {
-                        values.insert("margin-top".into(), decl.values[0].clone());
-                        values.insert("margin-bottom".into(), decl.values[1].clone());
-                        values.insert("margin-left".into(), decl.values[1].clone());
-                        values.insert("margin-right".into(), decl.values[2].clone());
-                    }
-                    4 => {
-                        values.insert("margin-top".into(), decl.values[0].clone());
-                        values.insert("margin-bottom".into(), decl.values[1].clone());
-                        values.insert("margin-left".into(), decl.values[2].clone());
-                        values.insert("margin-right".into(), decl.values[3].clone());
-                    }
-                    _ => {}
-                },
-                _ => {
-                    values.insert(decl.name.clone(), decl.values[0].clone());
+
+    for decl in declarations {
+        match decl.name.as_str() {
+            "margin" => match decl.values.len() {
+                1 => {
+                    values.insert("margin".into(), decl.values[0].clone());
+                }
+                2 => {
+                    values.insert("margin-top".into(), decl.values[0].clone());
+                    values.insert("margin-bottom".into(), decl.values[0].clone());
+                    values.insert("margin-left".into(), decl.values[1].clone());
+                    values.insert("margin-right".into(), decl.values[1].clone());
+                }
+                3 => {
+                    values.insert("margin-top".into(), decl.values[0].clone());
+                    values.insert("margin-bottom".into(), decl.values[1].clone());
+                    values.insert("margin-left".into(), decl.values[1].clone());
+                    values.insert("margin-right".into(), decl.values[2].clone());
+                }
+                4 => {
+                    values.insert("margin-top".into(), decl.values[0].clone());
+                    values.insert("margin-bottom".into(), decl.values[1].clone());
+                    values.insert("margin-left".into(), decl.values[2].clone());
+                    values.insert("margin-right".into(), decl.values[3].clone());
+                }
+                _ => {}
+            },
+            "flex" => {
+                for (name, value) in expand_flex(&decl.values) {
+                    values.insert(name.into(), value);
+                }
+            }
+            "flex-flow" => {
+                for value in &decl.values {
+                    let name = match *value {
+                        Value::Keyword(ref s) if s.contains("wrap") => "flex-wrap",
+                        _ => "flex-direction",
+                    };
+                    values.insert(name.into(), value.clone());
                 }
             }
+            "gap" => {
+                let column_gap = decl.values.get(1).unwrap_or(&decl.values[0]);
+                values.insert("row-gap".into(), decl.values[0].clone());
+                values.insert("column-gap".into(), column_gap.clone());
+            }
+            "grid-row" | "grid-column" | "grid-area" => {
+                for (name, value) in expand_grid_placement(&decl.name, &decl.values) {
+                    values.insert(name, value);
+                }
+            }
+            "list-style" => {
+                for value in &decl.values {
+                    let name = match *value {
+                        Value::Keyword(ref s) if s == "inside" || s == "outside" => {
+                            "list-style-position"
+                        }
+                        Value::Url(_) => "list-style-image",
+                        _ => "list-style-type",
+                    };
+                    values.insert(name.into(), value.clone());
+                }
+            }
+            // Properties taking a list of values keep all of them.
+            "grid-template-rows"
+            | "grid-template-columns"
+            | "grid-template-areas"
+            | "grid-auto-rows"
+            | "grid-auto-columns"
+            | "grid-auto-flow"
+            | "border-spacing"
+            | "counter-reset"
+            | "counter-increment"
+            | "counter-set"
+            | "object-position"
+            | "size"
+            | "content"
+            | "font-family"
+                if decl.values.len() > 1 =>
+            {
+                values.insert(decl.name.clone(), Value::List(decl.values.clone()));
+            }
+            _ => {
+                values.insert(decl.name.clone(), decl.values[0].clone());
+            }
         }
     }
 
     values
 }
 
-type MatchedRule<'a> = (Specificity, &'a Rule);
+/// The styles HTML attributes map to, which any rule overrides: the dimensions of images and
+/// table parts, the spacing and padding of table cells, and the numbering of lists. The
+/// defaults of the spacing, padding and `list-item` counter reset are given here so that
+/// `cellspacing`, `cellpadding`, `start` and `reversed` can replace them.
+fn presentational_hints(elem: &ElementData, ancestors: &[&ElementData]) -> PropertyMap {
+    let mut values = HashMap::new();
+    let attribute = |elem: &ElementData, name: &str| {
+        elem.attrs
+            .get(name)
+            .and_then(|value| dimension_attribute(value))
+    };
+    let tag_name = elem.tag_name.as_str();
 
-fn matching_rules<'a>(elem: &ElementData, stylesheet: &'a Stylesheet) -> Vec<MatchedRule<'a>> {
-    stylesheet
-        .rules
+    if let "table" | "td" | "th" | "col" | "colgroup" | "img" = tag_name {
+        if let Some(width) = attribute(elem, "width") {
+            values.insert("width".to_string(), width);
+        }
+    }
+    if let "table" | "tr" | "td" | "th" | "img" = tag_name {
+        if let Some(height) = attribute(elem, "height") {
+            values.insert("height".to_string(), height);
+        }
+    }
+    match tag_name {
+        "table" => {
+            let spacing = attribute(elem, "cellspacing").unwrap_or(Value::Length(2.0, Unit::Px));
+            values.insert("border-spacing".to_string(), spacing);
+        }
+        "td" | "th" => {
+            let table = ancestors.iter().rev().find(|elem| elem.tag_name == "table");
+            let padding = table
+                .and_then(|table| attribute(table, "cellpadding"))
+                .unwrap_or(Value::Length(1.0, Unit::Px));
+            values.insert("padding".to_string(), padding);
+        }
+        "ol" | "ul" | "menu" => {
+            let list_item = Value::Keyword("list-item".to_string());
+            let start = elem
+                .attrs
+                .get("start")
+                .and_then(|s| s.trim().parse::<i32>().ok());
+            let reset = if tag_name == "ol" && elem.attrs.contains_key("reversed") {
+                // The first item shows the start, or the number of items.
+                let counter = Value::Function("reversed".to_string(), vec![list_item]);
+                match start {
+                    Some(start) => Value::List(vec![counter, Value::Number(start as f32 + 1.0)]),
+                    None => counter,
+                }
+            } else {
+                let start = start.unwrap_or(1);
+                Value::List(vec![list_item, Value::Number(start as f32 - 1.0)])
+            };
+            values.insert("counter-reset".to_string(), reset);
+        }
+        "li" => {
+            if let Some(value) = elem
+                .attrs
+                .get("value")
+                .and_then(|s| s.trim().parse::<i32>().ok())
+            {
+                let set = vec![
+                    Value::Keyword("list-item".to_string()),
+                    Value::Number(value as f32),
+                ];
+                values.insert("counter-set".to_string(), Value::List(set));
+            }
+        }
+        _ => {}
+    }
+    if let "ol" | "ul" | "li" = tag_name {
+        let list_style_type = match elem.attrs.get("type").map(String::as_str) {
+            Some("1") => Some("decimal"),
+            Some("a") => Some("lower-alpha"),
+            Some("A") => Some("upper-alpha"),
+            Some("i") => Some("lower-roman"),
+            Some("I") => Some("upper-roman"),
+            Some(bullet) => match bullet.to_ascii_lowercase().as_str() {
+                "disc" => Some("disc"),
+                "circle" => Some("circle"),
+                "square" => Some("square"),
+                _ => None,
+            },
+            None => None,
+        };
+        if let Some(list_style_type) = list_style_type {
+            let value = Value::Keyword(list_style_type.to_string());
+            values.insert("list-style-type".to_string(), value);
+        }
+    }
+    values
+}
+
+/// Parses an HTML dimension attribute: a number of pixels, or a percentage.
+fn dimension_attribute(value: &str) -> Option<Value> {
+    let value = value.trim();
+    match value.strip_suffix('%') {
+        Some(percentage) => percentage.trim().parse().ok().map(Value::Percentage),
+        None => value
+            .strip_suffix("px")
+            .unwrap_or(value)
+            .parse()
+            .ok()
+            .map(|px| Value::Length(px, Unit::Px)),
+    }
+}
+
+/// Expands `grid-row`, `grid-column` or `grid-area` into the grid lines they set, in the
+/// order `row-start / column-start / row-end / column-end` for `grid-area`. A line made of
+/// several values, like `span 2`, is a `Value::List`. Omitted lines copy a named line
+/// before them, and are `auto` otherwise.
+fn expand_grid_placement(name: &str, values: &[Value]) -> Vec<(String, Value)> {
+    let mut lines: Vec<Value> = values
+        .split(|value| *value == Value::Keyword("/".to_string()))
+        .map(|line| match line {
+            [value] => value.clone(),
+            _ => Value::List(line.to_vec()),
+        })
+        .collect();
+    let names: &[&str] = match name {
+        "grid-area" => &[
+            "grid-row-start",
+            "grid-column-start",
+            "grid-row-end",
+            "grid-column-end",
+        ],
+        "grid-row" => &["grid-row-start", "grid-row-end"],
+        _ => &["grid-column-start", "grid-column-end"],
+    };
+    for i in lines.len()..names.len() {
+        // The end line of an axis copies its start line, the column start the row start.
+        let copied = if names.len() == 4 && i == 3 { 1 } else { 0 };
+        let line = match lines.get(copied) {
+            Some(Value::Keyword(ref s)) if s != "auto" && s != "span" => lines[copied].clone(),
+            _ => Value::Keyword("auto".to_string()),
+        };
+        lines.push(line);
+    }
+    names
         .iter()
-        .filter_map(|rule| match_rule(elem, rule))
+        .map(|name| name.to_string())
+        .zip(lines)
         .collect()
 }
 
-fn match_rule<'a>(elem: &ElementData, rule: &'a Rule) -> Option<MatchedRule<'a>> {
-    rule.selectors
-        .iter()
-        .find(|selector| matches(elem, *selector))
-        .map(|selector| (selector.specificity(), rule))
+/// Expands the `flex` shorthand into `flex-grow`, `flex-shrink` and `flex-basis`. A lone
+/// number sets the grow factor with a zero basis, and a lone basis a grow factor of 1.
+fn expand_flex(values: &[Value]) -> Vec<(&'static str, Value)> {
+    let keyword = |s: &str| Value::Keyword(s.to_string());
+    let (grow, shrink, basis) = match values {
+        [Value::Keyword(ref s)] if s == "none" => (0.0, 0.0, keyword("auto")),
+        [Value::Keyword(ref s)] if s == "auto" => (1.0, 1.0, keyword("auto")),
+        [Value::Number(grow)] => (*grow, 1.0, Value::Length(0.0, Unit::Px)),
+        [Value::Number(grow), Value::Number(shrink)] => {
+            (*grow, *shrink, Value::Length(0.0, Unit::Px))
+        }
+        [Value::Number(grow), basis] => (*grow, 1.0, basis.clone()),
+        [Value::Number(grow), Value::Number(shrink), basis] => (*grow, *shrink, basis.clone()),
+        [basis] => (1.0, 1.0, basis.clone()),
+        _ => return Vec::new(),
+    };
+    vec![
+        ("flex-grow", Value::Number(grow)),
+        ("flex-shrink", Value::Number(shrink)),
+        ("flex-basis", basis),
+    ]
 }
 
-fn matches(elem: &ElementData, selector: &Selector) -> bool {
+/// Tests a selector against an element whose ancestors, from the root down, are `ancestors`.
+pub fn matches(elem: &ElementData, ancestors: &[&ElementData], selector: &Selector) -> bool {
     match *selector {
         Selector::Simple(ref simple_selector) => matches_simple_selector(elem, simple_selector),
+        Selector::Descendant(ref parent, ref simple_selector) => {
+            matches_simple_selector(elem, simple_selector)
+                && (0..ancestors.len())
+                    .rev()
+                    .any(|i| matches(ancestors[i], &ancestors[..i], parent))
+        }
+        Selector::Child(ref parent, ref simple_selector) => {
+            matches_simple_selector(elem, simple_selector)
+                && match ancestors.split_last() {
+                    Some((elem, ancestors)) => matches(elem, ancestors, parent),
+                    None => false,
+                }
+        }
     }
 }
 
@@ -197,5 +1144,142 @@ fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector) -> boo
         return false;
     }
 
-    return true;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{style_tree, FontStyle};
+    use crate::css::media::{Device, MediaType};
+    use crate::css::{self, Color, Value};
+    use crate::html;
+    use crate::layout::Rect;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_style_tree_combinators() {
+        let document = html::parse(
+            r#"<div class="a"><p id="x"><span>1</span></p><span>2</span></div>"#.to_string(),
+        );
+        let stylesheet = css::parse(
+            r#"
+            span { color: #000000; }
+            div span { color: #ff0000; }
+            div > span { color: #00ff00; }
+            .a p span, #x > span { color: #0000ff; }
+            p > span { color: #ffffff; }"#
+                .to_string(),
+        );
+        let device = Device::new(MediaType::Print, Rect::new(0.0, 0.0, 800.0, 600.0));
+
+        let root = style_tree(&document.root_node, &stylesheet, &device);
+        let color = |path: &[usize]| {
+            let mut node = &root;
+            for &i in path {
+                node = &node.children[i];
+            }
+            node.value("color")
+        };
+
+        // `#x > span` outranks the later `p > span`, and `div > span` the earlier `div span`.
+        assert_eq!(
+            color(&[0, 0]),
+            Some(Value::ColorValue(Color::new(0, 0, 255, 255)))
+        );
+        assert_eq!(
+            color(&[1]),
+            Some(Value::ColorValue(Color::new(0, 255, 0, 255)))
+        );
+    }
+
+    #[test]
+    fn test_style_sharing() {
+        let document = html::parse(
+            r#"<ul><li class="a">1</li><li class="a">2</li><li class="b">3</li><li class="a" id="x">4</li></ul>"#
+                .to_string(),
+        );
+        let stylesheet = css::parse("li.a { color: #ff0000; } #x { color: #00ff00; }".to_string());
+        let device = Device::new(MediaType::Print, Rect::new(0.0, 0.0, 800.0, 600.0));
+
+        let root = style_tree(&document.root_node, &stylesheet, &device);
+        let items = &root.children;
+
+        assert!(Arc::ptr_eq(
+            &items[0].specified_values,
+            &items[1].specified_values
+        ));
+        assert!(!Arc::ptr_eq(
+            &items[0].specified_values,
+            &items[2].specified_values
+        ));
+        assert_eq!(
+            items[3].value("color"),
+            Some(Value::ColorValue(Color::new(0, 255, 0, 255)))
+        );
+    }
+
+    #[test]
+    fn test_font() {
+        let document = html::parse(r#"<div><p><span>1</span></p><em>2</em></div>"#.to_string());
+        let stylesheet = css::parse(
+            r#"
+            div { color: #ff0000; font-family: "Open Sans", DejaVu Serif, serif; font-weight: 300; }
+            p { font-weight: bolder; font-stretch: condensed; }
+            span { font-weight: bolder; }
+            em { font-style: italic; }"#
+                .to_string(),
+        );
+        let device = Device::new(MediaType::Print, Rect::new(0.0, 0.0, 800.0, 600.0));
+
+        let root = style_tree(&document.root_node, &stylesheet, &device);
+        let span = &root.children[0].children[0];
+        let em = &root.children[1];
+
+        let font = span.font();
+        assert_eq!(font.families, ["Open Sans", "DejaVu Serif", "serif"]);
+        assert_eq!((font.weight, font.stretch), (700.0, 75.0));
+        assert_eq!(span.color(), Color::new(255, 0, 0, 255));
+        assert_eq!(em.font().weight, 300.0);
+        assert_eq!(em.font().style, FontStyle::Italic);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_par_style_tree() {
+        use super::{par_style_tree, StyledNode};
+
+        fn assert_same(a: &StyledNode, b: &StyledNode) {
+            assert!(std::ptr::eq(a.node, b.node));
+            assert_eq!(*a.specified_values, *b.specified_values);
+            assert_eq!(a.children.len(), b.children.len());
+            for (a, b) in a.children.iter().zip(&b.children) {
+                assert_same(a, b);
+            }
+        }
+
+        let mut html_source = String::from("<body>");
+        for i in 0..200 {
+            html_source.push_str(&format!(
+                r#"<div class="c{}"><p>text <span class="s">{}</span></p></div>"#,
+                i % 7,
+                i
+            ));
+        }
+        html_source.push_str("</body>");
+        let document = html::parse(html_source);
+        let stylesheet = css::parse(
+            r#"
+            div { font-size: 2em; }
+            .c3 p { margin: 1em; }
+            .c5 > p span.s { padding: 1rem; }
+            p { font-size: 75%; }"#
+                .to_string(),
+        );
+        let device = Device::new(MediaType::Print, Rect::new(0.0, 0.0, 800.0, 600.0));
+
+        assert_same(
+            &style_tree(&document.root_node, &stylesheet, &device),
+            &par_style_tree(&document.root_node, &stylesheet, &device),
+        );
+    }
 }
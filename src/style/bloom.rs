@@ -0,0 +1,119 @@
+use crate::css::{Selector, SimpleSelector};
+use crate::html::ElementData;
+
+const KEY_BITS: u32 = 12;
+const KEY_MASK: u32 = (1 << KEY_BITS) - 1;
+
+/// How many ancestor hashes a selector keeps for fast rejection.
+pub const MAX_ANCESTOR_HASHES: usize = 4;
+
+/// A counting Bloom filter over the tag names, ids and classes of the current element's
+/// ancestors. A miss proves that a descendant selector can't match.
+#[derive(Clone)]
+pub struct AncestorFilter {
+    counters: Box<[u8; 1 << KEY_BITS]>,
+}
+
+impl AncestorFilter {
+    pub fn new() -> Self {
+        Self {
+            counters: Box::new([0; 1 << KEY_BITS]),
+        }
+    }
+
+    pub fn push(&mut self, elem: &ElementData) {
+        for_each_hash(elem, |hash| self.insert_hash(hash));
+    }
+
+    pub fn pop(&mut self, elem: &ElementData) {
+        for_each_hash(elem, |hash| self.remove_hash(hash));
+    }
+
+    pub fn might_contain(&self, hash: u32) -> bool {
+        self.counters[first_slot(hash)] != 0 && self.counters[second_slot(hash)] != 0
+    }
+
+    fn insert_hash(&mut self, hash: u32) {
+        for slot in [first_slot(hash), second_slot(hash)] {
+            let counter = &mut self.counters[slot];
+            *counter = counter.saturating_add(1);
+        }
+    }
+
+    fn remove_hash(&mut self, hash: u32) {
+        for slot in [first_slot(hash), second_slot(hash)] {
+            let counter = &mut self.counters[slot];
+            // A saturated counter has lost count and has to stay set.
+            if *counter != u8::MAX {
+                *counter -= 1;
+            }
+        }
+    }
+}
+
+impl Default for AncestorFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn first_slot(hash: u32) -> usize {
+    (hash & KEY_MASK) as usize
+}
+
+fn second_slot(hash: u32) -> usize {
+    ((hash >> KEY_BITS) & KEY_MASK) as usize
+}
+
+/// Collects hashes of the compound selectors left of the rightmost one, which every
+/// matching element must have among its ancestors.
+pub fn ancestor_hashes(selector: &Selector) -> Vec<u32> {
+    let mut hashes = Vec::new();
+    let mut parent = match *selector {
+        Selector::Simple(_) => return hashes,
+        Selector::Descendant(ref parent, _) | Selector::Child(ref parent, _) => parent,
+    };
+    loop {
+        simple_selector_hashes(parent.rightmost(), &mut hashes);
+        if hashes.len() >= MAX_ANCESTOR_HASHES {
+            hashes.truncate(MAX_ANCESTOR_HASHES);
+            return hashes;
+        }
+        parent = match **parent {
+            Selector::Simple(_) => return hashes,
+            Selector::Descendant(ref parent, _) | Selector::Child(ref parent, _) => parent,
+        };
+    }
+}
+
+fn simple_selector_hashes(selector: &SimpleSelector, hashes: &mut Vec<u32>) {
+    if let Some(ref id) = selector.id {
+        hashes.push(hash('#', id));
+    }
+    for class in &selector.class {
+        hashes.push(hash('.', class));
+    }
+    if let Some(ref tag_name) = selector.tag_name {
+        hashes.push(hash('<', tag_name));
+    }
+}
+
+fn for_each_hash<F: FnMut(u32)>(elem: &ElementData, mut f: F) {
+    if let Some(id) = elem.id() {
+        f(hash('#', id));
+    }
+    for class in elem.classes() {
+        f(hash('.', class));
+    }
+    f(hash('<', &elem.tag_name));
+}
+
+/// 32-bit FNV-1a, salted with the kind of name so that `div` and `.div` differ.
+fn hash(kind: char, name: &str) -> u32 {
+    let mut hash = 0x811c_9dc5u32 ^ kind as u32;
+    for byte in name.bytes() {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
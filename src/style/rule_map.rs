@@ -0,0 +1,107 @@
+use super::bloom::{ancestor_hashes, AncestorFilter};
+use crate::css::media::Device;
+use crate::css::{Rule, Selector, Specificity, Stylesheet};
+use crate::html::ElementData;
+use std::collections::HashMap;
+
+/// The rules that apply to a device, bucketed by the rightmost id, class or tag name of
+/// each selector so that an element only has to be tested against plausible rules.
+#[derive(Default)]
+pub struct RuleMap<'a> {
+    by_id: HashMap<&'a str, Vec<RuleEntry<'a>>>,
+    by_class: HashMap<&'a str, Vec<RuleEntry<'a>>>,
+    by_tag: HashMap<&'a str, Vec<RuleEntry<'a>>>,
+    universal: Vec<RuleEntry<'a>>,
+}
+
+struct RuleEntry<'a> {
+    rule: &'a Rule,
+    selector: &'a Selector,
+    /// Position of the rule in the stylesheet, to break specificity ties.
+    order: usize,
+    ancestor_hashes: Vec<u32>,
+}
+
+pub type MatchedRule<'a> = (Specificity, &'a Rule);
+
+impl<'a> RuleMap<'a> {
+    pub fn new(stylesheet: &'a Stylesheet, device: &Device) -> Self {
+        let mut map = RuleMap::default();
+
+        for (order, rule) in stylesheet.rules.iter().enumerate() {
+            if !rule.applies_to(device) {
+                continue;
+            }
+            for selector in &rule.selectors {
+                map.insert(RuleEntry {
+                    rule,
+                    selector,
+                    order,
+                    ancestor_hashes: ancestor_hashes(selector),
+                });
+            }
+        }
+
+        map
+    }
+
+    fn insert(&mut self, entry: RuleEntry<'a>) {
+        let simple = entry.selector.rightmost();
+        let bucket = if let Some(ref id) = simple.id {
+            self.by_id.entry(id).or_default()
+        } else if let Some(class) = simple.class.first() {
+            self.by_class.entry(class).or_default()
+        } else if let Some(ref tag_name) = simple.tag_name {
+            self.by_tag.entry(tag_name).or_default()
+        } else {
+            &mut self.universal
+        };
+        bucket.push(entry);
+    }
+
+    /// Returns the matching rules, sorted from lowest to highest precedence.
+    /// `ancestors` lists the element's ancestors from the root down, and `filter` holds
+    /// their hashes.
+    pub fn matching_rules(
+        &self,
+        elem: &ElementData,
+        ancestors: &[&ElementData],
+        filter: &AncestorFilter,
+    ) -> Vec<MatchedRule<'a>> {
+        let mut matched: Vec<(Specificity, usize, &'a Rule)> = Vec::new();
+        let mut collect = |entries: Option<&Vec<RuleEntry<'a>>>| {
+            for entry in entries.into_iter().flatten() {
+                if !entry
+                    .ancestor_hashes
+                    .iter()
+                    .all(|hash| filter.might_contain(*hash))
+                {
+                    continue;
+                }
+                if super::matches(elem, ancestors, entry.selector) {
+                    matched.push((entry.selector.specificity(), entry.order, entry.rule));
+                }
+            }
+        };
+
+        if let Some(id) = elem.id() {
+            collect(self.by_id.get(&**id));
+        }
+        for class in elem.classes() {
+            collect(self.by_class.get(class));
+        }
+        collect(self.by_tag.get(&*elem.tag_name));
+        collect(Some(&self.universal));
+
+        // A rule matched through several of its selectors counts once, with the most
+        // specific of them.
+        matched.sort_by(|a, b| (a.1, b.0).cmp(&(b.1, a.0)));
+        matched.dedup_by_key(|m| m.1);
+        matched.sort_by_key(|m| (m.0, m.1));
+
+        matched
+            .into_iter()
+            .map(|(specificity, _, rule)| (specificity, rule))
+            .collect()
+    }
+}
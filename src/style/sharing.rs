@@ -0,0 +1,63 @@
+use super::PropertyMap;
+use crate::html::ElementData;
+use std::sync::Arc;
+
+const CACHE_SIZE: usize = 8;
+
+/// Computed values of recently styled siblings. Siblings have the same ancestors and
+/// inherit the same values, so two elements with the same tag name and attributes match
+/// exactly the same rules and can share one `PropertyMap`.
+pub struct StyleSharingCache<'a> {
+    /// Most recently used first.
+    entries: Vec<(&'a ElementData, Arc<PropertyMap>)>,
+    text_values: Option<Arc<PropertyMap>>,
+}
+
+impl<'a> StyleSharingCache<'a> {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            text_values: None,
+        }
+    }
+
+    pub fn lookup(&mut self, elem: &ElementData) -> Option<Arc<PropertyMap>> {
+        // Ids are unique, and id selectors don't need to be ruled out then.
+        if elem.id().is_some() {
+            return None;
+        }
+
+        let index = self.entries.iter().position(|(candidate, _)| {
+            candidate.tag_name == elem.tag_name && candidate.attrs == elem.attrs
+        })?;
+        let entry = self.entries.remove(index);
+        let values = entry.1.clone();
+        self.entries.insert(0, entry);
+        Some(values)
+    }
+
+    pub fn insert(&mut self, elem: &'a ElementData, values: Arc<PropertyMap>) {
+        if elem.id().is_some() {
+            return;
+        }
+
+        self.entries.insert(0, (elem, values));
+        self.entries.truncate(CACHE_SIZE);
+    }
+
+    /// Text nodes only carry inherited values, which are the same for all siblings.
+    pub fn text_values<F>(&mut self, inherited_values: F) -> Arc<PropertyMap>
+    where
+        F: FnOnce() -> PropertyMap,
+    {
+        self.text_values
+            .get_or_insert_with(|| Arc::new(inherited_values()))
+            .clone()
+    }
+}
+
+impl<'a> Default for StyleSharingCache<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
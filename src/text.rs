@@ -1,10 +1,127 @@
+mod face;
+pub mod font_face;
+mod fontconfig;
+mod freetype;
+mod harfbuzz;
+mod shaping;
+
 use crate::layout::Region;
+use crate::style::FontStyle;
+use cairo::{Context, Format, ImageSurface};
+use font_face::FontFace;
+use std::sync::{Arc, RwLock};
+
+pub(crate) use face::handles;
+pub use face::FaceId;
+pub(crate) use fontconfig::local_font;
+pub(crate) use freetype::is_font;
+pub use shaping::{shape, Glyph, GlyphRun};
+
+/// The faces of `@font-face` rules, shared by every thread.
+static FONT_FACES: RwLock<Vec<Arc<FontFace>>> = RwLock::new(Vec::new());
+
+thread_local! {
+    /// A scratch context for measuring fonts, created once per thread.
+    static CONTEXT: Context = {
+        let surface = ImageSurface::create(Format::A1, 256, 256).unwrap();
+        Context::new(&surface)
+    };
+}
+
+/// A font as CSS describes it: the families to pick from in order, and the face and size
+/// wanted of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Font {
+    pub families: Vec<String>,
+    pub size: f32,
+    /// The weight from 1 to 1000, 400 being normal and 700 bold.
+    pub weight: f32,
+    pub style: FontStyle,
+    /// The width as a percentage of the normal one.
+    pub stretch: f32,
+}
+
+/// Makes `faces` available to text measurement and rendering, in addition to the installed
+/// fonts. Faces that are already available, as when the same stylesheet is loaded again,
+/// are skipped.
+pub fn add_font_faces(faces: Vec<FontFace>) {
+    let mut font_faces = FONT_FACES.write().unwrap();
+    for face in faces {
+        if !font_faces.iter().any(|added| **added == face) {
+            font_faces.push(Arc::new(face));
+        }
+    }
+}
+
+pub fn calc_text_region(text: &str, font: &Font) -> Region {
+    let width = shape(text, font).iter().map(GlyphRun::width).sum();
+    let metrics = font_metrics(font);
+    Region::new(width, metrics.ascent + metrics.descent)
+}
+
+/// Vertical metrics of the font used at a given size.
+#[derive(Debug, Clone, Copy)]
+pub struct FontMetrics {
+    pub ascent: f32,
+    pub descent: f32,
+    /// The font's recommended distance between baselines, used for `line-height: normal`.
+    pub line_spacing: f32,
+    pub x_height: f32,
+}
+
+pub fn font_metrics(font: &Font) -> FontMetrics {
+    metrics(shaping::primary_face(font), font.size)
+}
+
+/// The metrics of `face` at `size`, which a glyph run is drawn in.
+pub fn face_metrics(face: FaceId, size: f32) -> FontMetrics {
+    metrics(Some(face), size)
+}
+
+fn metrics(face: Option<FaceId>, size: f32) -> FontMetrics {
+    CONTEXT.with(|ctx| {
+        if let Some(cairo_face) = face.and_then(|face| handles(face).cairo_face.clone()) {
+            ctx.set_font_face(&cairo_face);
+        }
+        ctx.set_font_size(size as f64);
+        let extents = ctx.font_extents();
+        FontMetrics {
+            ascent: extents.ascent as f32,
+            descent: extents.descent as f32,
+            line_spacing: extents.height as f32,
+            x_height: ctx.text_extents("x").height as f32,
+        }
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::add_font_faces;
+    use super::font_face::FontFace;
+    use crate::css;
+    use std::sync::Once;
+
+    /// A font with a glyph for each printable ASCII character, half an em wide but for the
+    /// space, a quarter of an em, and for U+0301, U+2026 "…", one em, and U+2200. "AV" kerns
+    /// by a tenth of an em. Its ascent is 0.8 em and its descent 0.2 em.
+    pub(crate) const TEST_FONT: &[u8] = include_bytes!("text/testdata/shaping-test.ttf");
 
-pub fn calc_text_region(text: String, font_size: f32) -> Region {
-    let surface = cairo::ImageSurface::create(cairo::Format::A1, 256, 256).unwrap();
-    let ctx = cairo::Context::new(&surface);
+    /// The family `TEST_FONT` is available as once `add_test_font` is called.
+    pub(crate) const TEST_FAMILY: &str = "Shigi Test";
 
-    ctx.set_font_size(font_size as f64);
-    let extents = ctx.text_extents(&text);
-    Region::new(extents.x_advance as f32, extents.height as f32)
+    /// Makes `TEST_FONT` available as `TEST_FAMILY`, through an `@font-face` rule.
+    pub(crate) fn add_test_font() {
+        static ADDED: Once = Once::new();
+        ADDED.call_once(|| {
+            let rule = format!("@font-face {{ font-family: {}; }}", TEST_FAMILY);
+            let stylesheet = css::parse(rule);
+            add_font_faces(
+                stylesheet
+                    .font_faces
+                    .iter()
+                    .filter_map(|rule| FontFace::from_rule(rule, TEST_FONT.to_vec(), 0))
+                    .collect(),
+            );
+        });
+    }
 }
@@ -0,0 +1,95 @@
+use super::{freetype, harfbuzz};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, RwLock};
+
+/// A face text is shaped and drawn in, installed or loaded by `@font-face`, by the order it
+/// was loaded in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FaceId(usize);
+
+struct FaceData {
+    data: Arc<Vec<u8>>,
+    index: isize,
+    /// The file of an installed face.
+    path: Option<PathBuf>,
+}
+
+/// What a thread shapes and draws a face with.
+pub(crate) struct Handles {
+    pub font: harfbuzz::Font,
+    pub cairo_face: Option<cairo::FontFace>,
+    /// Keeps the data the fonts read alive.
+    _data: Arc<FaceData>,
+}
+
+/// The faces loaded so far, shared by every thread. Faces are never unloaded, but one is
+/// only loaded once, however many times it is added.
+static FACES: RwLock<Vec<Arc<FaceData>>> = RwLock::new(Vec::new());
+
+thread_local! {
+    static HANDLES: RefCell<HashMap<FaceId, Rc<Handles>>> = RefCell::new(HashMap::new());
+}
+
+/// Loads face `index` of the font file `data`, unless the same face is already loaded.
+pub(crate) fn add_face(data: Vec<u8>, index: isize) -> FaceId {
+    let mut faces = FACES.write().unwrap();
+    let loaded = faces
+        .iter()
+        .position(|face| face.index == index && *face.data == data);
+    if let Some(id) = loaded {
+        return FaceId(id);
+    }
+    faces.push(Arc::new(FaceData {
+        data: Arc::new(data),
+        index,
+        path: None,
+    }));
+    FaceId(faces.len() - 1)
+}
+
+/// Loads face `index` of an installed font file, once.
+pub(crate) fn installed_face(path: &Path, index: isize) -> Option<FaceId> {
+    let find = |faces: &[Arc<FaceData>]| {
+        faces
+            .iter()
+            .position(|face| face.index == index && face.path.as_deref() == Some(path))
+            .map(FaceId)
+    };
+    if let Some(id) = find(&FACES.read().unwrap()) {
+        return Some(id);
+    }
+
+    let data = fs::read(path).ok()?;
+    let mut faces = FACES.write().unwrap();
+    if let Some(id) = find(&faces) {
+        return Some(id);
+    }
+    faces.push(Arc::new(FaceData {
+        data: Arc::new(data),
+        index,
+        path: Some(path.to_path_buf()),
+    }));
+    Some(FaceId(faces.len() - 1))
+}
+
+/// The fonts of face `id` for this thread, created on first use.
+pub(crate) fn handles(id: FaceId) -> Rc<Handles> {
+    HANDLES.with(|handles| {
+        handles
+            .borrow_mut()
+            .entry(id)
+            .or_insert_with(|| {
+                let face = FACES.read().unwrap()[id.0].clone();
+                Rc::new(Handles {
+                    font: harfbuzz::Font::new(&face.data, face.index),
+                    cairo_face: freetype::cairo_font_face(face.data.clone(), face.index),
+                    _data: face,
+                })
+            })
+            .clone()
+    })
+}
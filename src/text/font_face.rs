@@ -0,0 +1,325 @@
+use super::face::{add_face, FaceId};
+use super::Font;
+use crate::css::{FontFaceRule, Value};
+use crate::style::{font_stretch, font_weight, FontStyle};
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// The formats of `src: url() format()` that FreeType reads.
+const SUPPORTED_FORMATS: [&str; 5] = ["truetype", "opentype", "woff", "woff2", "collection"];
+
+/// A font loaded by an `@font-face` rule, with the descriptors it is matched by. Weights and
+/// widths are ranges, as a variable font covers.
+#[derive(Debug, PartialEq)]
+pub struct FontFace {
+    pub family: String,
+    pub weight: (f32, f32),
+    pub style: FontStyle,
+    /// The range of widths, as percentages of the normal one.
+    pub stretch: (f32, f32),
+    /// The ranges of code points the face is used for, inclusive.
+    pub unicode_range: Vec<(u32, u32)>,
+    pub face: FaceId,
+}
+
+/// Where the `src` of an `@font-face` rule loads a font from.
+#[derive(Debug, PartialEq)]
+pub enum FontSource {
+    Url(String),
+    /// An installed face by its full or PostScript name.
+    Local(String),
+}
+
+impl FontFace {
+    /// The face `rule` describes, face `index` of the font file `data`, or `None` if it names
+    /// no family.
+    pub fn from_rule(rule: &FontFaceRule, data: Vec<u8>, index: isize) -> Option<FontFace> {
+        let mut family = String::new();
+        let mut weight = (400.0, 400.0);
+        let mut style = FontStyle::Normal;
+        let mut stretch = (100.0, 100.0);
+        let mut unicode_ranges = vec![(0, 0x10ffff)];
+        for decl in &rule.declarations {
+            let values = &decl.values;
+            match decl.name.as_str() {
+                "font-family" => family = name(values),
+                "font-weight" => weight = range(values, |value| font_weight(value, 400.0)),
+                "font-stretch" => stretch = range(values, font_stretch),
+                "font-style" => {
+                    style = match values.first() {
+                        Some(Value::Keyword(ref s)) if s.starts_with("italic") => FontStyle::Italic,
+                        Some(Value::Keyword(ref s)) if s.starts_with("oblique") => {
+                            FontStyle::Oblique
+                        }
+                        _ => FontStyle::Normal,
+                    }
+                }
+                "unicode-range" => {
+                    unicode_ranges = values.iter().filter_map(unicode_range).collect();
+                }
+                _ => {}
+            }
+        }
+        if family.is_empty() {
+            return None;
+        }
+        Some(FontFace {
+            family,
+            weight,
+            style,
+            stretch,
+            unicode_range: unicode_ranges,
+            face: add_face(data, index),
+        })
+    }
+
+    pub(crate) fn covers(&self, c: char) -> bool {
+        let c = c as u32;
+        self.unicode_range
+            .iter()
+            .any(|&(first, last)| first <= c && c <= last)
+    }
+}
+
+/// The sources `src` lists in `rule`, leaving out URLs only given in formats FreeType can't
+/// read.
+pub fn sources(rule: &FontFaceRule) -> Vec<FontSource> {
+    let src = match rule
+        .declarations
+        .iter()
+        .rev()
+        .find(|decl| decl.name == "src")
+    {
+        Some(decl) => &decl.values,
+        None => return Vec::new(),
+    };
+    let mut sources = Vec::new();
+    for source in src.split(|value| *value == Value::Keyword(",".to_string())) {
+        match source {
+            [Value::Url(url), hints @ ..] => {
+                let formats: Vec<String> = hints
+                    .iter()
+                    .filter_map(|hint| match *hint {
+                        Value::Function(ref function, ref args) if function == "format" => {
+                            Some(name(args).to_ascii_lowercase())
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                let supported = formats
+                    .iter()
+                    .any(|format| SUPPORTED_FORMATS.contains(&format.as_str()));
+                if formats.is_empty() || supported {
+                    sources.push(FontSource::Url(url.clone()));
+                }
+            }
+            [Value::Function(function, args)] if function == "local" => {
+                sources.push(FontSource::Local(name(args)));
+            }
+            _ => {}
+        }
+    }
+    sources
+}
+
+/// The faces of `faces` that `family` is drawn in, by the CSS Fonts font matching algorithm:
+/// those of the family narrowed down to the ones nearest the width of `font`, then its
+/// style, then its weight. They differ at most in their `unicode-range`, and the last
+/// defined come first. Empty if no `@font-face` rule defines the family.
+pub(crate) fn family_faces(
+    faces: &[Arc<FontFace>],
+    family: &str,
+    font: &Font,
+) -> Vec<Arc<FontFace>> {
+    let candidates: Vec<&Arc<FontFace>> = faces
+        .iter()
+        .filter(|face| face.family.eq_ignore_ascii_case(family))
+        .collect();
+    if candidates.is_empty() {
+        return Vec::new();
+    }
+    let candidates = narrow(candidates, |face| stretch_key(face.stretch, font.stretch));
+    let candidates = narrow(candidates, |face| style_key(face.style, font.style));
+    let candidates = narrow(candidates, |face| weight_key(face.weight, font.weight));
+    candidates.into_iter().rev().cloned().collect()
+}
+
+/// The faces with the lowest `key`.
+fn narrow(faces: Vec<&Arc<FontFace>>, key: impl Fn(&FontFace) -> (u8, f32)) -> Vec<&Arc<FontFace>> {
+    let best = faces
+        .iter()
+        .map(|face| key(face))
+        .min_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    faces
+        .into_iter()
+        .filter(|face| Some(key(face)) == best)
+        .collect()
+}
+
+/// Narrower widths are tried first for a condensed font, and wider ones for an expanded one.
+fn stretch_key((min, max): (f32, f32), desired: f32) -> (u8, f32) {
+    let nearest = desired.max(min).min(max);
+    let preferred = if desired <= 100.0 {
+        nearest <= desired
+    } else {
+        nearest >= desired
+    };
+    (if preferred { 0 } else { 1 }, (desired - nearest).abs())
+}
+
+/// Italic and oblique stand in for each other before normal does.
+fn style_key(style: FontStyle, desired: FontStyle) -> (u8, f32) {
+    let order = match desired {
+        FontStyle::Italic => [FontStyle::Italic, FontStyle::Oblique, FontStyle::Normal],
+        FontStyle::Oblique => [FontStyle::Oblique, FontStyle::Italic, FontStyle::Normal],
+        FontStyle::Normal => [FontStyle::Normal, FontStyle::Oblique, FontStyle::Italic],
+    };
+    let rank = order
+        .iter()
+        .position(|&s| s == style)
+        .unwrap_or(order.len());
+    (rank as u8, 0.0)
+}
+
+/// From 400 to 500, heavier weights up to 500 are tried first, then lighter ones, then those
+/// above 500. Below 400 lighter weights come first, and above 500 heavier ones.
+fn weight_key((min, max): (f32, f32), desired: f32) -> (u8, f32) {
+    let nearest = desired.max(min).min(max);
+    let distance = (desired - nearest).abs();
+    let tier = if (400.0..=500.0).contains(&desired) {
+        if nearest < desired {
+            1
+        } else if nearest <= 500.0 {
+            0
+        } else {
+            2
+        }
+    } else if desired < 400.0 {
+        if nearest <= desired {
+            0
+        } else {
+            1
+        }
+    } else if nearest >= desired {
+        0
+    } else {
+        1
+    };
+    (tier, distance)
+}
+
+/// The range a descriptor of one or two values gives, `value` mapping each to a number.
+fn range(values: &[Value], value: impl Fn(&Value) -> f32) -> (f32, f32) {
+    let first = values.first().map_or(0.0, &value);
+    let last = values.get(1).map_or(first, &value);
+    (first.min(last), first.max(last))
+}
+
+/// A family or face name, quoted or given as identifiers.
+fn name(values: &[Value]) -> String {
+    let mut words = Vec::new();
+    for value in values {
+        match *value {
+            Value::Keyword(ref s) | Value::Str(ref s) => words.push(s.as_str()),
+            Value::List(ref values) => return name(values),
+            _ => {}
+        }
+    }
+    words.join(" ")
+}
+
+/// A range of `unicode-range`, like `U+0-7F`, `U+4??` or `U+20AC`.
+fn unicode_range(value: &Value) -> Option<(u32, u32)> {
+    let range = match *value {
+        Value::Keyword(ref s) if s.len() > 2 && s[..2].eq_ignore_ascii_case("u+") => &s[2..],
+        _ => return None,
+    };
+    let hex = |s: &str| u32::from_str_radix(s, 16).ok();
+    match range.find('-') {
+        Some(dash) => Some((hex(&range[..dash])?, hex(&range[dash + 1..])?)),
+        None => Some((
+            hex(&range.replace('?', "0"))?,
+            hex(&range.replace('?', "f"))?,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{family_faces, sources, FontFace, FontSource};
+    use crate::css;
+    use crate::style::FontStyle;
+    use crate::text::testing::TEST_FONT;
+    use crate::text::{add_font_faces, Font, FONT_FACES};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_match_face() {
+        let stylesheet = css::parse(
+            r#"
+            @font-face { font-family: Brand; src: local(Brand Regular), url(regular.woff2) format("woff2"); }
+            @font-face { font-family: Brand; src: url(bold.eot) format("embedded-opentype"), url(bold.ttf); font-weight: 600 800; }
+            @font-face { font-family: "Brand"; src: url(italic.otf); font-style: oblique 10deg; }
+            @font-face { font-family: Brand; src: url(greek.otf); unicode-range: U+37?, U+0380-03FF; }"#
+                .to_string(),
+        );
+        assert_eq!(
+            sources(&stylesheet.font_faces[0]),
+            vec![
+                FontSource::Local("Brand Regular".to_string()),
+                FontSource::Url("regular.woff2".to_string())
+            ]
+        );
+        assert_eq!(
+            sources(&stylesheet.font_faces[1]),
+            vec![FontSource::Url("bold.ttf".to_string())]
+        );
+        let faces: Vec<Arc<FontFace>> = stylesheet
+            .font_faces
+            .iter()
+            .map(|rule| Arc::new(FontFace::from_rule(rule, Vec::new(), 0).unwrap()))
+            .collect();
+
+        let font = |weight, style| Font {
+            families: vec!["Other".to_string(), "brand".to_string()],
+            size: 16.0,
+            weight,
+            style,
+            stretch: 100.0,
+        };
+        let face = |weight, style, c| {
+            family_faces(&faces, "brand", &font(weight, style))
+                .iter()
+                .find(|face| face.covers(c))
+                .and_then(|face| faces.iter().position(|f| Arc::ptr_eq(f, face)))
+        };
+        // The Greek face is defined last, but doesn't cover Latin letters.
+        assert_eq!(face(400.0, FontStyle::Normal, 'a'), Some(0));
+        assert_eq!(face(400.0, FontStyle::Normal, 'α'), Some(3));
+        // 500 prefers lighter weights to those above 500, but 700 is in the bold face's range.
+        assert_eq!(face(500.0, FontStyle::Normal, 'a'), Some(0));
+        assert_eq!(face(700.0, FontStyle::Normal, 'a'), Some(1));
+        assert_eq!(face(900.0, FontStyle::Normal, 'a'), Some(1));
+        assert_eq!(face(400.0, FontStyle::Italic, 'a'), Some(2));
+    }
+
+    #[test]
+    fn test_add_faces_once() {
+        let stylesheet = css::parse("@font-face { font-family: Added Twice; }".to_string());
+        let load = || -> Vec<FontFace> {
+            stylesheet
+                .font_faces
+                .iter()
+                .filter_map(|rule| FontFace::from_rule(rule, TEST_FONT.to_vec(), 0))
+                .collect()
+        };
+        let (first, second) = (load(), load());
+        assert_eq!(first[0].face, second[0].face);
+
+        add_font_faces(first);
+        add_font_faces(second);
+        let faces = FONT_FACES.read().unwrap();
+        let added = faces.iter().filter(|face| face.family == "Added Twice");
+        assert_eq!(added.count(), 1);
+    }
+}
@@ -0,0 +1,249 @@
+use super::face::{installed_face, FaceId};
+use super::Font;
+use crate::style::FontStyle;
+use fontconfig_sys::constants::{
+    FC_CHARSET, FC_FAMILY, FC_FILE, FC_FULLNAME, FC_INDEX, FC_POSTSCRIPT_NAME, FC_SLANT,
+    FC_SLANT_ITALIC, FC_SLANT_OBLIQUE, FC_SLANT_ROMAN, FC_WEIGHT, FC_WEIGHT_BLACK, FC_WEIGHT_BOLD,
+    FC_WEIGHT_DEMIBOLD, FC_WEIGHT_EXTRABOLD, FC_WEIGHT_EXTRALIGHT, FC_WEIGHT_LIGHT,
+    FC_WEIGHT_MEDIUM, FC_WEIGHT_REGULAR, FC_WEIGHT_THIN, FC_WIDTH,
+};
+use fontconfig_sys::{
+    FcChar8, FcCharSetAddChar, FcCharSetCreate, FcCharSetDestroy, FcConfigSubstitute,
+    FcDefaultSubstitute, FcFontList, FcFontMatch, FcFontSetDestroy, FcMatchPattern, FcObjectSetAdd,
+    FcObjectSetCreate, FcObjectSetDestroy, FcPattern, FcPatternAddCharSet, FcPatternAddInteger,
+    FcPatternAddString, FcPatternCreate, FcPatternDestroy, FcPatternGetInteger, FcPatternGetString,
+    FcResultMatch,
+};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+/// The fontconfig names of the CSS generic families.
+const GENERIC_FAMILIES: [(&str, &str); 6] = [
+    ("serif", "serif"),
+    ("sans-serif", "sans-serif"),
+    ("monospace", "monospace"),
+    ("cursive", "cursive"),
+    ("fantasy", "fantasy"),
+    ("system-ui", "sans-serif"),
+];
+
+/// A family, or none for the default one, and the weight, slant and width wanted of it.
+type FaceKey = (Option<String>, u32, FontStyle, u32);
+
+thread_local! {
+    /// The installed faces already matched, by what they were matched for.
+    static FACES: RefCell<HashMap<FaceKey, Option<FaceId>>> = RefCell::new(HashMap::new());
+    /// The installed faces found for characters, by the character and the font key.
+    static FALLBACKS: RefCell<HashMap<(char, FaceKey), Option<FaceId>>> =
+        RefCell::new(HashMap::new());
+}
+
+/// The installed face of `family` nearest to the weight, slant and width of `font`, or of
+/// fontconfig's default family without one. A generic family always stands for the one
+/// fontconfig substitutes for it; any other has to be installed.
+pub(crate) fn family_face(family: Option<&str>, font: &Font) -> Option<FaceId> {
+    let key = face_key(family, font);
+    FACES.with(|faces| {
+        if let Some(&face) = faces.borrow().get(&key) {
+            return face;
+        }
+        let face = find_face(family, font);
+        faces.borrow_mut().insert(key, face);
+        face
+    })
+}
+
+/// An installed face with a glyph for `c`, as near to the first family and the weight, slant
+/// and width of `font` as fontconfig finds.
+pub(crate) fn fallback_face(font: &Font, c: char) -> Option<FaceId> {
+    let family = font
+        .families
+        .first()
+        .map(|family| fontconfig_family(family));
+    let key = (c, face_key(family, font));
+    FALLBACKS.with(|fallbacks| {
+        if let Some(&face) = fallbacks.borrow().get(&key) {
+            return face;
+        }
+        let face = font_match(family, font, Some(c)).and_then(|font| font.face());
+        fallbacks.borrow_mut().insert(key, face);
+        face
+    })
+}
+
+fn face_key(family: Option<&str>, font: &Font) -> FaceKey {
+    (
+        family.map(str::to_string),
+        font.weight.to_bits(),
+        font.style,
+        font.stretch.to_bits(),
+    )
+}
+
+fn find_face(family: Option<&str>, font: &Font) -> Option<FaceId> {
+    let matched = font_match(family.map(fontconfig_family), font, None)?;
+    let installed = match family {
+        Some(family) => {
+            GENERIC_FAMILIES
+                .iter()
+                .any(|&(css, _)| family.eq_ignore_ascii_case(css))
+                || matched
+                    .families
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(family))
+        }
+        None => true,
+    };
+    if installed {
+        matched.face()
+    } else {
+        None
+    }
+}
+
+/// The name fontconfig knows `family` by.
+fn fontconfig_family(family: &str) -> &str {
+    GENERIC_FAMILIES
+        .iter()
+        .find(|&&(css, _)| family.eq_ignore_ascii_case(css))
+        .map_or(family, |&(_, name)| name)
+}
+
+/// A face fontconfig matched.
+struct Matched {
+    families: Vec<String>,
+    file: Option<String>,
+    index: isize,
+}
+
+impl Matched {
+    fn face(&self) -> Option<FaceId> {
+        installed_face(Path::new(self.file.as_ref()?), self.index)
+    }
+}
+
+/// The face fontconfig matches for `family` in the weight, slant and width of `font`, with a
+/// glyph for `c` if given.
+fn font_match(family: Option<&str>, font: &Font, c: Option<char>) -> Option<Matched> {
+    let family = match family {
+        Some(family) => Some(CString::new(family).ok()?),
+        None => None,
+    };
+    let slant = match font.style {
+        FontStyle::Normal => FC_SLANT_ROMAN,
+        FontStyle::Italic => FC_SLANT_ITALIC,
+        FontStyle::Oblique => FC_SLANT_OBLIQUE,
+    };
+
+    unsafe {
+        let pattern = FcPatternCreate();
+        if let Some(ref family) = family {
+            FcPatternAddString(
+                pattern,
+                FC_FAMILY.as_ptr(),
+                family.as_ptr() as *const FcChar8,
+            );
+        }
+        FcPatternAddInteger(pattern, FC_WEIGHT.as_ptr(), weight(font.weight));
+        FcPatternAddInteger(pattern, FC_SLANT.as_ptr(), slant);
+        FcPatternAddInteger(pattern, FC_WIDTH.as_ptr(), font.stretch.round() as c_int);
+        if let Some(c) = c {
+            let charset = FcCharSetCreate();
+            FcCharSetAddChar(charset, c as u32);
+            FcPatternAddCharSet(pattern, FC_CHARSET.as_ptr(), charset);
+            FcCharSetDestroy(charset);
+        }
+        let matched = best_match(pattern)?;
+        let mut index = 0;
+        FcPatternGetInteger(matched, FC_INDEX.as_ptr(), 0, &mut index);
+        let result = Matched {
+            families: strings(matched, FC_FAMILY.as_ptr()),
+            file: strings(matched, FC_FILE.as_ptr()).pop(),
+            index: index as isize,
+        };
+        FcPatternDestroy(matched);
+        Some(result)
+    }
+}
+
+/// The file and face index of the installed face whose full or PostScript name is `name`,
+/// as `local()` in `@font-face` refers to it.
+pub(crate) fn local_font(name: &str) -> Option<(PathBuf, isize)> {
+    let name = CString::new(name).ok()?;
+    [FC_FULLNAME.as_ptr(), FC_POSTSCRIPT_NAME.as_ptr()]
+        .iter()
+        .find_map(|&object| unsafe { list_font(object, &name) })
+}
+
+/// The file and face index of the first installed face whose `object` is `value`.
+unsafe fn list_font(object: *const c_char, value: &CStr) -> Option<(PathBuf, isize)> {
+    let pattern = FcPatternCreate();
+    FcPatternAddString(pattern, object, value.as_ptr() as *const FcChar8);
+    let objects = FcObjectSetCreate();
+    FcObjectSetAdd(objects, FC_FILE.as_ptr());
+    FcObjectSetAdd(objects, FC_INDEX.as_ptr());
+    let fonts = FcFontList(ptr::null_mut(), pattern, objects);
+    FcObjectSetDestroy(objects);
+    FcPatternDestroy(pattern);
+    if fonts.is_null() {
+        return None;
+    }
+
+    let mut font = None;
+    if (*fonts).nfont > 0 {
+        let pattern = *(*fonts).fonts;
+        let mut index = 0;
+        FcPatternGetInteger(pattern, FC_INDEX.as_ptr(), 0, &mut index);
+        font = strings(pattern, FC_FILE.as_ptr())
+            .pop()
+            .map(|file| (PathBuf::from(file), index as isize));
+    }
+    FcFontSetDestroy(fonts);
+    font
+}
+
+/// The installed face nearest to `pattern`, which is destroyed.
+unsafe fn best_match(pattern: *mut FcPattern) -> Option<*mut FcPattern> {
+    FcConfigSubstitute(ptr::null_mut(), pattern, FcMatchPattern);
+    FcDefaultSubstitute(pattern);
+    let mut result = FcResultMatch;
+    let matched = FcFontMatch(ptr::null_mut(), pattern, &mut result);
+    FcPatternDestroy(pattern);
+    if matched.is_null() {
+        None
+    } else {
+        Some(matched)
+    }
+}
+
+/// The string values of `object` in `pattern`.
+unsafe fn strings(pattern: *mut FcPattern, object: *const c_char) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut value: *mut FcChar8 = ptr::null_mut();
+    while FcPatternGetString(pattern, object, values.len() as c_int, &mut value) == FcResultMatch {
+        let value = CStr::from_ptr(value as *const c_char);
+        values.push(value.to_string_lossy().into_owned());
+    }
+    values
+}
+
+/// The fontconfig weight nearest to a CSS one.
+fn weight(weight: f32) -> c_int {
+    let weights = [
+        FC_WEIGHT_THIN,
+        FC_WEIGHT_EXTRALIGHT,
+        FC_WEIGHT_LIGHT,
+        FC_WEIGHT_REGULAR,
+        FC_WEIGHT_MEDIUM,
+        FC_WEIGHT_DEMIBOLD,
+        FC_WEIGHT_BOLD,
+        FC_WEIGHT_EXTRABOLD,
+        FC_WEIGHT_BLACK,
+    ];
+    let index = (weight / 100.0).round().clamp(1.0, 9.0) as usize - 1;
+    weights[index]
+}
@@ -0,0 +1,120 @@
+use cairo::FontFace;
+use std::cell::Cell;
+use std::os::raw::{c_int, c_long, c_void};
+use std::ptr;
+use std::sync::Arc;
+
+type FtLibrary = *mut c_void;
+type FtFace = *mut c_void;
+
+extern "C" {
+    fn FT_Init_FreeType(library: *mut FtLibrary) -> c_int;
+    fn FT_New_Memory_Face(
+        library: FtLibrary,
+        base: *const u8,
+        size: c_long,
+        face_index: c_long,
+        face: *mut FtFace,
+    ) -> c_int;
+    fn FT_Done_Face(face: FtFace) -> c_int;
+}
+
+/// A `cairo_user_data_key_t`, of which only the address matters.
+#[repr(C)]
+struct UserDataKey {
+    _unused: c_int,
+}
+
+/// The key the owner of the FreeType face of a cairo font face is attached under.
+static OWNER_KEY: UserDataKey = UserDataKey { _unused: 0 };
+
+// Part of cairo itself, which is built with FreeType support wherever fontconfig is used.
+extern "C" {
+    fn cairo_ft_font_face_create_for_ft_face(face: FtFace, load_flags: c_int) -> *mut c_void;
+    fn cairo_font_face_set_user_data(
+        font_face: *mut c_void,
+        key: *const UserDataKey,
+        user_data: *mut c_void,
+        destroy: Option<unsafe extern "C" fn(*mut c_void)>,
+    ) -> c_int;
+    fn cairo_font_face_destroy(font_face: *mut c_void);
+}
+
+thread_local! {
+    /// FreeType libraries can't be shared between threads, so each has its own.
+    static LIBRARY: Cell<FtLibrary> = const { Cell::new(ptr::null_mut()) };
+}
+
+fn library() -> Option<FtLibrary> {
+    LIBRARY.with(|library| {
+        if library.get().is_null() {
+            let mut new = ptr::null_mut();
+            if unsafe { FT_Init_FreeType(&mut new) } != 0 {
+                return None;
+            }
+            library.set(new);
+        }
+        Some(library.get())
+    })
+}
+
+fn new_face(data: &[u8], index: isize) -> Option<FtFace> {
+    let library = library()?;
+    let mut face = ptr::null_mut();
+    let error = unsafe {
+        FT_New_Memory_Face(
+            library,
+            data.as_ptr(),
+            data.len() as c_long,
+            index as c_long,
+            &mut face,
+        )
+    };
+    if error == 0 {
+        Some(face)
+    } else {
+        None
+    }
+}
+
+/// Whether FreeType can open face `index` of `data`, which may be a TrueType or OpenType
+/// font or collection, or a WOFF or WOFF2 file.
+pub(crate) fn is_font(data: &[u8], index: isize) -> bool {
+    match new_face(data, index) {
+        Some(face) => {
+            unsafe { FT_Done_Face(face) };
+            true
+        }
+        None => false,
+    }
+}
+
+/// The FreeType face a cairo font face draws with, and the data it reads, which the font
+/// face owns until cairo destroys it.
+struct FaceOwner {
+    face: FtFace,
+    _data: Arc<Vec<u8>>,
+}
+
+unsafe extern "C" fn destroy_owner(owner: *mut c_void) {
+    let owner = Box::from_raw(owner as *mut FaceOwner);
+    FT_Done_Face(owner.face);
+}
+
+/// A cairo font face for face `index` of `data`. The FreeType face is freed, and `data`
+/// released, once cairo is done with the font face.
+pub(crate) fn cairo_font_face(data: Arc<Vec<u8>>, index: isize) -> Option<FontFace> {
+    let face = new_face(&data, index)?;
+    unsafe {
+        let font_face = cairo_ft_font_face_create_for_ft_face(face, 0);
+        let owner = Box::into_raw(Box::new(FaceOwner { face, _data: data })) as *mut c_void;
+        let status =
+            cairo_font_face_set_user_data(font_face, &OWNER_KEY, owner, Some(destroy_owner));
+        if status != 0 {
+            cairo_font_face_destroy(font_face);
+            destroy_owner(owner);
+            return None;
+        }
+        Some(FontFace::from_raw_full(font_face as *mut _))
+    }
+}
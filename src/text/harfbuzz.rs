@@ -0,0 +1,153 @@
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::ptr;
+use std::slice;
+
+type HbBlob = *mut c_void;
+type HbFace = *mut c_void;
+type HbFont = *mut c_void;
+type HbBuffer = *mut c_void;
+
+const HB_MEMORY_MODE_READONLY: c_int = 1;
+const HB_DIRECTION_RTL: c_int = 5;
+
+#[repr(C)]
+struct HbGlyphInfo {
+    codepoint: u32,
+    mask: u32,
+    cluster: u32,
+    var1: u32,
+    var2: u32,
+}
+
+#[repr(C)]
+struct HbGlyphPosition {
+    x_advance: i32,
+    y_advance: i32,
+    x_offset: i32,
+    y_offset: i32,
+    var: u32,
+}
+
+extern "C" {
+    fn hb_blob_create(
+        data: *const c_char,
+        length: c_uint,
+        mode: c_int,
+        user_data: *mut c_void,
+        destroy: *mut c_void,
+    ) -> HbBlob;
+    fn hb_blob_destroy(blob: HbBlob);
+    fn hb_face_create(blob: HbBlob, index: c_uint) -> HbFace;
+    fn hb_face_get_upem(face: HbFace) -> c_uint;
+    fn hb_face_destroy(face: HbFace);
+    fn hb_font_create(face: HbFace) -> HbFont;
+    fn hb_font_set_scale(font: HbFont, x_scale: c_int, y_scale: c_int);
+    fn hb_font_get_nominal_glyph(font: HbFont, unicode: u32, glyph: *mut u32) -> c_int;
+    fn hb_font_destroy(font: HbFont);
+    fn hb_buffer_create() -> HbBuffer;
+    fn hb_buffer_add_utf8(
+        buffer: HbBuffer,
+        text: *const c_char,
+        text_length: c_int,
+        item_offset: c_uint,
+        item_length: c_int,
+    );
+    fn hb_buffer_guess_segment_properties(buffer: HbBuffer);
+    fn hb_buffer_get_direction(buffer: HbBuffer) -> c_int;
+    fn hb_buffer_get_glyph_infos(buffer: HbBuffer, length: *mut c_uint) -> *const HbGlyphInfo;
+    fn hb_buffer_get_glyph_positions(
+        buffer: HbBuffer,
+        length: *mut c_uint,
+    ) -> *const HbGlyphPosition;
+    fn hb_buffer_destroy(buffer: HbBuffer);
+    fn hb_shape(font: HbFont, buffer: HbBuffer, features: *const c_void, num_features: c_uint);
+}
+
+/// A glyph as HarfBuzz positions it, in font units.
+pub(crate) struct ShapedGlyph {
+    pub id: u32,
+    /// The byte offset in the text of the cluster the glyph belongs to.
+    pub cluster: usize,
+    pub x_advance: i32,
+    pub x_offset: i32,
+    pub y_offset: i32,
+}
+
+/// A HarfBuzz font at the size of its units per em, over font data that has to outlive it.
+pub(crate) struct Font {
+    font: HbFont,
+    pub units_per_em: u32,
+}
+
+impl Font {
+    pub(crate) fn new(data: &[u8], index: isize) -> Font {
+        unsafe {
+            let blob = hb_blob_create(
+                data.as_ptr() as *const c_char,
+                data.len() as c_uint,
+                HB_MEMORY_MODE_READONLY,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+            let face = hb_face_create(blob, index as c_uint);
+            let units_per_em = hb_face_get_upem(face);
+            let font = hb_font_create(face);
+            hb_font_set_scale(font, units_per_em as c_int, units_per_em as c_int);
+            hb_face_destroy(face);
+            hb_blob_destroy(blob);
+            Font { font, units_per_em }
+        }
+    }
+
+    pub(crate) fn has_glyph(&self, c: char) -> bool {
+        let mut glyph = 0;
+        unsafe { hb_font_get_nominal_glyph(self.font, c as u32, &mut glyph) != 0 }
+    }
+
+    /// The glyphs of `text` in visual order, and whether it runs right to left. The script,
+    /// direction and language are guessed from the text.
+    pub(crate) fn shape(&self, text: &str) -> (Vec<ShapedGlyph>, bool) {
+        unsafe {
+            let buffer = hb_buffer_create();
+            hb_buffer_add_utf8(
+                buffer,
+                text.as_ptr() as *const c_char,
+                text.len() as c_int,
+                0,
+                text.len() as c_int,
+            );
+            hb_buffer_guess_segment_properties(buffer);
+            hb_shape(self.font, buffer, ptr::null(), 0);
+
+            let mut length = 0;
+            let infos = hb_buffer_get_glyph_infos(buffer, &mut length);
+            let positions = hb_buffer_get_glyph_positions(buffer, &mut length);
+            let glyphs = if length == 0 {
+                Vec::new()
+            } else {
+                let infos = slice::from_raw_parts(infos, length as usize);
+                let positions = slice::from_raw_parts(positions, length as usize);
+                infos
+                    .iter()
+                    .zip(positions)
+                    .map(|(info, position)| ShapedGlyph {
+                        id: info.codepoint,
+                        cluster: info.cluster as usize,
+                        x_advance: position.x_advance,
+                        x_offset: position.x_offset,
+                        y_offset: position.y_offset,
+                    })
+                    .collect()
+            };
+            let rtl = hb_buffer_get_direction(buffer) == HB_DIRECTION_RTL;
+            hb_buffer_destroy(buffer);
+            (glyphs, rtl)
+        }
+    }
+}
+
+impl Drop for Font {
+    fn drop(&mut self) {
+        unsafe { hb_font_destroy(self.font) };
+    }
+}
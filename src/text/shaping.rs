@@ -0,0 +1,243 @@
+use super::face::{handles, FaceId};
+use super::{font_face, fontconfig, Font, FONT_FACES};
+use crate::style::FontStyle;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// A shaped glyph, positioned in points relative to the pen.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glyph {
+    pub id: u32,
+    pub x_advance: f32,
+    pub x_offset: f32,
+    /// How far the glyph is raised above the baseline.
+    pub y_offset: f32,
+    /// The byte offset in the run's text of the cluster the glyph belongs to.
+    pub cluster: usize,
+}
+
+/// Text shaped in one face and size, its glyphs in visual order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlyphRun {
+    pub text: String,
+    pub face: FaceId,
+    pub size: f32,
+    pub glyphs: Vec<Glyph>,
+    /// Whether the text runs right to left, so its clusters are in reverse order.
+    pub rtl: bool,
+}
+
+impl GlyphRun {
+    pub fn width(&self) -> f32 {
+        self.glyphs.iter().map(|glyph| glyph.x_advance).sum()
+    }
+}
+
+/// The families, weight, slant and width of a font, which the face for a character depends
+/// on.
+type FontKey = (Vec<String>, u32, FontStyle, u32);
+
+thread_local! {
+    /// The faces already picked for characters, by the character and the font.
+    static CHAR_FACES: RefCell<HashMap<(char, FontKey), Option<FaceId>>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Shapes `text` in `font` into runs, each character drawn in the first family with a glyph
+/// for it, or else in an installed face fontconfig finds for it. Characters that combine
+/// with the one before them stay in its face, so clusters aren't split between fonts, and
+/// those no face has a glyph for are drawn as the primary face's .notdef glyph.
+pub fn shape(text: &str, font: &Font) -> Vec<GlyphRun> {
+    // Without even fontconfig's default face there is nothing to shape with.
+    let primary = match primary_face(font) {
+        Some(face) => face,
+        None => return Vec::new(),
+    };
+    let face_for = |c| char_face(font, c).unwrap_or(primary);
+
+    let mut segments: Vec<(Range<usize>, FaceId)> = Vec::new();
+    for (i, c) in text.char_indices() {
+        let end = i + c.len_utf8();
+        if let Some((range, face)) = segments.last_mut() {
+            let keep = if c.is_whitespace() {
+                handles(*face).font.has_glyph(c)
+            } else {
+                extends_cluster(c)
+            };
+            if keep || *face == face_for(c) {
+                range.end = end;
+                continue;
+            }
+        }
+        segments.push((i..end, face_for(c)));
+    }
+
+    segments
+        .into_iter()
+        .map(|(range, face)| shape_run(&text[range], face, font.size))
+        .collect()
+}
+
+/// The face text in `font` is measured by when no character picks one: the first family
+/// available, or else fontconfig's default.
+pub(crate) fn primary_face(font: &Font) -> Option<FaceId> {
+    let faces = FONT_FACES.read().unwrap();
+    font.families
+        .iter()
+        .find_map(
+            |family| match font_face::family_faces(&faces, family, font).first() {
+                Some(face) => Some(face.face),
+                None => fontconfig::family_face(Some(family), font),
+            },
+        )
+        .or_else(|| fontconfig::family_face(None, font))
+}
+
+/// The face with a glyph for `c` that it is drawn in, if any.
+fn char_face(font: &Font, c: char) -> Option<FaceId> {
+    let key = (
+        font.families.clone(),
+        font.weight.to_bits(),
+        font.style,
+        font.stretch.to_bits(),
+    );
+    CHAR_FACES.with(|char_faces| {
+        if let Some(&face) = char_faces.borrow().get(&(c, key.clone())) {
+            return face;
+        }
+        let face = find_char_face(font, c);
+        char_faces.borrow_mut().insert((c, key), face);
+        face
+    })
+}
+
+fn find_char_face(font: &Font, c: char) -> Option<FaceId> {
+    let has_glyph = |face: FaceId| handles(face).font.has_glyph(c);
+    let faces = FONT_FACES.read().unwrap();
+    for family in &font.families {
+        let web_faces = font_face::family_faces(&faces, family, font);
+        let face = if web_faces.is_empty() {
+            fontconfig::family_face(Some(family), font).filter(|&face| has_glyph(face))
+        } else {
+            web_faces
+                .iter()
+                .filter(|face| face.covers(c))
+                .map(|face| face.face)
+                .find(|&face| has_glyph(face))
+        };
+        if face.is_some() {
+            return face;
+        }
+    }
+    drop(faces);
+    fontconfig::fallback_face(font, c).filter(|&face| has_glyph(face))
+}
+
+/// Whether `c` belongs to the cluster of the character before it: a combining mark, a
+/// joiner or a variation selector.
+fn extends_cluster(c: char) -> bool {
+    matches!(
+        c,
+        '\u{0300}'..='\u{036f}'
+            | '\u{1ab0}'..='\u{1aff}'
+            | '\u{1dc0}'..='\u{1dff}'
+            | '\u{200c}'..='\u{200d}'
+            | '\u{20d0}'..='\u{20ff}'
+            | '\u{fe00}'..='\u{fe0f}'
+            | '\u{fe20}'..='\u{fe2f}'
+            | '\u{1f3fb}'..='\u{1f3ff}'
+            | '\u{e0020}'..='\u{e007f}'
+            | '\u{e0100}'..='\u{e01ef}'
+    )
+}
+
+fn shape_run(text: &str, face: FaceId, size: f32) -> GlyphRun {
+    let font = &handles(face).font;
+    let scale = size / font.units_per_em as f32;
+    let (glyphs, rtl) = font.shape(text);
+    GlyphRun {
+        text: text.to_string(),
+        face,
+        size,
+        glyphs: glyphs
+            .into_iter()
+            .map(|glyph| Glyph {
+                id: glyph.id,
+                x_advance: glyph.x_advance as f32 * scale,
+                x_offset: glyph.x_offset as f32 * scale,
+                y_offset: glyph.y_offset as f32 * scale,
+                cluster: glyph.cluster,
+            })
+            .collect(),
+        rtl,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::shape;
+    use crate::css;
+    use crate::style::FontStyle;
+    use crate::text::font_face::FontFace;
+    use crate::text::testing::TEST_FONT;
+    use crate::text::{add_font_faces, Font};
+
+    #[test]
+    fn test_shape() {
+        let stylesheet = css::parse(
+            "@font-face { font-family: Shaping Latin; unicode-range: U+0-7F, U+300-36F; }
+            @font-face { font-family: Shaping Symbols; }"
+                .to_string(),
+        );
+        // The same font would be loaded once and shared, so the second family gets a padded
+        // copy, which is a face of its own.
+        let data = [TEST_FONT.to_vec(), [TEST_FONT, &[0; 4]].concat()];
+        add_font_faces(
+            stylesheet
+                .font_faces
+                .iter()
+                .zip(data)
+                .filter_map(|(rule, data)| FontFace::from_rule(rule, data, 0))
+                .collect(),
+        );
+        let font = Font {
+            families: vec!["Shaping Latin".to_string(), "Shaping Symbols".to_string()],
+            size: 20.0,
+            weight: 400.0,
+            style: FontStyle::Normal,
+            stretch: 100.0,
+        };
+        let width = |text| {
+            shape(text, &font)
+                .iter()
+                .map(|run| run.width())
+                .sum::<f32>()
+        };
+        // Kerning pulls the letters together.
+        assert!(width("AV") < width("A") + width("V"));
+
+        // A combining accent stays in the cluster of its letter.
+        let runs = shape("e\u{301}", &font);
+        assert_eq!(runs.len(), 1);
+        assert!(runs[0].glyphs.iter().all(|glyph| glyph.cluster == 0));
+
+        // A character the first family doesn't cover falls back to the next one.
+        let runs = shape("A\u{2200}B", &font);
+        let texts: Vec<&str> = runs.iter().map(|run| run.text.as_str()).collect();
+        assert_eq!(texts, ["A", "\u{2200}", "B"]);
+        assert_eq!(runs[0].face, runs[2].face);
+        assert_ne!(runs[0].face, runs[1].face);
+        assert!(runs
+            .iter()
+            .all(|run| run.glyphs.iter().all(|glyph| glyph.id != 0)));
+
+        // One no face has a glyph for is still drawn, as the primary face's .notdef.
+        let runs = shape("A\u{10fffd}", &font);
+        assert_eq!(runs.len(), 1);
+        let ids: Vec<u32> = runs[0].glyphs.iter().map(|glyph| glyph.id).collect();
+        assert_eq!(ids.len(), 2);
+        assert_ne!(ids[0], 0);
+        assert_eq!(ids[1], 0);
+    }
+}